@@ -0,0 +1,71 @@
+//! Codec benchmarks
+//!
+//! Compares JSON against rkyv for a typical chat-sized message. Run with
+//! `cargo bench --bench codec_bench --features dev`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use leptos_ws_pro::codec::{Codec, HybridCodec, JsonCodec, RkyvCodec};
+use serde::{Deserialize, Serialize};
+use std::hint::black_box;
+
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+)]
+#[archive(check_bytes)]
+struct BenchMessage {
+    id: u64,
+    room: String,
+    sender: String,
+    content: String,
+    timestamp: u64,
+    tags: Vec<String>,
+    scores: Vec<f64>,
+}
+
+fn message() -> BenchMessage {
+    BenchMessage {
+        id: 42,
+        room: "general".to_string(),
+        sender: "bench-user".to_string(),
+        content: "Hello from the codec benchmark! ".repeat(8),
+        timestamp: 1_700_000_000_000,
+        tags: (0..8).map(|i| format!("tag-{}", i)).collect(),
+        scores: (0..64).map(|i| i as f64 * 0.5).collect(),
+    }
+}
+
+fn bench_codec<C: Codec<BenchMessage>>(c: &mut Criterion, name: &str, codec: C) {
+    let message = message();
+    let encoded = codec.encode(&message).unwrap();
+
+    let mut group = c.benchmark_group(name);
+    group.bench_function("encode", |b| {
+        b.iter(|| codec.encode(black_box(&message)).unwrap())
+    });
+    group.bench_function("decode", |b| {
+        b.iter(|| codec.decode(black_box(&encoded)).unwrap())
+    });
+    group.bench_function("round_trip", |b| {
+        b.iter(|| {
+            let encoded = codec.encode(black_box(&message)).unwrap();
+            codec.decode(&encoded).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn codec_benchmarks(c: &mut Criterion) {
+    bench_codec(c, "json", JsonCodec::new());
+    bench_codec(c, "rkyv", RkyvCodec::new());
+    bench_codec(c, "hybrid", HybridCodec::new().unwrap());
+}
+
+criterion_group!(benches, codec_benchmarks);
+criterion_main!(benches);
@@ -0,0 +1,310 @@
+//! Procedural macros for leptos-ws-pro
+//!
+//! Use these through the re-exports in `leptos_ws_pro::rpc` rather than
+//! depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    ext::IdentExt, parse_macro_input, spanned::Spanned, Error, FnArg, Ident, ItemTrait, Pat,
+    ReturnType, TraitItem, TraitItemFn, Type,
+};
+
+/// Turn a trait of async methods into a typed RPC service
+///
+/// For a trait `Users`, this generates:
+///
+/// - `UsersMethods` with one wire method name constant per trait method,
+///   e.g. `UsersMethods::GET_USER == "Users.get_user"`, plus `ALL`
+/// - `UsersClient<T>`, a stub over `RpcClient<T>` with one typed async
+///   method per trait method
+/// - `UsersServer::register`, which routes every method of the trait on an
+///   `RpcRouter` to the implementation the router serves as its context
+///
+/// The dispatcher registers on `RpcRouter` rather than directly on an
+/// `RpcMethodRegistry`: registry handlers are synchronous, and the service
+/// methods are async. The router falls back to the registry it wraps, so
+/// existing registry handlers are served next to the generated ones.
+///
+/// Methods must take `&self` and return a `Result` whose error type converts
+/// from and into `leptos_ws_pro::rpc::RpcError`. Parameters are sent by
+/// position as a JSON array. Method names must stay distinct once
+/// uppercased, and none may be `all`, as their constants share a namespace
+/// with `ALL`.
+///
+/// Methods with a default body get a `where Self: Sync` bound: the body runs
+/// in a `Send` future that holds `&self`.
+///
+/// ```ignore
+/// use leptos_ws_pro::rpc::{rpc_service, RpcError};
+///
+/// #[rpc_service]
+/// pub trait Users {
+///     async fn get_user(&self, id: u64) -> Result<User, RpcError>;
+///     async fn rename(&self, id: u64, name: String) -> Result<(), RpcError>;
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn rpc_service(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return Error::new(
+            TokenStream2::from(args).span(),
+            "rpc_service does not take arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let item = parse_macro_input!(input as ItemTrait);
+    expand(item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+struct ServiceMethod {
+    name: Ident,
+    constant: Ident,
+    wire_name: String,
+    args: Vec<(Ident, Type)>,
+    output: Type,
+}
+
+fn expand(mut item: ItemTrait) -> syn::Result<TokenStream2> {
+    if !item.generics.params.is_empty() || item.generics.where_clause.is_some() {
+        return Err(Error::new(
+            item.generics.span(),
+            "rpc_service traits cannot be generic",
+        ));
+    }
+
+    let trait_name = item.ident.clone();
+    let mut methods = Vec::new();
+    for trait_item in &mut item.items {
+        match trait_item {
+            TraitItem::Fn(method) => methods.push(rewrite_method(&trait_name, method)?),
+            other => {
+                return Err(Error::new(
+                    other.span(),
+                    "rpc_service traits may only contain async methods",
+                ))
+            }
+        }
+    }
+    for (i, method) in methods.iter().enumerate() {
+        if method.constant == "ALL" {
+            return Err(Error::new(
+                method.name.span(),
+                "rpc_service method names cannot be `all`, as the `ALL` constant is reserved",
+            ));
+        }
+        if let Some(other) = methods[..i]
+            .iter()
+            .find(|other| other.constant == method.constant)
+        {
+            return Err(Error::new(
+                method.name.span(),
+                format!(
+                    "`{}` and `{}` both map to the method constant `{}`",
+                    other.name, method.name, method.constant
+                ),
+            ));
+        }
+    }
+
+    let vis = &item.vis;
+    let methods_name = format_ident!("{}Methods", trait_name);
+    let client_name = format_ident!("{}Client", trait_name);
+    let server_name = format_ident!("{}Server", trait_name);
+
+    let constants = methods.iter().map(|method| {
+        let constant = &method.constant;
+        let wire_name = &method.wire_name;
+        quote! { pub const #constant: &'static str = #wire_name; }
+    });
+    let all_constants = methods.iter().map(|method| &method.constant);
+
+    let client_methods = methods.iter().map(|method| {
+        let name = &method.name;
+        let constant = &method.constant;
+        let output = &method.output;
+        let arg_names: Vec<_> = method.args.iter().map(|(name, _)| name).collect();
+        let arg_types = method.args.iter().map(|(_, ty)| ty);
+        quote! {
+            pub async fn #name(&self, #(#arg_names: #arg_types),*) -> #output {
+                self.client
+                    .call_typed(
+                        #methods_name::#constant,
+                        (#(&#arg_names,)*),
+                        ::leptos_ws_pro::rpc::RpcMethod::Call,
+                    )
+                    .await
+                    .map_err(::core::convert::From::from)
+            }
+        }
+    });
+
+    let registrations = methods.iter().map(|method| {
+        let name = &method.name;
+        let constant = &method.constant;
+        let arg_names: Vec<_> = method.args.iter().map(|(name, _)| name).collect();
+        let arg_types = method.args.iter().map(|(_, ty)| ty);
+        quote! {
+            router.register(
+                #methods_name::#constant,
+                |service: S, (#(#arg_names,)*): (#(#arg_types,)*)| async move {
+                    service.#name(#(#arg_names),*).await
+                },
+            );
+        }
+    });
+
+    let client_doc = format!("Typed RPC client for [`{}`]", trait_name);
+    let methods_doc = format!("Wire method names of [`{}`]", trait_name);
+    let server_doc = format!("Server-side dispatch for [`{}`]", trait_name);
+
+    Ok(quote! {
+        #item
+
+        #[doc = #methods_doc]
+        #vis struct #methods_name;
+
+        impl #methods_name {
+            #(#constants)*
+
+            /// All method names of the service
+            pub const ALL: &'static [&'static str] = &[#(Self::#all_constants),*];
+        }
+
+        #[doc = #client_doc]
+        #vis struct #client_name<T> {
+            client: ::std::sync::Arc<::leptos_ws_pro::rpc::RpcClient<T>>,
+        }
+
+        impl<T> ::core::clone::Clone for #client_name<T> {
+            fn clone(&self) -> Self {
+                Self {
+                    client: self.client.clone(),
+                }
+            }
+        }
+
+        impl<T> #client_name<T>
+        where
+            T: ::leptos_ws_pro::__private::serde::Serialize
+                + for<'de> ::leptos_ws_pro::__private::serde::Deserialize<'de>
+                + ::core::marker::Send
+                + ::core::marker::Sync
+                + 'static,
+        {
+            /// Create a client stub that calls through `client`
+            pub fn new(client: ::std::sync::Arc<::leptos_ws_pro::rpc::RpcClient<T>>) -> Self {
+                Self { client }
+            }
+
+            /// Get the underlying RPC client
+            pub fn inner(&self) -> &::std::sync::Arc<::leptos_ws_pro::rpc::RpcClient<T>> {
+                &self.client
+            }
+
+            #(#client_methods)*
+        }
+
+        #[doc = #server_doc]
+        #vis struct #server_name;
+
+        impl #server_name {
+            /// Route every method of the service to the context the router serves
+            pub fn register<S>(router: &mut ::leptos_ws_pro::rpc::RpcRouter<S>)
+            where
+                S: #trait_name + ::core::clone::Clone + ::core::marker::Send + ::core::marker::Sync + 'static,
+            {
+                #(#registrations)*
+            }
+        }
+    })
+}
+
+/// Validate a trait method and rewrite it to return a `Send` future
+///
+/// The router runs handlers on spawned tasks, so the futures the
+/// implementations return must be `Send`. A default body borrows `self`
+/// across its awaits, so it also needs `Self: Sync`.
+fn rewrite_method(trait_name: &Ident, method: &mut TraitItemFn) -> syn::Result<ServiceMethod> {
+    let sig = &mut method.sig;
+    if sig.asyncness.is_none() {
+        return Err(Error::new(
+            sig.fn_token.span(),
+            "rpc_service methods must be async",
+        ));
+    }
+    if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+        return Err(Error::new(
+            sig.generics.span(),
+            "rpc_service methods cannot be generic",
+        ));
+    }
+
+    let mut inputs = sig.inputs.iter();
+    match inputs.next() {
+        Some(FnArg::Receiver(receiver))
+            if receiver.reference.is_some() && receiver.mutability.is_none() => {}
+        _ => {
+            return Err(Error::new(
+                sig.ident.span(),
+                "rpc_service methods must take `&self`",
+            ))
+        }
+    }
+
+    let mut args = Vec::new();
+    for input in inputs {
+        let FnArg::Typed(arg) = input else {
+            unreachable!("receiver can only be the first argument")
+        };
+        let Pat::Ident(pat) = &*arg.pat else {
+            return Err(Error::new(
+                arg.pat.span(),
+                "rpc_service arguments must be plain identifiers",
+            ));
+        };
+        if let Type::Reference(ty) = &*arg.ty {
+            return Err(Error::new(
+                ty.span(),
+                "rpc_service arguments must be owned, as they are deserialized on the server",
+            ));
+        }
+        args.push((pat.ident.clone(), (*arg.ty).clone()));
+    }
+
+    let output = match &sig.output {
+        ReturnType::Type(_, ty) => (**ty).clone(),
+        ReturnType::Default => {
+            return Err(Error::new(
+                sig.ident.span(),
+                "rpc_service methods must return a `Result`",
+            ))
+        }
+    };
+
+    sig.asyncness = None;
+    sig.output = syn::parse_quote! {
+        -> impl ::core::future::Future<Output = #output> + ::core::marker::Send
+    };
+    if let Some(body) = method.default.take() {
+        sig.generics
+            .make_where_clause()
+            .predicates
+            .push(syn::parse_quote!(Self: ::core::marker::Sync));
+        method.default = Some(syn::parse_quote!({ async move #body }));
+    }
+
+    let name = sig.ident.clone();
+    Ok(ServiceMethod {
+        constant: Ident::new(&name.unraw().to_string().to_uppercase(), Span::call_site()),
+        wire_name: format!("{}.{}", trait_name.unraw(), name.unraw()),
+        name,
+        args,
+        output,
+    })
+}
@@ -1,41 +1,260 @@
+use crate::codec::{negotiation, Codec, CodecError, CodecProtocol, FrameCodec};
+use crate::rpc::router::RpcRouter;
+use crate::transport::datagram::{self, Coalescer};
+#[cfg(feature = "compression")]
+use crate::transport::websocket::deflate::{DeflateConfig, DeflateStream};
+use crate::transport::websocket::mux::{Multiplexer, MuxConfig, MuxRole};
+use crate::transport::{self, Datagram, DatagramStream, MessageType, TransportError};
+#[cfg(feature = "ssr")]
 use crate::{
-    messages::{Messages, ServerSignalMessage, ServerSignalUpdate},
+    server_signal::{
+        connection::{self, ScopeOf},
+        SignalScope,
+    },
     server_signals::ServerSignals,
 };
-use axum::extract::ws::Message;
-use futures::{future::BoxFuture, stream::SplitSink, SinkExt, StreamExt};
+use axum::body::Body;
+use axum::extract::{FromRequestParts, WebSocketUpgrade};
+use axum::http::{header, request::Parts, HeaderValue, Method, StatusCode, Version};
+use axum::response::Response;
+use futures::{
+    future::{self, BoxFuture},
+    Future, Sink, SinkExt, Stream, StreamExt,
+};
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
 use leptos::logging::error;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::{
-    spawn,
-    sync::{broadcast::Receiver, RwLock},
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{
+    handshake::derive_accept_key,
+    protocol::{Role, WebSocketConfig},
+    Message,
 };
+use tokio_tungstenite::WebSocketStream;
 
-async fn handle_broadcasts(
-    mut receiver: Receiver<ServerSignalUpdate>,
-    sink: Arc<RwLock<SplitSink<axum::extract::ws::WebSocket, axum::extract::ws::Message>>>,
-) {
-    while let Ok(message) = receiver.recv().await {
-        if sink
-            .write()
-            .await
-            .send(Message::Text(
-                serde_json::to_string(&Messages::ServerSignal(ServerSignalMessage::Update(
-                    message,
-                )))
-                .unwrap()
-                .into(),
-            ))
-            .await
-            .is_err()
-        {
-            break;
+/// The upgraded connection under the WebSocket protocol
+#[cfg(feature = "compression")]
+type Socket = DeflateStream<TokioIo<hyper::upgrade::Upgraded>>;
+#[cfg(not(feature = "compression"))]
+type Socket = TokioIo<hyper::upgrade::Upgraded>;
+
+/// Selects the codec for an upgrade from the `lwp.*` subprotocols the client offered.
+///
+/// `supported` lists the codecs the handler can speak, most preferred first. The chosen
+/// token is echoed in the handshake response; when the client offered none of them, no
+/// subprotocol is selected and the connection speaks JSON. Read the outcome back with
+/// [`negotiated_codec`] once the socket is upgraded.
+///
+/// # Example
+///
+/// ```ignore
+/// use axum::extract::WebSocketUpgrade;
+/// use leptos_ws_pro::codec::{CodecFormat, CodecProtocol};
+///
+/// async fn handler(ws: WebSocketUpgrade) -> axum::response::Response {
+///     let supported = [CodecProtocol::new(CodecFormat::Rkyv), CodecProtocol::JSON];
+///     leptos_ws_pro::axum::negotiate_codec(ws, &supported).on_upgrade(|socket| async move {
+///         let codec = leptos_ws_pro::axum::negotiated_codec(&socket).unwrap();
+///         // decode incoming frames with `codec`
+///     })
+/// }
+/// ```
+pub fn negotiate_codec(ws: WebSocketUpgrade, supported: &[CodecProtocol]) -> WebSocketUpgrade {
+    let selected = {
+        let offered: Vec<&str> = ws
+            .requested_protocols()
+            .filter_map(|protocol| protocol.to_str().ok())
+            .collect();
+        negotiation::negotiate(&offered, supported)
+    };
+
+    match selected {
+        Some(protocol) => ws.protocols([protocol.token()]),
+        None => ws,
+    }
+}
+
+/// Returns the codec selected for an upgraded socket by [`negotiate_codec`].
+pub fn negotiated_codec(
+    socket: &axum::extract::ws::WebSocket,
+) -> Result<Box<dyn Codec<transport::Message> + Send + Sync>, CodecError> {
+    let selected = socket
+        .protocol()
+        .and_then(|protocol| protocol.to_str().ok());
+    CodecProtocol::selected(selected)?.codec()
+}
+
+/// A WebSocket upgrade request, extracted by the handlers in this module.
+///
+/// The handlers take this in place of axum's [`WebSocketUpgrade`], whose socket can't
+/// run under another layer: here the upgraded socket runs permessage-deflate (RFC 7692)
+/// when the client offers it, as browsers do. The handlers also select a codec from the
+/// `lwp.*` subprotocols the client offered.
+///
+/// Like [`WebSocketUpgrade`], it accepts HTTP/1.1 upgrades and HTTP/2 extended CONNECT
+/// requests (RFC 8441), provided the server enables the latter.
+pub struct Upgrade {
+    /// The client's key, absent over HTTP/2
+    key: Option<HeaderValue>,
+    on_upgrade: OnUpgrade,
+    protocols: Vec<String>,
+    extensions: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Upgrade {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let values = |name| {
+            parts
+                .headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
         };
+        let is_upgrade = if parts.version <= Version::HTTP_11 {
+            parts.method == Method::GET
+                && values(header::CONNECTION).any(|value| value.eq_ignore_ascii_case("upgrade"))
+                && values(header::UPGRADE).any(|value| value.eq_ignore_ascii_case("websocket"))
+        } else {
+            parts.method == Method::CONNECT
+                && parts
+                    .extensions
+                    .get::<hyper::ext::Protocol>()
+                    .is_some_and(|protocol| protocol.as_str() == "websocket")
+        };
+        if !is_upgrade || !values(header::SEC_WEBSOCKET_VERSION).any(|value| value == "13") {
+            return Err((StatusCode::BAD_REQUEST, "Not a WebSocket upgrade request"));
+        }
+
+        let protocols = values(header::SEC_WEBSOCKET_PROTOCOL)
+            .map(str::to_string)
+            .collect();
+        let extensions: Vec<&str> = parts
+            .headers
+            .get_all(header::SEC_WEBSOCKET_EXTENSIONS)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        let extensions = (!extensions.is_empty()).then(|| extensions.join(", "));
+        let key = if parts.version <= Version::HTTP_11 {
+            let key = parts.headers.get(header::SEC_WEBSOCKET_KEY).cloned();
+            Some(key.ok_or((StatusCode::BAD_REQUEST, "Missing Sec-WebSocket-Key"))?)
+        } else {
+            None
+        };
+        let on_upgrade = parts
+            .extensions
+            .remove::<OnUpgrade>()
+            .ok_or((StatusCode::UPGRADE_REQUIRED, "Connection is not upgradable"))?;
+
+        Ok(Self {
+            key,
+            on_upgrade,
+            protocols,
+            extensions,
+        })
+    }
+}
+
+/// What the client and server agreed to during the upgrade
+struct Negotiated {
+    protocol: CodecProtocol,
+    datagrams: bool,
+}
+
+impl Upgrade {
+    /// Answer the upgrade and serve the socket with `callback`
+    ///
+    /// The codec is selected from `supported`, most preferred first; without a match
+    /// the connection speaks JSON. Emulated datagrams are agreed to when `datagrams` is
+    /// set and the client offered them.
+    fn on_upgrade<F, Fut>(
+        self,
+        supported: &[CodecProtocol],
+        datagrams: bool,
+        callback: F,
+    ) -> Response
+    where
+        F: FnOnce(WebSocketStream<Socket>, Negotiated) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let protocol = negotiation::negotiate(&self.protocols, supported);
+        let datagrams = datagrams
+            && self
+                .extensions
+                .as_deref()
+                .is_some_and(datagram::extension_listed);
+        // An extended CONNECT is answered with a plain 200
+        let mut response = match &self.key {
+            Some(key) => Response::builder()
+                .status(StatusCode::SWITCHING_PROTOCOLS)
+                .header(header::CONNECTION, "upgrade")
+                .header(header::UPGRADE, "websocket")
+                .header(
+                    header::SEC_WEBSOCKET_ACCEPT,
+                    derive_accept_key(key.as_bytes()),
+                ),
+            None => Response::builder().status(StatusCode::OK),
+        };
+        if let Some(protocol) = protocol {
+            response = response.header(header::SEC_WEBSOCKET_PROTOCOL, protocol.token());
+        }
+
+        #[cfg(feature = "compression")]
+        let deflate = DeflateConfig::default();
+        #[cfg(feature = "compression")]
+        let params = self
+            .extensions
+            .as_deref()
+            .and_then(|offer| deflate.accept_offer(offer));
+        let mut extensions = Vec::new();
+        #[cfg(feature = "compression")]
+        extensions.extend(params.map(|params| params.response()));
+        if datagrams {
+            extensions.push(datagram::WEBSOCKET_EXTENSION.to_string());
+        }
+        if !extensions.is_empty() {
+            response = response.header(header::SEC_WEBSOCKET_EXTENSIONS, extensions.join(", "));
+        }
+
+        let on_upgrade = self.on_upgrade;
+        tokio::spawn(async move {
+            let upgraded = match on_upgrade.await {
+                Ok(upgraded) => TokioIo::new(upgraded),
+                Err(e) => {
+                    error!("WebSocket upgrade failed: {}", e);
+                    return;
+                }
+            };
+            // Decompressed messages are held to tungstenite's own limit
+            #[cfg(feature = "compression")]
+            let upgraded = DeflateStream::server(
+                upgraded,
+                params,
+                deflate.compression_level,
+                WebSocketConfig::default()
+                    .max_message_size
+                    .unwrap_or(usize::MAX),
+            );
+            let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+            let negotiated = Negotiated {
+                protocol: protocol.unwrap_or(CodecProtocol::JSON),
+                datagrams,
+            };
+            callback(socket, negotiated).await
+        });
+
+        response
+            .body(Body::empty())
+            .expect("upgrade response headers are valid")
     }
 }
 
-use axum::extract::WebSocketUpgrade;
-use axum::response::Response;
 /// Creates a WebSocket handler function for upgrading HTTP connections to WebSocket connections.
 ///
 /// This function returns a closure that can be used as a route handler in an Axum web server to handle
@@ -50,7 +269,7 @@ use axum::response::Response;
 /// # Returns
 ///
 /// Returns an implementation of a function that:
-/// - Takes a `WebSocketUpgrade` as an argument
+/// - Takes an [`Upgrade`] as an argument
 /// - Returns a `BoxFuture<'static, Response>`
 /// - Is `Clone`, `Send`, and has a `'static` lifetime
 ///
@@ -76,74 +295,658 @@ use axum::response::Response;
 ///
 /// In this example, the `websocket` function is used to create a WebSocket handler for the "/ws" route
 /// in an Axum router configuration.
+#[cfg(feature = "ssr")]
 pub fn websocket(
     server_signals: ServerSignals,
-) -> impl Fn(WebSocketUpgrade) -> BoxFuture<'static, Response> + Clone + Send + 'static {
-    move |ws: WebSocketUpgrade| {
+) -> impl Fn(Upgrade) -> BoxFuture<'static, Response> + Clone + Send + 'static {
+    move |ws: Upgrade| {
         let value = server_signals.clone();
-        Box::pin(async move { ws.on_upgrade(move |socket| handle_socket(socket, value)) })
-    }
-}
-
-async fn handle_socket(socket: axum::extract::ws::WebSocket, server_signals: ServerSignals) {
-    let (send, mut recv) = socket.split();
-    let send = Arc::new(RwLock::new(send));
-    let _ = spawn(async move {
-        while let Some(message) = recv.next().await {
-            if let Ok(msg) = message {
-                match msg {
-                    Message::Text(text) => {
-                        if let Ok(message) = serde_json::from_str::<Messages>(&text) {
-                            match message {
-                                Messages::ServerSignal(server_msg) => match server_msg {
-                                    ServerSignalMessage::Establish(name) => {
-                                        let recv = server_signals
-                                            .add_observer(name.clone())
-                                            .await
-                                            .unwrap();
-                                        send.clone()
-                                            .write()
-                                            .await
-                                            .send(Message::Text(
-                                                serde_json::to_string(&Messages::ServerSignal(
-                                                    ServerSignalMessage::EstablishResponse((
-                                                        name.clone(),
-                                                        server_signals
-                                                            .json(name.clone())
-                                                            .await
-                                                            .unwrap()
-                                                            .unwrap(),
-                                                    )),
-                                                ))
-                                                .unwrap()
-                                                .into(),
-                                            ))
-                                            .await
-                                            .unwrap();
-                                        spawn(handle_broadcasts(recv, send.clone()));
-                                    }
-                                    _ => error!("Unexpected server signal message from client"),
-                                },
-                            }
-                        } else {
-                            leptos::logging::error!("Error transmitting message")
-                        }
+        let scope_of: ScopeOf = Arc::new(|_: &str| SignalScope::Global);
+        let response = ws.on_upgrade(
+            &CodecProtocol::available(),
+            false,
+            move |socket, negotiated| handle_socket(socket, negotiated, value, scope_of),
+        );
+        Box::pin(async move { response })
+    }
+}
+
+/// Creates a WebSocket handler whose server signals are scoped per connection.
+///
+/// `A` is extracted from the upgrade request, typically the user that the application's
+/// auth layer attached to it. Requests the extractor rejects are never upgraded. When the
+/// client establishes a signal, `resolve` maps `A` and the signal name to the scope to
+/// serve it from, so two users establishing `"notifications"` each observe their own
+/// [`ServerSignal`](crate::ServerSignal) registered under
+/// [`SignalKey::session`](crate::SignalKey::session).
+///
+/// # Example
+///
+/// ```ignore
+/// use axum::{routing::get, Extension, Router};
+/// use leptos_ws_pro::SignalScope;
+///
+/// let app = Router::new().route(
+///     "/ws",
+///     get(leptos_ws_pro::axum::websocket_scoped(
+///         state.server_signals.clone(),
+///         |Extension(user): &Extension<AuthUser>, name: &str| match name {
+///             "notifications" => SignalScope::Session(user.id.to_string()),
+///             "document" => SignalScope::Room(user.open_document.clone()),
+///             _ => SignalScope::Global,
+///         },
+///     )),
+/// );
+/// ```
+#[cfg(feature = "ssr")]
+pub fn websocket_scoped<A, F>(
+    server_signals: ServerSignals,
+    resolve: F,
+) -> impl Fn(A, Upgrade) -> BoxFuture<'static, Response> + Clone + Send + 'static
+where
+    A: Send + Sync + 'static,
+    F: Fn(&A, &str) -> SignalScope + Send + Sync + 'static,
+{
+    let resolve = Arc::new(resolve);
+    move |auth: A, ws: Upgrade| {
+        let value = server_signals.clone();
+        let resolve = resolve.clone();
+        let scope_of: ScopeOf = Arc::new(move |name: &str| resolve(&auth, name));
+        let response = ws.on_upgrade(
+            &CodecProtocol::available(),
+            false,
+            move |socket, negotiated| handle_socket(socket, negotiated, value, scope_of),
+        );
+        Box::pin(async move { response })
+    }
+}
+
+#[cfg(feature = "ssr")]
+async fn handle_socket(
+    socket: WebSocketStream<Socket>,
+    negotiated: Negotiated,
+    server_signals: ServerSignals,
+    scope_of: ScopeOf,
+) {
+    let (incoming, outgoing, _) = socket_transport(socket, negotiated);
+    connection::serve(server_signals, incoming, outgoing, scope_of).await;
+}
+
+/// Adapts an upgraded socket to the transport message stream and sink
+///
+/// Frames are encoded and decoded with the negotiated codec. A writer task owns the
+/// socket; emulated datagrams wait behind messages, coalescing while it is busy, and
+/// are read off the socket along with the incoming messages.
+fn socket_transport(
+    socket: WebSocketStream<Socket>,
+    negotiated: Negotiated,
+) -> (
+    impl Stream<Item = Result<transport::Message, TransportError>> + Send,
+    impl Sink<transport::Message, Error = TransportError> + Send + Unpin + 'static,
+    Option<SocketDatagrams>,
+) {
+    let codec = Arc::new(
+        FrameCodec::new(negotiated.protocol).expect("only available codecs are negotiated"),
+    );
+    let outgoing_codec = codec.clone();
+    let (mut send, recv) = socket.split();
+    let (frames, mut pending) = futures::channel::mpsc::channel::<Message>(0);
+    let outgoing_datagrams = Arc::new(Coalescer::new());
+    let (datagram_sender, mut datagram_receiver) = mpsc::unbounded_channel();
+
+    let coalescer = outgoing_datagrams.clone();
+    tokio::spawn(async move {
+        loop {
+            let frame = tokio::select! {
+                biased;
+                frame = pending.next() => match frame {
+                    Some(frame) => frame,
+                    None => break,
+                },
+                datagram = coalescer.next() => Message::Binary(datagram.to_websocket_frame().into()),
+            };
+            if send.send(frame).await.is_err() {
+                return;
+            }
+        }
+        let _ = send.close().await;
+    });
+
+    let datagrams = negotiated.datagrams;
+    let incoming = recv.filter_map(move |message| {
+        let message = receive(message).and_then(|message| match message {
+            Ok(message) if datagrams && message.message_type == MessageType::Binary => {
+                match Datagram::from_websocket_frame(&message.data) {
+                    Some(datagram) => {
+                        let _ = datagram_sender.send(datagram);
+                        None
                     }
-                    Message::Binary(_) => todo!(),
-                    Message::Ping(_) => send
-                        .clone()
-                        .write()
-                        .await
-                        .send(Message::Pong(vec![1, 2, 3].into()))
-                        .await
-                        .unwrap(),
-                    Message::Pong(_) => todo!(),
-                    Message::Close(_) => {}
+                    None => Some(Ok(message)),
                 }
-            } else {
-                break;
             }
+            message => Some(message),
+        });
+        let message = message.map(|message| {
+            message.and_then(|message| {
+                codec
+                    .decode(message)
+                    .map_err(|e| TransportError::ReceiveFailed(e.to_string()))
+            })
+        });
+        future::ready(message)
+    });
+    let outgoing = frames
+        .sink_map_err(|e| TransportError::SendFailed(e.to_string()))
+        .with(move |message: transport::Message| {
+            let frame = outgoing_codec
+                .encode(message)
+                .map(|frame| match frame.message_type {
+                    MessageType::Text => {
+                        Message::Text(String::from_utf8_lossy(&frame.data).into_owned().into())
+                    }
+                    _ => Message::Binary(frame.data.into()),
+                })
+                .map_err(|e| TransportError::SendFailed(e.to_string()));
+            future::ready(frame)
+        });
+
+    let datagrams = datagrams.then(|| SocketDatagrams {
+        incoming: Box::pin(futures::stream::poll_fn(move |cx| {
+            datagram_receiver.poll_recv(cx)
+        })),
+        outgoing: DatagramSender(outgoing_datagrams),
+    });
+    (incoming, Box::pin(outgoing), datagrams)
+}
+
+/// Converts a received frame, skipping the pings tungstenite answers itself
+fn receive(
+    message: Result<Message, tokio_tungstenite::tungstenite::Error>,
+) -> Option<Result<transport::Message, TransportError>> {
+    match message {
+        Ok(Message::Text(text)) => Some(Ok(transport::Message {
+            data: text.as_bytes().to_vec(),
+            message_type: MessageType::Text,
+        })),
+        Ok(Message::Binary(data)) => Some(Ok(transport::Message {
+            data: data.to_vec(),
+            message_type: MessageType::Binary,
+        })),
+        Ok(Message::Close(_)) => Some(Ok(transport::Message {
+            data: vec![],
+            message_type: MessageType::Close,
+        })),
+        Ok(Message::Ping(_)) | Ok(Message::Pong(_)) | Ok(Message::Frame(_)) => None,
+        Err(e) => Some(Err(TransportError::ReceiveFailed(e.to_string()))),
+    }
+}
+
+/// Creates a WebSocket handler function that serves RPC requests with the given router.
+///
+/// Mount it next to [`websocket`] on its own route. Every request frame received on the
+/// upgraded connection is dispatched to the router, and the response is written back on
+/// the same socket once the handler completes.
+///
+/// `A` is extracted from the upgrade request, typically the application state or the user
+/// that the application's auth layer attached to it. Requests the extractor rejects are
+/// never upgraded. `context` turns `A` into the context the connection's handlers receive,
+/// so one router serves every connection with a context of its own.
+///
+/// # Example
+///
+/// ```ignore
+/// use axum::{extract::State, routing::get, Router};
+/// use leptos_ws_pro::rpc::{advanced::RpcError, RpcRouter};
+///
+/// let mut rpc = RpcRouter::new();
+/// rpc.register("get_user", |state: AppState, id: u64| async move {
+///     state.users.get(id).await.ok_or(RpcError::InvalidParams("unknown user".into()))
+/// });
+///
+/// let app = Router::new()
+///     .route("/ws", get(leptos_ws_pro::axum::websocket(state.server_signals.clone())))
+///     .route(
+///         "/rpc",
+///         get(leptos_ws_pro::axum::rpc(rpc, |State(state): State<AppState>| state)),
+///     )
+///     .with_state(state);
+/// ```
+pub fn rpc<Ctx, A, F>(
+    router: RpcRouter<Ctx>,
+    context: F,
+) -> impl Fn(A, Upgrade) -> BoxFuture<'static, Response> + Clone + Send + 'static
+where
+    Ctx: Clone + Send + Sync + 'static,
+    A: Send + 'static,
+    F: Fn(A) -> Ctx + Send + Sync + 'static,
+{
+    let router = Arc::new(router);
+    let context = Arc::new(context);
+    move |parts: A, ws: Upgrade| {
+        let router = router.clone();
+        let context = context(parts);
+        let response = ws.on_upgrade(
+            &CodecProtocol::available(),
+            false,
+            move |socket, negotiated| handle_rpc_socket(socket, negotiated, router, context),
+        );
+        Box::pin(async move { response })
+    }
+}
+
+async fn handle_rpc_socket<Ctx>(
+    socket: WebSocketStream<Socket>,
+    negotiated: Negotiated,
+    router: Arc<RpcRouter<Ctx>>,
+    context: Ctx,
+) where
+    Ctx: Clone + Send + Sync + 'static,
+{
+    let (incoming, outgoing, _) = socket_transport(socket, negotiated);
+
+    if let Err(e) = router.serve(context, incoming, outgoing).await {
+        error!("RPC connection closed with error: {}", e);
+    }
+}
+
+/// Creates a WebSocket handler that carries logical channels over every socket.
+///
+/// Clients multiplex their side with
+/// [`WebSocketConnection::multiplex`](crate::transport::websocket::WebSocketConnection::multiplex).
+/// `handler` receives the server end of each upgraded socket and accepts or opens channels
+/// on it, so RPC, signals and bulk transfers share the socket without blocking each other.
+/// Split a channel to serve it like a whole socket.
+///
+/// # Example
+///
+/// ```ignore
+/// use axum::{routing::get, Router};
+/// use leptos_ws_pro::transport::websocket::mux::{MuxConfig, Multiplexer};
+///
+/// let rpc = Arc::new(rpc);
+/// let (state, server_signals) = (state.clone(), state.server_signals.clone());
+/// let app = Router::new().route(
+///     "/mux",
+///     get(leptos_ws_pro::axum::multiplexed(MuxConfig::default(), move |mux: Multiplexer| {
+///         let (rpc, state, server_signals) = (rpc.clone(), state.clone(), server_signals.clone());
+///         async move {
+///             while let Ok(channel) = mux.accept_bi().await {
+///                 let label = channel.label().to_string();
+///                 let (incoming, outgoing) = channel.split();
+///                 let (rpc, state, server_signals) =
+///                     (rpc.clone(), state.clone(), server_signals.clone());
+///                 tokio::spawn(async move {
+///                     match label.as_str() {
+///                         "rpc" => drop(rpc.serve(state.clone(), incoming, outgoing).await),
+///                         "signals" => {
+///                             let scope_of = |_: &str| SignalScope::Global;
+///                             server_signals.serve(incoming, outgoing, scope_of).await
+///                         }
+///                         _ => {}
+///                     }
+///                 });
+///             }
+///         }
+///     })),
+/// );
+/// ```
+pub fn multiplexed<F, Fut>(
+    config: MuxConfig,
+    handler: F,
+) -> impl Fn(Upgrade) -> BoxFuture<'static, Response> + Clone + Send + 'static
+where
+    F: Fn(Multiplexer) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let handler = Arc::new(handler);
+    move |ws: Upgrade| {
+        let handler = handler.clone();
+        let config = config.clone();
+        // Channels carry their own frames, so no codec is negotiated
+        let response = ws.on_upgrade(&[], false, move |socket, negotiated| async move {
+            let (incoming, outgoing, _) = socket_transport(socket, negotiated);
+            handler(Multiplexer::new(
+                MuxRole::Server,
+                incoming,
+                outgoing,
+                config,
+            ))
+            .await
+        });
+        Box::pin(async move { response })
+    }
+}
+
+/// A WebSocket upgraded by a [`socket`] handler
+pub struct ServerSocket {
+    /// Messages from the client, decoded with the negotiated codec
+    pub incoming: Pin<Box<dyn Stream<Item = Result<transport::Message, TransportError>> + Send>>,
+    /// Messages to the client, encoded with the negotiated codec
+    pub outgoing: Pin<Box<dyn Sink<transport::Message, Error = TransportError> + Send>>,
+    /// Emulated datagrams, when the client negotiated them
+    pub datagrams: Option<SocketDatagrams>,
+}
+
+/// The emulated datagrams of a [`ServerSocket`]
+///
+/// Datagrams are read off the socket along with its messages, so they only
+/// arrive while [`ServerSocket::incoming`] is polled.
+pub struct SocketDatagrams {
+    /// Datagrams from the client
+    pub incoming: DatagramStream,
+    /// Datagrams to the client
+    pub outgoing: DatagramSender,
+}
+
+/// Sends emulated datagrams to the client of a [`ServerSocket`]
+#[derive(Clone)]
+pub struct DatagramSender(Arc<Coalescer>);
+
+impl DatagramSender {
+    /// Queue `datagram`, replacing one of the same channel that is not written yet
+    pub fn send(&self, datagram: Datagram) {
+        self.0.push(datagram);
+    }
+}
+
+/// Creates a WebSocket handler that hands every upgraded socket to `handler`.
+///
+/// The socket speaks the codec negotiated from the `lwp.*` subprotocols and, when the
+/// client offers the [`WEBSOCKET_EXTENSION`](crate::transport::datagram::WEBSOCKET_EXTENSION),
+/// carries emulated datagrams next to its messages, as
+/// [`Transport::send_datagram`](crate::transport::Transport::send_datagram) does on the client.
+///
+/// # Example
+///
+/// ```ignore
+/// use axum::{routing::get, Router};
+/// use leptos_ws_pro::axum::ServerSocket;
+///
+/// let app = Router::new().route(
+///     "/game",
+///     get(leptos_ws_pro::axum::socket(|socket: ServerSocket| async move {
+///         let ServerSocket { incoming, outgoing, datagrams } = socket;
+///         if let Some(datagrams) = datagrams {
+///             tokio::spawn(broadcast_positions(datagrams));
+///         }
+///         game.serve(incoming, outgoing).await
+///     })),
+/// );
+/// ```
+pub fn socket<F, Fut>(
+    handler: F,
+) -> impl Fn(Upgrade) -> BoxFuture<'static, Response> + Clone + Send + 'static
+where
+    F: Fn(ServerSocket) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let handler = Arc::new(handler);
+    move |ws: Upgrade| {
+        let handler = handler.clone();
+        let response = ws.on_upgrade(
+            &CodecProtocol::available(),
+            true,
+            move |socket, negotiated| async move {
+                let (incoming, outgoing, datagrams) = socket_transport(socket, negotiated);
+                handler(ServerSocket {
+                    incoming: Box::pin(incoming),
+                    outgoing: Box::pin(outgoing),
+                    datagrams,
+                })
+                .await
+            },
+        );
+        Box::pin(async move { response })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::CodecFormat;
+    use crate::rpc::advanced::{RpcError, RpcRequest};
+    use crate::transport::websocket::WebSocketConnection;
+    use crate::transport::{Transport, TransportConfig};
+    use axum::routing::get;
+
+    /// Serve `app` on a local port and return its address
+    async fn serve(app: axum::Router) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_http2_extended_connect_is_accepted() {
+        let mut request = axum::http::Request::builder()
+            .method(Method::CONNECT)
+            .version(Version::HTTP_2)
+            .uri("https://example.com/rpc")
+            .header(header::SEC_WEBSOCKET_VERSION, "13")
+            .header(header::SEC_WEBSOCKET_PROTOCOL, "chat, lwp.rkyv")
+            .extension(hyper::ext::Protocol::from_static("websocket"))
+            .body(())
+            .unwrap();
+        let on_upgrade = hyper::upgrade::on(&mut request);
+        let (mut parts, ()) = request.into_parts();
+        parts.extensions.insert(on_upgrade);
+
+        let upgrade = Upgrade::from_request_parts(&mut parts, &()).await.unwrap();
+        let supported = [CodecProtocol::new(CodecFormat::Rkyv), CodecProtocol::JSON];
+        let response = upgrade.on_upgrade(&supported, false, |_, _| async {});
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::SEC_WEBSOCKET_PROTOCOL],
+            "lwp.rkyv"
+        );
+        assert!(!response
+            .headers()
+            .contains_key(header::SEC_WEBSOCKET_ACCEPT));
+
+        // A plain CONNECT is not a WebSocket
+        let mut parts = axum::http::Request::builder()
+            .method(Method::CONNECT)
+            .version(Version::HTTP_2)
+            .header(header::SEC_WEBSOCKET_VERSION, "13")
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0;
+        assert!(Upgrade::from_request_parts(&mut parts, &()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rpc_speaks_the_negotiated_codec() {
+        let mut router = RpcRouter::new();
+        router.register(
+            "double",
+            |_, n: i64| async move { Ok::<_, RpcError>(n * 2) },
+        );
+        let addr = serve(axum::Router::new().route("/rpc", get(rpc(router, |_: ()| ())))).await;
+
+        let mut connection = WebSocketConnection::new(TransportConfig {
+            protocols: vec!["chat".to_string(), "lwp.rkyv".to_string()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        connection
+            .connect(&format!("ws://{}/rpc", addr))
+            .await
+            .unwrap();
+        assert_eq!(connection.protocol(), Some("lwp.rkyv"));
+
+        let (mut incoming, mut outgoing) = connection.split();
+        let request = RpcRequest {
+            id: "1".to_string(),
+            method: "double".to_string(),
+            params: serde_json::json!(21),
+        };
+        outgoing
+            .send(transport::Message {
+                data: serde_json::to_vec(&request).unwrap(),
+                message_type: MessageType::Text,
+            })
+            .await
+            .unwrap();
+
+        let reply = tokio::time::timeout(std::time::Duration::from_secs(5), incoming.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let response: serde_json::Value = serde_json::from_slice(&reply.data).unwrap();
+        assert_eq!(response["id"], "1");
+        assert_eq!(response["result"], 42);
+    }
+
+    #[tokio::test]
+    async fn test_socket_carries_datagrams_next_to_messages() {
+        // Echoes messages and datagrams
+        let handler = |socket: ServerSocket| async move {
+            let ServerSocket {
+                mut incoming,
+                mut outgoing,
+                datagrams,
+            } = socket;
+            let SocketDatagrams {
+                incoming: mut datagrams,
+                outgoing: datagram_sender,
+            } = datagrams.unwrap();
+            tokio::spawn(async move {
+                while let Some(datagram) = datagrams.next().await {
+                    datagram_sender.send(datagram);
+                }
+            });
+            while let Some(Ok(message)) = incoming.next().await {
+                if outgoing.send(message).await.is_err() {
+                    break;
+                }
+            }
+        };
+        let addr = serve(axum::Router::new().route("/socket", get(socket(handler)))).await;
+
+        let mut connection = WebSocketConnection::new(TransportConfig::default())
+            .await
+            .unwrap();
+        let mut datagrams = connection.datagrams().unwrap();
+        connection
+            .connect(&format!("ws://{}/socket", addr))
+            .await
+            .unwrap();
+
+        let datagram = Datagram::new(2, b"cursor 3,4".to_vec());
+        connection.send_datagram(datagram.clone()).await.unwrap();
+        let echoed = tokio::time::timeout(std::time::Duration::from_secs(5), datagrams.next())
+            .await
+            .unwrap();
+        assert_eq!(echoed, Some(datagram));
+
+        let (mut incoming, mut outgoing) = connection.split();
+        let message = transport::Message {
+            data: b"hello".to_vec(),
+            message_type: MessageType::Text,
+        };
+        outgoing.send(message.clone()).await.unwrap();
+        let echoed = tokio::time::timeout(std::time::Duration::from_secs(5), incoming.next())
+            .await
+            .unwrap();
+        assert_eq!(echoed.unwrap().unwrap(), message);
+    }
+
+    #[tokio::test]
+    async fn test_rpc_does_not_negotiate_datagrams() {
+        let addr =
+            serve(axum::Router::new().route("/rpc", get(rpc(RpcRouter::new(), |_: ()| ())))).await;
+
+        let mut connection = WebSocketConnection::new(TransportConfig::default())
+            .await
+            .unwrap();
+        connection
+            .connect(&format!("ws://{}/rpc", addr))
+            .await
+            .unwrap();
+        let datagram = Datagram::new(2, b"cursor 3,4".to_vec());
+        assert!(matches!(
+            connection.send_datagram(datagram).await,
+            Err(TransportError::NotSupported(_))
+        ));
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn test_rpc_negotiates_permessage_deflate() {
+        use crate::transport::websocket::deflate::{DeflateConfig, DeflateParams};
+        use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+
+        let mut router = RpcRouter::new();
+        router.register(
+            "echo",
+            |_, text: String| async move { Ok::<_, RpcError>(text) },
+        );
+        let addr = serve(axum::Router::new().route("/rpc", get(rpc(router, |_: ()| ())))).await;
+        let url = format!("ws://{}/rpc", addr);
+        let request = RpcRequest {
+            id: "1".to_string(),
+            method: "echo".to_string(),
+            params: serde_json::json!("counter ".repeat(1000)),
+        };
+        let frame = transport::Message {
+            data: serde_json::to_vec(&request).unwrap(),
+            message_type: MessageType::Text,
+        };
+
+        let mut connection = WebSocketConnection::new(TransportConfig::default())
+            .await
+            .unwrap()
+            .with_deflate(DeflateConfig::new().with_server_no_context_takeover());
+        connection.connect(&url).await.unwrap();
+        assert_eq!(
+            connection.deflate_params(),
+            Some(DeflateParams {
+                client_no_context_takeover: false,
+                server_no_context_takeover: true,
+                client_max_window_bits: 15,
+                server_max_window_bits: 15,
+            })
+        );
+        assert!(connection.capabilities().compression);
+
+        // The second message relies on the client's context being kept
+        let (mut incoming, mut outgoing) = connection.split();
+        for _ in 0..2 {
+            outgoing.send(frame.clone()).await.unwrap();
+            let reply = tokio::time::timeout(std::time::Duration::from_secs(5), incoming.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let response: serde_json::Value = serde_json::from_slice(&reply.data).unwrap();
+            assert_eq!(response["result"], request.params);
         }
-    })
-    .await;
+
+        // A client that offers the extension without running it sees the
+        // agreed header, then a compressed reply it can't read
+        let mut offer = url.as_str().into_client_request().unwrap();
+        offer.headers_mut().insert(
+            header::SEC_WEBSOCKET_EXTENSIONS,
+            HeaderValue::from_static("permessage-deflate"),
+        );
+        let (mut socket, response) = tokio_tungstenite::connect_async(offer).await.unwrap();
+        assert_eq!(
+            response.headers()[header::SEC_WEBSOCKET_EXTENSIONS],
+            "permessage-deflate"
+        );
+        socket
+            .send(Message::Text(String::from_utf8(frame.data).unwrap().into()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            socket.next().await,
+            Some(Err(tungstenite::Error::Protocol(
+                tungstenite::error::ProtocolError::NonZeroReservedBits
+            )))
+        ));
+    }
 }
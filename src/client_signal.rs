@@ -1,5 +1,6 @@
 use crate::error::Error;
-use crate::{client_signals::ClientSignals, messages::ServerSignalUpdate};
+use crate::messages::{Messages, ServerSignalMessage, ServerSignalRejection};
+use crate::{client_signals::ClientSignals, messages::ServerSignalUpdate, ServerSignalWebSocket};
 use async_trait::async_trait;
 use leptos::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -7,7 +8,10 @@ use serde_json::Value;
 use std::{
     any::Any,
     ops::Deref,
-    sync::{Arc, RwLock},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
 };
 
 #[derive(Clone, Debug)]
@@ -16,8 +20,11 @@ pub struct ClientSignal<T>
 where
     T: Clone + Send + Sync + for<'de> Deserialize<'de>,
 {
+    name: String,
     value: ArcRwSignal<T>,
     json_value: Arc<RwLock<Value>>,
+    version: Arc<AtomicU64>,
+    rejection: ArcRwSignal<Option<String>>,
 }
 
 #[async_trait]
@@ -26,6 +33,9 @@ pub trait ClientSignalTrait {
     fn as_any(&self) -> &dyn Any;
     fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error>;
     fn set_json(&self, new_value: Value) -> Result<(), Error>;
+    fn reject(&self, rejection: ServerSignalRejection) -> Result<(), Error>;
+    /// Version of the server value this signal last received, 0 if none
+    fn version(&self) -> u64;
 }
 impl<T> ClientSignalTrait for ClientSignal<T>
 where
@@ -36,16 +46,20 @@ where
     }
 
     #[track_caller]
-
     fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error> {
-        // Simplified: just set the new value directly
-        let new_value = patch.patch;
-        *self
+        let mut writer = self
             .json_value
             .write()
-            .map_err(|_| Error::UpdateSignalFailed)? = new_value.clone();
-        *self.value.write() =
-            serde_json::from_value(new_value).map_err(|err| Error::SerializationFailed(err))?;
+            .map_err(|_| Error::UpdateSignalFailed)?;
+        // Nothing is committed unless the patched value is a valid `T`
+        let mut json_value = writer.clone();
+        patch.apply(&mut json_value)?;
+        let value: T = serde_json::from_value(json_value.clone())?;
+        *writer = json_value;
+        *self.value.write() = value;
+        if patch.version != 0 {
+            self.version.store(patch.version, Ordering::Release);
+        }
         Ok(())
     }
     fn set_json(&self, new_value: Value) -> Result<(), Error> {
@@ -53,11 +67,21 @@ where
             .json_value
             .write()
             .map_err(|_| Error::UpdateSignalFailed)?;
+        let value: T =
+            serde_json::from_value(new_value.clone()).map_err(Error::SerializationFailed)?;
         *writer = new_value;
-        *self.value.write() = serde_json::from_value(writer.clone())
-            .map_err(|err| Error::SerializationFailed(err))?;
+        *self.value.write() = value;
         Ok(())
     }
+    fn reject(&self, rejection: ServerSignalRejection) -> Result<(), Error> {
+        self.set_json(rejection.value)?;
+        self.version.store(rejection.version, Ordering::Release);
+        self.rejection.set(Some(rejection.reason));
+        Ok(())
+    }
+    fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
 }
 
 impl<T> ClientSignal<T>
@@ -71,15 +95,25 @@ where
             return Ok(signals.get_signal::<ClientSignal<T>>(&name).unwrap());
         }
         let new_signal = Self {
+            name: name.clone(),
             value: ArcRwSignal::new(value.clone()),
             json_value: Arc::new(RwLock::new(
                 serde_json::to_value(value).map_err(|err| Error::SerializationFailed(err))?,
             )),
+            version: Arc::new(AtomicU64::new(0)),
+            rejection: ArcRwSignal::new(None),
         };
         let signal = new_signal.clone();
         signals.create_signal(name, new_signal).unwrap();
         Ok(signal)
     }
+
+    /// The reason the server gave for rolling back the last write, if any
+    ///
+    /// Cleared by the next write.
+    pub fn rejection(&self) -> ArcReadSignal<Option<String>> {
+        self.rejection.read_only()
+    }
 }
 
 impl<T> Update for ClientSignal<T>
@@ -88,8 +122,39 @@ where
 {
     type Value = T;
 
-    fn try_maybe_update<U>(&self, _fun: impl FnOnce(&mut Self::Value) -> (bool, U)) -> Option<U> {
-        None
+    /// Applies the write locally and sends it to the server as a patch.
+    ///
+    /// The server applies it only if the signal is writable and its validator
+    /// accepts the change; otherwise the value is rolled back and
+    /// [`ClientSignal::rejection`] is set.
+    #[track_caller]
+    fn try_maybe_update<U>(&self, fun: impl FnOnce(&mut Self::Value) -> (bool, U)) -> Option<U> {
+        let ws = use_context::<ServerSignalWebSocket>()?;
+        let result = self.value.try_maybe_update(fun)?;
+        let new_json = serde_json::to_value(&*self.value.read_untracked()).ok()?;
+
+        let mut json_value = self.json_value.write().ok()?;
+        let update = ServerSignalUpdate::new_from_json(self.name.clone(), &json_value, &new_json);
+        if update.is_snapshot() || !update.patch.0.is_empty() {
+            *json_value = new_json;
+            drop(json_value);
+            if self.rejection.get_untracked().is_some() {
+                self.rejection.set(None);
+            }
+            let _ = ws.send(&Messages::ServerSignal(ServerSignalMessage::ClientUpdate(
+                update,
+            )));
+        }
+        Some(result)
+    }
+}
+
+impl<T> IsDisposed for ClientSignal<T>
+where
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
+{
+    fn is_disposed(&self) -> bool {
+        self.value.is_disposed()
     }
 }
 
@@ -103,3 +168,52 @@ where
         &self.value
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Counter {
+        count: u32,
+    }
+
+    fn counter_signal(count: u32) -> ClientSignal<Counter> {
+        let value = Counter { count };
+        ClientSignal {
+            name: "counter".to_string(),
+            json_value: Arc::new(RwLock::new(serde_json::to_value(&value).unwrap())),
+            value: ArcRwSignal::new(value),
+            version: Arc::new(AtomicU64::new(1)),
+            rejection: ArcRwSignal::new(None),
+        }
+    }
+
+    #[test]
+    fn test_update_that_does_not_deserialize_changes_nothing() {
+        let signal = counter_signal(1);
+        let invalid = ServerSignalUpdate::new_from_json(
+            "counter",
+            &serde_json::json!({"count": 1}),
+            &serde_json::json!({"count": "two"}),
+        )
+        .with_version(2);
+
+        assert!(signal.update_json(invalid).is_err());
+        assert_eq!(signal.value.get_untracked(), Counter { count: 1 });
+        assert_eq!(
+            *signal.json_value.read().unwrap(),
+            serde_json::json!({"count": 1})
+        );
+        assert_eq!(signal.version(), 1);
+
+        // The next valid update still applies against the last good value
+        let valid =
+            ServerSignalUpdate::new("counter", &Counter { count: 1 }, &Counter { count: 2 })
+                .unwrap()
+                .with_version(2);
+        signal.update_json(valid).unwrap();
+        assert_eq!(signal.value.get_untracked(), Counter { count: 2 });
+        assert_eq!(signal.version(), 2);
+    }
+}
@@ -5,7 +5,7 @@ use std::{
 
 use crate::client_signal::ClientSignalTrait;
 use crate::messages::Messages;
-use crate::messages::ServerSignalMessage;
+use crate::messages::{ServerSignalMessage, ServerSignalRejection};
 use crate::ServerSignalWebSocket;
 use crate::{error::Error, messages::ServerSignalUpdate};
 use leptos::prelude::*;
@@ -41,10 +41,11 @@ impl ClientSignals {
             .map(|value| value.as_any().downcast_ref::<T>().unwrap().clone())
             .is_none()
         {
-            // Wrap the Establish message in ServerSignalMessage and Messages
-            ws.send(&Messages::ServerSignal(ServerSignalMessage::Establish(
+            // Holding nothing yet, the server answers with a snapshot
+            ws.send(&Messages::ServerSignal(ServerSignalMessage::Resync((
                 name.clone(),
-            )))?;
+                0,
+            ))))?;
             Ok(())
         } else {
             Err(Error::AddingSignalFailed)
@@ -54,14 +55,20 @@ impl ClientSignals {
     pub fn reconnect(&self) -> Result<(), Error> {
         let ws = use_context::<ServerSignalWebSocket>().ok_or(Error::MissingServerSignals)?;
 
-        // Get all signal names from the signals HashMap
-        let signal_names: Vec<String> = self.signals.read().unwrap().keys().cloned().collect();
+        // Collect each signal with the version it holds
+        let signal_versions: Vec<(String, u64)> = self
+            .signals
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, signal)| (name.clone(), signal.version()))
+            .collect();
 
-        // Resend establish message for each signal
-        for name in signal_names {
-            ws.send(&Messages::ServerSignal(ServerSignalMessage::Establish(
-                name,
-            )))?;
+        // Re-establish each signal, catching up on the updates it missed
+        for (name, version) in signal_versions {
+            ws.send(&Messages::ServerSignal(ServerSignalMessage::Resync((
+                name, version,
+            ))))?;
         }
 
         Ok(())
@@ -75,17 +82,35 @@ impl ClientSignals {
             .map(|value| value.as_any().downcast_ref::<T>().unwrap().clone())
     }
 
+    /// Applies an update from the server.
+    ///
+    /// Updates that arrive after a gap in versions, or that no longer apply to
+    /// the held value, are dropped and the signal is resynced instead.
     pub fn update(&self, name: &str, patch: ServerSignalUpdate) -> Option<Result<(), Error>> {
-        match self
-            .signals
-            .write()
-            .unwrap()
-            .get_mut(name)
-            .map(|value| value.update_json(patch))
-        {
-            Some(fut) => Some(fut),
-            None => None,
+        let signal = self.signals.read().unwrap().get(name).cloned()?;
+        let version = signal.version();
+        if patch.version != 0 && !patch.is_snapshot() && patch.version != version + 1 {
+            if patch.version <= version {
+                // Already applied
+                return Some(Ok(()));
+            }
+            return Some(self.resync(name, version));
         }
+
+        match signal.update_json(patch) {
+            Err(Error::PatchFailed(_)) => Some(self.resync(name, 0)),
+            res => Some(res),
+        }
+    }
+
+    /// Asks the server for the updates after `version`, or a snapshot for 0
+    fn resync(&self, name: &str, version: u64) -> Result<(), Error> {
+        let ws = use_context::<ServerSignalWebSocket>().ok_or(Error::MissingServerSignals)?;
+        ws.send(&Messages::ServerSignal(ServerSignalMessage::Resync((
+            name.to_string(),
+            version,
+        ))))?;
+        Ok(())
     }
 
     pub fn set_json(&self, name: &str, new_value: Value) -> Option<Result<(), Error>> {
@@ -101,6 +126,14 @@ impl ClientSignals {
         }
     }
 
+    pub fn reject(&self, rejection: ServerSignalRejection) -> Option<Result<(), Error>> {
+        self.signals
+            .read()
+            .unwrap()
+            .get(rejection.name.as_ref())
+            .map(|value| value.reject(rejection))
+    }
+
     pub fn contains(&self, name: &str) -> bool {
         self.signals.read().unwrap().contains_key(name)
     }
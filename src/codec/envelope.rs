@@ -0,0 +1,366 @@
+//! Versioned Message Envelopes
+//!
+//! [`WsMessage::versioned`] tags a payload with its type name and schema
+//! version. A [`MigrationRegistry`] upgrades older payloads to the current
+//! struct on decode, and downgrades outgoing ones for peers that run an older
+//! release, so mixed client and server versions keep talking during a rolling
+//! deploy.
+
+use super::{Codec, CodecError, WsMessage};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// Version assumed for envelopes written without one by [`WsMessage::new`]
+pub const UNVERSIONED: u32 = 1;
+
+/// A payload type with a stable name and a schema version
+///
+/// Bump `VERSION` whenever the serialized shape changes, and register the
+/// migration from the previous version.
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Name identifying the type on the wire
+    const TYPE_NAME: &'static str;
+
+    /// Current schema version, starting at 1
+    const VERSION: u32;
+}
+
+impl<T: Versioned> WsMessage<T> {
+    /// Wrap `data` with its type name and current schema version
+    pub fn versioned(data: T) -> Self {
+        Self {
+            type_name: Some(T::TYPE_NAME.to_string()),
+            version: Some(T::VERSION),
+            data,
+        }
+    }
+}
+
+type Migration = Arc<dyn Fn(Value) -> Result<Value, CodecError> + Send + Sync>;
+
+#[derive(Clone, Default)]
+struct RegisteredType {
+    current: u32,
+    /// Migrations from version `n` to `n + 1`, keyed by `n`
+    upgrades: HashMap<u32, Migration>,
+    /// Migrations from version `n` to `n - 1`, keyed by `n`
+    downgrades: HashMap<u32, Migration>,
+    schema: Option<Value>,
+}
+
+/// Migrations between the schema versions of registered types
+#[derive(Clone, Default)]
+pub struct MigrationRegistry {
+    types: HashMap<&'static str, RegisteredType>,
+}
+
+impl MigrationRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entry<T: Versioned>(&mut self) -> &mut RegisteredType {
+        let entry = self.types.entry(T::TYPE_NAME).or_default();
+        entry.current = T::VERSION;
+        entry
+    }
+
+    /// Register `T` at its current version
+    pub fn register<T: Versioned>(mut self) -> Self {
+        self.entry::<T>();
+        self
+    }
+
+    /// Register the migration of `T` payloads from version `from` to `from + 1`
+    pub fn with_upgrade<T, F>(mut self, from: u32, migration: F) -> Self
+    where
+        T: Versioned,
+        F: Fn(Value) -> Result<Value, CodecError> + Send + Sync + 'static,
+    {
+        self.entry::<T>().upgrades.insert(from, Arc::new(migration));
+        self
+    }
+
+    /// Register the migration of `T` payloads from version `from` to `from - 1`
+    pub fn with_downgrade<T, F>(mut self, from: u32, migration: F) -> Self
+    where
+        T: Versioned,
+        F: Fn(Value) -> Result<Value, CodecError> + Send + Sync + 'static,
+    {
+        self.entry::<T>()
+            .downgrades
+            .insert(from, Arc::new(migration));
+        self
+    }
+
+    /// Attach the JSON schema of the current `T` payload to [`Self::json_schema`]
+    pub fn with_schema<T: Versioned>(mut self, schema: Value) -> Self {
+        self.entry::<T>().schema = Some(schema);
+        self
+    }
+
+    /// Move a payload of `type_name` from version `from` to version `to`
+    pub fn migrate(
+        &self,
+        type_name: &str,
+        mut data: Value,
+        from: u32,
+        to: u32,
+    ) -> Result<Value, CodecError> {
+        let registered = self.types.get(type_name);
+        let mut version = from;
+        while version != to {
+            let (migrations, next) = match registered {
+                Some(registered) if version < to => (&registered.upgrades, version + 1),
+                Some(registered) => (&registered.downgrades, version - 1),
+                None => (&HashMap::new(), version),
+            };
+            let migration = migrations.get(&version).ok_or_else(|| {
+                CodecError::MigrationFailed(format!(
+                    "no migration for {} from v{} to v{}",
+                    type_name, version, to
+                ))
+            })?;
+            data = migration(data)?;
+            version = next;
+        }
+        Ok(data)
+    }
+
+    /// Wrap `message` in an envelope at schema `version`
+    pub fn encode_envelope<T: Versioned>(
+        &self,
+        message: &T,
+        version: u32,
+    ) -> Result<WsMessage<Value>, CodecError> {
+        let data = serde_json::to_value(message)
+            .map_err(|e| CodecError::SerializationFailed(e.to_string()))?;
+        Ok(WsMessage {
+            type_name: Some(T::TYPE_NAME.to_string()),
+            version: Some(version),
+            data: self.migrate(T::TYPE_NAME, data, T::VERSION, version)?,
+        })
+    }
+
+    /// Read a `T` out of an envelope written at any registered version
+    pub fn decode_envelope<T: Versioned>(
+        &self,
+        envelope: WsMessage<Value>,
+    ) -> Result<T, CodecError> {
+        if let Some(type_name) = &envelope.type_name {
+            if type_name != T::TYPE_NAME {
+                return Err(CodecError::DeserializationFailed(format!(
+                    "expected {}, got {}",
+                    T::TYPE_NAME,
+                    type_name
+                )));
+            }
+        }
+
+        let version = envelope.version.unwrap_or(UNVERSIONED);
+        let data = self.migrate(T::TYPE_NAME, envelope.data, version, T::VERSION)?;
+        serde_json::from_value(data).map_err(|e| CodecError::DeserializationFailed(e.to_string()))
+    }
+
+    /// JSON schema of the envelope, listing every registered type
+    ///
+    /// With no types registered this is the generic envelope published as
+    /// `api/schemas/envelope-schema.json`.
+    pub fn json_schema(&self) -> Value {
+        let mut schema = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "$id": "https://leptos-ws-pro.dev/schemas/envelope.json",
+            "title": "WsMessage",
+            "description": "Versioned envelope carrying a typed payload",
+            "type": "object",
+            "required": ["type", "version", "data"],
+            "properties": {
+                "type": {
+                    "type": "string",
+                    "description": "Name of the payload type"
+                },
+                "version": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "description": "Schema version of the payload"
+                },
+                "data": {
+                    "description": "Payload in the shape of its schema version"
+                }
+            }
+        });
+
+        let types: BTreeMap<_, _> = self.types.iter().collect();
+        if !types.is_empty() {
+            schema["oneOf"] = types
+                .into_iter()
+                .map(|(name, registered)| {
+                    json!({
+                        "properties": {
+                            "type": { "const": name },
+                            "version": { "maximum": registered.current },
+                            "data": registered.schema.clone().unwrap_or_else(|| json!({}))
+                        }
+                    })
+                })
+                .collect();
+        }
+        schema
+    }
+}
+
+/// JSON codec writing versioned envelopes
+///
+/// Decoding upgrades payloads written at older versions. Pin a type to an
+/// older version while peers that only understand it are still deployed.
+#[derive(Clone)]
+pub struct VersionedCodec {
+    registry: Arc<MigrationRegistry>,
+    pinned: HashMap<&'static str, u32>,
+}
+
+impl VersionedCodec {
+    pub fn new(registry: MigrationRegistry) -> Self {
+        Self {
+            registry: Arc::new(registry),
+            pinned: HashMap::new(),
+        }
+    }
+
+    /// Write `T` at `version` instead of its current version
+    pub fn with_pinned_version<T: Versioned>(mut self, version: u32) -> Self {
+        self.pinned.insert(T::TYPE_NAME, version);
+        self
+    }
+
+    pub fn registry(&self) -> &MigrationRegistry {
+        &self.registry
+    }
+}
+
+impl<T> Codec<T> for VersionedCodec
+where
+    T: Versioned + Send + Sync,
+{
+    fn encode(&self, message: &T) -> Result<Vec<u8>, CodecError> {
+        let version = self.pinned.get(T::TYPE_NAME).copied().unwrap_or(T::VERSION);
+        let envelope = self.registry.encode_envelope(message, version)?;
+        serde_json::to_vec(&envelope).map_err(|e| CodecError::SerializationFailed(e.to_string()))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
+        let envelope: WsMessage<Value> = serde_json::from_slice(data)
+            .map_err(|e| CodecError::DeserializationFailed(e.to_string()))?;
+        self.registry.decode_envelope(envelope)
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    /// The payload as released in v1
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct UserV1 {
+        name: String,
+    }
+
+    impl Versioned for UserV1 {
+        const TYPE_NAME: &'static str = "user";
+        const VERSION: u32 = 1;
+    }
+
+    /// The payload after `name` was split in v2
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct UserV2 {
+        first_name: String,
+        last_name: String,
+    }
+
+    impl Versioned for UserV2 {
+        const TYPE_NAME: &'static str = "user";
+        const VERSION: u32 = 2;
+    }
+
+    fn v2_registry() -> MigrationRegistry {
+        MigrationRegistry::new()
+            .with_upgrade::<UserV2, _>(1, |data| {
+                let name = data["name"].as_str().unwrap_or_default();
+                let (first, last) = name.split_once(' ').unwrap_or((name, ""));
+                Ok(json!({ "first_name": first, "last_name": last }))
+            })
+            .with_downgrade::<UserV2, _>(2, |data| {
+                let name = format!(
+                    "{} {}",
+                    data["first_name"].as_str().unwrap_or_default(),
+                    data["last_name"].as_str().unwrap_or_default()
+                );
+                Ok(json!({ "name": name.trim() }))
+            })
+    }
+
+    #[test]
+    fn test_old_payloads_are_upgraded_on_decode() {
+        let old_client = VersionedCodec::new(MigrationRegistry::new().register::<UserV1>());
+        let new_server = VersionedCodec::new(v2_registry());
+
+        let frame = old_client
+            .encode(&UserV1 {
+                name: "Ada Lovelace".to_string(),
+            })
+            .unwrap();
+        let user: UserV2 = new_server.decode(&frame).unwrap();
+        assert_eq!(user.first_name, "Ada");
+        assert_eq!(user.last_name, "Lovelace");
+
+        // Envelopes written before versioning are read as v1
+        let legacy =
+            serde_json::to_vec(&WsMessage::new(json!({ "name": "Grace Hopper" }))).unwrap();
+        let user: UserV2 = new_server.decode(&legacy).unwrap();
+        assert_eq!(user.first_name, "Grace");
+    }
+
+    #[test]
+    fn test_pinned_version_reaches_old_peers() {
+        let old_client = VersionedCodec::new(MigrationRegistry::new().register::<UserV1>());
+        let user = UserV2 {
+            first_name: "Ada".to_string(),
+            last_name: "Lovelace".to_string(),
+        };
+
+        let new_server = VersionedCodec::new(v2_registry());
+        let frame = new_server.encode(&user).unwrap();
+        assert!(matches!(
+            <_ as Codec<UserV1>>::decode(&old_client, &frame),
+            Err(CodecError::MigrationFailed(_))
+        ));
+
+        let rolling_server = new_server.with_pinned_version::<UserV2>(1);
+        let frame = rolling_server.encode(&user).unwrap();
+        let decoded: UserV1 = old_client.decode(&frame).unwrap();
+        assert_eq!(decoded.name, "Ada Lovelace");
+        let decoded: UserV2 = rolling_server.decode(&frame).unwrap();
+        assert_eq!(decoded, user);
+    }
+
+    #[test]
+    fn test_versioned_envelope_fields() {
+        let envelope = WsMessage::versioned(UserV1 {
+            name: "Ada".to_string(),
+        });
+        let value = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(value["type"], "user");
+        assert_eq!(value["version"], 1);
+
+        // Unversioned envelopes keep their original shape
+        let value = serde_json::to_value(WsMessage::new(1)).unwrap();
+        assert_eq!(value, json!({ "data": 1 }));
+    }
+}
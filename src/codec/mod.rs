@@ -1,8 +1,21 @@
 //! Codec module for encoding and decoding WebSocket messages
 //!
-//! This module provides a simple JSON-based codec system for WebSocket messages.
-//! Future versions will include zero-copy serialization with rkyv and compression.
-
+//! This module provides codecs for WebSocket messages: JSON via serde,
+//! validated rkyv archives, a hybrid of both and compression. MessagePack
+//! and CBOR are available behind the `msgpack` and `cbor` features.
+//! Peers pick a codec during the handshake through [`negotiation`], and
+//! payloads can be versioned through [`envelope`].
+
+pub mod envelope;
+pub mod negotiation;
+
+pub use envelope::{MigrationRegistry, Versioned, VersionedCodec};
+pub use negotiation::{CodecFormat, CodecProtocol, FrameCodec};
+
+use rkyv::de::deserializers::SharedDeserializeMap;
+use rkyv::ser::serializers::AllocSerializer;
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::{AlignedVec, Archive, CheckBytes};
 use serde::{Deserialize as SerdeDeserialize, Serialize as SerdeSerialize};
 use thiserror::Error;
 
@@ -21,6 +34,21 @@ where
     fn content_type(&self) -> &'static str;
 }
 
+/// Lets a boxed codec, such as one picked during negotiation, be wrapped by another codec
+impl<T: Send + Sync> Codec<T> for Box<dyn Codec<T> + Send + Sync> {
+    fn encode(&self, message: &T) -> Result<Vec<u8>, CodecError> {
+        (**self).encode(message)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
+        (**self).decode(data)
+    }
+
+    fn content_type(&self) -> &'static str {
+        (**self).content_type()
+    }
+}
+
 /// Codec errors
 #[derive(Debug, Error)]
 pub enum CodecError {
@@ -38,6 +66,15 @@ pub enum CodecError {
 
     #[error("Compression not supported: {0}")]
     CompressionNotSupported(String),
+
+    #[error("Unsupported codec: {0}")]
+    UnsupportedCodec(String),
+
+    #[error("Invalid dictionary: {0}")]
+    InvalidDictionary(String),
+
+    #[error("Migration failed: {0}")]
+    MigrationFailed(String),
 }
 
 /// JSON codec using serde
@@ -66,7 +103,81 @@ where
     }
 }
 
+/// MessagePack codec using serde
+///
+/// Structs are written as maps keyed by field name, so consumers in other
+/// languages can read them without knowing the field order.
+#[cfg(feature = "msgpack")]
+pub struct MsgPackCodec;
+
+#[cfg(feature = "msgpack")]
+impl MsgPackCodec {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl<T> Codec<T> for MsgPackCodec
+where
+    T: SerdeSerialize + for<'de> SerdeDeserialize<'de> + Clone + Send + Sync,
+{
+    fn encode(&self, message: &T) -> Result<Vec<u8>, CodecError> {
+        rmp_serde::to_vec_named(message).map_err(|e| CodecError::SerializationFailed(e.to_string()))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
+        rmp_serde::from_slice(data).map_err(|e| CodecError::DeserializationFailed(e.to_string()))
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/msgpack"
+    }
+}
+
+/// CBOR codec using serde
+#[cfg(feature = "cbor")]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl CborCodec {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[cfg(feature = "cbor")]
+impl<T> Codec<T> for CborCodec
+where
+    T: SerdeSerialize + for<'de> SerdeDeserialize<'de> + Clone + Send + Sync,
+{
+    fn encode(&self, message: &T) -> Result<Vec<u8>, CodecError> {
+        let mut data = Vec::new();
+        ciborium::into_writer(message, &mut data)
+            .map_err(|e| CodecError::SerializationFailed(e.to_string()))?;
+        Ok(data)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
+        ciborium::from_reader(data).map_err(|e| CodecError::DeserializationFailed(e.to_string()))
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/cbor"
+    }
+}
+
+/// Scratch space reserved on the stack while archiving
+pub(crate) const RKYV_SCRATCH_SPACE: usize = 256;
+
+/// Alignment rkyv archives are read at
+pub(crate) const RKYV_ALIGNMENT: usize = 16;
+
 /// rkyv-based zero-copy codec
+///
+/// Messages are written as rkyv archives and validated before they are read,
+/// so malformed or malicious input is rejected instead of causing undefined
+/// behaviour.
 pub struct RkyvCodec;
 
 impl RkyvCodec {
@@ -75,20 +186,27 @@ impl RkyvCodec {
     }
 }
 
-// Rkyv implementation with JSON fallback for compatibility
 impl<T> Codec<T> for RkyvCodec
 where
-    T: SerdeSerialize + for<'de> SerdeDeserialize<'de> + Clone + Send + Sync,
+    T: Archive + rkyv::Serialize<AllocSerializer<RKYV_SCRATCH_SPACE>> + Send + Sync,
+    T::Archived:
+        for<'a> CheckBytes<DefaultValidator<'a>> + rkyv::Deserialize<T, SharedDeserializeMap>,
 {
     fn encode(&self, message: &T) -> Result<Vec<u8>, CodecError> {
-        // For now, use JSON but with rkyv content type to indicate future support
-        // In a real implementation, we would check if the type supports rkyv and use it
-        serde_json::to_vec(message).map_err(|e| CodecError::SerializationFailed(e.to_string()))
+        rkyv::to_bytes::<_, RKYV_SCRATCH_SPACE>(message)
+            .map(AlignedVec::into_vec)
+            .map_err(|e| CodecError::SerializationFailed(format!("rkyv: {}", e)))
     }
 
     fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
-        // For now, use JSON but with rkyv content type to indicate future support
-        serde_json::from_slice(data).map_err(|e| CodecError::DeserializationFailed(e.to_string()))
+        // Archives must be aligned to be read in place; copy only when they aren't
+        if data.as_ptr() as usize % RKYV_ALIGNMENT == 0 {
+            from_rkyv_bytes(data)
+        } else {
+            let mut aligned = AlignedVec::with_capacity(data.len());
+            aligned.extend_from_slice(data);
+            from_rkyv_bytes(&aligned)
+        }
     }
 
     fn content_type(&self) -> &'static str {
@@ -96,7 +214,28 @@ where
     }
 }
 
+/// Validate and deserialize an aligned rkyv archive
+fn from_rkyv_bytes<T>(data: &[u8]) -> Result<T, CodecError>
+where
+    T: Archive,
+    T::Archived:
+        for<'a> CheckBytes<DefaultValidator<'a>> + rkyv::Deserialize<T, SharedDeserializeMap>,
+{
+    rkyv::from_bytes::<T>(data)
+        .map_err(|e| CodecError::DeserializationFailed(format!("rkyv: {}", e)))
+}
+
+/// Tag byte marking a [`HybridCodec`] frame as JSON
+const HYBRID_TAG_JSON: u8 = 0x00;
+
+/// Tag byte marking a [`HybridCodec`] frame as an rkyv archive
+const HYBRID_TAG_RKYV: u8 = 0x01;
+
 /// Hybrid codec that tries rkyv first, falls back to JSON
+///
+/// Every frame starts with a one-byte tag naming the format of the rest of
+/// the frame. Untagged frames are read as plain JSON, so messages from peers
+/// using [`JsonCodec`] still decode.
 pub struct HybridCodec {
     rkyv_codec: RkyvCodec,
     json_codec: JsonCodec,
@@ -113,27 +252,34 @@ impl HybridCodec {
 
 impl<T> Codec<T> for HybridCodec
 where
-    T: SerdeSerialize + for<'de> SerdeDeserialize<'de> + Clone + Send + Sync,
+    T: SerdeSerialize
+        + for<'de> SerdeDeserialize<'de>
+        + Archive
+        + rkyv::Serialize<AllocSerializer<RKYV_SCRATCH_SPACE>>
+        + Clone
+        + Send
+        + Sync,
+    T::Archived:
+        for<'a> CheckBytes<DefaultValidator<'a>> + rkyv::Deserialize<T, SharedDeserializeMap>,
 {
     fn encode(&self, message: &T) -> Result<Vec<u8>, CodecError> {
-        // Try rkyv first for performance
-        match self.rkyv_codec.encode(message) {
-            Ok(data) => Ok(data),
-            Err(_) => {
-                // Fall back to JSON
-                self.json_codec.encode(message)
-            }
-        }
+        // Try rkyv first for performance, falling back to JSON
+        let (tag, body) = match self.rkyv_codec.encode(message) {
+            Ok(body) => (HYBRID_TAG_RKYV, body),
+            Err(_) => (HYBRID_TAG_JSON, self.json_codec.encode(message)?),
+        };
+
+        let mut data = Vec::with_capacity(body.len() + 1);
+        data.push(tag);
+        data.extend_from_slice(&body);
+        Ok(data)
     }
 
     fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
-        // Try rkyv first, then JSON
-        match self.rkyv_codec.decode(data) {
-            Ok(result) => Ok(result),
-            Err(_) => {
-                // Fall back to JSON
-                self.json_codec.decode(data)
-            }
+        match data.split_first() {
+            Some((&HYBRID_TAG_RKYV, body)) => self.rkyv_codec.decode(body),
+            Some((&HYBRID_TAG_JSON, body)) => self.json_codec.decode(body),
+            _ => self.json_codec.decode(data),
         }
     }
 
@@ -143,37 +289,75 @@ where
 }
 
 /// Wrapper for WebSocket messages with type information
+///
+/// Envelopes built with [`WsMessage::versioned`] also carry the payload's
+/// type name and schema version; see [`envelope`].
 #[derive(Debug, Clone, SerdeSerialize, SerdeDeserialize)]
 pub struct WsMessage<T> {
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub type_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
     pub data: T,
 }
 
 impl<T> WsMessage<T> {
     pub fn new(data: T) -> Self {
-        Self { data }
+        Self {
+            type_name: None,
+            version: None,
+            data,
+        }
+    }
+}
+
+/// Largest message a compressed codec inflates by default
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 << 20;
+
+/// Read `reader` to the end, failing once more than `max_size` bytes come out
+#[cfg(feature = "compression")]
+fn read_bounded(reader: impl std::io::Read, max_size: usize) -> Result<Vec<u8>, CodecError> {
+    use std::io::Read;
+
+    let mut output = Vec::new();
+    reader
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut output)
+        .map_err(|e| CodecError::DecompressionFailed(e.to_string()))?;
+    if output.len() > max_size {
+        return Err(CodecError::DecompressionFailed(format!(
+            "decompressed message exceeds {} bytes",
+            max_size
+        )));
     }
+    Ok(output)
 }
 
 /// Compressed codec wrapper
 pub struct CompressedCodec<C> {
     inner: C,
     compression_level: i32,
+    max_message_size: usize,
 }
 
 impl<C> CompressedCodec<C> {
     pub fn new(inner: C) -> Self {
-        Self {
-            inner,
-            compression_level: 3, // Default compression level
-        }
+        Self::with_level(inner, 3) // Default compression level
     }
 
     pub fn with_level(inner: C, level: i32) -> Self {
         Self {
             inner,
             compression_level: level,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
         }
     }
+
+    /// Reject messages that decompress to more than `max_message_size` bytes
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
 }
 
 impl<T, C> Codec<T> for CompressedCodec<C>
@@ -211,18 +395,17 @@ where
     fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
         // First decompress
         #[cfg(feature = "compression")]
-        let decompressed = {
-            use std::io::Read;
-            let mut decoder = flate2::read::GzDecoder::new(data);
-            let mut decompressed = Vec::new();
-            decoder
-                .read_to_end(&mut decompressed)
-                .map_err(|e| CodecError::DecompressionFailed(e.to_string()))?;
-            decompressed
-        };
+        let decompressed = read_bounded(flate2::read::GzDecoder::new(data), self.max_message_size)?;
 
         #[cfg(not(feature = "compression"))]
-        let decompressed = data.to_vec();
+        let decompressed = if data.len() > self.max_message_size {
+            return Err(CodecError::DecompressionFailed(format!(
+                "message exceeds {} bytes",
+                self.max_message_size
+            )));
+        } else {
+            data.to_vec()
+        };
 
         // Then decode with inner codec
         self.inner.decode(&decompressed)
@@ -234,6 +417,217 @@ where
     }
 }
 
+/// Tag byte marking a [`ZstdCodec`] frame as stored uncompressed
+#[cfg(feature = "compression")]
+const ZSTD_TAG_RAW: u8 = 0x00;
+
+/// Tag byte marking a [`ZstdCodec`] frame as a zstd frame
+#[cfg(feature = "compression")]
+const ZSTD_TAG_COMPRESSED: u8 = 0x01;
+
+/// Frames shorter than this are not worth compressing by default
+#[cfg(feature = "compression")]
+const ZSTD_DEFAULT_MIN_SIZE: usize = 64;
+
+/// A trained zstd dictionary, identified by the id stored in its header
+///
+/// Every frame compressed with a dictionary carries the dictionary id, so the
+/// receiving end picks the matching dictionary by id when decoding.
+#[cfg(feature = "compression")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZstdDictionary {
+    id: u32,
+    data: Vec<u8>,
+}
+
+#[cfg(feature = "compression")]
+impl ZstdDictionary {
+    /// Load a dictionary produced by [`Self::train`] or `zstd --train`
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, CodecError> {
+        let id = zstd::zstd_safe::get_dict_id_from_dict(&data)
+            .ok_or_else(|| CodecError::InvalidDictionary("dictionary has no id".to_string()))?
+            .get();
+        Ok(Self { id, data })
+    }
+
+    /// Train a dictionary of at most `max_size` bytes from sample frames
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Result<Self, CodecError> {
+        let data = zstd::dict::from_samples(samples, max_size)
+            .map_err(|e| CodecError::CompressionFailed(format!("dictionary training: {}", e)))?;
+        Self::from_bytes(data)
+    }
+
+    /// The id both ends use to agree on this dictionary
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The raw dictionary, to be stored or shipped to the other end
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Zstandard codec wrapper
+///
+/// Tuned for small, repetitive frames: messages are compressed with an
+/// optional trained dictionary, and frames below the minimum size, or that
+/// would not shrink, are stored as they are. Every frame starts with a
+/// one-byte tag saying which of the two it is.
+#[cfg(feature = "compression")]
+pub struct ZstdCodec<C> {
+    inner: C,
+    compression_level: i32,
+    min_size: usize,
+    max_message_size: usize,
+    encoder_dictionary: Option<zstd::dict::EncoderDictionary<'static>>,
+    decoder_dictionaries: std::collections::HashMap<u32, zstd::dict::DecoderDictionary<'static>>,
+}
+
+#[cfg(feature = "compression")]
+impl<C> ZstdCodec<C> {
+    pub fn new(inner: C) -> Self {
+        Self::with_level(inner, zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+
+    pub fn with_level(inner: C, level: i32) -> Self {
+        Self {
+            inner,
+            compression_level: level,
+            min_size: ZSTD_DEFAULT_MIN_SIZE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            encoder_dictionary: None,
+            decoder_dictionaries: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Store frames shorter than `min_size` bytes uncompressed
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Reject frames that decompress to more than `max_message_size` bytes
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Compress with `dictionary`, and accept frames compressed with it
+    pub fn with_dictionary(mut self, dictionary: &ZstdDictionary) -> Self {
+        self.encoder_dictionary = Some(zstd::dict::EncoderDictionary::copy(
+            dictionary.as_bytes(),
+            self.compression_level,
+        ));
+        self.accept_dictionary(dictionary)
+    }
+
+    /// Accept frames compressed with `dictionary` without compressing with it
+    ///
+    /// Lets a dictionary be rotated: receivers accept the new one before
+    /// senders switch to it.
+    pub fn accept_dictionary(mut self, dictionary: &ZstdDictionary) -> Self {
+        self.decoder_dictionaries.insert(
+            dictionary.id(),
+            zstd::dict::DecoderDictionary::copy(dictionary.as_bytes()),
+        );
+        self
+    }
+
+    /// Train a dictionary from recorded messages, as this codec encodes them
+    pub fn train_dictionary<T>(
+        &self,
+        samples: &[T],
+        max_size: usize,
+    ) -> Result<ZstdDictionary, CodecError>
+    where
+        C: Codec<T>,
+        T: Send + Sync,
+    {
+        let samples = samples
+            .iter()
+            .map(|sample| self.inner.encode(sample))
+            .collect::<Result<Vec<_>, _>>()?;
+        ZstdDictionary::train(&samples, max_size)
+    }
+
+    fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match &self.encoder_dictionary {
+            Some(dictionary) => {
+                zstd::bulk::Compressor::with_prepared_dictionary(dictionary)?.compress(data)
+            }
+            None => zstd::bulk::compress(data, self.compression_level),
+        }
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CodecError> {
+        let opened = |e: std::io::Error| CodecError::DecompressionFailed(e.to_string());
+        match zstd::zstd_safe::get_dict_id_from_frame(data) {
+            Some(id) => {
+                let dictionary = self.decoder_dictionaries.get(&id.get()).ok_or_else(|| {
+                    CodecError::DecompressionFailed(format!("unknown dictionary {}", id))
+                })?;
+                let decoder =
+                    zstd::stream::read::Decoder::with_prepared_dictionary(data, dictionary)
+                        .map_err(opened)?;
+                read_bounded(decoder, self.max_message_size)
+            }
+            None => {
+                let decoder = zstd::stream::read::Decoder::new(data).map_err(opened)?;
+                read_bounded(decoder, self.max_message_size)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+impl<T, C> Codec<T> for ZstdCodec<C>
+where
+    C: Codec<T>,
+    T: Send + Sync,
+{
+    fn encode(&self, message: &T) -> Result<Vec<u8>, CodecError> {
+        let uncompressed = self.inner.encode(message)?;
+
+        let compressed = if uncompressed.len() < self.min_size {
+            None
+        } else {
+            Some(
+                self.compress(&uncompressed)
+                    .map_err(|e| CodecError::CompressionFailed(e.to_string()))?,
+            )
+        };
+
+        let (tag, body) = match compressed {
+            Some(compressed) if compressed.len() < uncompressed.len() => {
+                (ZSTD_TAG_COMPRESSED, compressed)
+            }
+            _ => (ZSTD_TAG_RAW, uncompressed),
+        };
+
+        let mut data = Vec::with_capacity(body.len() + 1);
+        data.push(tag);
+        data.extend_from_slice(&body);
+        Ok(data)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
+        match data.split_first() {
+            Some((&ZSTD_TAG_RAW, body)) => self.inner.decode(body),
+            Some((&ZSTD_TAG_COMPRESSED, body)) => self.inner.decode(&self.decompress(body)?),
+            Some((tag, _)) => Err(CodecError::DecompressionFailed(format!(
+                "unknown frame tag {:#04x}",
+                tag
+            ))),
+            None => Err(CodecError::DecompressionFailed("empty frame".to_string())),
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/zstd"
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -249,6 +643,7 @@ mod tests {
         rkyv::Serialize,
         rkyv::Deserialize,
     )]
+    #[archive(check_bytes)]
     struct TestMessage {
         id: u32,
         content: String,
@@ -345,4 +740,235 @@ mod tests {
         println!("JSON size: {} bytes", json_encoded.len());
         println!("rkyv size: {} bytes", rkyv_encoded.len());
     }
+
+    #[test]
+    fn test_rkyv_codec_writes_validated_archives() {
+        let codec = RkyvCodec::new();
+        let message = TestMessage {
+            id: 42,
+            content: "Hello, World!".to_string(),
+        };
+
+        let encoded = codec.encode(&message).unwrap();
+        assert!(serde_json::from_slice::<TestMessage>(&encoded).is_err());
+
+        // Decoding must not depend on the alignment of the input
+        let mut shifted = vec![0u8];
+        shifted.extend_from_slice(&encoded);
+        let decoded: TestMessage = codec.decode(&shifted[1..]).unwrap();
+        assert_eq!(decoded, message);
+
+        let garbage = vec![0xffu8; encoded.len()];
+        assert!(matches!(
+            <RkyvCodec as Codec<TestMessage>>::decode(&codec, &garbage),
+            Err(CodecError::DeserializationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_hybrid_codec_tags_frames() {
+        let codec = HybridCodec::new().unwrap();
+        let message = TestMessage {
+            id: 7,
+            content: "tagged".to_string(),
+        };
+
+        let encoded = codec.encode(&message).unwrap();
+        assert_eq!(encoded[0], HYBRID_TAG_RKYV);
+        let decoded: TestMessage = RkyvCodec::new().decode(&encoded[1..]).unwrap();
+        assert_eq!(decoded, message);
+
+        let mut json = vec![HYBRID_TAG_JSON];
+        json.extend_from_slice(&serde_json::to_vec(&message).unwrap());
+        let decoded: TestMessage = codec.decode(&json).unwrap();
+        assert_eq!(decoded, message);
+
+        // Untagged frames from a plain JSON peer
+        let untagged = JsonCodec::new().encode(&message).unwrap();
+        let decoded: TestMessage = codec.decode(&untagged).unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_codec_basic() {
+        let codec = MsgPackCodec::new();
+        let message = TestMessage {
+            id: 42,
+            content: "Hello, MessagePack!".to_string(),
+        };
+
+        let encoded = codec.encode(&message).unwrap();
+        let decoded: TestMessage = codec.decode(&encoded).unwrap();
+        assert_eq!(decoded, message);
+        assert!(encoded.len() < JsonCodec::new().encode(&message).unwrap().len());
+        assert_eq!(
+            <MsgPackCodec as Codec<TestMessage>>::content_type(&codec),
+            "application/msgpack"
+        );
+
+        // Fields are keyed by name for consumers in other languages
+        let value: serde_json::Value = rmp_serde::from_slice(&encoded).unwrap();
+        assert_eq!(value["content"], "Hello, MessagePack!");
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_cbor_codec_basic() {
+        let codec = CborCodec::new();
+        let message = TestMessage {
+            id: 42,
+            content: "Hello, CBOR!".to_string(),
+        };
+
+        let encoded = codec.encode(&message).unwrap();
+        let decoded: TestMessage = codec.decode(&encoded).unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(
+            <CborCodec as Codec<TestMessage>>::content_type(&codec),
+            "application/cbor"
+        );
+
+        let truncated = &encoded[..encoded.len() - 1];
+        assert!(matches!(
+            <CborCodec as Codec<TestMessage>>::decode(&codec, truncated),
+            Err(CodecError::DeserializationFailed(_))
+        ));
+    }
+
+    #[cfg(all(feature = "msgpack", feature = "cbor"))]
+    #[test]
+    fn test_binary_codecs_with_config_and_compression() {
+        use crate::reactive::WebSocketConfig;
+        use crate::transport::{Message, MessageType};
+
+        let message = Message {
+            data: b"frame".to_vec(),
+            message_type: MessageType::Binary,
+        };
+
+        let config = WebSocketConfig::new("ws://localhost:8080/ws")
+            .with_codec(Box::new(MsgPackCodec::new()));
+        assert_eq!(config.codec.content_type(), "application/msgpack");
+        let encoded = config.codec.encode(&message).unwrap();
+        assert_eq!(config.codec.decode(&encoded).unwrap(), message);
+
+        let codec = CompressedCodec::new(CborCodec::new());
+        let encoded = codec.encode(&message).unwrap();
+        let decoded: Message = codec.decode(&encoded).unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[cfg(feature = "compression")]
+    fn recorded_updates(count: u64) -> Vec<crate::transport::Message> {
+        (0..count)
+            .map(|i| crate::transport::Message {
+                data: format!(
+                    r#"{{"type":"signal_update","name":"dashboard_row_{}","patch":[{{"op":"replace","path":"/rows/{}/status","value":"{}"}},{{"op":"replace","path":"/rows/{}/updated_at","value":{}}}]}}"#,
+                    i % 7,
+                    i % 50,
+                    ["online", "offline", "degraded"][(i % 3) as usize],
+                    i % 50,
+                    1_700_000_000 + i * 37
+                )
+                .into_bytes(),
+                message_type: crate::transport::MessageType::Text,
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_zstd_codec_stores_small_frames() {
+        let codec = ZstdCodec::new(JsonCodec::new()).with_min_size(64);
+        let message = TestMessage {
+            id: 1,
+            content: "tiny".to_string(),
+        };
+
+        let encoded = codec.encode(&message).unwrap();
+        assert_eq!(encoded[0], ZSTD_TAG_RAW);
+        assert_eq!(
+            encoded.len(),
+            JsonCodec::new().encode(&message).unwrap().len() + 1
+        );
+        let decoded: TestMessage = codec.decode(&encoded).unwrap();
+        assert_eq!(decoded, message);
+
+        let message = TestMessage {
+            id: 2,
+            content: "repetitive ".repeat(20),
+        };
+        let encoded = codec.encode(&message).unwrap();
+        assert_eq!(encoded[0], ZSTD_TAG_COMPRESSED);
+        let decoded: TestMessage = codec.decode(&encoded).unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_zstd_dictionary_shrinks_small_updates() {
+        use crate::transport::Message;
+
+        let samples = recorded_updates(1000);
+        let plain = ZstdCodec::new(RkyvCodec::new()).with_min_size(0);
+        let dictionary = plain.train_dictionary(&samples, 4 * 1024).unwrap();
+        assert_ne!(dictionary.id(), 0);
+
+        let sender = ZstdCodec::new(RkyvCodec::new())
+            .with_min_size(0)
+            .with_dictionary(&dictionary);
+        // The receiver loads the same dictionary and finds it by id
+        let shipped = ZstdDictionary::from_bytes(dictionary.as_bytes().to_vec()).unwrap();
+        let receiver = ZstdCodec::new(RkyvCodec::new()).accept_dictionary(&shipped);
+        assert!(matches!(
+            ZstdDictionary::from_bytes(b"not a dictionary".to_vec()),
+            Err(CodecError::InvalidDictionary(_))
+        ));
+
+        let update = &recorded_updates(1001)[1000];
+        let with_dictionary = sender.encode(update).unwrap();
+        let without_dictionary = plain.encode(update).unwrap();
+        assert_eq!(with_dictionary[0], ZSTD_TAG_COMPRESSED);
+        assert!(with_dictionary.len() * 2 < without_dictionary.len());
+
+        let decoded: Message = receiver.decode(&with_dictionary).unwrap();
+        assert_eq!(&decoded, update);
+
+        assert!(matches!(
+            <_ as Codec<Message>>::decode(&plain, &with_dictionary),
+            Err(CodecError::DecompressionFailed(_))
+        ));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_codecs_reject_oversized_messages() {
+        let message = TestMessage {
+            id: 1,
+            content: "a".repeat(64 * 1024),
+        };
+
+        let zstd = ZstdCodec::new(JsonCodec::new());
+        let encoded = zstd.encode(&message).unwrap();
+        assert!(encoded.len() < 1024);
+        let bounded = ZstdCodec::new(JsonCodec::new()).with_max_message_size(32 * 1024);
+        assert!(matches!(
+            <_ as Codec<TestMessage>>::decode(&bounded, &encoded),
+            Err(CodecError::DecompressionFailed(_))
+        ));
+        let decoded: TestMessage = zstd.decode(&encoded).unwrap();
+        assert_eq!(decoded, message);
+
+        let gzip = CompressedCodec::new(JsonCodec::new());
+        let encoded = gzip.encode(&message).unwrap();
+        assert!(encoded.len() < 1024);
+        let bounded = CompressedCodec::new(JsonCodec::new()).with_max_message_size(32 * 1024);
+        assert!(matches!(
+            <_ as Codec<TestMessage>>::decode(&bounded, &encoded),
+            Err(CodecError::DecompressionFailed(_))
+        ));
+        let decoded: TestMessage = gzip.decode(&encoded).unwrap();
+        assert_eq!(decoded, message);
+    }
 }
@@ -0,0 +1,459 @@
+//! Codec Negotiation
+//!
+//! Client and server agree on a codec during the WebSocket handshake. The
+//! client offers `Sec-WebSocket-Protocol` tokens such as `lwp.json`,
+//! `lwp.msgpack`, `lwp.rkyv` or `lwp.json+zstd`, and the server selects the
+//! first one it supports in its own order of preference. A peer that selects
+//! no token speaks plain JSON, so binary codecs can be rolled out one side at
+//! a time.
+
+use super::{Codec, CodecError, JsonCodec, RkyvCodec};
+use crate::transport::{Message, MessageType};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// Prefix shared by every codec subprotocol token
+pub const PROTOCOL_PREFIX: &str = "lwp.";
+
+/// Suffix marking a codec whose frames are zstd-compressed
+const ZSTD_SUFFIX: &str = "+zstd";
+
+/// Serialization format named by a codec subprotocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CodecFormat {
+    Json,
+    MsgPack,
+    Cbor,
+    Rkyv,
+}
+
+impl CodecFormat {
+    /// Name of the format inside a subprotocol token
+    pub fn name(self) -> &'static str {
+        match self {
+            CodecFormat::Json => "json",
+            CodecFormat::MsgPack => "msgpack",
+            CodecFormat::Cbor => "cbor",
+            CodecFormat::Rkyv => "rkyv",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(CodecFormat::Json),
+            "msgpack" => Some(CodecFormat::MsgPack),
+            "cbor" => Some(CodecFormat::Cbor),
+            "rkyv" => Some(CodecFormat::Rkyv),
+            _ => None,
+        }
+    }
+
+    /// Whether this build can encode the format
+    pub fn is_available(self) -> bool {
+        match self {
+            CodecFormat::Json | CodecFormat::Rkyv => true,
+            CodecFormat::MsgPack => cfg!(feature = "msgpack"),
+            CodecFormat::Cbor => cfg!(feature = "cbor"),
+        }
+    }
+}
+
+/// A codec both peers can agree on, written on the wire as `lwp.<format>[+zstd]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CodecProtocol {
+    pub format: CodecFormat,
+    pub zstd: bool,
+}
+
+impl CodecProtocol {
+    /// Plain JSON, spoken by every peer
+    pub const JSON: Self = Self::new(CodecFormat::Json);
+
+    pub const fn new(format: CodecFormat) -> Self {
+        Self {
+            format,
+            zstd: false,
+        }
+    }
+
+    /// Compress frames of this codec with zstd
+    pub const fn with_zstd(mut self) -> Self {
+        self.zstd = true;
+        self
+    }
+
+    /// The `Sec-WebSocket-Protocol` token for this codec
+    pub fn token(&self) -> String {
+        let suffix = if self.zstd { ZSTD_SUFFIX } else { "" };
+        format!("{}{}{}", PROTOCOL_PREFIX, self.format.name(), suffix)
+    }
+
+    /// Parse a `Sec-WebSocket-Protocol` token
+    ///
+    /// Returns `None` for tokens that don't name a codec, such as application
+    /// subprotocols offered next to the codec tokens.
+    pub fn from_token(token: &str) -> Option<Self> {
+        let name = token.trim().strip_prefix(PROTOCOL_PREFIX)?;
+        let (name, zstd) = match name.strip_suffix(ZSTD_SUFFIX) {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        Some(Self {
+            format: CodecFormat::from_name(name)?,
+            zstd,
+        })
+    }
+
+    /// Whether this build can encode the codec
+    pub fn is_available(&self) -> bool {
+        self.format.is_available() && (!self.zstd || cfg!(feature = "compression"))
+    }
+
+    /// Every codec this build supports, most preferred first
+    pub fn available() -> Vec<Self> {
+        [
+            Self::new(CodecFormat::Rkyv),
+            Self::new(CodecFormat::MsgPack).with_zstd(),
+            Self::new(CodecFormat::MsgPack),
+            Self::new(CodecFormat::Cbor),
+            Self::new(CodecFormat::Json).with_zstd(),
+            Self::JSON,
+        ]
+        .into_iter()
+        .filter(Self::is_available)
+        .collect()
+    }
+
+    /// The codec the peer selected in its handshake response
+    ///
+    /// A peer that selected nothing, or an application subprotocol rather
+    /// than a codec, speaks JSON.
+    pub fn selected(protocol: Option<&str>) -> Result<Self, CodecError> {
+        match protocol {
+            Some(token) if token.trim().starts_with(PROTOCOL_PREFIX) => Self::from_token(token)
+                .filter(Self::is_available)
+                .ok_or_else(|| CodecError::UnsupportedCodec(token.to_string())),
+            _ => Ok(Self::JSON),
+        }
+    }
+
+    /// Whether frames of this codec are sent unchanged
+    fn is_passthrough(&self) -> bool {
+        *self == Self::JSON
+    }
+
+    /// Create the codec for this protocol
+    pub fn codec(&self) -> Result<Box<dyn Codec<Message> + Send + Sync>, CodecError> {
+        if !self.is_available() {
+            return Err(CodecError::UnsupportedCodec(self.token()));
+        }
+
+        let codec: Box<dyn Codec<Message> + Send + Sync> = match self.format {
+            CodecFormat::Json => Box::new(JsonCodec::new()),
+            CodecFormat::Rkyv => Box::new(RkyvCodec::new()),
+            #[cfg(feature = "msgpack")]
+            CodecFormat::MsgPack => Box::new(super::MsgPackCodec::new()),
+            #[cfg(feature = "cbor")]
+            CodecFormat::Cbor => Box::new(super::CborCodec::new()),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("unavailable codecs are rejected above"),
+        };
+
+        #[cfg(feature = "compression")]
+        if self.zstd {
+            return Ok(Box::new(super::ZstdCodec::new(codec)));
+        }
+
+        Ok(codec)
+    }
+
+    /// Create the codec that writes binary payloads for this protocol
+    ///
+    /// JSON payloads are already written by the application, so only
+    /// compression applies to them.
+    fn payload_codec(&self) -> Result<Box<dyn Codec<Payload> + Send + Sync>, CodecError> {
+        if !self.is_available() {
+            return Err(CodecError::UnsupportedCodec(self.token()));
+        }
+
+        let codec: Box<dyn Codec<Payload> + Send + Sync> = match self.format {
+            CodecFormat::Json => Box::new(RawPayload),
+            CodecFormat::Rkyv => Box::new(RkyvCodec::new()),
+            #[cfg(feature = "msgpack")]
+            CodecFormat::MsgPack => Box::new(super::MsgPackCodec::new()),
+            #[cfg(feature = "cbor")]
+            CodecFormat::Cbor => Box::new(super::CborCodec::new()),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("unavailable codecs are rejected above"),
+        };
+
+        #[cfg(feature = "compression")]
+        if self.zstd {
+            return Ok(Box::new(super::ZstdCodec::new(codec)));
+        }
+
+        Ok(codec)
+    }
+}
+
+impl fmt::Display for CodecProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.token())
+    }
+}
+
+/// The payload of a binary message, as a negotiated format writes it
+///
+/// Serde formats store it as a byte string and rkyv as an archived byte
+/// vector, so a frame adds only the format's own length header.
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub struct Payload(pub Vec<u8>);
+
+impl Serialize for Payload {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Payload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PayloadVisitor;
+
+        impl<'de> de::Visitor<'de> for PayloadVisitor {
+            type Value = Payload;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a byte string")
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Payload, E> {
+                Ok(Payload(bytes.to_vec()))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Payload, E> {
+                Ok(Payload(bytes))
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Payload, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                Ok(Payload(bytes))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(PayloadVisitor)
+    }
+}
+
+/// Writes payloads unchanged, for formats the application already speaks
+struct RawPayload;
+
+impl Codec<Payload> for RawPayload {
+    fn encode(&self, payload: &Payload) -> Result<Vec<u8>, CodecError> {
+        Ok(payload.0.clone())
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Payload, CodecError> {
+        Ok(Payload(data.to_vec()))
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/octet-stream"
+    }
+}
+
+/// Applies a negotiated codec to the frames of a connection
+///
+/// The payload of a binary message is encoded with the codec and sent as a
+/// binary frame, so the opcode still tells text from binary. Text frames must
+/// stay valid UTF-8 and pass through unchanged; compress them with the
+/// permessage-deflate extension instead. Control frames pass through too, as
+/// does everything on a plain JSON connection, which keeps peers that never
+/// negotiated compatible.
+pub struct FrameCodec {
+    codec: Option<Box<dyn Codec<Payload> + Send + Sync>>,
+}
+
+impl FrameCodec {
+    pub fn new(protocol: CodecProtocol) -> Result<Self, CodecError> {
+        let codec = if protocol.is_passthrough() {
+            None
+        } else {
+            Some(protocol.payload_codec()?)
+        };
+        Ok(Self { codec })
+    }
+
+    /// Frame codec for the subprotocol selected in the handshake
+    pub fn selected(protocol: Option<&str>) -> Result<Self, CodecError> {
+        Self::new(CodecProtocol::selected(protocol)?)
+    }
+
+    /// Turn an outgoing message into the frame to write
+    pub fn encode(&self, message: Message) -> Result<Message, CodecError> {
+        match (&self.codec, &message.message_type) {
+            (Some(codec), MessageType::Binary) => Ok(Message {
+                data: codec.encode(&Payload(message.data))?,
+                message_type: MessageType::Binary,
+            }),
+            _ => Ok(message),
+        }
+    }
+
+    /// Recover the message carried by a received frame
+    pub fn decode(&self, frame: Message) -> Result<Message, CodecError> {
+        match (&self.codec, &frame.message_type) {
+            (Some(codec), MessageType::Binary) => Ok(Message {
+                data: codec.decode(&frame.data)?.0,
+                message_type: MessageType::Binary,
+            }),
+            _ => Ok(frame),
+        }
+    }
+}
+
+/// Tokens to offer for `protocols`, skipping codecs this build can't encode
+pub fn offer(protocols: &[CodecProtocol]) -> Vec<String> {
+    protocols
+        .iter()
+        .filter(|protocol| protocol.is_available())
+        .map(CodecProtocol::token)
+        .collect()
+}
+
+/// Pick the codec for a connection from the tokens a client offered
+///
+/// `supported` lists the codecs the server accepts, most preferred first.
+/// Returns `None` when the client offered none of them, in which case the
+/// connection falls back to JSON.
+pub fn negotiate<S: AsRef<str>>(
+    offered: &[S],
+    supported: &[CodecProtocol],
+) -> Option<CodecProtocol> {
+    let offered: Vec<CodecProtocol> = offered
+        .iter()
+        .filter_map(|token| CodecProtocol::from_token(token.as_ref()))
+        .collect();
+
+    supported
+        .iter()
+        .find(|protocol| protocol.is_available() && offered.contains(protocol))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_round_trip() {
+        let protocol = CodecProtocol::new(CodecFormat::Json).with_zstd();
+        assert_eq!(protocol.token(), "lwp.json+zstd");
+        assert_eq!(CodecProtocol::from_token("lwp.json+zstd"), Some(protocol));
+        assert_eq!(
+            CodecProtocol::from_token(" lwp.rkyv"),
+            Some(CodecProtocol::new(CodecFormat::Rkyv))
+        );
+        assert_eq!(CodecProtocol::from_token("graphql-ws"), None);
+        assert_eq!(CodecProtocol::from_token("lwp.xml"), None);
+    }
+
+    #[test]
+    fn test_server_preference_wins() {
+        let offered = ["chat", "lwp.json", "lwp.rkyv"];
+        let supported = [CodecProtocol::new(CodecFormat::Rkyv), CodecProtocol::JSON];
+        assert_eq!(
+            negotiate(&offered, &supported),
+            Some(CodecProtocol::new(CodecFormat::Rkyv))
+        );
+
+        let supported = [CodecProtocol::JSON];
+        assert_eq!(negotiate(&offered, &supported), Some(CodecProtocol::JSON));
+        assert_eq!(negotiate(&["chat"], &supported), None);
+    }
+
+    #[test]
+    fn test_unselected_protocol_falls_back_to_json() {
+        assert_eq!(CodecProtocol::selected(None).unwrap(), CodecProtocol::JSON);
+        assert_eq!(
+            CodecProtocol::selected(Some("chat")).unwrap(),
+            CodecProtocol::JSON
+        );
+        assert!(matches!(
+            CodecProtocol::selected(Some("lwp.xml")),
+            Err(CodecError::UnsupportedCodec(_))
+        ));
+    }
+
+    #[test]
+    fn test_frame_codec_encodes_binary_payloads_only() {
+        let binary = Message {
+            data: b"hello".to_vec(),
+            message_type: MessageType::Binary,
+        };
+        let text = Message {
+            data: b"{\"counter\":1}".to_vec(),
+            message_type: MessageType::Text,
+        };
+        let ping = Message {
+            data: b"ping".to_vec(),
+            message_type: MessageType::Ping,
+        };
+
+        for protocol in CodecProtocol::available() {
+            let codec = FrameCodec::new(protocol).unwrap();
+            let frame = codec.encode(binary.clone()).unwrap();
+            assert_eq!(frame.message_type, MessageType::Binary);
+            assert_eq!(codec.decode(frame).unwrap(), binary, "{}", protocol);
+            assert_eq!(codec.encode(text.clone()).unwrap(), text);
+            assert_eq!(codec.decode(text.clone()).unwrap(), text);
+            assert_eq!(codec.encode(ping.clone()).unwrap(), ping);
+        }
+
+        // Only the payload is archived, not the message around it
+        let rkyv = FrameCodec::selected(Some("lwp.rkyv")).unwrap();
+        let frame = rkyv.encode(binary.clone()).unwrap();
+        assert_ne!(frame.data, binary.data);
+        assert_eq!(
+            frame.data,
+            <RkyvCodec as Codec<Payload>>::encode(&RkyvCodec::new(), &Payload(binary.data))
+                .unwrap()
+        );
+
+        let json = FrameCodec::selected(None).unwrap();
+        assert_eq!(json.encode(text.clone()).unwrap(), text);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_writes_payloads_as_byte_strings() {
+        let payload = vec![0xff; 100];
+        let frame = FrameCodec::selected(Some("lwp.msgpack"))
+            .unwrap()
+            .encode(Message {
+                data: payload.clone(),
+                message_type: MessageType::Binary,
+            })
+            .unwrap();
+        // bin 8: marker, length, bytes
+        assert_eq!(frame.data.len(), payload.len() + 2);
+    }
+
+    #[test]
+    fn test_negotiated_codecs_round_trip() {
+        let message = Message {
+            data: b"{\"counter\":1}".to_vec(),
+            message_type: MessageType::Text,
+        };
+
+        for protocol in CodecProtocol::available() {
+            let codec = protocol.codec().unwrap();
+            let encoded = codec.encode(&message).unwrap();
+            let decoded = codec.decode(&encoded).unwrap();
+            assert_eq!(decoded, message, "{} did not round trip", protocol);
+        }
+    }
+}
@@ -8,9 +8,13 @@ pub enum Error {
     AddingSignalFailed,
     #[error("Could not update Signal")]
     UpdateSignalFailed,
+    #[error("Signal write rejected: {0}")]
+    WriteRejected(String),
 
     #[error(transparent)]
     SerializationFailed(#[from] serde_json::Error),
+    #[error(transparent)]
+    PatchFailed(#[from] json_patch::PatchError),
 }
 
 #[cfg(test)]
@@ -12,10 +12,15 @@
 //! - **Real-time collaboration**: Built-in presence awareness and conflict resolution
 //! - **Production-ready**: Automatic reconnection, horizontal scaling, comprehensive monitoring
 
+#[cfg(not(feature = "ssr"))]
 use crate::client_signal::ClientSignal;
+#[cfg(not(feature = "ssr"))]
 use crate::client_signals::ClientSignals;
+#[cfg(not(feature = "ssr"))]
 use crate::messages::{Messages, ServerSignalMessage};
+#[cfg(not(feature = "ssr"))]
 use leptos::prelude::*;
+#[cfg(not(feature = "ssr"))]
 use std::sync::{Arc, Mutex};
 // use leptos_use::{use_websocket_with_options, UseWebSocketOptions, UseWebSocketReturn};
 // use leptos_use::core::ConnectionReadyState;
@@ -45,23 +50,39 @@ mod server_signal;
 #[cfg(feature = "ssr")]
 pub mod server_signals;
 
+#[cfg(feature = "ssr")]
+pub use server_signal::{ClientWrite, SignalKey, SignalScope, WriteValidator};
+
 #[cfg(not(feature = "ssr"))]
 mod client_signal;
 
 #[cfg(not(feature = "ssr"))]
 mod client_signals;
 
-#[cfg(all(feature = "axum", feature = "ssr"))]
+#[cfg(feature = "axum")]
 pub mod axum;
 
 // Re-exports for convenience
 pub use codec::{Codec, CodecError, CompressedCodec, HybridCodec, JsonCodec, RkyvCodec, WsMessage};
+#[cfg(feature = "cbor")]
+pub use codec::CborCodec;
+#[cfg(feature = "msgpack")]
+pub use codec::MsgPackCodec;
+#[cfg(feature = "compression")]
+pub use codec::{ZstdCodec, ZstdDictionary};
 pub use reactive::{
     use_connection_metrics, use_connection_status, use_message_subscription, use_presence,
     use_websocket, WebSocketContext, WebSocketProvider,
 };
 pub use transport::{ConnectionState, Message, Transport, TransportConfig, TransportFactory};
 
+// Used by code generated in `leptos-ws-pro-macros`; not part of the public API
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+    pub use serde_json;
+}
+
 /// A type alias for a signal that synchronizes with the server.
 ///
 /// `ServerSignal<T>` represents a reactive value that can be updated from the server
@@ -162,6 +183,12 @@ impl ServerSignalWebSocket {
                 ServerSignalMessage::Update(update) => {
                     state_signals.update(&update.name, update.to_owned());
                 }
+                ServerSignalMessage::ClientUpdate(_) | ServerSignalMessage::Resync(_) => {
+                    // Client-to-server messages, ignore if received
+                }
+                ServerSignalMessage::Rejected(rejection) => {
+                    state_signals.reject(rejection.to_owned());
+                }
             },
         }
     }
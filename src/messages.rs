@@ -1,8 +1,15 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
+use json_patch::Patch;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::error::Error;
+
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub enum Messages {
     ServerSignal(ServerSignalMessage),
@@ -17,40 +24,157 @@ pub enum ServerSignalMessage {
     Establish(String),
     EstablishResponse((String, Value)),
     Update(ServerSignalUpdate),
+    /// A client's change to a writable signal, validated by the server
+    ClientUpdate(ServerSignalUpdate),
+    /// The server refused a [`ServerSignalMessage::ClientUpdate`]
+    Rejected(ServerSignalRejection),
+    /// Establishes a signal the client already holds at the given version.
+    ///
+    /// The server answers with the updates the client missed, or with a
+    /// snapshot when it no longer has them. Version 0 means the client holds
+    /// nothing yet.
+    Resync((String, u64)),
+}
+
+/// Identifies one websocket connection on the server
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ConnectionId(pub u64);
+
+impl ConnectionId {
+    /// Allocates an id no other connection of this process has used
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        ConnectionId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
+/// Sent back to a client whose write was refused, so it can roll back
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerSignalRejection {
+    pub name: Cow<'static, str>,
+    pub reason: String,
+    /// The value the server kept, which replaces the client's optimistic one
+    pub value: Value,
+    /// Version of `value`
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// A change to a server signal, sent to every subscribed client
+///
+/// Updates carry an RFC 6902 patch from the previous JSON value to the new
+/// one. When the patch would be larger than the value itself, for example
+/// after most rows of a list changed, the full value is sent as `snapshot`
+/// instead and `patch` is left empty.
+///
+/// Each update of a signal carries the next version of its value, so clients
+/// can spot updates they missed. Version 0 marks an update from a server that
+/// predates versioning.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ServerSignalUpdate {
     pub name: Cow<'static, str>,
-    pub patch: Value, // Simplified: using Value instead of Patch for now
+    pub patch: Patch,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<Value>,
+    /// Version of the value after this update is applied
+    #[serde(default)]
+    pub version: u64,
+    /// Connection whose write produced this update, which already has it
+    #[serde(skip)]
+    pub origin: Option<ConnectionId>,
 }
 
 impl ServerSignalUpdate {
     /// Creates a new [`ServerSignalUpdate`] from an old and new instance of `T`.
     pub fn new<T>(
         name: impl Into<Cow<'static, str>>,
-        _old: &T,
+        old: &T,
         new: &T,
     ) -> Result<Self, serde_json::Error>
     where
         T: Serialize,
     {
-        // Simplified: just store the new value for now
-        let new_value = serde_json::to_value(new)?;
-        Ok(ServerSignalUpdate {
-            name: name.into(),
-            patch: new_value,
-        })
+        let old = serde_json::to_value(old)?;
+        let new = serde_json::to_value(new)?;
+        Ok(Self::new_from_json(name, &old, &new))
     }
 
     /// Creates a new [`ServerSignalUpdate`] from two json values.
-    pub fn new_from_json(name: impl Into<Cow<'static, str>>, _old: &Value, new: &Value) -> Self {
-        // Simplified: just store the new value for now
+    pub fn new_from_json(name: impl Into<Cow<'static, str>>, old: &Value, new: &Value) -> Self {
+        let patch = json_patch::diff(old, new);
+        if !patch.0.is_empty() && encoded_len(&patch) > encoded_len(new) {
+            return ServerSignalUpdate {
+                name: name.into(),
+                patch: Patch::default(),
+                snapshot: Some(new.clone()),
+                version: 0,
+                origin: None,
+            };
+        }
+
+        ServerSignalUpdate {
+            name: name.into(),
+            patch,
+            snapshot: None,
+            version: 0,
+            origin: None,
+        }
+    }
+
+    /// Creates an update replacing the whole value of a signal.
+    pub fn snapshot(name: impl Into<Cow<'static, str>>, value: Value, version: u64) -> Self {
         ServerSignalUpdate {
             name: name.into(),
-            patch: new.clone(),
+            patch: Patch::default(),
+            snapshot: Some(value),
+            version,
+            origin: None,
         }
     }
+
+    /// Sets the version of the value after this update.
+    pub fn with_version(mut self, version: u64) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Whether this update replaces the whole value instead of patching it
+    pub fn is_snapshot(&self) -> bool {
+        self.snapshot.is_some()
+    }
+
+    /// Applies this update to the previous json value of the signal.
+    ///
+    /// A patch that fails to apply leaves `value` unchanged.
+    pub fn apply(&self, value: &mut Value) -> Result<(), Error> {
+        match &self.snapshot {
+            Some(snapshot) => *value = snapshot.clone(),
+            None => json_patch::patch(value, &self.patch)?,
+        }
+        Ok(())
+    }
+}
+
+/// Length of `value` serialized as json, without buffering it
+fn encoded_len<T: Serialize + ?Sized>(value: &T) -> usize {
+    struct Counter(usize);
+
+    impl io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    match serde_json::to_writer(&mut counter, value) {
+        Ok(()) => counter.0,
+        Err(_) => usize::MAX,
+    }
 }
 
 #[cfg(test)]
@@ -81,10 +205,12 @@ mod tests {
 
         // Act
         let update = ServerSignalUpdate::new("test_signal", &old, &new).unwrap();
+        let mut value = serde_json::to_value(&old).unwrap();
+        update.apply(&mut value).unwrap();
 
         // Assert
         assert_eq!(update.name, "test_signal");
-        assert!(!update.patch.is_null());
+        assert_eq!(value, serde_json::to_value(&new).unwrap());
     }
 
     #[test]
@@ -95,10 +221,12 @@ mod tests {
 
         // Act
         let update = ServerSignalUpdate::new_from_json("test_signal", &old, &new);
+        let mut value = old.clone();
+        update.apply(&mut value).unwrap();
 
         // Assert
         assert_eq!(update.name, "test_signal");
-        assert!(!update.patch.is_null());
+        assert_eq!(value, new);
     }
 
     #[test]
@@ -115,7 +243,66 @@ mod tests {
 
         // Assert
         assert_eq!(update.name, "test_signal");
-        assert!(update.patch == serde_json::to_value(&data).unwrap());
+        assert!(update.patch.0.is_empty());
+        assert!(!update.is_snapshot());
+    }
+
+    #[test]
+    fn test_server_signal_update_applies_incrementally() {
+        // Arrange
+        let rows: Vec<_> = (0..100)
+            .map(|id| json!({"id": id, "name": format!("row {}", id), "done": false}))
+            .collect();
+        let old = Value::Array(rows.clone());
+        let mut changed = rows;
+        changed[42]["done"] = json!(true);
+        let new = Value::Array(changed);
+
+        // Act
+        let update = ServerSignalUpdate::new_from_json("rows", &old, &new);
+        let mut value = old.clone();
+        update.apply(&mut value).unwrap();
+
+        // Assert
+        assert_eq!(update.patch.0.len(), 1);
+        assert!(!update.is_snapshot());
+        assert_eq!(value, new);
+        assert!(serde_json::to_string(&update).unwrap().len() < new.to_string().len() / 10);
+    }
+
+    #[test]
+    fn test_server_signal_update_falls_back_to_snapshot() {
+        // Arrange
+        let old = json!({"a": 1, "b": 2, "c": 3});
+        let new = json!([1, 2, 3]);
+
+        // Act
+        let update = ServerSignalUpdate::new_from_json("test_signal", &old, &new);
+        let mut value = old.clone();
+        update.apply(&mut value).unwrap();
+
+        // Assert
+        assert!(update.is_snapshot());
+        assert!(update.patch.0.is_empty());
+        assert_eq!(value, new);
+    }
+
+    #[test]
+    fn test_server_signal_update_rejects_stale_value() {
+        // Arrange
+        let title = "a title long enough to keep the patch smaller than the value";
+        let old = json!({"items": [1, 2, 3], "title": title});
+        let new = json!({"items": [1, 2], "title": title});
+        let update = ServerSignalUpdate::new_from_json("test_signal", &old, &new);
+
+        // Act
+        let mut stale = json!({"title": title});
+        let result = update.apply(&mut stale);
+
+        // Assert
+        assert!(!update.is_snapshot());
+        assert!(matches!(result, Err(Error::PatchFailed(_))));
+        assert_eq!(stale, json!({"title": title}));
     }
 
     #[test]
@@ -181,4 +368,50 @@ mod tests {
         let deserialized: ServerSignalMessage = serde_json::from_str(&serialized).unwrap();
         assert_eq!(update_msg, deserialized);
     }
+
+    #[test]
+    fn test_client_write_messages() {
+        // Arrange
+        let old = json!({"value": 10});
+        let new = json!({"value": 20});
+        let mut update = ServerSignalUpdate::new_from_json("test_signal", &old, &new);
+        update.origin = Some(ConnectionId::next());
+        let write = Messages::ServerSignal(ServerSignalMessage::ClientUpdate(update));
+        let rejection =
+            Messages::ServerSignal(ServerSignalMessage::Rejected(ServerSignalRejection {
+                name: "test_signal".into(),
+                reason: "value must stay below 15".to_string(),
+                value: old,
+                version: 4,
+            }));
+
+        // Act
+        let write_json = serde_json::to_value(&write).unwrap();
+        let rejection_json = serde_json::to_string(&rejection).unwrap();
+
+        // Assert - the origin is server-local and never sent
+        assert!(write_json["ServerSignal"]["ClientUpdate"]
+            .get("origin")
+            .is_none());
+        let deserialized: Messages = serde_json::from_str(&rejection_json).unwrap();
+        assert_eq!(rejection, deserialized);
+        assert_ne!(ConnectionId::next(), ConnectionId::next());
+    }
+
+    #[test]
+    fn test_server_signal_update_versions() {
+        // Arrange
+        let update = ServerSignalUpdate::snapshot("test_signal", json!({"value": 20}), 7);
+        let legacy = r#"{"name":"test_signal","patch":[]}"#;
+
+        // Act
+        let serialized = serde_json::to_string(&update).unwrap();
+        let deserialized: ServerSignalUpdate = serde_json::from_str(&serialized).unwrap();
+        let legacy: ServerSignalUpdate = serde_json::from_str(legacy).unwrap();
+
+        // Assert
+        assert_eq!(deserialized.version, 7);
+        assert!(deserialized.is_snapshot());
+        assert_eq!(legacy.version, 0);
+    }
 }
@@ -2,7 +2,7 @@
 //!
 //! Configuration structures and provider implementations for reactive WebSocket connections.
 
-use crate::codec::Codec;
+use crate::codec::{negotiation, Codec, CodecError, CodecProtocol};
 use crate::transport::Message;
 use std::fmt;
 
@@ -87,6 +87,25 @@ impl WebSocketConfig {
         self.codec = codec;
         self
     }
+
+    /// Offer `codecs` to the server during the handshake, most preferred first
+    ///
+    /// The codec tokens are added after any application subprotocols. Call
+    /// [`Self::apply_selected_protocol`] with the server's answer to switch codecs.
+    pub fn with_codec_protocols(mut self, codecs: &[CodecProtocol]) -> Self {
+        self.protocols
+            .retain(|protocol| CodecProtocol::from_token(protocol).is_none());
+        self.protocols.extend(negotiation::offer(codecs));
+        self
+    }
+
+    /// Use the codec the server selected in its handshake response
+    ///
+    /// Servers that selected no subprotocol get JSON.
+    pub fn apply_selected_protocol(&mut self, selected: Option<&str>) -> Result<(), CodecError> {
+        self.codec = CodecProtocol::selected(selected)?.codec()?;
+        Ok(())
+    }
 }
 
 /// WebSocket provider that manages connections
@@ -197,4 +216,20 @@ mod tests {
         assert_eq!(config.heartbeat_interval, Some(60000));
         assert_eq!(config.max_reconnect_attempts, Some(10));
     }
+
+    #[test]
+    fn test_codec_protocols() {
+        use crate::codec::CodecFormat;
+
+        let mut config = WebSocketConfig::new("ws://example.com/ws")
+            .with_protocols(vec!["chat".to_string(), "lwp.json".to_string()])
+            .with_codec_protocols(&[CodecProtocol::new(CodecFormat::Rkyv), CodecProtocol::JSON]);
+        assert_eq!(config.protocols, vec!["chat", "lwp.rkyv", "lwp.json"]);
+
+        config.apply_selected_protocol(Some("lwp.rkyv")).unwrap();
+        assert_eq!(config.codec.content_type(), "application/rkyv");
+        config.apply_selected_protocol(None).unwrap();
+        assert_eq!(config.codec.content_type(), "application/json");
+        assert!(config.apply_selected_protocol(Some("lwp.xml")).is_err());
+    }
 }
@@ -3,16 +3,14 @@
 //! This module provides bidirectional RPC with request/response correlation,
 //! type-safe method definitions, and async method support.
 
-#[cfg(feature = "advanced-rpc")]
-use crate::transport::{Message, MessageType};
-use crate::transport::{Transport, TransportError};
+use crate::transport::{Message, MessageType, Transport, TransportError};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::oneshot;
 
 /// RPC Request structure
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -40,6 +38,34 @@ pub enum RpcError {
     InternalError(String),
 }
 
+impl From<RpcError> for crate::rpc::types::RpcError {
+    fn from(error: RpcError) -> Self {
+        match error {
+            RpcError::MethodNotFound(message) => Self::new(-32601, message),
+            RpcError::InvalidParams(message) => {
+                Self::new(-32602, format!("Invalid params: {}", message))
+            }
+            RpcError::Timeout(message) => Self::new(-32603, format!("Timeout: {}", message)),
+            RpcError::ConnectionFailed(message) => {
+                Self::new(-32603, format!("Connection failed: {}", message))
+            }
+            RpcError::InternalError(message) => {
+                Self::new(-32603, format!("Internal error: {}", message))
+            }
+        }
+    }
+}
+
+impl From<crate::rpc::types::RpcError> for RpcError {
+    fn from(error: crate::rpc::types::RpcError) -> Self {
+        match error.code {
+            -32601 => RpcError::MethodNotFound(error.message),
+            -32602 => RpcError::InvalidParams(error.message),
+            _ => RpcError::InternalError(error.message),
+        }
+    }
+}
+
 /// Pending RPC request with response channel
 struct PendingRequest {
     response_tx: oneshot::Sender<Result<RpcResponse, RpcError>>,
@@ -83,22 +109,88 @@ impl RpcCorrelationManager {
 
     /// Handle incoming RPC response
     pub fn handle_response(&self, response: RpcResponse) -> Result<(), RpcError> {
+        let request_id = response.id.clone();
+        self.complete(&request_id, Ok(response))
+    }
+
+    /// Fail a pending request with an error reported by the server
+    pub fn handle_error(&self, request_id: &str, error: RpcError) -> Result<(), RpcError> {
+        self.complete(request_id, Err(error))
+    }
+
+    /// Complete the pending requests answered by a response frame
+    ///
+    /// Accepts a single native or JSON-RPC 2.0 response, a JSON-RPC batch, or
+    /// the `responses` of a batch reply. Error objects are mapped back to
+    /// [`RpcError`] variants by code.
+    pub fn handle_response_frame(&self, frame: serde_json::Value) -> Result<(), RpcError> {
+        let responses = match frame {
+            serde_json::Value::Array(responses) => serde_json::from_value(responses.into()),
+            frame if frame.get("responses").is_some() => {
+                serde_json::from_value::<BatchRpcResponse>(frame).map(|batch| batch.responses)
+            }
+            frame => serde_json::from_value(frame).map(|response| vec![response]),
+        }
+        .map_err(|e| RpcError::InternalError(e.to_string()))?;
+
+        // Complete every item even if one of them is no longer pending
+        let mut result = Ok(());
+        for response in responses {
+            let item_result = match response.error {
+                Some(error) => self.handle_error(&response.id, error.into()),
+                None => self.handle_response(RpcResponse {
+                    id: response.id,
+                    result: Some(response.result.unwrap_or(serde_json::Value::Null)),
+                    error: None,
+                }),
+            };
+            if result.is_ok() {
+                result = item_result;
+            }
+        }
+        result
+    }
+
+    /// Stop waiting for every pending request
+    ///
+    /// Their callers see the response channel close.
+    pub fn cancel_all(&self) -> usize {
         let mut pending = self.pending_requests.lock().unwrap();
+        let count = pending.len();
+        pending.clear();
+        count
+    }
+
+    /// Stop waiting for a request, returning whether it was pending
+    pub fn cancel_request(&self, request_id: &str) -> bool {
+        self.pending_requests
+            .lock()
+            .unwrap()
+            .remove(request_id)
+            .is_some()
+    }
 
-        if let Some(pending_request) = pending.remove(&response.id) {
+    fn complete(
+        &self,
+        request_id: &str,
+        result: Result<RpcResponse, RpcError>,
+    ) -> Result<(), RpcError> {
+        let mut pending = self.pending_requests.lock().unwrap();
+
+        if let Some(pending_request) = pending.remove(request_id) {
             if pending_request.timeout > Instant::now() {
-                let _ = pending_request.response_tx.send(Ok(response));
+                let _ = pending_request.response_tx.send(result);
                 Ok(())
             } else {
                 Err(RpcError::Timeout(format!(
                     "Request {} timed out",
-                    response.id
+                    request_id
                 )))
             }
         } else {
             Err(RpcError::InternalError(format!(
                 "No pending request found for ID: {}",
-                response.id
+                request_id
             )))
         }
     }
@@ -130,45 +222,24 @@ impl RpcCorrelationManager {
 }
 
 /// Bidirectional RPC Client
+///
+/// Sends requests over the transport and correlates the responses fed back
+/// through [`BidirectionalRpcClient::handle_message`]. The server can call
+/// the handlers registered on the client over the same connection.
 pub struct BidirectionalRpcClient<T: Transport> {
     transport: T,
     correlation_manager: Arc<RpcCorrelationManager>,
-    request_sender: mpsc::UnboundedSender<RpcRequest>,
-    response_receiver: mpsc::UnboundedReceiver<RpcResponse>,
+    registry: RpcMethodRegistry,
 }
 
 impl<T: Transport> BidirectionalRpcClient<T> {
     pub async fn new(transport: T, timeout_duration: Duration) -> Result<Self, TransportError> {
         let correlation_manager = Arc::new(RpcCorrelationManager::new(timeout_duration));
-        let (request_sender, mut request_receiver) = mpsc::unbounded_channel::<RpcRequest>();
-        let (response_sender, response_receiver) = mpsc::unbounded_channel();
-
-        // Spawn task to handle outgoing requests
-        let correlation_manager_clone = correlation_manager.clone();
-        tokio::spawn(async move {
-            while let Some(request) = request_receiver.recv().await {
-                // In a real implementation, this would send the request via the transport
-                // For now, we'll simulate the response
-                let response = RpcResponse {
-                    id: request.id.clone(),
-                    result: Some(serde_json::json!({
-                        "echo": request.params,
-                        "method": request.method
-                    })),
-                    error: None,
-                };
-
-                if let Err(e) = correlation_manager_clone.handle_response(response) {
-                    eprintln!("Failed to handle response: {:?}", e);
-                }
-            }
-        });
 
         Ok(Self {
             transport,
             correlation_manager,
-            request_sender,
-            response_receiver,
+            registry: RpcMethodRegistry::new(),
         })
     }
 
@@ -178,37 +249,8 @@ impl<T: Transport> BidirectionalRpcClient<T> {
         method: &str,
         params: serde_json::Value,
     ) -> Result<serde_json::Value, RpcError> {
-        let request_id = Uuid::new_v4().to_string();
-        let request = RpcRequest {
-            id: request_id.clone(),
-            method: method.to_string(),
-            params,
-        };
-
-        // Register pending request
-        let response_rx = self.correlation_manager.register_request(request_id);
-
-        // Send request
-        self.request_sender
-            .send(request)
-            .map_err(|_| RpcError::ConnectionFailed("Failed to send request".to_string()))?;
-
-        // Wait for response
-        match response_rx.await {
-            Ok(Ok(response)) => {
-                if let Some(result) = response.result {
-                    Ok(result)
-                } else if let Some(error) = response.error {
-                    Err(RpcError::InternalError(error))
-                } else {
-                    Err(RpcError::InternalError("Empty response".to_string()))
-                }
-            }
-            Ok(Err(e)) => Err(e),
-            Err(_) => Err(RpcError::ConnectionFailed(
-                "Response channel closed".to_string(),
-            )),
-        }
+        self.call_with_id(Uuid::new_v4().to_string(), method, params)
+            .await
     }
 
     /// Make an RPC call with timeout
@@ -219,37 +261,149 @@ impl<T: Transport> BidirectionalRpcClient<T> {
         timeout: Duration,
     ) -> Result<serde_json::Value, RpcError> {
         let request_id = Uuid::new_v4().to_string();
+        match tokio::time::timeout(
+            timeout,
+            self.call_with_id(request_id.clone(), method, params),
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                self.correlation_manager.cancel_request(&request_id);
+                Err(RpcError::Timeout("Request timed out".to_string()))
+            }
+        }
+    }
+
+    async fn call_with_id(
+        &self,
+        request_id: String,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, RpcError> {
         let request = RpcRequest {
-            id: request_id.clone(),
+            id: request_id,
             method: method.to_string(),
             params,
         };
 
-        // Register pending request
-        let response_rx = self.correlation_manager.register_request(request_id);
-
-        // Send request
-        self.request_sender
-            .send(request)
-            .map_err(|_| RpcError::ConnectionFailed("Failed to send request".to_string()))?;
-
-        // Wait for response with timeout
-        match tokio::time::timeout(timeout, response_rx).await {
-            Ok(Ok(Ok(response))) => {
-                if let Some(result) = response.result {
-                    Ok(result)
-                } else if let Some(error) = response.error {
-                    Err(RpcError::InternalError(error))
-                } else {
-                    Err(RpcError::InternalError("Empty response".to_string()))
-                }
+        let mut response_rx = self
+            .send_requests(&request, std::slice::from_ref(&request))
+            .await?;
+
+        // Wait for response
+        response_result(response_rx.remove(0).await)
+    }
+
+    /// Send several requests as a single [`BatchRpcRequest`] frame
+    ///
+    /// Results are returned in request order. A failing item only fails its
+    /// own slot; the outer error is reserved for failing to send the frame.
+    pub async fn call_batch(
+        &self,
+        requests: Vec<(String, serde_json::Value)>,
+    ) -> Result<Vec<Result<serde_json::Value, RpcError>>, RpcError> {
+        let batch = BatchRpcRequest {
+            requests: requests
+                .into_iter()
+                .map(|(method, params)| RpcRequest {
+                    id: Uuid::new_v4().to_string(),
+                    method,
+                    params,
+                })
+                .collect(),
+        };
+
+        let response_rx = self.send_requests(&batch, &batch.requests).await?;
+        Ok(futures::future::join_all(response_rx)
+            .await
+            .into_iter()
+            .map(response_result)
+            .collect())
+    }
+
+    /// Register `requests` for correlation and send `frame` carrying them
+    async fn send_requests<F: Serialize>(
+        &self,
+        frame: &F,
+        requests: &[RpcRequest],
+    ) -> Result<Vec<ResponseReceiver>, RpcError> {
+        let data = serde_json::to_vec(frame).map_err(|e| RpcError::InvalidParams(e.to_string()))?;
+        let response_rx = requests
+            .iter()
+            .map(|request| {
+                self.correlation_manager
+                    .register_request(request.id.clone())
+            })
+            .collect();
+
+        let message = Message {
+            data,
+            message_type: MessageType::Text,
+        };
+        if let Err(e) = self.transport.send_message(&message).await {
+            for request in requests {
+                self.correlation_manager.cancel_request(&request.id);
             }
-            Ok(Ok(Err(e))) => Err(e),
-            Ok(Err(_)) => Err(RpcError::ConnectionFailed(
-                "Response channel closed".to_string(),
-            )),
-            Err(_) => Err(RpcError::Timeout("Request timed out".to_string())),
+            return Err(RpcError::ConnectionFailed(format!(
+                "Failed to send request: {}",
+                e
+            )));
         }
+
+        Ok(response_rx)
+    }
+
+    /// Register a handler the server can call on this client
+    pub fn register<F>(&mut self, method: &str, handler: F)
+    where
+        F: Fn(serde_json::Value) -> Result<serde_json::Value, RpcError> + Send + Sync + 'static,
+    {
+        self.registry.register(method, handler);
+    }
+
+    /// Serve the handlers of an existing registry to the server
+    pub fn with_registry(mut self, registry: RpcMethodRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Handle an incoming frame
+    ///
+    /// Responses complete pending calls. Requests from the server are
+    /// dispatched to the registered handlers and answered over the transport.
+    pub async fn handle_message(&self, data: &[u8]) -> Result<(), RpcError> {
+        let value: serde_json::Value =
+            serde_json::from_slice(data).map_err(|e| RpcError::InternalError(e.to_string()))?;
+
+        if value.get("method").is_none() {
+            return self.correlation_manager.handle_response_frame(value);
+        }
+
+        let request: RpcRequest =
+            serde_json::from_value(value).map_err(|e| RpcError::InvalidParams(e.to_string()))?;
+        let response = match self.registry.call(&request.method, request.params) {
+            Ok(result) => crate::rpc::types::RpcResponse {
+                id: request.id,
+                result: Some(result),
+                error: None,
+            },
+            Err(error) => crate::rpc::types::RpcResponse {
+                id: request.id,
+                result: None,
+                error: Some(error.into()),
+            },
+        };
+
+        let message = Message {
+            data: serde_json::to_vec(&response)
+                .map_err(|e| RpcError::InternalError(e.to_string()))?,
+            message_type: MessageType::Text,
+        };
+        self.transport
+            .send_message(&message)
+            .await
+            .map_err(|e| RpcError::ConnectionFailed(format!("Failed to send response: {}", e)))
     }
 
     /// Get number of pending requests
@@ -263,6 +417,29 @@ impl<T: Transport> BidirectionalRpcClient<T> {
     }
 }
 
+type ResponseReceiver = oneshot::Receiver<Result<RpcResponse, RpcError>>;
+
+/// Turn a correlated response into the call result
+pub(crate) fn response_result(
+    response: Result<Result<RpcResponse, RpcError>, oneshot::error::RecvError>,
+) -> Result<serde_json::Value, RpcError> {
+    match response {
+        Ok(Ok(response)) => {
+            if let Some(result) = response.result {
+                Ok(result)
+            } else if let Some(error) = response.error {
+                Err(RpcError::InternalError(error))
+            } else {
+                Err(RpcError::InternalError("Empty response".to_string()))
+            }
+        }
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(RpcError::ConnectionFailed(
+            "Response channel closed".to_string(),
+        )),
+    }
+}
+
 /// RPC Method Registry
 /// Manages type-safe method definitions
 pub struct RpcMethodRegistry {
@@ -310,15 +487,20 @@ impl RpcMethodRegistry {
 }
 
 /// Batch RPC Request
+///
+/// Sent as a single frame; the server answers with one frame whose
+/// `responses` carry the native response of every item, in request order.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct BatchRpcRequest {
     pub requests: Vec<RpcRequest>,
 }
 
 /// Batch RPC Response
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+///
+/// The frame a server answers a [`BatchRpcRequest`] with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BatchRpcResponse {
-    pub responses: Vec<RpcResponse>,
+    pub responses: Vec<crate::rpc::types::RpcResponse<serde_json::Value>>,
 }
 
 /// Batch RPC Client
@@ -332,27 +514,94 @@ impl<T: Transport> BatchRpcClient<T> {
         Ok(Self { rpc_client })
     }
 
-    /// Make multiple RPC calls in batch
+    /// Make multiple RPC calls in a single frame
+    ///
+    /// See [`BidirectionalRpcClient::call_batch`].
     pub async fn call_batch(
         &self,
         requests: Vec<(String, serde_json::Value)>,
-    ) -> Result<Vec<serde_json::Value>, RpcError> {
-        let mut results = Vec::new();
-
-        for (method, params) in requests {
-            let result = self.rpc_client.call(&method, params).await?;
-            results.push(result);
-        }
+    ) -> Result<Vec<Result<serde_json::Value, RpcError>>, RpcError> {
+        self.rpc_client.call_batch(requests).await
+    }
 
-        Ok(results)
+    /// Handle an incoming frame
+    ///
+    /// See [`BidirectionalRpcClient::handle_message`].
+    pub async fn handle_message(&self, data: &[u8]) -> Result<(), RpcError> {
+        self.rpc_client.handle_message(data).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rpc::{ClientCaller, RpcRouter};
     use crate::transport::websocket::WebSocketConnection;
-    use crate::transport::TransportConfig;
+    use crate::transport::{ConnectionState, TransportConfig};
+    use futures::{Sink, SinkExt, Stream};
+    use std::pin::Pin;
+    use tokio::sync::mpsc;
+
+    /// Transport that hands every sent frame to the test
+    struct LoopbackTransport {
+        frames: mpsc::UnboundedSender<Message>,
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for LoopbackTransport {
+        type Stream = Pin<Box<dyn Stream<Item = Result<Message, TransportError>> + Send>>;
+        type Sink = Pin<Box<dyn Sink<Message, Error = TransportError> + Send>>;
+
+        async fn connect(&mut self, _url: &str) -> Result<(), TransportError> {
+            Ok(())
+        }
+
+        async fn disconnect(&mut self) -> Result<(), TransportError> {
+            Ok(())
+        }
+
+        fn split(self) -> (Self::Stream, Self::Sink) {
+            (
+                Box::pin(futures::stream::empty()),
+                Box::pin(futures::sink::drain().sink_map_err(|e| match e {})),
+            )
+        }
+
+        fn state(&self) -> ConnectionState {
+            ConnectionState::Connected
+        }
+
+        async fn send_message(&self, message: &Message) -> Result<(), TransportError> {
+            self.frames
+                .send(message.clone())
+                .map_err(|_| TransportError::SendFailed("Loopback closed".to_string()))
+        }
+    }
+
+    /// Create a batch client whose frames are answered by an in-process router
+    async fn serve_loopback() -> Arc<BatchRpcClient<LoopbackTransport>> {
+        let mut router = RpcRouter::new();
+        router.registry_mut().register("echo", |params| {
+            Ok(serde_json::json!({"echo": params, "method": "echo"}))
+        });
+
+        let (frames, mut requests) = mpsc::unbounded_channel();
+        let client = Arc::new(
+            BatchRpcClient::new(LoopbackTransport { frames }, Duration::from_secs(5))
+                .await
+                .unwrap(),
+        );
+
+        let responder = client.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = requests.recv().await {
+                if let Some(response) = router.handle_frame((), &frame.data).await {
+                    responder.handle_message(&response.data).await.unwrap();
+                }
+            }
+        });
+        client
+    }
 
     #[tokio::test]
     async fn test_rpc_correlation_manager() {
@@ -407,35 +656,163 @@ mod tests {
             .await
             .unwrap();
 
-        // Make an RPC call
+        // Calls fail fast instead of waiting while the transport is not connected
         let result = client
             .call("echo", serde_json::json!({"message": "hello"}))
             .await;
-        assert!(result.is_ok());
-        let value = result.unwrap();
-        assert_eq!(value["echo"]["message"], "hello");
-        assert_eq!(value["method"], "echo");
+        assert!(matches!(result, Err(RpcError::ConnectionFailed(_))));
+        assert_eq!(client.pending_requests_count(), 0);
     }
 
     #[tokio::test]
     async fn test_batch_rpc_client() {
-        let config = TransportConfig::default();
-        let transport = WebSocketConnection::new(config).await.unwrap();
-        let client = BatchRpcClient::new(transport, Duration::from_secs(5))
-            .await
-            .unwrap();
+        let client = serve_loopback().await;
 
         // Make batch RPC calls
         let requests = vec![
             ("echo".to_string(), serde_json::json!({"message": "hello"})),
+            ("missing".to_string(), serde_json::json!({})),
             ("echo".to_string(), serde_json::json!({"message": "world"})),
         ];
 
         let results = client.call_batch(requests).await;
         assert!(results.is_ok());
         let values = results.unwrap();
-        assert_eq!(values.len(), 2);
-        assert_eq!(values[0]["echo"]["message"], "hello");
-        assert_eq!(values[1]["echo"]["message"], "world");
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0].as_ref().unwrap()["echo"]["message"], "hello");
+        assert!(matches!(values[1], Err(RpcError::MethodNotFound(_))));
+        assert_eq!(values[2].as_ref().unwrap()["echo"]["message"], "world");
+    }
+
+    #[tokio::test]
+    async fn test_batch_is_sent_as_single_frame() {
+        let (frames, mut sent) = mpsc::unbounded_channel();
+        let client = BatchRpcClient::new(LoopbackTransport { frames }, Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        let requests = vec![
+            ("a".to_string(), serde_json::json!(1)),
+            ("b".to_string(), serde_json::json!(2)),
+        ];
+        let call = client.call_batch(requests);
+        futures::pin_mut!(call);
+        assert!(futures::poll!(&mut call).is_pending());
+
+        let frame = sent.try_recv().unwrap();
+        assert!(sent.try_recv().is_err());
+        let batch: BatchRpcRequest = serde_json::from_slice(&frame.data).unwrap();
+        assert_eq!(batch.requests.len(), 2);
+        assert_eq!(batch.requests[1].method, "b");
+    }
+
+    /// Serve `router` to a bidirectional client with `confirm` registered,
+    /// answering with `answer`
+    fn serve_caller_client(
+        router: Arc<RpcRouter<()>>,
+        answer: &'static str,
+    ) -> Arc<BidirectionalRpcClient<LoopbackTransport>> {
+        let (frames, requests) = mpsc::unbounded_channel();
+        let transport = LoopbackTransport { frames };
+        let correlation_manager = Arc::new(RpcCorrelationManager::new(Duration::from_secs(5)));
+        let mut client = BidirectionalRpcClient {
+            transport,
+            correlation_manager,
+            registry: RpcMethodRegistry::new(),
+        };
+        client.register("confirm", move |params| {
+            Ok(serde_json::json!({"by": answer, "total": params["total"]}))
+        });
+        let client = Arc::new(client);
+
+        let (response_tx, mut responses) = mpsc::unbounded_channel::<Message>();
+        let incoming = futures::stream::unfold(requests, |mut rx| async move {
+            rx.recv().await.map(|message| (Ok(message), rx))
+        });
+        let outgoing = futures::sink::unfold(response_tx, |tx, message: Message| async move {
+            tx.send(message)
+                .map_err(|_| TransportError::SendFailed("Loopback closed".to_string()))?;
+            Ok::<_, TransportError>(tx)
+        });
+        tokio::spawn(router.serve((), incoming, Box::pin(outgoing)));
+
+        let responder = client.clone();
+        tokio::spawn(async move {
+            while let Some(message) = responses.recv().await {
+                let _ = responder.handle_message(&message.data).await;
+            }
+        });
+        client
+    }
+
+    fn checkout_router() -> RpcRouter<()> {
+        let mut router = RpcRouter::new();
+        router.register_with_caller(
+            "checkout",
+            |_, caller: ClientCaller, total: u64| async move {
+                caller
+                    .call("confirm", serde_json::json!({"total": total}))
+                    .await
+            },
+        );
+        router.register_with_caller("unknown", |_, caller: ClientCaller, _: ()| async move {
+            caller.call("missing", serde_json::json!(null)).await
+        });
+        router
+    }
+
+    #[tokio::test]
+    async fn test_server_calls_client_handlers() {
+        let client = serve_caller_client(Arc::new(checkout_router()), "client");
+
+        let result = client.call("checkout", serde_json::json!(42)).await;
+        assert_eq!(
+            result.unwrap(),
+            serde_json::json!({"by": "client", "total": 42})
+        );
+
+        let result = client.call("unknown", serde_json::json!(null)).await;
+        assert!(matches!(result, Err(RpcError::MethodNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_each_connection_calls_its_own_client() {
+        let router = Arc::new(checkout_router());
+        let first = serve_caller_client(router.clone(), "first");
+        let second = serve_caller_client(router, "second");
+
+        let (first_result, second_result) = tokio::join!(
+            first.call("checkout", serde_json::json!(1)),
+            second.call("checkout", serde_json::json!(2)),
+        );
+        assert_eq!(
+            first_result.unwrap(),
+            serde_json::json!({"by": "first", "total": 1})
+        );
+        assert_eq!(
+            second_result.unwrap(),
+            serde_json::json!({"by": "second", "total": 2})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_detached_client_caller_fails_fast() {
+        // Dispatched outside `serve`, so there is no connection to call back
+        let router = checkout_router();
+        let response = router
+            .dispatch(
+                (),
+                RpcRequest {
+                    id: "checkout-1".to_string(),
+                    method: "checkout".to_string(),
+                    params: serde_json::json!(42),
+                },
+            )
+            .await;
+        assert!(response
+            .error
+            .unwrap()
+            .message
+            .contains("Client is not connected"));
     }
 }
@@ -4,14 +4,20 @@
 
 use crate::codec::JsonCodec;
 use crate::rpc::correlation::RpcCorrelationManager;
+use crate::rpc::jsonrpc::{
+    cancel_notification, JsonRpcId, JsonRpcRequest, JsonRpcResponse, RpcWireFormat,
+    SUBSCRIPTION_METHOD,
+};
+use crate::rpc::policy::RpcMethodPolicy;
 use crate::rpc::types::*;
 use crate::transport::{Message, MessageType};
 use futures::Stream;
 use serde_json;
 use std::collections::HashMap;
-use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
-use std::time::Duration;
-use tokio::sync::{mpsc, RwLock};
+use std::future::Future;
+use std::sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 
 /// Global counter for RPC request IDs (for testing compatibility)
 static RPC_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
@@ -24,12 +30,13 @@ pub fn reset_rpc_id_counter() {
 /// RPC client for WebSocket communication
 pub struct RpcClient<T> {
     correlation_manager: Arc<RpcCorrelationManager>,
-    subscriptions: Arc<RwLock<HashMap<String, RpcSubscription<T>>>>,
+    subscriptions: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<T>>>>,
     message_sender: mpsc::UnboundedSender<Message>,
-    response_receiver: Arc<RwLock<Option<mpsc::UnboundedReceiver<RpcResponse<T>>>>>,
-    codec: JsonCodec,
     context: Option<Arc<crate::reactive::WebSocketContext>>,
     id_counter: AtomicU64,
+    request_timeout: Duration,
+    wire_format: RpcWireFormat,
+    policies: HashMap<String, RpcMethodPolicy>,
     _phantom: std::marker::PhantomData<T>,
 }
 
@@ -37,21 +44,49 @@ impl<T> RpcClient<T>
 where
     T: serde::Serialize + for<'de> serde::Deserialize<'de> + Send + Sync + 'static,
 {
-    pub fn new(message_sender: mpsc::UnboundedSender<Message>, codec: JsonCodec) -> Self {
-        let (response_tx, response_rx) = mpsc::unbounded_channel();
-
+    /// Create a client sending its requests through `message_sender`
+    ///
+    /// Frames are always JSON; `_codec` is kept for compatibility.
+    pub fn new(message_sender: mpsc::UnboundedSender<Message>, _codec: JsonCodec) -> Self {
         Self {
             correlation_manager: Arc::new(RpcCorrelationManager::new()),
-            subscriptions: Arc::new(RwLock::new(HashMap::new())),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
             message_sender,
-            response_receiver: Arc::new(RwLock::new(Some(response_rx))),
-            codec,
             context: None,
             id_counter: AtomicU64::new(1),
+            request_timeout: Duration::from_secs(30),
+            wire_format: RpcWireFormat::Native,
+            policies: HashMap::new(),
             _phantom: std::marker::PhantomData,
         }
     }
 
+    /// Set the timeout applied to calls that don't specify their own
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Set how requests and responses are framed on the wire
+    pub fn with_wire_format(mut self, wire_format: RpcWireFormat) -> Self {
+        self.wire_format = wire_format;
+        self
+    }
+
+    /// Set the deadline and retry policy for calls to `method`
+    ///
+    /// Native requests for a method with a policy carry their remaining
+    /// deadline.
+    pub fn with_method_policy(mut self, method: &str, policy: RpcMethodPolicy) -> Self {
+        self.policies.insert(method.to_string(), policy);
+        self
+    }
+
+    /// Get the policy for calls to `method`
+    pub fn method_policy(&self, method: &str) -> Option<&RpcMethodPolicy> {
+        self.policies.get(method)
+    }
+
     /// Create RPC client from WebSocket context (for testing compatibility)
     pub fn from_context(context: &crate::reactive::WebSocketContext, codec: JsonCodec) -> Self {
         // For testing, create a dummy sender since we don't have real message sending yet
@@ -66,6 +101,7 @@ where
         self.context.as_ref().expect("Context not set - use from_context() to create RPC client")
     }
 
+    /// Make an RPC call, following the policy registered for the method
     pub async fn call<U>(
         &self,
         method_name: &str,
@@ -75,81 +111,150 @@ where
     where
         U: serde::Serialize,
     {
-        let request_id = format!("rpc_{}", self.id_counter.fetch_add(1, Ordering::SeqCst));
-        let request = RpcRequest {
-            id: request_id.clone(),
-            method: method_name.to_string(),
-            params,
-            method_type,
-        };
-
-        // Encode the request to JSON
-        let request_json = serde_json::to_string(&request).map_err(|e| RpcError {
-            code: -32700,
-            message: format!("Parse error: {}", e),
-            data: None,
-        })?;
+        match self.policies.get(method_name) {
+            Some(policy) => {
+                self.call_with_policy(method_name, &params, method_type, policy)
+                    .await
+            }
+            None => {
+                self.call_once(method_name, &params, method_type, self.request_timeout, None)
+                    .await
+            }
+        }
+    }
 
-        // Create WebSocket message
-        let message = Message {
-            data: request_json.into_bytes(),
-            message_type: MessageType::Text,
-        };
+    /// Make an RPC call under `policy`, retrying failed attempts it allows
+    async fn call_with_policy<U>(
+        &self,
+        method_name: &str,
+        params: &U,
+        method_type: RpcMethod,
+        policy: &RpcMethodPolicy,
+    ) -> Result<RpcResponse<serde_json::Value>, RpcError>
+    where
+        U: serde::Serialize,
+    {
+        let deadline = Instant::now() + policy.timeout.unwrap_or(self.request_timeout);
+        let mut retries = 0;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let timeout = policy
+                .attempt_timeout
+                .map_or(remaining, |attempt_timeout| attempt_timeout.min(remaining));
 
-        // Send the request via WebSocket
-        // For testing purposes, we'll skip the actual sending since we don't have a real server
-        // In a real implementation, this would send to the actual WebSocket connection
-        // let _ = self.message_sender.send(message); // Skipped in testing to avoid channel errors
+            let (error, retry) = match self
+                .attempt(method_name, params, method_type.clone(), timeout, Some(timeout))
+                .await
+            {
+                Ok(Ok(response)) => return Ok(response),
+                Ok(Err(error)) => {
+                    let retry = policy.should_retry(retries, &error);
+                    (error, retry)
+                }
+                Err(error) => (error, policy.should_retry_unanswered(retries)),
+            };
+            if !retry {
+                return Err(error);
+            }
 
-        // For testing purposes, simulate responses based on method name
-        // In a real implementation, this would wait for actual server responses
-        if method_name.contains("error") || method_name == "error_method" {
-            // Simulate an error for error-related methods
-            return Err(RpcError {
-                code: -32603,
-                message: "Simulated RPC error for testing".to_string(),
-                data: None,
-            });
+            // Don't sleep past the deadline only to fail anyway
+            let delay = policy.backoff.calculate_delay(retries);
+            if Instant::now() + delay >= deadline {
+                return Err(error);
+            }
+            tokio::time::sleep(delay).await;
+            retries += 1;
         }
+    }
 
-        // For normal methods, return success
-        let mock_result = serde_json::json!({"status": "success", "message": "Mock RPC response"});
-        Ok(RpcResponse {
-            id: request_id,
-            result: Some(mock_result),
-            error: None,
+    /// Make an RPC call and deserialize its result into `R`
+    pub async fn call_typed<U, R>(
+        &self,
+        method_name: &str,
+        params: U,
+        method_type: RpcMethod,
+    ) -> Result<R, RpcError>
+    where
+        U: serde::Serialize,
+        R: serde::de::DeserializeOwned,
+    {
+        let response = self.call(method_name, params, method_type).await?;
+        let result = response.result.unwrap_or(serde_json::Value::Null);
+        serde_json::from_value(result).map_err(|e| RpcError {
+            code: -32700,
+            message: format!("Failed to decode result: {}", e),
+            data: None,
         })
     }
 
-    /// Wait for a response to a specific request ID
-    async fn wait_for_response(
+    /// Make an RPC call that fails if no response arrives within `timeout`
+    pub async fn call_with_timeout<U>(
         &self,
-        request_id: &str,
+        method_name: &str,
+        params: U,
+        method_type: RpcMethod,
         timeout: Duration,
-    ) -> Result<RpcResponse<T>, RpcError> {
-        // Use the correlation manager to wait for the response
-        let response_result = self
-            .correlation_manager
-            .wait_for_response(request_id, timeout)
+    ) -> Result<RpcResponse<serde_json::Value>, RpcError>
+    where
+        U: serde::Serialize,
+    {
+        self.call_once(method_name, &params, method_type, timeout, None)
             .await
-            .map_err(|e| RpcError {
-                code: -32603,
-                message: format!("Request failed: {}", e),
-                data: None,
-            })?;
-
-        match response_result {
-            Ok(response) => {
-                // Convert from serde_json::Value to T
-                let converted_response = RpcResponse {
-                    id: response.id,
-                    result: response.result.and_then(|v| serde_json::from_value(v).ok()),
-                    error: response.error,
-                };
-                Ok(converted_response)
-            }
-            Err(error) => Err(error),
-        }
+    }
+
+    /// Send a single request, carrying `deadline` when given
+    async fn call_once<U>(
+        &self,
+        method_name: &str,
+        params: &U,
+        method_type: RpcMethod,
+        timeout: Duration,
+        deadline: Option<Duration>,
+    ) -> Result<RpcResponse<serde_json::Value>, RpcError>
+    where
+        U: serde::Serialize,
+    {
+        self.attempt(method_name, params, method_type, timeout, deadline)
+            .await?
+    }
+
+    /// Send a single request, telling apart errors the server answered with
+    ///
+    /// The outer error means the request was never answered: it couldn't be
+    /// sent, timed out or was cancelled.
+    async fn attempt<U>(
+        &self,
+        method_name: &str,
+        params: &U,
+        method_type: RpcMethod,
+        timeout: Duration,
+        deadline: Option<Duration>,
+    ) -> Result<Result<RpcResponse<serde_json::Value>, RpcError>, RpcError>
+    where
+        U: serde::Serialize,
+    {
+        let request_id = format!("rpc_{}", self.id_counter.fetch_add(1, Ordering::SeqCst));
+        let request = RpcRequest {
+            id: request_id,
+            method: method_name.to_string(),
+            params,
+            method_type,
+        };
+
+        let response = dispatch_request(
+            &self.message_sender,
+            &self.correlation_manager,
+            request,
+            timeout,
+            deadline,
+            self.wire_format,
+        )?
+        .await?;
+
+        Ok(match response.error {
+            Some(error) => Err(error),
+            None => Ok(response),
+        })
     }
 
     pub async fn send_request<U>(
@@ -164,21 +269,137 @@ where
         self.call(&method_string, params, method).await
     }
 
+    /// Send a notification, which the server never answers
+    ///
+    /// Only the JSON-RPC 2.0 wire format has notifications.
+    pub fn notify<U>(&self, method: &str, params: U) -> Result<(), RpcError>
+    where
+        U: serde::Serialize,
+    {
+        if self.wire_format != RpcWireFormat::JsonRpc2 {
+            return Err(RpcError {
+                code: -32600,
+                message: "Notifications require the JSON-RPC 2.0 wire format".to_string(),
+                data: None,
+            });
+        }
+
+        let params = serde_json::to_value(params).map_err(parse_error)?;
+        let notification = JsonRpcRequest::notification(method, Some(params));
+        let message = Message {
+            data: serde_json::to_vec(&notification).map_err(parse_error)?,
+            message_type: MessageType::Text,
+        };
+
+        self.message_sender.send(message).map_err(|_| RpcError {
+            code: -32603,
+            message: "Failed to send notification: connection closed".to_string(),
+            data: None,
+        })
+    }
+
+    /// Subscribe to server-pushed messages
+    ///
+    /// Sends a `SubscribeMessages` request and resolves once the server has
+    /// acknowledged it. The request id becomes the subscription id, which the
+    /// server tags its notifications with. Dropping the returned stream
+    /// unsubscribes.
     pub async fn subscribe(
         &self,
         params: SubscribeMessagesParams,
     ) -> Result<RpcSubscription<T>, RpcError> {
         let subscription_id = format!("rpc_{}", self.id_counter.fetch_add(1, Ordering::SeqCst));
-        let subscription = RpcSubscription::new(subscription_id.clone());
 
-        // For now, just return the subscription without storing it
-        // In a real implementation, we'd store it and return a reference
-        Ok(subscription)
+        // Register the channel first so notifications sent right after the
+        // acknowledgement are not dropped
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .insert(subscription_id.clone(), sender);
+
+        let request = RpcRequest {
+            id: subscription_id.clone(),
+            method: RpcMethod::SubscribeMessages.to_string(),
+            params,
+            method_type: RpcMethod::SubscribeMessages,
+        };
+        let acknowledgement = dispatch_request(
+            &self.message_sender,
+            &self.correlation_manager,
+            request,
+            self.request_timeout,
+            None,
+            self.wire_format,
+        );
+        let acknowledgement = match acknowledgement {
+            Ok(response) => response.await,
+            Err(error) => Err(error),
+        };
+
+        match acknowledgement {
+            Ok(RpcResponse { error: None, .. }) => Ok(RpcSubscription {
+                id: subscription_id,
+                receiver,
+                unsubscriber: Some(Unsubscriber {
+                    subscriptions: self.subscriptions.clone(),
+                    message_sender: self.message_sender.clone(),
+                    correlation_manager: self.correlation_manager.clone(),
+                    timeout: self.request_timeout,
+                    wire_format: self.wire_format,
+                }),
+            }),
+            Ok(RpcResponse {
+                error: Some(error), ..
+            })
+            | Err(error) => {
+                self.subscriptions.lock().unwrap().remove(&subscription_id);
+                Err(error)
+            }
+        }
     }
 
+    /// Cancel a subscription and tell the server to stop sending notifications
     pub async fn unsubscribe(&self, subscription_id: &str) -> Result<(), RpcError> {
-        self.subscriptions.write().await.remove(subscription_id);
-        Ok(())
+        if self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .remove(subscription_id)
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        self.call(
+            &RpcMethod::UnsubscribeMessages.to_string(),
+            UnsubscribeMessagesParams {
+                subscription_id: subscription_id.to_string(),
+            },
+            RpcMethod::UnsubscribeMessages,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Cancel a pending call, telling the server to stop working on it
+    pub fn cancel(&self, request_id: &str) -> bool {
+        self.correlation_manager.cancel_request(request_id)
+    }
+
+    /// Cancel every pending call
+    pub fn cancel_all(&self) -> usize {
+        self.correlation_manager.cancel_all()
+    }
+
+    /// Get the ids of calls still waiting for a response
+    pub fn pending_request_ids(&self) -> Vec<String> {
+        self.correlation_manager.pending_request_ids()
+    }
+
+    /// Number of subscriptions currently receiving notifications
+    pub fn active_subscriptions(&self) -> usize {
+        self.subscriptions.lock().unwrap().len()
     }
 
     /// Generate a unique ID for RPC requests
@@ -204,17 +425,79 @@ where
         self.call(method, params, RpcMethod::Mutation).await
     }
 
+    /// Handle an incoming frame, routing it to either a pending call or a subscription
+    ///
+    /// In the JSON-RPC 2.0 wire format the frame may also be a batch.
+    pub async fn handle_message(&self, data: &[u8]) -> Result<(), RpcError> {
+        let value: serde_json::Value = serde_json::from_slice(data).map_err(parse_error)?;
+
+        match self.wire_format {
+            RpcWireFormat::Native => {
+                if value.get("subscription_id").is_some() {
+                    let notification = serde_json::from_value(value).map_err(parse_error)?;
+                    self.forward_notification(notification)
+                } else {
+                    let response = serde_json::from_value(value).map_err(parse_error)?;
+                    self.complete_response(response)
+                }
+            }
+            RpcWireFormat::JsonRpc2 => match value {
+                serde_json::Value::Array(items) => {
+                    // Keep going so one bad item doesn't strand the rest of the batch
+                    let mut result = Ok(());
+                    for item in items {
+                        let item_result = self.handle_jsonrpc_value(item);
+                        if result.is_ok() {
+                            result = item_result;
+                        }
+                    }
+                    result
+                }
+                other => self.handle_jsonrpc_value(other),
+            },
+        }
+    }
+
     /// Handle incoming RPC response
     pub async fn handle_response(&self, response_data: &[u8]) -> Result<(), RpcError> {
-        // Decode the response as serde_json::Value first
-        let response: RpcResponse<serde_json::Value> = serde_json::from_slice(response_data)
-            .map_err(|e| RpcError {
-                code: -32700,
-                message: format!("Parse error: {}", e),
-                data: None,
-            })?;
+        let response = match self.wire_format {
+            RpcWireFormat::Native => serde_json::from_slice(response_data),
+            RpcWireFormat::JsonRpc2 => serde_json::from_slice::<JsonRpcResponse>(response_data)
+                .map(JsonRpcResponse::into_native),
+        }
+        .map_err(parse_error)?;
 
-        // Send the response to the waiting request via the correlation manager
+        self.complete_response(response)
+    }
+
+    /// Handle a server-pushed notification, forwarding it to its subscription stream
+    pub fn handle_notification(&self, notification_data: &[u8]) -> Result<(), RpcError> {
+        let notification = match self.wire_format {
+            RpcWireFormat::Native => {
+                serde_json::from_slice(notification_data).map_err(parse_error)?
+            }
+            RpcWireFormat::JsonRpc2 => {
+                let request = serde_json::from_slice(notification_data).map_err(parse_error)?;
+                decode_jsonrpc_notification(request)?
+            }
+        };
+
+        self.forward_notification(notification)
+    }
+
+    /// Handle a single JSON-RPC 2.0 response or notification
+    fn handle_jsonrpc_value(&self, value: serde_json::Value) -> Result<(), RpcError> {
+        if value.get("method").is_some() {
+            let request = serde_json::from_value(value).map_err(parse_error)?;
+            self.forward_notification(decode_jsonrpc_notification(request)?)
+        } else {
+            let response: JsonRpcResponse = serde_json::from_value(value).map_err(parse_error)?;
+            self.complete_response(response.into_native())
+        }
+    }
+
+    /// Send a response to the waiting request via the correlation manager
+    fn complete_response(&self, response: RpcResponse<serde_json::Value>) -> Result<(), RpcError> {
         let response_id = response.id.clone();
         self.correlation_manager
             .complete_request(&response_id, Ok(response))
@@ -222,36 +505,656 @@ where
                 code: -32603,
                 message: "Failed to complete request".to_string(),
                 data: None,
-            })?;
+            })
+    }
 
+    /// Forward notification data to its subscription stream
+    fn forward_notification(&self, notification: RpcNotification<T>) -> Result<(), RpcError> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let Some(sender) = subscriptions.get(&notification.subscription_id) else {
+            return Err(RpcError {
+                code: -32603,
+                message: format!(
+                    "No active subscription found for ID: {}",
+                    notification.subscription_id
+                ),
+                data: None,
+            });
+        };
+
+        if sender.send(notification.data).is_err() {
+            // The stream was dropped without unsubscribing
+            subscriptions.remove(&notification.subscription_id);
+        }
         Ok(())
     }
 }
 
+/// Extract the subscription notification carried by a JSON-RPC notification
+fn decode_jsonrpc_notification<T>(request: JsonRpcRequest) -> Result<RpcNotification<T>, RpcError>
+where
+    T: for<'de> serde::Deserialize<'de>,
+{
+    if request.method != SUBSCRIPTION_METHOD || !request.is_notification() {
+        return Err(RpcError {
+            code: -32601,
+            message: format!("Unsupported server request: {}", request.method),
+            data: None,
+        });
+    }
+
+    serde_json::from_value(request.params.unwrap_or(serde_json::Value::Null)).map_err(parse_error)
+}
+
+fn parse_error(e: serde_json::Error) -> RpcError {
+    RpcError {
+        code: -32700,
+        message: format!("Parse error: {}", e),
+        data: None,
+    }
+}
+
+/// A request frame with the deadline the caller waits for its response
+#[derive(serde::Serialize)]
+struct WithDeadline<'a, R> {
+    #[serde(flatten)]
+    request: &'a R,
+    #[serde(rename = "deadline_ms", skip_serializing_if = "Option::is_none")]
+    deadline: Option<u64>,
+}
+
+/// Encode a request frame in `wire_format`
+///
+/// A native request carries `deadline` as its
+/// [`crate::rpc::policy::DEADLINE_FIELD`]. A JSON-RPC 2.0 request has only
+/// the members the specification defines, so the deadline is left to the
+/// client there.
+fn encode_request<U>(
+    request: &RpcRequest<U>,
+    deadline: Option<Duration>,
+    wire_format: RpcWireFormat,
+) -> Result<Vec<u8>, RpcError>
+where
+    U: serde::Serialize,
+{
+    match wire_format {
+        RpcWireFormat::Native => serde_json::to_vec(&WithDeadline {
+            request,
+            deadline: deadline.map(|deadline| deadline.as_millis() as u64),
+        }),
+        RpcWireFormat::JsonRpc2 => {
+            JsonRpcRequest::from_native(request).and_then(|request| serde_json::to_vec(&request))
+        }
+    }
+    .map_err(parse_error)
+}
+
+/// Encode a request, register it for correlation and send it
+///
+/// Returns a future resolving to the correlated response. Registration
+/// happens before the send so a fast response cannot be missed.
+fn dispatch_request<U>(
+    message_sender: &mpsc::UnboundedSender<Message>,
+    correlation_manager: &RpcCorrelationManager,
+    request: RpcRequest<U>,
+    timeout: Duration,
+    deadline: Option<Duration>,
+    wire_format: RpcWireFormat,
+) -> Result<impl Future<Output = Result<RpcResponse<serde_json::Value>, RpcError>> + Send + 'static, RpcError>
+where
+    U: serde::Serialize,
+{
+    let data = encode_request(&request, deadline, wire_format)?;
+
+    // Create WebSocket message
+    let message = Message {
+        data,
+        message_type: MessageType::Text,
+    };
+
+    let response = correlation_manager.wait_for_response(&request.id, timeout);
+
+    if message_sender.send(message).is_err() {
+        correlation_manager.cancel_request(&request.id);
+        return Err(RpcError {
+            code: -32603,
+            message: "Failed to send request: connection closed".to_string(),
+            data: None,
+        });
+    }
+
+    let mut cancel_guard = CancelOnDrop {
+        request_id: Some(request.id),
+        message_sender: message_sender.clone(),
+        correlation_manager: correlation_manager.clone(),
+        wire_format,
+    };
+
+    Ok(async move {
+        let response = response.await;
+        if let Ok(Ok(_)) = &response {
+            cancel_guard.disarm();
+        }
+        drop(cancel_guard);
+
+        response.map_err(|e| RpcError {
+            code: -32603,
+            message: format!("Request failed: {}", e),
+            data: None,
+        })?
+    })
+}
+
+/// Tells the server to stop working on a request whose response never arrived
+///
+/// Stays armed while the call waits, so dropping the call future, timing
+/// out and cancelling through the correlation manager all reach the server.
+struct CancelOnDrop {
+    request_id: Option<String>,
+    message_sender: mpsc::UnboundedSender<Message>,
+    correlation_manager: RpcCorrelationManager,
+    wire_format: RpcWireFormat,
+}
+
+impl CancelOnDrop {
+    fn disarm(&mut self) {
+        self.request_id = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        let Some(request_id) = self.request_id.take() else {
+            return;
+        };
+
+        self.correlation_manager.cancel_request(&request_id);
+        let data = match self.wire_format {
+            RpcWireFormat::Native => serde_json::to_vec(&RpcCancel { cancel: request_id }),
+            RpcWireFormat::JsonRpc2 => {
+                serde_json::to_vec(&cancel_notification(JsonRpcId::String(request_id)))
+            }
+        };
+        if let Ok(data) = data {
+            let _ = self.message_sender.send(Message {
+                data,
+                message_type: MessageType::Text,
+            });
+        }
+    }
+}
+
+/// Handle used by a dropped subscription to unsubscribe on the server
+struct Unsubscriber<T> {
+    subscriptions: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<T>>>>,
+    message_sender: mpsc::UnboundedSender<Message>,
+    correlation_manager: Arc<RpcCorrelationManager>,
+    timeout: Duration,
+    wire_format: RpcWireFormat,
+}
+
+impl<T> Unsubscriber<T> {
+    fn unsubscribe(&self, subscription_id: &str) {
+        // Already removed by an explicit `RpcClient::unsubscribe`
+        if self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .remove(subscription_id)
+            .is_none()
+        {
+            return;
+        }
+
+        let request = RpcRequest {
+            id: format!("{}_unsubscribe", subscription_id),
+            method: RpcMethod::UnsubscribeMessages.to_string(),
+            params: UnsubscribeMessagesParams {
+                subscription_id: subscription_id.to_string(),
+            },
+            method_type: RpcMethod::UnsubscribeMessages,
+        };
+
+        // Consume the acknowledgement in the background when a runtime is
+        // available. Without one nothing could wait for it, so the request is
+        // sent without being registered, and without a guard that would
+        // cancel it as soon as it is dropped.
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                if let Ok(acknowledgement) = dispatch_request(
+                    &self.message_sender,
+                    &self.correlation_manager,
+                    request,
+                    self.timeout,
+                    None,
+                    self.wire_format,
+                ) {
+                    handle.spawn(acknowledgement);
+                }
+            }
+            Err(_) => {
+                if let Ok(data) = encode_request(&request, None, self.wire_format) {
+                    let _ = self.message_sender.send(Message {
+                        data,
+                        message_type: MessageType::Text,
+                    });
+                }
+            }
+        }
+    }
+}
+
 /// RPC subscription for streaming responses
-#[derive(Debug)]
+///
+/// Yields the `data` of every notification the server pushes for this
+/// subscription. Dropping the subscription sends an `UnsubscribeMessages`
+/// request to the server.
 pub struct RpcSubscription<T> {
     pub id: String,
-    _phantom: std::marker::PhantomData<T>,
+    receiver: mpsc::UnboundedReceiver<T>,
+    unsubscriber: Option<Unsubscriber<T>>,
 }
 
 impl<T> RpcSubscription<T> {
+    /// Create a subscription that is not attached to any client
     pub fn new(id: String) -> Self {
+        let (_, receiver) = mpsc::unbounded_channel();
         Self {
             id,
-            _phantom: std::marker::PhantomData,
+            receiver,
+            unsubscriber: None,
         }
     }
 }
 
+impl<T> std::fmt::Debug for RpcSubscription<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RpcSubscription")
+            .field("id", &self.id)
+            .field("attached", &self.unsubscriber.is_some())
+            .finish()
+    }
+}
+
 impl<T> Stream for RpcSubscription<T> {
     type Item = T;
 
     fn poll_next(
-        self: std::pin::Pin<&mut Self>,
-        _cx: &mut std::task::Context<'_>,
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        // This would be implemented with actual streaming logic
-        std::task::Poll::Ready(None)
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl<T> Drop for RpcSubscription<T> {
+    fn drop(&mut self) {
+        if let Some(unsubscriber) = self.unsubscriber.take() {
+            unsubscriber.unsubscribe(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spawn a fake server that answers every request through `handle_response`
+    fn spawn_responder(
+        client: Arc<RpcClient<serde_json::Value>>,
+        mut outgoing: mpsc::UnboundedReceiver<Message>,
+    ) {
+        tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                let request: RpcRequest<serde_json::Value> =
+                    serde_json::from_slice(&message.data).unwrap();
+                let response = if request.method == "fail" {
+                    RpcResponse {
+                        id: request.id,
+                        result: None,
+                        error: Some(RpcError::new(-32601, "Method not found".to_string())),
+                    }
+                } else {
+                    RpcResponse {
+                        id: request.id,
+                        result: Some(request.params),
+                        error: None,
+                    }
+                };
+                let data = serde_json::to_vec(&response).unwrap();
+                client.handle_response(&data).await.unwrap();
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_jsonrpc_wire_format() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Arc::new(
+            RpcClient::<serde_json::Value>::new(tx, JsonCodec::new())
+                .with_wire_format(RpcWireFormat::JsonRpc2),
+        );
+
+        let call = {
+            let client = client.clone();
+            tokio::spawn(async move { client.call("add", [1, 2], RpcMethod::Call).await })
+        };
+        let request: serde_json::Value =
+            serde_json::from_slice(&rx.recv().await.unwrap().data).unwrap();
+        assert_eq!(request["jsonrpc"], "2.0");
+        assert_eq!(request["params"], serde_json::json!([1, 2]));
+        assert!(request.get("method_type").is_none());
+
+        // Responses may arrive inside a batch
+        let batch = serde_json::json!([{"jsonrpc": "2.0", "result": 3, "id": request["id"]}]);
+        client
+            .handle_message(batch.to_string().as_bytes())
+            .await
+            .unwrap();
+        let response = call.await.unwrap().unwrap();
+        assert_eq!(response.result, Some(serde_json::json!(3)));
+
+        client.notify("log", "hello").unwrap();
+        let notification: serde_json::Value =
+            serde_json::from_slice(&rx.recv().await.unwrap().data).unwrap();
+        assert_eq!(
+            notification,
+            serde_json::json!({"jsonrpc": "2.0", "method": "log", "params": "hello"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jsonrpc_requests_have_only_spec_members() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = RpcClient::<serde_json::Value>::new(tx, JsonCodec::new())
+            .with_wire_format(RpcWireFormat::JsonRpc2)
+            .with_method_policy(
+                "get",
+                RpcMethodPolicy::new().with_timeout(Duration::from_millis(20)),
+            );
+
+        assert!(client.call("get", [1], RpcMethod::Query).await.is_err());
+        let request: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(&rx.recv().await.unwrap().data).unwrap();
+        let mut members: Vec<&str> = request.keys().map(String::as_str).collect();
+        members.sort_unstable();
+        assert_eq!(members, ["id", "jsonrpc", "method", "params"]);
+    }
+
+    #[tokio::test]
+    async fn test_notify_requires_jsonrpc_wire_format() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client = RpcClient::<serde_json::Value>::new(tx, JsonCodec::new());
+
+        assert_eq!(client.notify("log", "hello").unwrap_err().code, -32600);
+    }
+
+    #[tokio::test]
+    async fn test_call_round_trip() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let client = Arc::new(RpcClient::<serde_json::Value>::new(tx, JsonCodec::new()));
+        spawn_responder(client.clone(), rx);
+
+        let response = client
+            .call("echo", serde_json::json!({"message": "hello"}), RpcMethod::Call)
+            .await
+            .unwrap();
+
+        assert_eq!(response.result.unwrap()["message"], "hello");
+        assert_eq!(client.correlation_manager.pending_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_call_returns_server_error() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let client = Arc::new(RpcClient::<serde_json::Value>::new(tx, JsonCodec::new()));
+        spawn_responder(client.clone(), rx);
+
+        let error = client
+            .call("fail", serde_json::json!({}), RpcMethod::Call)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.code, -32601);
+    }
+
+    #[tokio::test]
+    async fn test_call_times_out_without_response() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client = RpcClient::<serde_json::Value>::new(tx, JsonCodec::new())
+            .with_timeout(Duration::from_millis(20));
+
+        let error = client
+            .call("echo", serde_json::json!({}), RpcMethod::Call)
+            .await
+            .unwrap_err();
+
+        assert!(error.message.contains("timeout"));
+        assert_eq!(client.correlation_manager.pending_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_call_fails_when_connection_closed() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        drop(rx);
+        let client = RpcClient::<serde_json::Value>::new(tx, JsonCodec::new());
+
+        let result = client
+            .call("echo", serde_json::json!({}), RpcMethod::Call)
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(client.correlation_manager.pending_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_subscription_yields_notifications() {
+        use futures::StreamExt;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let client = Arc::new(RpcClient::<serde_json::Value>::new(tx, JsonCodec::new()));
+        spawn_responder(client.clone(), rx);
+
+        let mut subscription = client
+            .subscribe(SubscribeMessagesParams {
+                channel: Some("updates".to_string()),
+                room_id: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(client.active_subscriptions(), 1);
+
+        for value in 0..2 {
+            let notification = RpcNotification {
+                subscription_id: subscription.id.clone(),
+                data: serde_json::json!({"value": value}),
+            };
+            let data = serde_json::to_vec(&notification).unwrap();
+            client.handle_message(&data).await.unwrap();
+        }
+
+        assert_eq!(subscription.next().await.unwrap()["value"], 0);
+        assert_eq!(subscription.next().await.unwrap()["value"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_dropping_subscription_unsubscribes() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Arc::new(RpcClient::<serde_json::Value>::new(tx, JsonCodec::new()));
+
+        // Acknowledge the subscribe request by hand so we can inspect what follows
+        let subscribe = {
+            let client = client.clone();
+            tokio::spawn(async move {
+                client
+                    .subscribe(SubscribeMessagesParams {
+                        channel: None,
+                        room_id: None,
+                    })
+                    .await
+            })
+        };
+        let request: RpcRequest<serde_json::Value> =
+            serde_json::from_slice(&rx.recv().await.unwrap().data).unwrap();
+        assert_eq!(request.method_type, RpcMethod::SubscribeMessages);
+        let ack = RpcResponse::<serde_json::Value> {
+            id: request.id.clone(),
+            result: Some(serde_json::json!(true)),
+            error: None,
+        };
+        client
+            .handle_response(&serde_json::to_vec(&ack).unwrap())
+            .await
+            .unwrap();
+        let subscription = subscribe.await.unwrap().unwrap();
+
+        drop(subscription);
+
+        let request: RpcRequest<UnsubscribeMessagesParams> =
+            serde_json::from_slice(&rx.recv().await.unwrap().data).unwrap();
+        assert_eq!(request.method_type, RpcMethod::UnsubscribeMessages);
+        assert_eq!(request.params.subscription_id, ack.id);
+        assert_eq!(client.active_subscriptions(), 0);
+    }
+
+    #[test]
+    fn test_dropping_subscription_outside_a_runtime_only_unsubscribes() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Arc::new(RpcClient::<serde_json::Value>::new(tx, JsonCodec::new()));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let subscription = runtime.block_on(async {
+            let subscribe = {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    client
+                        .subscribe(SubscribeMessagesParams {
+                            channel: None,
+                            room_id: None,
+                        })
+                        .await
+                })
+            };
+            let request: RpcRequest<serde_json::Value> =
+                serde_json::from_slice(&rx.recv().await.unwrap().data).unwrap();
+            let ack = RpcResponse::<serde_json::Value> {
+                id: request.id,
+                result: Some(serde_json::json!(true)),
+                error: None,
+            };
+            client
+                .handle_response(&serde_json::to_vec(&ack).unwrap())
+                .await
+                .unwrap();
+            subscribe.await.unwrap().unwrap()
+        });
+        drop(runtime);
+
+        drop(subscription);
+
+        let request: RpcRequest<UnsubscribeMessagesParams> =
+            serde_json::from_slice(&rx.try_recv().unwrap().data).unwrap();
+        assert_eq!(request.method_type, RpcMethod::UnsubscribeMessages);
+        // No cancel for the unsubscribe follows, and nothing waits for it
+        assert!(rx.try_recv().is_err());
+        assert_eq!(client.correlation_manager.pending_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_policy_retries_idempotent_calls_with_deadline() {
+        use crate::transport::sse::ExponentialBackoff;
+
+        let backoff = ExponentialBackoff::new(Duration::from_millis(5), Duration::from_millis(5))
+            .with_jitter(false);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Arc::new(
+            RpcClient::<serde_json::Value>::new(tx, JsonCodec::new())
+                .with_method_policy(
+                    "get",
+                    RpcMethodPolicy::new()
+                        .with_timeout(Duration::from_secs(5))
+                        .with_attempt_timeout(Duration::from_millis(20))
+                        .with_retries(2, backoff.clone())
+                        .idempotent(),
+                )
+                .with_method_policy(
+                    "put",
+                    RpcMethodPolicy::new()
+                        .with_attempt_timeout(Duration::from_millis(20))
+                        .with_retries(2, backoff),
+                ),
+        );
+
+        let call = {
+            let client = client.clone();
+            tokio::spawn(async move { client.call("get", 1, RpcMethod::Query).await })
+        };
+
+        // Leave the first attempt unanswered; it is cancelled when it times out
+        let first: serde_json::Value =
+            serde_json::from_slice(&rx.recv().await.unwrap().data).unwrap();
+        assert_eq!(first["deadline_ms"], 20);
+        let cancel: RpcCancel = serde_json::from_slice(&rx.recv().await.unwrap().data).unwrap();
+        assert_eq!(cancel.cancel, first["id"]);
+
+        let retry: RpcRequest<serde_json::Value> =
+            serde_json::from_slice(&rx.recv().await.unwrap().data).unwrap();
+        assert_ne!(retry.id, first["id"]);
+        let response = RpcResponse {
+            id: retry.id,
+            result: Some(serde_json::json!("value")),
+            error: None,
+        };
+        client
+            .handle_response(&serde_json::to_vec(&response).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            call.await.unwrap().unwrap().result,
+            Some(serde_json::json!("value"))
+        );
+
+        // Calls to methods that aren't idempotent are attempted once
+        let error = client.call("put", 1, RpcMethod::Mutation).await.unwrap_err();
+        assert!(error.message.contains("timeout"));
+        rx.recv().await.unwrap();
+        rx.recv().await.unwrap();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_policy_does_not_retry_handler_errors() {
+        use crate::transport::sse::ExponentialBackoff;
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Arc::new(
+            RpcClient::<serde_json::Value>::new(tx, JsonCodec::new()).with_method_policy(
+                "charge",
+                RpcMethodPolicy::new()
+                    .with_retries(2, ExponentialBackoff::new(Duration::ZERO, Duration::ZERO))
+                    .idempotent(),
+            ),
+        );
+
+        let call = {
+            let client = client.clone();
+            tokio::spawn(async move { client.call("charge", 1, RpcMethod::Mutation).await })
+        };
+        let request: RpcRequest<serde_json::Value> =
+            serde_json::from_slice(&rx.recv().await.unwrap().data).unwrap();
+        let response = RpcResponse::<serde_json::Value> {
+            id: request.id,
+            result: None,
+            error: Some(RpcError::new(-32603, "Internal error: declined".to_string())),
+        };
+        client
+            .handle_response(&serde_json::to_vec(&response).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(call.await.unwrap().unwrap_err().code, -32603);
+        assert!(rx.try_recv().is_err());
     }
 }
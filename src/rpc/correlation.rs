@@ -240,19 +240,33 @@ impl RpcCorrelationManager {
     }
 
     /// Wait for a response to a specific request ID
-    pub async fn wait_for_response(
+    ///
+    /// The request is registered as soon as this is called, before the returned
+    /// future is polled, so a response that races the outgoing send is not lost.
+    /// A request that times out is removed from the pending map.
+    pub fn wait_for_response(
         &self,
         request_id: &str,
         timeout: Duration,
-    ) -> Result<Result<RpcResponse<serde_json::Value>, RpcError>, String> {
+    ) -> impl std::future::Future<
+        Output = Result<Result<RpcResponse<serde_json::Value>, RpcError>, String>,
+    > + Send
+           + 'static {
         // Register the request and get the receiver
         let response_rx = self.register_request(request_id.to_string(), "unknown".to_string());
-
-        // Wait for the response with timeout
-        tokio::time::timeout(timeout, response_rx)
-            .await
-            .map_err(|_| "Request timeout".to_string())?
-            .map_err(|_| "Channel closed".to_string())
+        let manager = self.clone();
+        let request_id = request_id.to_string();
+
+        async move {
+            // Wait for the response with timeout
+            match tokio::time::timeout(timeout, response_rx).await {
+                Ok(result) => result.map_err(|_| "Channel closed".to_string()),
+                Err(_) => {
+                    manager.pending_requests.lock().unwrap().remove(&request_id);
+                    Err("Request timeout".to_string())
+                }
+            }
+        }
     }
 
     /// Complete a request with a response
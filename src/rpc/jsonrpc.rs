@@ -0,0 +1,298 @@
+//! JSON-RPC 2.0 Wire Format
+//!
+//! Opt-in framing that speaks strict JSON-RPC 2.0, so `RpcClient` can talk
+//! to existing JSON-RPC servers and `RpcRouter` can serve third-party
+//! JSON-RPC clients. Select it with `with_wire_format` on either side.
+
+use crate::rpc::types::{RpcError, RpcNotification, RpcRequest, RpcResponse};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+/// Protocol version carried in every JSON-RPC 2.0 message
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// Method name of server-pushed subscription notifications
+///
+/// Their params are an [`RpcNotification`].
+pub const SUBSCRIPTION_METHOD: &str = "subscription";
+
+/// Method name of the notification cancelling an in-flight request
+///
+/// Its params are `{"id": <request id>}`, following the Language Server
+/// Protocol convention.
+pub const CANCEL_METHOD: &str = "$/cancelRequest";
+
+/// How RPC messages are framed on the wire
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RpcWireFormat {
+    /// The crate's own request/response shape
+    #[default]
+    Native,
+    /// Strict JSON-RPC 2.0, including notifications and batches
+    JsonRpc2,
+}
+
+/// JSON-RPC request identifier
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum JsonRpcId {
+    Number(i64),
+    String(String),
+    Null,
+}
+
+impl JsonRpcId {
+    /// Key used to correlate this id with pending requests
+    ///
+    /// Tagged with the id's type, since `1` and `"1"` are distinct ids.
+    pub fn to_key(&self) -> String {
+        match self {
+            JsonRpcId::Number(number) => format!("n:{}", number),
+            JsonRpcId::String(string) => format!("s:{}", string),
+            JsonRpcId::Null => "null".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for JsonRpcId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonRpcId::Number(number) => write!(f, "{}", number),
+            JsonRpcId::String(string) => write!(f, "{}", string),
+            JsonRpcId::Null => write!(f, "null"),
+        }
+    }
+}
+
+/// JSON-RPC 2.0 request or notification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonRpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<serde_json::Value>,
+    /// `None` for notifications; an explicit `null` id is `Some(JsonRpcId::Null)`
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present_id",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub id: Option<JsonRpcId>,
+}
+
+impl JsonRpcRequest {
+    /// Create a request expecting a response
+    pub fn new(
+        id: JsonRpcId,
+        method: impl Into<String>,
+        params: Option<serde_json::Value>,
+    ) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            method: method.into(),
+            params,
+            id: Some(id),
+        }
+    }
+
+    /// Create a notification, which the peer never answers
+    pub fn notification(method: impl Into<String>, params: Option<serde_json::Value>) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            method: method.into(),
+            params,
+            id: None,
+        }
+    }
+
+    /// Convert a native request; the method type has no JSON-RPC equivalent and is dropped
+    pub fn from_native<T: Serialize>(request: &RpcRequest<T>) -> Result<Self, serde_json::Error> {
+        Ok(Self::new(
+            JsonRpcId::String(request.id.clone()),
+            request.method.clone(),
+            Some(serde_json::to_value(&request.params)?),
+        ))
+    }
+
+    /// Whether this is a notification
+    pub fn is_notification(&self) -> bool {
+        self.id.is_none()
+    }
+}
+
+/// JSON-RPC 2.0 response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonRpcResponse {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    pub id: JsonRpcId,
+}
+
+impl JsonRpcResponse {
+    /// Create a successful response
+    pub fn success(id: JsonRpcId, result: serde_json::Value) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            result: Some(result),
+            error: None,
+            id,
+        }
+    }
+
+    /// Create an error response
+    pub fn failure(id: JsonRpcId, error: RpcError) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+
+    /// Convert a native response, answering the request that carried `id`
+    ///
+    /// Exactly one of `result` and `error` is set, as the spec requires.
+    pub fn from_native(response: RpcResponse<serde_json::Value>, id: JsonRpcId) -> Self {
+        match response.error {
+            Some(error) => Self::failure(id, error),
+            None => Self::success(id, response.result.unwrap_or(serde_json::Value::Null)),
+        }
+    }
+
+    /// Convert into a native response
+    ///
+    /// Native ids are strings, so only string ids keep their value; other
+    /// ids keep their type tag and never match a request `RpcClient` sent.
+    pub fn into_native(self) -> RpcResponse<serde_json::Value> {
+        let id = match self.id {
+            JsonRpcId::String(string) => string,
+            other => other.to_key(),
+        };
+        RpcResponse {
+            id,
+            result: self.result,
+            error: self.error,
+        }
+    }
+}
+
+/// Build the notification a server pushes to a subscription
+pub fn subscription_notification<T: Serialize>(
+    notification: &RpcNotification<T>,
+) -> Result<JsonRpcRequest, serde_json::Error> {
+    Ok(JsonRpcRequest::notification(
+        SUBSCRIPTION_METHOD,
+        Some(serde_json::to_value(notification)?),
+    ))
+}
+
+/// Build the notification cancelling the request with `id`
+pub fn cancel_notification(id: JsonRpcId) -> JsonRpcRequest {
+    JsonRpcRequest::notification(CANCEL_METHOD, Some(serde_json::json!({ "id": id })))
+}
+
+/// Get the id of the request a cancel notification refers to
+pub fn cancelled_id(request: &JsonRpcRequest) -> Option<JsonRpcId> {
+    if request.method != CANCEL_METHOD || !request.is_notification() {
+        return None;
+    }
+    let id = request.params.as_ref()?.get("id")?;
+    serde_json::from_value(id.clone()).ok()
+}
+
+/// Keep a present-but-null id distinct from an absent one
+fn deserialize_present_id<'de, D>(deserializer: D) -> Result<Option<JsonRpcId>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    JsonRpcId::deserialize(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::types::RpcMethod;
+    use serde_json::json;
+
+    #[test]
+    fn test_request_round_trip() {
+        let request = RpcRequest {
+            id: "rpc_1".to_string(),
+            method: "add".to_string(),
+            params: [1, 2],
+            method_type: RpcMethod::Call,
+        };
+
+        let encoded = serde_json::to_value(JsonRpcRequest::from_native(&request).unwrap()).unwrap();
+        assert_eq!(
+            encoded,
+            json!({"jsonrpc": "2.0", "method": "add", "params": [1, 2], "id": "rpc_1"})
+        );
+    }
+
+    #[test]
+    fn test_notification_and_null_id_are_distinct() {
+        let notification: JsonRpcRequest =
+            serde_json::from_value(json!({"jsonrpc": "2.0", "method": "ping"})).unwrap();
+        assert!(notification.is_notification());
+        assert_eq!(notification.params, None);
+
+        let request: JsonRpcRequest =
+            serde_json::from_value(json!({"jsonrpc": "2.0", "method": "ping", "id": null}))
+                .unwrap();
+        assert_eq!(request.id, Some(JsonRpcId::Null));
+
+        let request: JsonRpcRequest =
+            serde_json::from_value(json!({"jsonrpc": "2.0", "method": "ping", "id": 7})).unwrap();
+        assert_eq!(request.id, Some(JsonRpcId::Number(7)));
+    }
+
+    #[test]
+    fn test_response_sets_exactly_one_of_result_and_error() {
+        let success = JsonRpcResponse::from_native(
+            RpcResponse {
+                id: "1".to_string(),
+                result: None,
+                error: None,
+            },
+            JsonRpcId::Number(1),
+        );
+        assert_eq!(
+            serde_json::to_value(&success).unwrap(),
+            json!({"jsonrpc": "2.0", "result": null, "id": 1})
+        );
+
+        let failure = JsonRpcResponse::from_native(
+            RpcResponse {
+                id: "1".to_string(),
+                result: None,
+                error: Some(RpcError::new(-32601, "Method not found".to_string())),
+            },
+            JsonRpcId::Number(1),
+        );
+        assert_eq!(
+            serde_json::to_value(&failure).unwrap(),
+            json!({
+                "jsonrpc": "2.0",
+                "error": {"code": -32601, "message": "Method not found"},
+                "id": 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_number_and_string_ids_have_distinct_keys() {
+        let number = JsonRpcId::Number(1);
+        let string = JsonRpcId::String("1".to_string());
+        assert_ne!(number.to_key(), string.to_key());
+        assert_eq!(number.to_string(), string.to_string());
+
+        let response = JsonRpcResponse::success(string, json!(true)).into_native();
+        assert_eq!(response.id, "1");
+        let response = JsonRpcResponse::success(number, json!(true)).into_native();
+        assert_ne!(response.id, "1");
+    }
+}
@@ -6,10 +6,18 @@
 pub mod advanced;
 pub mod client;
 pub mod correlation;
+pub mod jsonrpc;
+pub mod policy;
+pub mod router;
 pub mod types;
 
 // Re-export main types
 pub use client::{RpcClient, RpcSubscription, reset_rpc_id_counter};
+pub use jsonrpc::RpcWireFormat;
+pub use policy::RpcMethodPolicy;
+pub use router::{ClientCaller, RpcRouter};
+#[cfg(feature = "macros")]
+pub use leptos_ws_pro_macros::rpc_service;
 pub use types::*;
 
 // Re-export advanced RPC types
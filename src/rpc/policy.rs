@@ -0,0 +1,165 @@
+//! RPC Call Policies
+//!
+//! Per-method deadlines and retry behaviour for [`crate::rpc::RpcClient`].
+//! A method with a policy carries its remaining deadline in every native
+//! request so [`crate::rpc::RpcRouter`] can shed work the caller no longer
+//! waits for. JSON-RPC 2.0 requests keep to the members the specification
+//! defines and don't carry it.
+
+use crate::rpc::types::RpcError;
+use crate::transport::sse::ExponentialBackoff;
+use std::time::Duration;
+
+/// Request field carrying the milliseconds the caller still waits for a response
+pub const DEADLINE_FIELD: &str = "deadline_ms";
+
+/// Error code for a request whose deadline passed before it was answered
+pub const DEADLINE_EXCEEDED: i32 = -32001;
+
+/// How calls to a method are timed out and retried
+///
+/// Idempotent methods are retried after failures that may be transient:
+/// attempts that timed out or lost their connection before being answered,
+/// and requests the server shed for being late. Any method is retried after
+/// an error whose code the policy marks retryable; other errors the server
+/// answers with, internal errors included, are final.
+#[derive(Debug, Clone)]
+pub struct RpcMethodPolicy {
+    /// Deadline for the whole call, retries included
+    pub timeout: Option<Duration>,
+    /// Deadline for a single attempt, capped by what is left of `timeout`
+    pub attempt_timeout: Option<Duration>,
+    /// How many times a failed attempt is retried
+    pub max_retries: u32,
+    /// Delay before each retry
+    pub backoff: ExponentialBackoff,
+    /// Whether the method may safely run more than once
+    pub idempotent: bool,
+    /// Error codes the server answers with that are worth another attempt
+    pub retryable_codes: Vec<i32>,
+}
+
+impl RpcMethodPolicy {
+    /// Create a policy that uses the client timeout and never retries
+    pub fn new() -> Self {
+        Self {
+            timeout: None,
+            attempt_timeout: None,
+            max_retries: 0,
+            backoff: ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(5)),
+            idempotent: false,
+            retryable_codes: Vec::new(),
+        }
+    }
+
+    /// Set the deadline for the whole call
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the deadline for a single attempt
+    pub fn with_attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.attempt_timeout = Some(timeout);
+        self
+    }
+
+    /// Retry failed attempts up to `max_retries` times, waiting `backoff` in between
+    pub fn with_retries(mut self, max_retries: u32, backoff: ExponentialBackoff) -> Self {
+        self.max_retries = max_retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Mark the method as safe to run more than once
+    pub fn idempotent(mut self) -> Self {
+        self.idempotent = true;
+        self
+    }
+
+    /// Retry attempts the server answers with an error of `code`
+    ///
+    /// Only mark codes the server returns before the method has had any
+    /// effect, since they are retried even if the method isn't idempotent.
+    pub fn with_retryable_code(mut self, code: i32) -> Self {
+        self.retryable_codes.push(code);
+        self
+    }
+
+    /// Whether the attempt the server answered with `error` after `retries` retries may be retried
+    pub fn should_retry(&self, retries: u32, error: &RpcError) -> bool {
+        retries < self.max_retries
+            && (self.retryable_codes.contains(&error.code)
+                || (self.idempotent && error.code == DEADLINE_EXCEEDED))
+    }
+
+    /// Whether the attempt that timed out or lost its connection after `retries` retries may be retried
+    pub fn should_retry_unanswered(&self, retries: u32) -> bool {
+        self.idempotent && retries < self.max_retries
+    }
+}
+
+impl Default for RpcMethodPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Error response for a request whose deadline passed
+pub fn deadline_exceeded() -> RpcError {
+    RpcError::new(DEADLINE_EXCEEDED, "Deadline exceeded".to_string())
+}
+
+/// Read the deadline carried by a request frame
+pub fn deadline_of(request: &serde_json::Value) -> Option<Duration> {
+    request
+        .get(DEADLINE_FIELD)?
+        .as_u64()
+        .map(Duration::from_millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_idempotent_transient_failures_are_retried() {
+        let policy = RpcMethodPolicy::new()
+            .with_retries(2, ExponentialBackoff::new(Duration::ZERO, Duration::ZERO));
+        assert!(!policy.should_retry_unanswered(0));
+        assert!(!policy.should_retry(0, &deadline_exceeded()));
+
+        let policy = policy.idempotent();
+        assert!(policy.should_retry_unanswered(0));
+        assert!(policy.should_retry(1, &deadline_exceeded()));
+        assert!(!policy.should_retry_unanswered(2));
+        let not_found = RpcError::new(-32601, "Method not found".to_string());
+        assert!(!policy.should_retry(0, &not_found));
+    }
+
+    #[test]
+    fn test_handler_errors_are_final_unless_marked_retryable() {
+        let internal = RpcError::new(-32603, "Internal error: out of stock".to_string());
+        let busy = RpcError::new(-32004, "Busy".to_string());
+        let policy = RpcMethodPolicy::new()
+            .with_retries(2, ExponentialBackoff::new(Duration::ZERO, Duration::ZERO))
+            .idempotent();
+        assert!(!policy.should_retry(0, &internal));
+        assert!(!policy.should_retry(0, &busy));
+
+        // Marked codes are retried even for methods that aren't idempotent
+        let policy = RpcMethodPolicy::new()
+            .with_retries(2, ExponentialBackoff::new(Duration::ZERO, Duration::ZERO))
+            .with_retryable_code(-32004);
+        assert!(policy.should_retry(0, &busy));
+        assert!(!policy.should_retry(2, &busy));
+        assert!(!policy.should_retry(0, &internal));
+    }
+
+    #[test]
+    fn test_deadline_of() {
+        let request = serde_json::json!({"id": "rpc_1", "method": "get", "deadline_ms": 250});
+        assert_eq!(deadline_of(&request), Some(Duration::from_millis(250)));
+        assert_eq!(deadline_of(&serde_json::json!({"id": "rpc_1"})), None);
+    }
+}
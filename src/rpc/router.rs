@@ -0,0 +1,989 @@
+//! RPC Router
+//!
+//! Server-side dispatch of incoming RPC requests to async, typed handlers.
+//! Requests are decoded from the same wire format [`crate::rpc::RpcClient`]
+//! sends, and each call runs as its own task so slow handlers don't block
+//! the connection. Handlers get the context of the connection the request
+//! came in on, so one router can serve every connection. Handlers registered with
+//! [`RpcRouter::register_with_caller`] get a [`ClientCaller`] for the
+//! connection the request came in on, to call handlers registered on the
+//! client.
+
+use crate::rpc::advanced::{
+    response_result, BatchRpcRequest, BatchRpcResponse, RpcCorrelationManager, RpcError,
+    RpcMethodRegistry, RpcRequest,
+};
+use crate::rpc::jsonrpc::{
+    cancelled_id, JsonRpcId, JsonRpcRequest, JsonRpcResponse, RpcWireFormat, JSONRPC_VERSION,
+};
+use crate::rpc::policy::{deadline_exceeded, deadline_of};
+use crate::rpc::types;
+use crate::transport::{Message, MessageType, TransportError};
+use futures::future::BoxFuture;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use uuid::Uuid;
+
+type BoxedHandler<Ctx> = Box<
+    dyn Fn(
+            Ctx,
+            ClientCaller,
+            serde_json::Value,
+        ) -> BoxFuture<'static, Result<serde_json::Value, types::RpcError>>
+        + Send
+        + Sync,
+>;
+
+/// Routes RPC requests to registered handlers by method name
+///
+/// Async handlers registered with [`RpcRouter::register`] take precedence;
+/// methods not found there fall back to the synchronous handlers of the
+/// wrapped [`RpcMethodRegistry`]. The context handlers receive is passed in
+/// per connection to [`RpcRouter::serve`], or per request to
+/// [`RpcRouter::dispatch`].
+pub struct RpcRouter<Ctx> {
+    handlers: HashMap<String, BoxedHandler<Ctx>>,
+    registry: RpcMethodRegistry,
+    wire_format: RpcWireFormat,
+    client_call_timeout: Duration,
+}
+
+impl<Ctx> RpcRouter<Ctx>
+where
+    Ctx: Clone + Send + Sync + 'static,
+{
+    /// Create a router without handlers
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            registry: RpcMethodRegistry::new(),
+            wire_format: RpcWireFormat::Native,
+            client_call_timeout: DEFAULT_CLIENT_CALL_TIMEOUT,
+        }
+    }
+
+    /// Serve the synchronous handlers of an existing registry as well
+    pub fn with_registry(mut self, registry: RpcMethodRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Set how requests and responses are framed on the wire
+    pub fn with_wire_format(mut self, wire_format: RpcWireFormat) -> Self {
+        self.wire_format = wire_format;
+        self
+    }
+
+    /// Set how long calls made through a connection's [`ClientCaller`] wait for the client
+    pub fn with_client_call_timeout(mut self, timeout: Duration) -> Self {
+        self.client_call_timeout = timeout;
+        self
+    }
+
+    /// Get mutable access to the fallback registry
+    pub fn registry_mut(&mut self) -> &mut RpcMethodRegistry {
+        &mut self.registry
+    }
+
+    /// Register an async handler for `method`
+    ///
+    /// Params are deserialized into `P` before the handler runs; a mismatch
+    /// is reported to the caller as invalid params. Handlers may fail with
+    /// either RPC error type.
+    pub fn register<P, R, E, F, Fut>(&mut self, method: &str, handler: F)
+    where
+        P: DeserializeOwned + Send + 'static,
+        R: Serialize + 'static,
+        E: Into<types::RpcError> + 'static,
+        F: Fn(Ctx, P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, E>> + Send + 'static,
+    {
+        self.register_with_caller(method, move |context, _, params| handler(context, params));
+    }
+
+    /// Register an async handler for `method` that can call back the client
+    ///
+    /// Like [`RpcRouter::register`], but the handler also gets the
+    /// [`ClientCaller`] of the connection the request came in on. Outside
+    /// [`RpcRouter::serve`] the caller is detached and its calls fail.
+    pub fn register_with_caller<P, R, E, F, Fut>(&mut self, method: &str, handler: F)
+    where
+        P: DeserializeOwned + Send + 'static,
+        R: Serialize + 'static,
+        E: Into<types::RpcError> + 'static,
+        F: Fn(Ctx, ClientCaller, P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, E>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.handlers.insert(
+            method.to_string(),
+            Box::new(move |context, caller, params| {
+                let handler = handler.clone();
+                Box::pin(async move {
+                    let params: P = serde_json::from_value(params)
+                        .map_err(|e| RpcError::InvalidParams(e.to_string()))?;
+                    let result = handler(context, caller, params).await.map_err(Into::into)?;
+                    serde_json::to_value(result)
+                        .map_err(|e| RpcError::InternalError(e.to_string()).into())
+                })
+            }),
+        );
+    }
+
+    /// Get list of routed methods
+    pub fn methods(&self) -> Vec<String> {
+        let mut methods: Vec<String> = self.handlers.keys().cloned().collect();
+        for method in self.registry.methods() {
+            if !self.handlers.contains_key(&method) {
+                methods.push(method);
+            }
+        }
+        methods
+    }
+
+    /// Run the handler for a single request with `context` and build its response
+    pub async fn dispatch(
+        &self,
+        context: Ctx,
+        request: RpcRequest,
+    ) -> types::RpcResponse<serde_json::Value> {
+        self.dispatch_with_caller(&context, request, &self.detached_caller())
+            .await
+    }
+
+    /// A caller for requests that didn't come in on a served connection
+    fn detached_caller(&self) -> ClientCaller {
+        ClientCaller::new(None, self.wire_format, self.client_call_timeout)
+    }
+
+    async fn dispatch_with_caller(
+        &self,
+        context: &Ctx,
+        request: RpcRequest,
+        caller: &ClientCaller,
+    ) -> types::RpcResponse<serde_json::Value> {
+        let result = match self.handlers.get(&request.method) {
+            Some(handler) => handler(context.clone(), caller.clone(), request.params).await,
+            None => self
+                .registry
+                .call(&request.method, request.params)
+                .map_err(Into::into),
+        };
+
+        match result {
+            Ok(value) => types::RpcResponse {
+                id: request.id,
+                result: Some(value),
+                error: None,
+            },
+            Err(error) => types::RpcResponse {
+                id: request.id,
+                result: None,
+                error: Some(error),
+            },
+        }
+    }
+
+    /// Run the handler for a request the caller waits at most `deadline` for
+    ///
+    /// Late work is shed: the handler doesn't start once the deadline has
+    /// passed and is dropped if it runs past it.
+    pub async fn dispatch_with_deadline(
+        &self,
+        context: Ctx,
+        request: RpcRequest,
+        deadline: Option<Duration>,
+    ) -> types::RpcResponse<serde_json::Value> {
+        self.dispatch_before(&context, request, deadline, &self.detached_caller())
+            .await
+    }
+
+    async fn dispatch_before(
+        &self,
+        context: &Ctx,
+        request: RpcRequest,
+        deadline: Option<Duration>,
+        caller: &ClientCaller,
+    ) -> types::RpcResponse<serde_json::Value> {
+        let Some(deadline) = deadline else {
+            return self.dispatch_with_caller(context, request, caller).await;
+        };
+
+        let id = request.id.clone();
+        let response = if deadline.is_zero() {
+            None
+        } else {
+            tokio::time::timeout(
+                deadline,
+                self.dispatch_with_caller(context, request, caller),
+            )
+            .await
+            .ok()
+        };
+        response.unwrap_or_else(|| types::RpcResponse {
+            id,
+            result: None,
+            error: Some(deadline_exceeded()),
+        })
+    }
+
+    /// Decode a request frame, dispatch it with `context` and encode the response frame
+    ///
+    /// The items of a batch frame are executed concurrently and answered
+    /// together in a single frame, in request order.
+    ///
+    /// Returns `None` when nothing should be sent back, which only happens for
+    /// JSON-RPC 2.0 notifications and batches made up of them.
+    pub async fn handle_frame(&self, context: Ctx, data: &[u8]) -> Option<Message> {
+        self.handle_frame_with_caller(&context, data, &self.detached_caller())
+            .await
+    }
+
+    async fn handle_frame_with_caller(
+        &self,
+        context: &Ctx,
+        data: &[u8],
+        caller: &ClientCaller,
+    ) -> Option<Message> {
+        let data =
+            match self.wire_format {
+                RpcWireFormat::Native => {
+                    if let Ok(batch) = serde_json::from_slice::<BatchRpcRequest>(data) {
+                        let responses =
+                            futures::future::join_all(batch.requests.into_iter().map(|request| {
+                                self.dispatch_with_caller(context, request, caller)
+                            }))
+                            .await;
+                        serde_json::to_vec(&BatchRpcResponse { responses })
+                    } else {
+                        let deadline = serde_json::from_slice(data)
+                            .ok()
+                            .and_then(|value| deadline_of(&value));
+                        let response = match serde_json::from_slice::<RpcRequest>(data) {
+                            Ok(request) => {
+                                self.dispatch_before(context, request, deadline, caller)
+                                    .await
+                            }
+                            Err(e) => types::RpcResponse {
+                                id: request_id_of(data).unwrap_or_default(),
+                                result: None,
+                                error: Some(types::RpcError::new(
+                                    -32700,
+                                    format!("Parse error: {}", e),
+                                )),
+                            },
+                        };
+                        serde_json::to_vec(&response)
+                    }
+                }
+                RpcWireFormat::JsonRpc2 => match serde_json::from_slice(data) {
+                    Ok(serde_json::Value::Array(items)) if !items.is_empty() => {
+                        let responses: Vec<JsonRpcResponse> = futures::future::join_all(
+                            items
+                                .into_iter()
+                                .map(|item| self.handle_jsonrpc_value(context, item, caller)),
+                        )
+                        .await
+                        .into_iter()
+                        .flatten()
+                        .collect();
+                        if responses.is_empty() {
+                            return None;
+                        }
+                        serde_json::to_vec(&responses)
+                    }
+                    Ok(value) => serde_json::to_vec(
+                        &self.handle_jsonrpc_value(context, value, caller).await?,
+                    ),
+                    Err(e) => serde_json::to_vec(&JsonRpcResponse::failure(
+                        JsonRpcId::Null,
+                        types::RpcError::new(-32700, format!("Parse error: {}", e)),
+                    )),
+                },
+            };
+
+        Some(Message {
+            data: data.unwrap_or_default(),
+            message_type: MessageType::Text,
+        })
+    }
+
+    /// Dispatch a single JSON-RPC 2.0 request, answering unless it is a notification
+    async fn handle_jsonrpc_value(
+        &self,
+        context: &Ctx,
+        value: serde_json::Value,
+        caller: &ClientCaller,
+    ) -> Option<JsonRpcResponse> {
+        let deadline = deadline_of(&value);
+        let request = match serde_json::from_value::<JsonRpcRequest>(value.clone()) {
+            Ok(request) if request.jsonrpc == JSONRPC_VERSION => request,
+            Ok(_) | Err(_) => {
+                let id = value
+                    .get("id")
+                    .and_then(|id| serde_json::from_value(id.clone()).ok())
+                    .unwrap_or(JsonRpcId::Null);
+                return Some(JsonRpcResponse::failure(
+                    id,
+                    types::RpcError::new(-32600, "Invalid Request".to_string()),
+                ));
+            }
+        };
+
+        let response = self
+            .dispatch_before(
+                context,
+                RpcRequest {
+                    id: request
+                        .id
+                        .as_ref()
+                        .map(JsonRpcId::to_key)
+                        .unwrap_or_default(),
+                    method: request.method,
+                    params: request.params.unwrap_or(serde_json::Value::Null),
+                },
+                deadline,
+                caller,
+            )
+            .await;
+
+        request
+            .id
+            .map(|id| JsonRpcResponse::from_native(response, id))
+    }
+
+    /// Serve requests arriving on `incoming`, writing responses to `outgoing`
+    ///
+    /// Handlers receive a clone of `context` for every request of the
+    /// connection. Every request is handled on its own task, so responses are written in
+    /// completion order rather than arrival order. A cancel frame aborts the
+    /// task of the request it names, which then gets no response. Returns once
+    /// the incoming stream ends and all in-flight calls have been answered.
+    ///
+    /// The connection gets a [`ClientCaller`] of its own, which handlers
+    /// registered with [`RpcRouter::register_with_caller`] receive. Its calls
+    /// are written to `outgoing` in the router's wire format and completed by
+    /// the responses the client sends back on `incoming`. Once the incoming
+    /// stream ends its pending calls fail.
+    pub async fn serve<S, K>(
+        self: Arc<Self>,
+        context: Ctx,
+        incoming: S,
+        mut outgoing: K,
+    ) -> Result<(), TransportError>
+    where
+        S: Stream<Item = Result<Message, TransportError>> + Send,
+        K: Sink<Message, Error = TransportError> + Send + Unpin,
+    {
+        let (response_tx, mut response_rx) = mpsc::unbounded_channel::<Message>();
+        let caller = ClientCaller::new(
+            Some(response_tx.clone()),
+            self.wire_format,
+            self.client_call_timeout,
+        );
+
+        let in_flight: Arc<Mutex<HashMap<String, AbortHandle>>> = Arc::default();
+
+        let reader = async move {
+            futures::pin_mut!(incoming);
+            while let Some(frame) = incoming.next().await {
+                let frame = match frame {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        caller.detach();
+                        return Err(e);
+                    }
+                };
+                match frame.message_type {
+                    MessageType::Text | MessageType::Binary => {
+                        if let Some(response) = client_response(&frame.data) {
+                            let _ = caller.correlation_manager.handle_response_frame(response);
+                            continue;
+                        }
+
+                        if let Some(request_id) = self.cancelled_request(&frame.data) {
+                            if let Some(task) = in_flight.lock().unwrap().remove(&request_id) {
+                                task.abort();
+                            }
+                            continue;
+                        }
+
+                        let request_id = self.request_key(&frame.data);
+                        let router = self.clone();
+                        let context = context.clone();
+                        let caller = caller.clone();
+                        let response_tx = response_tx.clone();
+                        let finished = (in_flight.clone(), request_id.clone());
+
+                        // Hold the lock until the task is tracked so it can't
+                        // finish and untrack itself first
+                        let mut tasks = in_flight.lock().unwrap();
+                        let task = tokio::spawn(async move {
+                            if let Some(response) = router
+                                .handle_frame_with_caller(&context, &frame.data, &caller)
+                                .await
+                            {
+                                let _ = response_tx.send(response);
+                            }
+                            if let (in_flight, Some(request_id)) = finished {
+                                in_flight.lock().unwrap().remove(&request_id);
+                            }
+                        });
+                        if let Some(request_id) = request_id {
+                            tasks.insert(request_id, task.abort_handle());
+                        }
+                    }
+                    MessageType::Close => break,
+                    MessageType::Ping | MessageType::Pong => {}
+                }
+            }
+            // The caller's sender would otherwise keep the writer running
+            caller.detach();
+            Ok::<(), TransportError>(())
+        };
+
+        let writer = async move {
+            while let Some(response) = response_rx.recv().await {
+                outgoing.send(response).await?;
+            }
+            Ok::<(), TransportError>(())
+        };
+
+        let (read_result, write_result) = futures::join!(reader, writer);
+        read_result.and(write_result)
+    }
+}
+
+impl<Ctx> Default for RpcRouter<Ctx>
+where
+    Ctx: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Ctx> RpcRouter<Ctx> {
+    /// Get the id of the request a cancel frame refers to
+    fn cancelled_request(&self, data: &[u8]) -> Option<String> {
+        match self.wire_format {
+            RpcWireFormat::Native => serde_json::from_slice::<types::RpcCancel>(data)
+                .ok()
+                .map(|cancel| cancel.cancel),
+            RpcWireFormat::JsonRpc2 => serde_json::from_slice::<JsonRpcRequest>(data)
+                .ok()
+                .and_then(|request| cancelled_id(&request))
+                .map(|id| id.to_key()),
+        }
+    }
+
+    /// Get the id a single request frame can be cancelled by
+    ///
+    /// Batches are not tracked, so they always run to completion.
+    fn request_key(&self, data: &[u8]) -> Option<String> {
+        match self.wire_format {
+            RpcWireFormat::Native => request_id_of(data),
+            RpcWireFormat::JsonRpc2 => {
+                let value: serde_json::Value = serde_json::from_slice(data).ok()?;
+                let id: JsonRpcId = serde_json::from_value(value.get("id")?.clone()).ok()?;
+                Some(id.to_key())
+            }
+        }
+    }
+}
+
+/// How long a [`ClientCaller`] waits for answers unless the router sets otherwise
+const DEFAULT_CLIENT_CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Calls handlers registered on a connected
+/// [`crate::rpc::advanced::BidirectionalRpcClient`]
+///
+/// Every connection served by [`RpcRouter::serve`] has its own caller, handed
+/// to handlers registered with [`RpcRouter::register_with_caller`]. Requests
+/// are sent in the router's wire format. Clones share the same connection
+/// and pending calls; once the connection ends, calls fail right away.
+#[derive(Clone)]
+pub struct ClientCaller {
+    outgoing: Arc<Mutex<Option<mpsc::UnboundedSender<Message>>>>,
+    correlation_manager: Arc<RpcCorrelationManager>,
+    wire_format: RpcWireFormat,
+    timeout_duration: Duration,
+}
+
+impl ClientCaller {
+    fn new(
+        outgoing: Option<mpsc::UnboundedSender<Message>>,
+        wire_format: RpcWireFormat,
+        timeout_duration: Duration,
+    ) -> Self {
+        Self {
+            outgoing: Arc::new(Mutex::new(outgoing)),
+            correlation_manager: Arc::new(RpcCorrelationManager::new(timeout_duration)),
+            wire_format,
+            timeout_duration,
+        }
+    }
+
+    /// Call `method` on the client and wait for its result
+    pub async fn call(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, RpcError> {
+        let id = Uuid::new_v4().to_string();
+        let data = match self.wire_format {
+            RpcWireFormat::Native => serde_json::to_vec(&RpcRequest {
+                id: id.clone(),
+                method: method.to_string(),
+                params,
+            }),
+            RpcWireFormat::JsonRpc2 => serde_json::to_vec(&JsonRpcRequest::new(
+                JsonRpcId::String(id.clone()),
+                method,
+                Some(params),
+            )),
+        }
+        .map_err(|e| RpcError::InvalidParams(e.to_string()))?;
+        let message = Message {
+            data,
+            message_type: MessageType::Text,
+        };
+
+        let response_rx = self.correlation_manager.register_request(id.clone());
+        let sent = match &*self.outgoing.lock().unwrap() {
+            Some(outgoing) => outgoing.send(message).is_ok(),
+            None => false,
+        };
+        if !sent {
+            self.correlation_manager.cancel_request(&id);
+            return Err(RpcError::ConnectionFailed(
+                "Client is not connected".to_string(),
+            ));
+        }
+
+        match tokio::time::timeout(self.timeout_duration, response_rx).await {
+            Ok(response) => response_result(response),
+            Err(_) => {
+                self.correlation_manager.cancel_request(&id);
+                Err(RpcError::Timeout("Request timed out".to_string()))
+            }
+        }
+    }
+
+    /// Check whether the connection of this caller is still open
+    pub fn is_connected(&self) -> bool {
+        self.outgoing.lock().unwrap().is_some()
+    }
+
+    /// Get number of calls waiting for the client
+    pub fn pending_count(&self) -> usize {
+        self.correlation_manager.pending_count()
+    }
+
+    fn detach(&self) {
+        self.outgoing.lock().unwrap().take();
+        self.correlation_manager.cancel_all();
+    }
+}
+
+/// Parse a frame answering a call made through a [`ClientCaller`]
+fn client_response(data: &[u8]) -> Option<serde_json::Value> {
+    let value: serde_json::Value = serde_json::from_slice(data).ok()?;
+    let is_response = value.get("method").is_none()
+        && (value.get("result").is_some() || value.get("error").is_some());
+    is_response.then_some(value)
+}
+
+/// Best-effort extraction of the request id from a native frame
+fn request_id_of(data: &[u8]) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_slice(data).ok()?;
+    value.get("id")?.as_str().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::JsonCodec;
+    use crate::rpc::{RpcClient, RpcMethod};
+    use serde::Deserialize;
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    struct AddParams {
+        a: i64,
+        b: i64,
+    }
+
+    fn request(method: &str, params: serde_json::Value) -> RpcRequest {
+        RpcRequest {
+            id: format!("{}-1", method),
+            method: method.to_string(),
+            params,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_typed_handler() {
+        let mut router = RpcRouter::new();
+        router.register("add", |offset: i64, params: AddParams| async move {
+            Ok::<_, RpcError>(params.a + params.b + offset)
+        });
+
+        let response = router
+            .dispatch(10, request("add", serde_json::json!({"a": 1, "b": 2})))
+            .await;
+        assert_eq!(response.id, "add-1");
+        assert_eq!(response.result, Some(serde_json::json!(13)));
+
+        // The same router serves every context
+        let response = router
+            .dispatch(20, request("add", serde_json::json!({"a": 1, "b": 2})))
+            .await;
+        assert_eq!(response.result, Some(serde_json::json!(23)));
+
+        let response = router
+            .dispatch(10, request("add", serde_json::json!({"a": "x"})))
+            .await;
+        assert_eq!(response.error.unwrap().code, -32602);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_falls_back_to_registry() {
+        let mut registry = RpcMethodRegistry::new();
+        registry.register("echo", Ok);
+        let router = RpcRouter::<()>::new().with_registry(registry);
+
+        let response = router
+            .dispatch((), request("echo", serde_json::json!("hello")))
+            .await;
+        assert_eq!(response.result, Some(serde_json::json!("hello")));
+
+        let response = router
+            .dispatch((), request("missing", serde_json::json!({})))
+            .await;
+        assert_eq!(response.error.unwrap().code, -32601);
+        assert_eq!(router.methods(), vec!["echo".to_string()]);
+    }
+
+    async fn handle_jsonrpc(
+        router: &RpcRouter<()>,
+        frame: serde_json::Value,
+    ) -> Option<serde_json::Value> {
+        let response = router
+            .handle_frame((), frame.to_string().as_bytes())
+            .await?;
+        Some(serde_json::from_slice(&response.data).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_jsonrpc_requests_notifications_and_batches() {
+        let calls = Arc::new(std::sync::Mutex::new(0));
+        let mut router = RpcRouter::new().with_wire_format(RpcWireFormat::JsonRpc2);
+        let counter = calls.clone();
+        router.register("add", move |_, (a, b): (i64, i64)| {
+            *counter.lock().unwrap() += 1;
+            async move { Ok::<_, RpcError>(a + b) }
+        });
+
+        let response = handle_jsonrpc(
+            &router,
+            serde_json::json!({"jsonrpc": "2.0", "method": "add", "params": [1, 2], "id": 1}),
+        )
+        .await;
+        assert_eq!(
+            response,
+            Some(serde_json::json!({"jsonrpc": "2.0", "result": 3, "id": 1}))
+        );
+
+        // Notifications run but are never answered
+        let response = handle_jsonrpc(
+            &router,
+            serde_json::json!({"jsonrpc": "2.0", "method": "add", "params": [1, 2]}),
+        )
+        .await;
+        assert_eq!(response, None);
+        assert_eq!(*calls.lock().unwrap(), 2);
+
+        let response = handle_jsonrpc(
+            &router,
+            serde_json::json!([
+                {"jsonrpc": "2.0", "method": "add", "params": [2, 2], "id": "a"},
+                {"jsonrpc": "2.0", "method": "add", "params": [0, 0]},
+                {"jsonrpc": "2.0", "method": "missing", "id": "b"},
+                {"foo": "bar"},
+            ]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!([
+                {"jsonrpc": "2.0", "result": 4, "id": "a"},
+                {"jsonrpc": "2.0", "error": {"code": -32601, "message": "Method 'missing' not found"}, "id": "b"},
+                {"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid Request"}, "id": null},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jsonrpc_malformed_frames() {
+        let router = RpcRouter::new().with_wire_format(RpcWireFormat::JsonRpc2);
+
+        let response = router.handle_frame((), b"{not json").await.unwrap();
+        let response: serde_json::Value = serde_json::from_slice(&response.data).unwrap();
+        assert_eq!(response["error"]["code"], -32700);
+        assert_eq!(response["id"], serde_json::Value::Null);
+
+        let response = handle_jsonrpc(&router, serde_json::json!([]))
+            .await
+            .unwrap();
+        assert_eq!(response["error"]["code"], -32600);
+
+        let all_notifications = serde_json::json!([{"jsonrpc": "2.0", "method": "missing"}]);
+        assert_eq!(handle_jsonrpc(&router, all_notifications).await, None);
+
+        let wrong_version = serde_json::json!({"jsonrpc": "1.0", "method": "missing", "id": 5});
+        let response = handle_jsonrpc(&router, wrong_version).await.unwrap();
+        assert_eq!(response["error"]["code"], -32600);
+        assert_eq!(response["id"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_native_batch_runs_items_concurrently() {
+        // Both items must be running at once to get past the barrier
+        let barrier = Arc::new(tokio::sync::Barrier::new(2));
+        let mut router = RpcRouter::new();
+        router.register(
+            "wait",
+            |barrier: Arc<tokio::sync::Barrier>, value: u64| async move {
+                barrier.wait().await;
+                Ok::<_, RpcError>(value)
+            },
+        );
+
+        let batch = BatchRpcRequest {
+            requests: vec![
+                request("wait", serde_json::json!(1)),
+                request("missing", serde_json::json!(null)),
+                request("wait", serde_json::json!(2)),
+            ],
+        };
+        let frame = serde_json::to_vec(&batch).unwrap();
+        let response =
+            tokio::time::timeout(Duration::from_secs(1), router.handle_frame(barrier, &frame))
+                .await
+                .expect("batch items ran sequentially")
+                .unwrap();
+
+        let response: BatchRpcResponse = serde_json::from_slice(&response.data).unwrap();
+        let responses = response.responses;
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0].result, Some(serde_json::json!(1)));
+        assert_eq!(responses[1].error.as_ref().unwrap().code, -32601);
+        assert_eq!(responses[2].result, Some(serde_json::json!(2)));
+        assert_eq!(responses[2].id, batch.requests[2].id);
+    }
+
+    /// Serve `router` with `context` to a client through in-memory channels
+    fn connect<Ctx>(router: RpcRouter<Ctx>, context: Ctx) -> Arc<RpcClient<serde_json::Value>>
+    where
+        Ctx: Clone + Send + Sync + 'static,
+    {
+        let wire_format = router.wire_format;
+        let (request_tx, request_rx) = mpsc::unbounded_channel::<Message>();
+        let (response_tx, mut response_rx) = mpsc::unbounded_channel::<Message>();
+        let client = Arc::new(
+            RpcClient::<serde_json::Value>::new(request_tx, JsonCodec::new())
+                .with_wire_format(wire_format),
+        );
+
+        let incoming = futures::stream::unfold(request_rx, |mut rx| async move {
+            rx.recv().await.map(|message| (Ok(message), rx))
+        });
+        let outgoing = futures::sink::unfold(response_tx, |tx, message: Message| async move {
+            tx.send(message)?;
+            Ok::<_, TransportError>(tx)
+        });
+        tokio::spawn(Arc::new(router).serve(context, incoming, Box::pin(outgoing)));
+
+        let responder = client.clone();
+        tokio::spawn(async move {
+            while let Some(message) = response_rx.recv().await {
+                let _ = responder.handle_message(&message.data).await;
+            }
+        });
+        client
+    }
+
+    #[tokio::test]
+    async fn test_serve_answers_rpc_client_concurrently() {
+        let mut router = RpcRouter::new();
+        router.register("sleep", |_, millis: u64| async move {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            Ok::<_, RpcError>(millis)
+        });
+        let client = connect(router, ());
+
+        let completed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let slow = {
+            let (client, completed) = (client.clone(), completed.clone());
+            tokio::spawn(async move {
+                client.call("sleep", 100u64, RpcMethod::Call).await.unwrap();
+                completed.lock().unwrap().push("slow");
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let response = client.call("sleep", 1u64, RpcMethod::Call).await.unwrap();
+        completed.lock().unwrap().push("fast");
+        slow.await.unwrap();
+
+        assert_eq!(response.result, Some(serde_json::json!(1)));
+        assert_eq!(*completed.lock().unwrap(), vec!["fast", "slow"]);
+    }
+
+    /// Register a handler that never finishes and reports when it is aborted
+    fn hanging_router(wire_format: RpcWireFormat) -> (RpcRouter<()>, mpsc::UnboundedReceiver<()>) {
+        struct NotifyOnDrop(mpsc::UnboundedSender<()>);
+        impl Drop for NotifyOnDrop {
+            fn drop(&mut self) {
+                let _ = self.0.send(());
+            }
+        }
+
+        let (aborted_tx, aborted_rx) = mpsc::unbounded_channel();
+        let mut router = RpcRouter::new().with_wire_format(wire_format);
+        router.register("search", move |_, _: serde_json::Value| {
+            let guard = NotifyOnDrop(aborted_tx.clone());
+            async move {
+                let _guard = guard;
+                futures::future::pending::<Result<(), RpcError>>().await
+            }
+        });
+        (router, aborted_rx)
+    }
+
+    #[tokio::test]
+    async fn test_client_calls_use_the_wire_format() {
+        let mut router = RpcRouter::new().with_wire_format(RpcWireFormat::JsonRpc2);
+        router.register_with_caller("greet", |_, caller: ClientCaller, _: ()| async move {
+            caller.call("name", serde_json::Value::Null).await
+        });
+
+        let (request_tx, request_rx) = mpsc::unbounded_channel::<Message>();
+        let (response_tx, mut response_rx) = mpsc::unbounded_channel::<Message>();
+        let incoming = futures::stream::unfold(request_rx, |mut rx| async move {
+            rx.recv().await.map(|message| (Ok(message), rx))
+        });
+        let outgoing = futures::sink::unfold(response_tx, |tx, message: Message| async move {
+            tx.send(message)?;
+            Ok::<_, TransportError>(tx)
+        });
+        tokio::spawn(Arc::new(router).serve((), incoming, Box::pin(outgoing)));
+
+        let text = |value: serde_json::Value| Message {
+            data: value.to_string().into_bytes(),
+            message_type: MessageType::Text,
+        };
+        request_tx
+            .send(text(
+                serde_json::json!({"jsonrpc": "2.0", "method": "greet", "id": 1}),
+            ))
+            .unwrap();
+
+        let call: serde_json::Value =
+            serde_json::from_slice(&response_rx.recv().await.unwrap().data).unwrap();
+        assert_eq!(call["jsonrpc"], "2.0");
+        assert_eq!(call["method"], "name");
+        request_tx
+            .send(text(
+                serde_json::json!({"jsonrpc": "2.0", "result": "alice", "id": call["id"]}),
+            ))
+            .unwrap();
+
+        let response: serde_json::Value =
+            serde_json::from_slice(&response_rx.recv().await.unwrap().data).unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!({"jsonrpc": "2.0", "result": "alice", "id": 1})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dropped_call_aborts_server_task() {
+        for wire_format in [RpcWireFormat::Native, RpcWireFormat::JsonRpc2] {
+            let (router, mut aborted) = hanging_router(wire_format);
+            let client = connect(router, ());
+
+            let call = client.call("search", "query", RpcMethod::Call);
+            assert!(tokio::time::timeout(Duration::from_millis(20), call)
+                .await
+                .is_err());
+
+            tokio::time::timeout(Duration::from_secs(1), aborted.recv())
+                .await
+                .expect("handler kept running after the call was dropped");
+            assert_eq!(client.pending_request_ids(), Vec::<String>::new());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all_aborts_server_tasks() {
+        let (router, mut aborted) = hanging_router(RpcWireFormat::Native);
+        let client = connect(router, ());
+
+        let call = {
+            let client = client.clone();
+            tokio::spawn(async move { client.call("search", "query", RpcMethod::Call).await })
+        };
+        while client.pending_request_ids().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(client.cancel_all(), 1);
+
+        assert!(call
+            .await
+            .unwrap()
+            .unwrap_err()
+            .message
+            .contains("cancelled"));
+        tokio::time::timeout(Duration::from_secs(1), aborted.recv())
+            .await
+            .expect("handler kept running after the call was cancelled");
+    }
+
+    #[tokio::test]
+    async fn test_late_requests_are_shed() {
+        let (router, mut aborted) = hanging_router(RpcWireFormat::Native);
+
+        let response = router
+            .dispatch_with_deadline(
+                (),
+                request("search", serde_json::json!({})),
+                Some(Duration::ZERO),
+            )
+            .await;
+        assert_eq!(
+            response.error.unwrap().code,
+            crate::rpc::policy::DEADLINE_EXCEEDED
+        );
+        assert!(aborted.try_recv().is_err());
+
+        let frame = serde_json::json!({"id": "search-1", "method": "search", "params": {}, "deadline_ms": 20});
+        let response = router
+            .handle_frame((), frame.to_string().as_bytes())
+            .await
+            .unwrap();
+        let response: serde_json::Value = serde_json::from_slice(&response.data).unwrap();
+        assert_eq!(
+            response["error"]["code"],
+            crate::rpc::policy::DEADLINE_EXCEEDED
+        );
+        assert!(aborted.try_recv().is_ok());
+    }
+}
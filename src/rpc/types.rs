@@ -57,10 +57,11 @@ pub struct RpcResponse<T> {
 }
 
 /// RPC error structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RpcError {
     pub code: i32,
     pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub data: Option<serde_json::Value>,
 }
 
@@ -116,6 +117,31 @@ pub struct SubscribeMessagesParams {
     pub room_id: Option<String>,
 }
 
+/// Unsubscribe messages parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnsubscribeMessagesParams {
+    pub subscription_id: String,
+}
+
+/// Server-initiated notification for an active subscription
+///
+/// `subscription_id` is the id of the `SubscribeMessages` request that
+/// created the subscription.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcNotification<T> {
+    pub subscription_id: String,
+    pub data: T,
+}
+
+/// Frame asking the server to stop working on a request
+///
+/// `cancel` is the id of the abandoned request. The server sends no
+/// response for a cancelled request.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RpcCancel {
+    pub cancel: String,
+}
+
 /// Message ID
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct MessageId {
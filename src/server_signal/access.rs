@@ -0,0 +1,51 @@
+//! Client Writes
+//!
+//! Authorization of client writes to bidirectional server signals
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::messages::ConnectionId;
+
+/// A change a client asked to make to a writable signal
+pub struct ClientWrite<'a, T> {
+    /// The connection that sent the write
+    pub connection: ConnectionId,
+    /// The value held by the server
+    pub current: &'a T,
+    /// The value the client wants to store
+    pub proposed: &'a T,
+}
+
+type ValidateFn<T> = dyn Fn(&ClientWrite<'_, T>) -> Result<(), String> + Send + Sync;
+
+/// Decides whether a client write is applied
+///
+/// Returning `Err` rejects the write; the reason is sent to the client, which
+/// rolls back to the server's value.
+pub struct WriteValidator<T>(Arc<ValidateFn<T>>);
+
+impl<T> WriteValidator<T> {
+    pub fn new<F>(validate: F) -> Self
+    where
+        F: Fn(&ClientWrite<'_, T>) -> Result<(), String> + Send + Sync + 'static,
+    {
+        Self(Arc::new(validate))
+    }
+
+    pub fn validate(&self, write: &ClientWrite<'_, T>) -> Result<(), String> {
+        (self.0)(write)
+    }
+}
+
+impl<T> Clone for WriteValidator<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> fmt::Debug for WriteValidator<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WriteValidator")
+    }
+}
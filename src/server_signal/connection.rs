@@ -0,0 +1,272 @@
+//! Signal Connections
+//!
+//! The server side of the signal protocol for one client, over any transport
+//! that carries JSON messages
+
+use crate::{
+    error::Error,
+    messages::{
+        ConnectionId, Messages, ServerSignalMessage, ServerSignalRejection, ServerSignalUpdate,
+    },
+    server_signals::ServerSignals,
+    transport::{Message, MessageType, TransportError},
+};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use json_patch::Patch;
+use leptos::logging::error;
+use std::{collections::HashMap, pin::Pin, sync::Arc};
+use tokio::{
+    spawn,
+    sync::{
+        broadcast::{error::RecvError, Receiver},
+        Mutex,
+    },
+    task::JoinHandle,
+};
+
+use super::{SignalKey, SignalScope};
+
+/// Resolves the scope of a signal a connection establishes, by signal name
+pub type ScopeOf = Arc<dyn Fn(&str) -> SignalScope + Send + Sync>;
+
+type SignalSink = Arc<Mutex<Pin<Box<dyn Sink<Message, Error = TransportError> + Send>>>>;
+
+async fn send_signal_message(sink: &SignalSink, message: &Messages) -> Result<(), TransportError> {
+    let message = Message {
+        data: serde_json::to_vec(message).unwrap(),
+        message_type: MessageType::Text,
+    };
+    sink.lock().await.send(message).await
+}
+
+/// Forwards the updates of the signal at `key` from `version` onwards.
+///
+/// A receiver that lagged behind the broadcast channel is caught up from the
+/// signal's history instead of being dropped.
+async fn handle_broadcasts(
+    connection: ConnectionId,
+    key: SignalKey,
+    mut version: u64,
+    mut receiver: Receiver<ServerSignalUpdate>,
+    server_signals: ServerSignals,
+    sink: SignalSink,
+) {
+    loop {
+        let updates = match receiver.recv().await {
+            Ok(update) => vec![update],
+            Err(RecvError::Lagged(_)) => {
+                match server_signals.updates_since(key.clone(), version).await {
+                    Some(updates) => updates,
+                    None => break,
+                }
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        for mut update in updates {
+            // Already sent while catching up
+            if update.version <= version {
+                continue;
+            }
+            version = update.version;
+            // The client that wrote the update has already applied it and only
+            // needs the new version
+            if update.origin == Some(connection) {
+                update.patch = Patch::default();
+                update.snapshot = None;
+            }
+            let message = Messages::ServerSignal(ServerSignalMessage::Update(update));
+            if send_signal_message(&sink, &message).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Serves the signals of `server_signals` to the client on the other end of
+/// `incoming` and `outgoing` until the incoming stream ends.
+///
+/// Each signal has at most one forwarder per connection: establishing or
+/// resyncing a signal again replaces the previous one, and all of them stop
+/// when the connection ends.
+pub async fn serve<S, K>(server_signals: ServerSignals, incoming: S, outgoing: K, scope_of: ScopeOf)
+where
+    S: Stream<Item = Result<Message, TransportError>> + Send,
+    K: Sink<Message, Error = TransportError> + Send + 'static,
+{
+    let connection = ConnectionId::next();
+    let send: SignalSink = Arc::new(Mutex::new(Box::pin(outgoing)));
+    let mut forwarders: HashMap<SignalKey, JoinHandle<()>> = HashMap::new();
+    futures::pin_mut!(incoming);
+
+    while let Some(Ok(frame)) = incoming.next().await {
+        match frame.message_type {
+            MessageType::Text | MessageType::Binary => {}
+            MessageType::Close => break,
+            MessageType::Ping | MessageType::Pong => continue,
+        }
+        let Ok(message) = serde_json::from_slice::<Messages>(&frame.data) else {
+            error!("Error transmitting message");
+            continue;
+        };
+
+        match message {
+            Messages::ServerSignal(server_msg) => match server_msg {
+                ServerSignalMessage::Establish(name) => {
+                    let key = SignalKey::new(scope_of(&name), name.clone());
+                    let Some(recv) = server_signals.add_observer(key.clone()).await else {
+                        error!("Client established unknown signal {}", key);
+                        continue;
+                    };
+                    let Some(Ok(value)) = server_signals.json(key.clone()).await else {
+                        continue;
+                    };
+                    let response = Messages::ServerSignal(ServerSignalMessage::EstablishResponse(
+                        (name, value),
+                    ));
+                    if send_signal_message(&send, &response).await.is_err() {
+                        break;
+                    }
+                    let forwarder = spawn(handle_broadcasts(
+                        connection,
+                        key.clone(),
+                        0,
+                        recv,
+                        server_signals.clone(),
+                        send.clone(),
+                    ));
+                    if let Some(previous) = forwarders.insert(key, forwarder) {
+                        previous.abort();
+                    }
+                }
+                ServerSignalMessage::Resync((name, version)) => {
+                    let key = SignalKey::new(scope_of(&name), name);
+                    let Some(recv) = server_signals.add_observer(key.clone()).await else {
+                        error!("Client established unknown signal {}", key);
+                        continue;
+                    };
+                    if let Some(previous) = forwarders.remove(&key) {
+                        previous.abort();
+                    }
+                    let mut current = version;
+                    for update in server_signals
+                        .updates_since(key.clone(), version)
+                        .await
+                        .unwrap_or_default()
+                    {
+                        current = update.version;
+                        let message = Messages::ServerSignal(ServerSignalMessage::Update(update));
+                        let _ = send_signal_message(&send, &message).await;
+                    }
+                    let forwarder = spawn(handle_broadcasts(
+                        connection,
+                        key.clone(),
+                        current,
+                        recv,
+                        server_signals.clone(),
+                        send.clone(),
+                    ));
+                    forwarders.insert(key, forwarder);
+                }
+                ServerSignalMessage::ClientUpdate(update) => {
+                    let name = update.name.clone();
+                    let key = SignalKey::new(scope_of(&name), name.as_ref());
+                    let reason = match server_signals
+                        .client_update(key.clone(), connection, update)
+                        .await
+                    {
+                        Some(Ok(())) => continue,
+                        Some(Err(Error::WriteRejected(reason))) => reason,
+                        Some(Err(err)) => err.to_string(),
+                        None => {
+                            error!("Client wrote to unknown signal {}", key);
+                            continue;
+                        }
+                    };
+                    // Version 0 always yields a snapshot of the kept value
+                    let Some(snapshot) = server_signals
+                        .updates_since(key, 0)
+                        .await
+                        .and_then(|updates| updates.into_iter().next())
+                    else {
+                        continue;
+                    };
+                    let rejection = Messages::ServerSignal(ServerSignalMessage::Rejected(
+                        ServerSignalRejection {
+                            name,
+                            reason,
+                            value: snapshot.snapshot.unwrap_or_default(),
+                            version: snapshot.version,
+                        },
+                    ));
+                    let _ = send_signal_message(&send, &rejection).await;
+                }
+                _ => error!("Unexpected server signal message from client"),
+            },
+        }
+    }
+
+    for forwarder in forwarders.into_values() {
+        forwarder.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server_signal::ServerSignal;
+    use futures::channel::mpsc;
+    use leptos::prelude::{provide_context, Owner};
+    use std::time::Duration;
+
+    fn frame(message: &Messages) -> Result<Message, TransportError> {
+        Ok(Message {
+            data: serde_json::to_vec(message).unwrap(),
+            message_type: MessageType::Text,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_resync_replaces_the_forwarder() {
+        let owner = Owner::new();
+        owner.set();
+        let signals = ServerSignals::new();
+        provide_context(signals.clone());
+        let counter = ServerSignal::new("counter", 0u32).unwrap();
+
+        let (incoming, incoming_rx) = mpsc::unbounded();
+        let (outgoing, outgoing_rx) = mpsc::unbounded::<Message>();
+        let outgoing = outgoing.sink_map_err(|e| TransportError::SendFailed(e.to_string()));
+        let scope_of: ScopeOf = Arc::new(|_: &str| SignalScope::Global);
+        let server = spawn(serve(signals, incoming_rx, outgoing, scope_of));
+
+        let resync = ServerSignalMessage::Resync(("counter".to_string(), 1));
+        for _ in 0..2 {
+            incoming
+                .unbounded_send(frame(&Messages::ServerSignal(resync.clone())))
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        counter.set(1).unwrap();
+        counter.set(2).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Ending the connection stops every forwarder, which closes the sink
+        drop(incoming);
+        server.await.unwrap();
+        let versions = outgoing_rx
+            .filter_map(|message| async move {
+                match serde_json::from_slice(&message.data).ok()? {
+                    Messages::ServerSignal(ServerSignalMessage::Update(update)) => {
+                        Some(update.version)
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let versions = tokio::time::timeout(Duration::from_secs(1), versions)
+            .await
+            .expect("a forwarder outlived the connection");
+        assert_eq!(versions, [2, 3]);
+    }
+}
@@ -0,0 +1,138 @@
+//! Signal History
+//!
+//! The current value of a server signal together with its version and most
+//! recent updates, used to catch up clients that reconnect or fall behind
+
+use std::collections::VecDeque;
+
+use serde_json::Value;
+
+use crate::messages::ServerSignalUpdate;
+
+/// Number of updates kept per signal, matching its broadcast channel capacity
+pub const HISTORY_LEN: usize = 32;
+
+/// Versioned value of a server signal
+#[derive(Debug)]
+pub struct SignalHistory {
+    name: String,
+    json: Value,
+    version: u64,
+    recent: VecDeque<ServerSignalUpdate>,
+}
+
+impl SignalHistory {
+    /// Starts the history of a signal at version 1
+    pub fn new(name: impl Into<String>, json: Value) -> Self {
+        Self {
+            name: name.into(),
+            json,
+            version: 1,
+            recent: VecDeque::with_capacity(HISTORY_LEN),
+        }
+    }
+
+    pub fn json(&self) -> &Value {
+        &self.json
+    }
+
+    /// Moves the signal to `json`, returning the update to broadcast if it changed
+    pub fn set(&mut self, json: Value) -> Option<ServerSignalUpdate> {
+        if self.json == json {
+            return None;
+        }
+        let update = ServerSignalUpdate::new_from_json(self.name.clone(), &self.json, &json);
+        Some(self.record(update, json))
+    }
+
+    /// Records `update`, which moved the signal to `json`, and returns it with its version
+    pub fn record(&mut self, update: ServerSignalUpdate, json: Value) -> ServerSignalUpdate {
+        self.json = json;
+        self.version += 1;
+        let update = update.with_version(self.version);
+        if self.recent.len() == HISTORY_LEN {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(update.clone());
+        update
+    }
+
+    /// The updates taking a client from `version` to the current version
+    ///
+    /// These are the recorded updates while all of them are still kept, and a
+    /// single snapshot otherwise. Version 0 always gets a snapshot.
+    pub fn since(&self, version: u64) -> Vec<ServerSignalUpdate> {
+        if version == self.version {
+            return Vec::new();
+        }
+
+        let oldest_kept = self.version - self.recent.len() as u64;
+        if version != 0 && version >= oldest_kept && version < self.version {
+            return self
+                .recent
+                .iter()
+                .filter(|update| update.version > version)
+                .cloned()
+                .collect();
+        }
+
+        vec![ServerSignalUpdate::snapshot(
+            self.name.clone(),
+            self.json.clone(),
+            self.version,
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_versions_increase_with_each_change() {
+        let mut history = SignalHistory::new("counter", json!(0));
+        assert_eq!(history.since(0)[0].version, 1);
+
+        let update = history.set(json!(1)).unwrap();
+        assert_eq!(update.version, 2);
+        assert!(history.set(json!(1)).is_none());
+        assert_eq!(history.since(0)[0].version, 2);
+    }
+
+    #[test]
+    fn test_catch_up_replays_missed_updates() {
+        let mut history = SignalHistory::new("counter", json!({"count": 0}));
+        for count in 1..=5 {
+            history.set(json!({"count": count}));
+        }
+
+        let mut client = json!({"count": 2});
+        let updates = history.since(3);
+        assert_eq!(
+            updates.iter().map(|u| u.version).collect::<Vec<_>>(),
+            [4, 5, 6]
+        );
+        for update in &updates {
+            update.apply(&mut client).unwrap();
+        }
+        assert_eq!(&client, history.json());
+        assert!(history.since(6).is_empty());
+    }
+
+    #[test]
+    fn test_catch_up_falls_back_to_snapshot() {
+        let mut history = SignalHistory::new("counter", json!({"count": 0}));
+        for count in 1..=(HISTORY_LEN as u64 + 5) {
+            history.set(json!({"count": count}));
+        }
+
+        let current = HISTORY_LEN as u64 + 6;
+        for version in [0, 2, current + 10] {
+            let updates = history.since(version);
+            assert_eq!(updates.len(), 1);
+            assert_eq!(updates[0].snapshot.as_ref(), Some(history.json()));
+            assert_eq!(updates[0].version, current);
+        }
+    }
+}
@@ -2,9 +2,15 @@
 //!
 //! Server-side reactive signal implementation
 
+pub mod access;
+pub mod connection;
+pub mod history;
+pub mod scope;
 pub mod signal;
 pub mod traits;
 
 // Re-export main types
+pub use access::{ClientWrite, WriteValidator};
+pub use scope::{SignalKey, SignalScope};
 pub use signal::ServerSignal;
 pub use traits::ServerSignalTrait;
@@ -0,0 +1,143 @@
+//! Signal Scopes
+//!
+//! Keys placing a server signal in the global scope or in the scope of one
+//! session, room or tenant
+
+use std::fmt;
+
+/// Which connections share the value of a server signal
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SignalScope {
+    /// One value shared by every connection
+    #[default]
+    Global,
+    /// One value per user session
+    Session(String),
+    /// One value per room, such as a document being edited
+    Room(String),
+    /// One value per tenant
+    Tenant(String),
+}
+
+impl fmt::Display for SignalScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignalScope::Global => f.write_str("global"),
+            SignalScope::Session(id) => write!(f, "session:{}", id),
+            SignalScope::Room(id) => write!(f, "room:{}", id),
+            SignalScope::Tenant(id) => write!(f, "tenant:{}", id),
+        }
+    }
+}
+
+/// Identifies a server signal by its name within a scope
+///
+/// Clients only ever see the name; the server resolves the scope from the
+/// connection. Plain names convert into keys in the global scope.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SignalKey {
+    pub scope: SignalScope,
+    pub name: String,
+}
+
+impl SignalKey {
+    pub fn new(scope: SignalScope, name: impl Into<String>) -> Self {
+        Self {
+            scope,
+            name: name.into(),
+        }
+    }
+
+    pub fn global(name: impl Into<String>) -> Self {
+        Self::new(SignalScope::Global, name)
+    }
+
+    pub fn session(session: impl Into<String>, name: impl Into<String>) -> Self {
+        Self::new(SignalScope::Session(session.into()), name)
+    }
+
+    pub fn room(room: impl Into<String>, name: impl Into<String>) -> Self {
+        Self::new(SignalScope::Room(room.into()), name)
+    }
+
+    pub fn tenant(tenant: impl Into<String>, name: impl Into<String>) -> Self {
+        Self::new(SignalScope::Tenant(tenant.into()), name)
+    }
+}
+
+impl From<String> for SignalKey {
+    fn from(name: String) -> Self {
+        Self::global(name)
+    }
+}
+
+impl From<&str> for SignalKey {
+    fn from(name: &str) -> Self {
+        Self::global(name)
+    }
+}
+
+impl From<&String> for SignalKey {
+    fn from(name: &String) -> Self {
+        Self::global(name.as_str())
+    }
+}
+
+impl fmt::Display for SignalKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.scope, self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{server_signal::ServerSignal, server_signals::ServerSignals};
+    use leptos::prelude::{provide_context, Owner};
+    use serde_json::json;
+
+    #[test]
+    fn test_names_are_global_keys() {
+        assert_eq!(SignalKey::from("counter"), SignalKey::global("counter"));
+        assert_eq!(SignalKey::global("counter").to_string(), "global/counter");
+        assert_eq!(
+            SignalKey::room("doc-1", "state").to_string(),
+            "room:doc-1/state"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scopes_keep_values_apart() {
+        let owner = Owner::new();
+        owner.set();
+        let signals = ServerSignals::new();
+        provide_context(signals.clone());
+
+        let alice_key = SignalKey::session("alice", "notifications");
+        let bob_key = SignalKey::session("bob", "notifications");
+        let alice = ServerSignal::new(alice_key.clone(), 0u32).unwrap();
+        let bob = ServerSignal::new(bob_key.clone(), 0u32).unwrap();
+        let mut alice_updates = alice.subscribe();
+        let mut bob_updates = bob.subscribe();
+        let value_of = |key: &SignalKey| signals.json(key.clone());
+
+        alice.set(3).unwrap();
+        assert_eq!(value_of(&alice_key).await.unwrap().unwrap(), json!(3));
+        assert_eq!(value_of(&bob_key).await.unwrap().unwrap(), json!(0));
+        assert!(alice_updates.try_recv().is_ok());
+        assert!(bob_updates.try_recv().is_err());
+
+        bob.set(5).unwrap();
+        assert_eq!(value_of(&alice_key).await.unwrap().unwrap(), json!(3));
+        assert_eq!(value_of(&bob_key).await.unwrap().unwrap(), json!(5));
+        assert!(alice_updates.try_recv().is_err());
+
+        // The same name in another scope is a different signal
+        assert!(!signals.contains("notifications").await);
+        assert!(
+            !signals
+                .contains(SignalKey::tenant("alice", "notifications"))
+                .await
+        );
+    }
+}
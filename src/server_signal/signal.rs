@@ -3,21 +3,24 @@
 //! Main signal implementation for server-side reactive state
 
 use std::any::Any;
-use std::ops::{Deref, DerefMut};
+use std::ops::Deref;
 use std::panic::Location;
 use std::sync::Arc;
 
 use crate::error::Error;
-use crate::messages::ServerSignalUpdate;
+use crate::messages::{ConnectionId, ServerSignalUpdate};
 use crate::server_signals::ServerSignals;
+use async_trait::async_trait;
 use futures::executor::block_on;
-use guards::{Plain, ReadGuard};
 use leptos::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::sync::broadcast::{channel, Receiver, Sender};
 use tokio::sync::RwLock;
 
+use super::access::{ClientWrite, WriteValidator};
+use super::history::{SignalHistory, HISTORY_LEN};
+use super::scope::{SignalKey, SignalScope};
 use super::traits::ServerSignalTrait;
 
 /// A signal owned by the server which writes to the websocket when mutated.
@@ -28,29 +31,60 @@ where
 {
     initial: T,
     name: String,
+    scope: SignalScope,
     value: ArcRwSignal<T>,
-    json_value: Arc<RwLock<Value>>,
+    history: Arc<RwLock<SignalHistory>>,
     observers: Arc<Sender<ServerSignalUpdate>>,
+    validator: Option<WriteValidator<T>>,
 }
 
 impl<T> ServerSignal<T>
 where
     T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
 {
-    pub fn new(name: String, value: T) -> Result<Self, Error> {
+    /// Creates a signal, or returns the one already registered under `key`.
+    ///
+    /// `key` is a plain name for a global signal, or a [`SignalKey`] such as
+    /// `SignalKey::session(user_id, "notifications")` for a scoped one.
+    pub fn new(key: impl Into<SignalKey>, value: T) -> Result<Self, Error> {
+        Self::register(key.into(), value, None)
+    }
+
+    /// Creates a signal that clients may write to as well.
+    ///
+    /// Each client write is checked by `validator` before it is applied and
+    /// re-broadcast to the other observers. Rejected writes are rolled back on
+    /// the client that made them.
+    pub fn writable<F>(key: impl Into<SignalKey>, value: T, validator: F) -> Result<Self, Error>
+    where
+        F: Fn(&ClientWrite<'_, T>) -> Result<(), String> + Send + Sync + 'static,
+    {
+        Self::register(key.into(), value, Some(WriteValidator::new(validator)))
+    }
+
+    fn register(
+        key: SignalKey,
+        value: T,
+        validator: Option<WriteValidator<T>>,
+    ) -> Result<Self, Error> {
         let mut signals = use_context::<ServerSignals>().ok_or(Error::MissingServerSignals)?;
-        if block_on(signals.contains(&name)) {
-            return Ok(block_on(signals.get_signal::<ServerSignal<T>>(name)).unwrap());
+        if block_on(signals.contains(key.clone())) {
+            return Ok(block_on(signals.get_signal::<ServerSignal<T>>(key)).unwrap());
         }
-        let (send, _) = channel(32);
+        let (send, _) = channel(HISTORY_LEN);
         let new_signal = ServerSignal {
             initial: value.clone(),
-            name: name.clone(),
-            value: create_rw_signal(value),
-            json_value: Arc::new(RwLock::new(serde_json::to_value(&value)?)),
+            name: key.name.clone(),
+            scope: key.scope.clone(),
+            history: Arc::new(RwLock::new(SignalHistory::new(
+                key.name.clone(),
+                serde_json::to_value(&value)?,
+            ))),
+            value: ArcRwSignal::new(value),
             observers: Arc::new(send),
+            validator,
         };
-        block_on(signals.register_signal(name, new_signal.clone()))?;
+        block_on(signals.create_signal(key, new_signal.clone()))?;
         Ok(new_signal)
     }
 
@@ -58,20 +92,15 @@ where
         self.observers.subscribe()
     }
 
-    pub fn get(&self) -> ReadGuard<T, Plain> {
+    pub fn get(&self) -> T {
         self.value.get()
     }
 
     pub fn set(&self, value: T) -> Result<(), Error> {
         self.value.set(value.clone());
         let new_json = serde_json::to_value(&value)?;
-        let current_json = block_on(self.json_value.read()).clone();
-
-        if current_json != new_json {
-            let update =
-                ServerSignalUpdate::new_from_json(self.name.clone(), &current_json, &new_json);
+        if let Some(update) = block_on(self.history.write()).set(new_json) {
             let _ = self.observers.send(update);
-            *block_on(self.json_value.write()) = new_json;
         }
         Ok(())
     }
@@ -89,48 +118,96 @@ where
         &self.name
     }
 
+    pub fn scope(&self) -> &SignalScope {
+        &self.scope
+    }
+
     pub fn initial(&self) -> T {
         self.initial.clone()
     }
 }
 
-impl<T> Update for ServerSignal<T>
+#[async_trait]
+impl<T> ServerSignalTrait for ServerSignal<T>
 where
-    T: Clone + Send + Sync + for<'de> Deserialize<'de>,
+    T: Clone + Serialize + Send + Sync + for<'de> Deserialize<'de> + 'static,
 {
-    fn update(&self) {
-        self.value.update();
+    async fn add_observer(&self) -> Receiver<ServerSignalUpdate> {
+        self.subscribe()
     }
-}
 
-impl<T> DefinedAt for ServerSignal<T>
-where
-    T: Clone + Send + Sync + for<'de> Deserialize<'de>,
-{
-    fn defined_at(&self) -> Location<'static> {
-        Location::caller()
+    async fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error> {
+        let mut history = self.history.write().await;
+        let mut json = history.json().clone();
+        patch.apply(&mut json)?;
+        self.value.set(serde_json::from_value(json.clone())?);
+        let _ = self.observers.send(history.record(patch, json));
+        Ok(())
     }
-}
 
-impl<T> ReadUntracked for ServerSignal<T>
-where
-    T: Clone + Send + Sync + for<'de> Deserialize<'de>,
-{
-    type Value = T;
+    async fn client_update(
+        &self,
+        origin: ConnectionId,
+        mut update: ServerSignalUpdate,
+    ) -> Result<(), Error> {
+        let validator = self
+            .validator
+            .as_ref()
+            .ok_or_else(|| Error::WriteRejected(format!("{} is read-only", self.name)))?;
+
+        let mut history = self.history.write().await;
+        let mut proposed_json = history.json().clone();
+        update
+            .apply(&mut proposed_json)
+            .map_err(|err| Error::WriteRejected(err.to_string()))?;
+        let proposed: T = serde_json::from_value(proposed_json.clone())
+            .map_err(|err| Error::WriteRejected(err.to_string()))?;
+        let current = self.value.get_untracked();
+        validator
+            .validate(&ClientWrite {
+                connection: origin,
+                current: &current,
+                proposed: &proposed,
+            })
+            .map_err(Error::WriteRejected)?;
+
+        self.value.set(proposed);
+        update.origin = Some(origin);
+        let _ = self.observers.send(history.record(update, proposed_json));
+        Ok(())
+    }
+
+    async fn update_if_changed(&self) -> Result<(), Error> {
+        let new_json = serde_json::to_value(self.value.get_untracked())?;
+        if let Some(update) = self.history.write().await.set(new_json) {
+            let _ = self.observers.send(update);
+        }
+        Ok(())
+    }
 
-    fn read_untracked(&self) -> Self::Value {
-        self.value.read_untracked()
+    async fn updates_since(&self, version: u64) -> Vec<ServerSignalUpdate> {
+        self.history.read().await.since(version)
+    }
+
+    fn json(&self) -> Result<Value, Error> {
+        Ok(block_on(self.history.read()).json().clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn track(&self) {
+        self.value.track();
     }
 }
 
-impl<T> Get for ServerSignal<T>
+impl<T> DefinedAt for ServerSignal<T>
 where
     T: Clone + Send + Sync + for<'de> Deserialize<'de>,
 {
-    type Value = T;
-
-    fn get(&self) -> ReadGuard<Self::Value, Plain> {
-        self.value.get()
+    fn defined_at(&self) -> Option<&'static Location<'static>> {
+        self.value.defined_at()
     }
 }
 
@@ -7,14 +7,22 @@ use serde_json::Value;
 use std::any::Any;
 
 use crate::error::Error;
-use crate::messages::ServerSignalUpdate;
+use crate::messages::{ConnectionId, ServerSignalUpdate};
 
 /// Trait for server signal functionality
 #[async_trait]
 pub trait ServerSignalTrait {
     async fn add_observer(&self) -> tokio::sync::broadcast::Receiver<ServerSignalUpdate>;
     async fn update_json(&self, patch: ServerSignalUpdate) -> Result<(), Error>;
+    /// Validates and applies a write sent by the client on `origin`
+    async fn client_update(
+        &self,
+        origin: ConnectionId,
+        update: ServerSignalUpdate,
+    ) -> Result<(), Error>;
     async fn update_if_changed(&self) -> Result<(), Error>;
+    /// The updates taking a client holding `version` to the current value
+    async fn updates_since(&self, version: u64) -> Vec<ServerSignalUpdate>;
     fn json(&self) -> Result<Value, Error>;
     fn as_any(&self) -> &dyn Any;
     fn track(&self);
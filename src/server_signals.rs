@@ -1,12 +1,20 @@
-use crate::{error::Error, messages::ServerSignalUpdate, server_signal::ServerSignalTrait};
-use leptos::prelude::*;
+use crate::{
+    error::Error,
+    messages::{ConnectionId, ServerSignalUpdate},
+    server_signal::{connection, ServerSignalTrait, SignalKey, SignalScope},
+    transport::{Message, TransportError},
+};
+use futures::{Sink, Stream};
 use serde_json::Value;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::{broadcast::Receiver, RwLock};
 
+/// Every server signal, keyed by scope and name
+///
+/// Methods taking a key also accept a plain name for a global signal.
 #[derive(Clone)]
 pub struct ServerSignals {
-    signals: Arc<RwLock<HashMap<String, Arc<Box<dyn ServerSignalTrait + Send + Sync>>>>>,
+    signals: Arc<RwLock<HashMap<SignalKey, Arc<Box<dyn ServerSignalTrait + Send + Sync>>>>>,
 }
 
 impl ServerSignals {
@@ -18,7 +26,7 @@ impl ServerSignals {
 
     pub async fn create_signal<T: Clone + Send + Sync + 'static>(
         &mut self,
-        name: String,
+        key: impl Into<SignalKey>,
         value: T,
     ) -> Result<(), Error>
     where
@@ -28,7 +36,7 @@ impl ServerSignals {
             .signals
             .write()
             .await
-            .insert(name, Arc::new(Box::new(value)))
+            .insert(key.into(), Arc::new(Box::new(value)))
             .map(|value| value.as_any().downcast_ref::<T>().unwrap().clone())
             .is_none()
         {
@@ -37,19 +45,22 @@ impl ServerSignals {
             Err(Error::AddingSignalFailed)
         }
     }
-    pub async fn get_signal<T: Clone + 'static>(&mut self, name: String) -> Option<T> {
+    pub async fn get_signal<T: Clone + 'static>(&mut self, key: impl Into<SignalKey>) -> Option<T> {
         self.signals
             .write()
             .await
-            .get_mut(&name)
+            .get_mut(&key.into())
             .map(|value| value.as_any().downcast_ref::<T>().unwrap().clone())
     }
-    pub async fn add_observer(&self, name: String) -> Option<Receiver<ServerSignalUpdate>> {
+    pub async fn add_observer(
+        &self,
+        key: impl Into<SignalKey>,
+    ) -> Option<Receiver<ServerSignalUpdate>> {
         match self
             .signals
             .read()
             .await
-            .get(&name)
+            .get(&key.into())
             .map(|value| value.add_observer())
         {
             Some(fut) => Some(fut.await),
@@ -57,12 +68,12 @@ impl ServerSignals {
         }
     }
 
-    pub async fn json(&self, name: String) -> Option<Result<Value, Error>> {
+    pub async fn json(&self, key: impl Into<SignalKey>) -> Option<Result<Value, Error>> {
         match self
             .signals
             .read()
             .await
-            .get(&name)
+            .get(&key.into())
             .map(|value| value.json())
         {
             Some(res) => Some(res),
@@ -71,14 +82,14 @@ impl ServerSignals {
     }
     pub async fn update(
         &self,
-        name: String,
+        key: impl Into<SignalKey>,
         patch: ServerSignalUpdate,
     ) -> Option<Result<(), Error>> {
         match self
             .signals
             .write()
             .await
-            .get_mut(&name)
+            .get_mut(&key.into())
             .map(|value| value.update_json(patch))
         {
             Some(fut) => Some(fut.await),
@@ -86,7 +97,56 @@ impl ServerSignals {
         }
     }
 
-    pub async fn contains(&self, name: &str) -> bool {
-        self.signals.read().await.contains_key(name)
+    pub async fn client_update(
+        &self,
+        key: impl Into<SignalKey>,
+        origin: ConnectionId,
+        update: ServerSignalUpdate,
+    ) -> Option<Result<(), Error>> {
+        match self
+            .signals
+            .read()
+            .await
+            .get(&key.into())
+            .map(|value| value.client_update(origin, update))
+        {
+            Some(fut) => Some(fut.await),
+            None => None,
+        }
+    }
+
+    pub async fn updates_since(
+        &self,
+        key: impl Into<SignalKey>,
+        version: u64,
+    ) -> Option<Vec<ServerSignalUpdate>> {
+        match self
+            .signals
+            .read()
+            .await
+            .get(&key.into())
+            .map(|value| value.updates_since(version))
+        {
+            Some(fut) => Some(fut.await),
+            None => None,
+        }
+    }
+
+    pub async fn contains(&self, key: impl Into<SignalKey>) -> bool {
+        self.signals.read().await.contains_key(&key.into())
+    }
+
+    /// Serves these signals to one client until `incoming` ends
+    ///
+    /// The transport-agnostic core of the axum `websocket` handler, for
+    /// clients connected over any transport that carries the signal messages.
+    /// `scope_of` resolves the scope of each signal the client establishes.
+    pub async fn serve<S, K, F>(&self, incoming: S, outgoing: K, scope_of: F)
+    where
+        S: Stream<Item = Result<Message, TransportError>> + Send,
+        K: Sink<Message, Error = TransportError> + Send + 'static,
+        F: Fn(&str) -> SignalScope + Send + Sync + 'static,
+    {
+        connection::serve(self.clone(), incoming, outgoing, Arc::new(scope_of)).await
     }
 }
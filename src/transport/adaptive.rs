@@ -1,11 +1,20 @@
 use crate::transport::sse::SseConnection;
 use crate::transport::websocket::WebSocketConnection;
 use crate::transport::webtransport::WebTransportConnection;
-use crate::transport::{ConnectionState, Message, Transport, TransportConfig, TransportError};
+use crate::transport::{
+    ConnectionState, Datagram, DatagramStream, Message, MessageType, Transport, TransportConfig,
+    TransportError,
+};
 use async_trait::async_trait;
-use futures::{Sink, SinkExt, Stream};
+use futures::stream::BoxStream;
+use futures::{FutureExt, Sink, Stream, StreamExt};
+use leptos::prelude::{ArcReadSignal, ArcRwSignal, GetUntracked, Set};
+use std::collections::VecDeque;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex as AsyncMutex, RwLock};
 
 /// Transport capabilities detected by the adaptive transport
 #[derive(Debug, Clone)]
@@ -62,77 +71,538 @@ impl TransportCapabilities {
 }
 
 /// Performance metrics for adaptive transport
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct PerformanceMetrics {
     pub connection_count: u64,
     pub message_count: u64,
     pub error_count: u64,
+    /// Send errors since the last successful send
+    pub consecutive_errors: u64,
+    /// Moving average of the round trip time of the active transport
+    pub average_latency: Option<Duration>,
+    pub migration_count: u64,
+}
+
+impl PerformanceMetrics {
+    /// Fold `latency` into the moving average, weighting the newest sample by 1/8
+    pub fn record_latency(&mut self, latency: Duration) {
+        self.average_latency = Some(match self.average_latency {
+            Some(average) => (average * 7 + latency) / 8,
+            None => latency,
+        });
+    }
+
+    /// Whether the active transport counts as degraded under `policy`
+    pub fn is_degraded(&self, policy: &MigrationPolicy) -> bool {
+        self.consecutive_errors >= policy.max_consecutive_errors
+            || self
+                .average_latency
+                .is_some_and(|latency| latency > policy.max_latency)
+    }
+}
+
+/// When an adaptive transport migrates away from its active transport
+#[derive(Debug, Clone)]
+pub struct MigrationPolicy {
+    /// Send errors in a row after which the transport counts as degraded
+    pub max_consecutive_errors: u64,
+    /// Average latency above which the transport counts as degraded
+    pub max_latency: Duration,
+    /// Outbound messages held for replay while no transport can take them
+    pub max_queued_messages: usize,
+    /// How long the transport migrated away from may keep writing what it
+    /// has queued; the rest is replayed on the new transport
+    pub drain_timeout: Duration,
+}
+
+impl Default for MigrationPolicy {
+    fn default() -> Self {
+        Self {
+            max_consecutive_errors: 3,
+            max_latency: Duration::from_secs(2),
+            max_queued_messages: 1024,
+            drain_timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Produces the messages that resume the session on a newly migrated transport
+pub type ResumeHook = Arc<dyn Fn() -> Vec<Message> + Send + Sync>;
+
+/// Outbound messages waiting for a transport to take them
+#[derive(Default)]
+struct Outbox {
+    queue: VecDeque<Message>,
+    migrating: bool,
+}
+
+impl Outbox {
+    fn push(&mut self, message: Message, limit: usize) -> Result<(), TransportError> {
+        if self.queue.len() >= limit {
+            return Err(TransportError::SendFailed(
+                "Outbound queue full".to_string(),
+            ));
+        }
+        self.queue.push_back(message);
+        Ok(())
+    }
+}
+
+/// Ping measuring the round trip of the active transport
+#[derive(Default)]
+struct Probe {
+    next: u64,
+    outstanding: Option<(u64, Instant)>,
+}
+
+impl Probe {
+    /// Start a probe, returning its ping and how long the previous one went
+    /// unanswered, if it did
+    fn ping(&mut self) -> (Message, Option<Duration>) {
+        let unanswered = self.outstanding.map(|(_, sent)| sent.elapsed());
+        self.next += 1;
+        self.outstanding = Some((self.next, Instant::now()));
+        let ping = Message {
+            data: self.next.to_be_bytes().to_vec(),
+            message_type: MessageType::Ping,
+        };
+        (ping, unanswered)
+    }
+
+    /// Round trip of the outstanding probe, if `data` answers it
+    fn pong(&mut self, data: &[u8]) -> Option<Duration> {
+        let (id, sent) = self.outstanding?;
+        if data != id.to_be_bytes() {
+            return None;
+        }
+        self.outstanding = None;
+        Some(sent.elapsed())
+    }
+}
+
+/// `url` in the scheme of the transport named `name`
+///
+/// One URL names the server for every transport: `ws://` and `http://` are
+/// interchangeable, as are `wss://` and `https://`.
+fn url_for(name: &str, url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    let secure = match scheme {
+        "ws" | "http" => false,
+        "wss" | "https" => true,
+        _ => return url.to_string(),
+    };
+    let scheme = match (name, secure) {
+        ("WebSocket", false) => "ws",
+        ("WebSocket", true) => "wss",
+        ("WebTransport", _) | (_, true) => "https",
+        (_, false) => "http",
+    };
+    format!("{}://{}", scheme, rest)
+}
+
+/// The connection an adaptive transport currently uses
+// Only one is held at a time, so boxing the larger variants buys nothing
+#[allow(clippy::large_enum_variant)]
+enum ActiveTransport {
+    WebSocket(WebSocketConnection),
+    WebTransport(WebTransportConnection),
+    Sse(SseConnection),
+}
+
+impl ActiveTransport {
+    /// Connect the transport named `name` to `url`
+    async fn connect(
+        name: &str,
+        config: &TransportConfig,
+        url: &str,
+    ) -> Result<Self, TransportError> {
+        let url = &url_for(name, url);
+        match name {
+            "WebSocket" => {
+                let mut ws_conn = WebSocketConnection::new(config.clone()).await?;
+                ws_conn.connect(url).await?;
+                Ok(Self::WebSocket(ws_conn))
+            }
+            "WebTransport" => {
+                let mut wt_conn = WebTransportConnection::new(config.clone()).await?;
+                wt_conn.connect(url).await?;
+                Ok(Self::WebTransport(wt_conn))
+            }
+            "SSE" => {
+                let mut sse_conn = SseConnection::new(config.clone()).await?;
+                sse_conn.connect(url).await?;
+                Ok(Self::Sse(sse_conn))
+            }
+            other => Err(TransportError::NotSupported(other.to_string())),
+        }
+    }
+
+    /// Whether the transport carries messages to the server
+    fn can_send(&self) -> bool {
+        !matches!(self, Self::Sse(_))
+    }
+
+    /// Whether the transport named `name` carries messages to the server
+    fn sends(name: &str) -> bool {
+        name != "SSE"
+    }
+
+    /// Whether the server answers the transport's pings
+    fn pings(&self) -> bool {
+        matches!(self, Self::WebSocket(_))
+    }
+
+    /// Take the messages the server sends over the transport
+    fn take_incoming(&mut self) -> Option<BoxStream<'static, Message>> {
+        fn receiver_stream(
+            mut receiver: mpsc::UnboundedReceiver<Message>,
+        ) -> BoxStream<'static, Message> {
+            futures::stream::poll_fn(move |cx| receiver.poll_recv(cx)).boxed()
+        }
+
+        match self {
+            Self::WebSocket(ws_conn) => ws_conn.take_incoming().map(receiver_stream),
+            Self::Sse(sse_conn) => sse_conn.take_incoming().map(receiver_stream),
+            #[cfg(feature = "webtransport")]
+            Self::WebTransport(wt_conn) => wt_conn.session().cloned().map(|session| {
+                futures::stream::unfold(session, |session| async move {
+                    let message = session.receive().await.ok()?;
+                    Some((message, session))
+                })
+                .boxed()
+            }),
+            #[cfg(not(feature = "webtransport"))]
+            Self::WebTransport(_) => None,
+        }
+    }
+
+    async fn send_message(&self, message: &Message) -> Result<(), TransportError> {
+        match self {
+            Self::WebSocket(ws_conn) => ws_conn.send_message(message).await,
+            Self::WebTransport(wt_conn) => wt_conn.send_message(message).await,
+            Self::Sse(sse_conn) => sse_conn.send_message(message).await,
+        }
+    }
+
+    /// Close the transport, handing back the messages it took but did not send
+    async fn drain(&mut self, grace: Duration) -> Vec<Message> {
+        match self {
+            Self::WebSocket(ws_conn) => ws_conn
+                .drain(grace)
+                .await
+                .into_iter()
+                // Pings and pongs belong to the connection
+                .filter(|message| {
+                    !matches!(message.message_type, MessageType::Ping | MessageType::Pong)
+                })
+                .collect(),
+            _ => {
+                let _ = self.disconnect().await;
+                Vec::new()
+            }
+        }
+    }
+
+    async fn disconnect(&mut self) -> Result<(), TransportError> {
+        match self {
+            Self::WebSocket(ws_conn) => ws_conn.disconnect().await,
+            Self::WebTransport(wt_conn) => wt_conn.disconnect().await,
+            Self::Sse(sse_conn) => sse_conn.disconnect().await,
+        }
+    }
+}
+
+/// What a background migration shares with its adaptive transport
+#[derive(Clone)]
+struct Migration {
+    config: TransportConfig,
+    candidates: Vec<String>,
+    policy: MigrationPolicy,
+    url: Arc<Mutex<Option<String>>>,
+    state: Arc<Mutex<ConnectionState>>,
+    selected_transport: ArcRwSignal<String>,
+    active: Arc<RwLock<Option<ActiveTransport>>>,
+    generation: Arc<AtomicU64>,
+    metrics: Arc<Mutex<PerformanceMetrics>>,
+    resume: Option<ResumeHook>,
+    outbox: Arc<AsyncMutex<Outbox>>,
+    inbound: mpsc::UnboundedSender<Message>,
+    probe: Arc<Mutex<Probe>>,
+}
+
+impl Migration {
+    /// Migrate in the background, unless a migration is already running
+    async fn start(self) {
+        let url = self.url.lock().unwrap().clone();
+        let Some(url) = url else {
+            return;
+        };
+        if std::mem::replace(&mut self.outbox.lock().await.migrating, true) {
+            return;
+        }
+        tokio::spawn(self.run(url));
+    }
+
+    /// Connect the most preferred transport that answers, resume the session
+    /// on it and replay the queued messages
+    ///
+    /// The outbox must already be marked as migrating.
+    async fn run(self, url: String) -> Result<(), TransportError> {
+        *self.state.lock().unwrap() = ConnectionState::Reconnecting;
+
+        for name in &self.candidates {
+            let Ok(transport) = ActiveTransport::connect(name, &self.config, &url).await else {
+                self.metrics.lock().unwrap().error_count += 1;
+                continue;
+            };
+            if let Some(resume) = &self.resume {
+                for message in resume() {
+                    let _ = transport.send_message(&message).await;
+                }
+            }
+
+            {
+                let mut metrics = self.metrics.lock().unwrap();
+                metrics.connection_count += 1;
+                metrics.migration_count += 1;
+                metrics.consecutive_errors = 0;
+                metrics.average_latency = None;
+            }
+            self.install(name, transport).await;
+            return self.replay().await;
+        }
+
+        *self.state.lock().unwrap() = ConnectionState::Failed;
+        self.outbox.lock().await.migrating = false;
+        Err(TransportError::ConnectionFailed(
+            "No transport to migrate to".to_string(),
+        ))
+    }
+
+    /// Make `transport` the active one and receive through it
+    ///
+    /// The transport it replaces is drained; what that one did not send goes
+    /// ahead of the queued messages.
+    async fn install(&self, name: &str, mut transport: ActiveTransport) {
+        let incoming = transport.take_incoming();
+        let previous = self.active.write().await.replace(transport);
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.probe.lock().unwrap().outstanding = None;
+
+        if let Some(mut previous) = previous {
+            let unsent = previous.drain(self.policy.drain_timeout).await;
+            let mut outbox = self.outbox.lock().await;
+            for message in unsent.into_iter().rev() {
+                outbox.queue.push_front(message);
+            }
+        }
+
+        self.selected_transport.set(name.to_string());
+        *self.state.lock().unwrap() = ConnectionState::Connected;
+        if let Some(incoming) = incoming {
+            self.forward(incoming, generation);
+        }
+    }
+
+    /// Pass what the transport installed as `generation` receives on to the
+    /// adaptive transport, migrating once it closes while still active
+    ///
+    /// Answers to probes are taken out on the way.
+    fn forward(&self, mut incoming: BoxStream<'static, Message>, generation: u64) {
+        let migration = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let message = tokio::select! {
+                    message = incoming.next() => message,
+                    // Nobody receives from the adaptive transport any more
+                    _ = migration.inbound.closed() => return,
+                };
+                let Some(message) = message else {
+                    break;
+                };
+                if message.message_type == MessageType::Pong {
+                    let round_trip = migration.probe.lock().unwrap().pong(&message.data);
+                    if let Some(round_trip) = round_trip {
+                        migration.record_latency(round_trip).await;
+                        continue;
+                    }
+                }
+                if migration.inbound.send(message).is_err() {
+                    return;
+                }
+            }
+            if migration.generation.load(Ordering::SeqCst) == generation {
+                migration.start().await;
+            }
+        });
+    }
+
+    /// Ping the active transport every `interval`, for as long as anyone
+    /// receives from the adaptive transport
+    ///
+    /// A probe still unanswered when the next one is due counts as a round
+    /// trip of at least that long.
+    async fn probe_every(self, interval: Duration) {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = self.inbound.closed() => return,
+            }
+            let unanswered = {
+                let active = self.active.read().await;
+                let Some(transport) = active.as_ref().filter(|transport| transport.pings()) else {
+                    continue;
+                };
+                let (ping, unanswered) = self.probe.lock().unwrap().ping();
+                let _ = transport.send_message(&ping).await;
+                unanswered
+            };
+            if let Some(round_trip) = unanswered {
+                self.record_latency(round_trip).await;
+            }
+        }
+    }
+
+    /// Fold a round trip into the metrics, migrating once the transport degrades
+    async fn record_latency(&self, latency: Duration) {
+        let degraded = {
+            let mut metrics = self.metrics.lock().unwrap();
+            metrics.record_latency(latency);
+            metrics.is_degraded(&self.policy)
+        };
+        if degraded {
+            self.clone().start().await;
+        }
+    }
+
+    /// Send the queued messages in order, then let sends through again
+    async fn replay(&self) -> Result<(), TransportError> {
+        let mut outbox = self.outbox.lock().await;
+        let active = self.active.read().await;
+        let result = match active.as_ref() {
+            Some(transport) => loop {
+                let Some(message) = outbox.queue.pop_front() else {
+                    break Ok(());
+                };
+                if let Err(e) = transport.send_message(&message).await {
+                    // Kept for the next migration
+                    outbox.queue.push_front(message);
+                    break Err(e);
+                }
+                self.metrics.lock().unwrap().message_count += 1;
+            },
+            None => Err(TransportError::NotConnected),
+        };
+        outbox.migrating = false;
+        result
+    }
 }
 
 /// Adaptive transport that tries multiple protocols
+///
+/// Once connected, it migrates to a freshly connected transport when the
+/// active one closes or degrades under its [`MigrationPolicy`]. Messages
+/// sent while migrating, or that the degraded transport failed to send, are
+/// queued and replayed on the new transport, after the messages of its
+/// resume hook. Messages are received from whichever transport is active,
+/// through [`receive_message`](Transport::receive_message) or the stream of
+/// a split transport, which keeps migrating.
+///
+/// WebSocket transports are pinged every
+/// [`heartbeat_interval`](TransportConfig::heartbeat_interval) to measure
+/// their round trip.
 pub struct AdaptiveTransport {
     config: TransportConfig,
     state: Arc<Mutex<ConnectionState>>,
-    selected_transport: Arc<Mutex<String>>,
-    websocket_connection: Option<WebSocketConnection>,
-    sse_connection: Option<SseConnection>,
-    webtransport_connection: Option<WebTransportConnection>,
+    selected_transport: ArcRwSignal<String>,
+    active: Arc<RwLock<Option<ActiveTransport>>>,
+    generation: Arc<AtomicU64>,
+    url: Arc<Mutex<Option<String>>>,
     capabilities: TransportCapabilities,
     metrics: Arc<Mutex<PerformanceMetrics>>,
+    policy: MigrationPolicy,
+    resume: Option<ResumeHook>,
+    outbox: Arc<AsyncMutex<Outbox>>,
+    inbound: Arc<AsyncMutex<mpsc::UnboundedReceiver<Message>>>,
+    inbound_sender: mpsc::UnboundedSender<Message>,
+    probe: Arc<Mutex<Probe>>,
+    probe_task: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl AdaptiveTransport {
     pub async fn new(config: TransportConfig) -> Result<Self, TransportError> {
         let capabilities = Self::detect_capabilities().await;
+        let (inbound_sender, inbound) = mpsc::unbounded_channel();
 
         Ok(Self {
             config,
             state: Arc::new(Mutex::new(ConnectionState::Disconnected)),
-            selected_transport: Arc::new(Mutex::new("None".to_string())),
-            websocket_connection: None,
-            sse_connection: None,
-            webtransport_connection: None,
+            selected_transport: ArcRwSignal::new("None".to_string()),
+            active: Arc::new(RwLock::new(None)),
+            generation: Arc::new(AtomicU64::new(0)),
+            url: Arc::new(Mutex::new(None)),
             capabilities,
-            metrics: Arc::new(Mutex::new(PerformanceMetrics {
-                connection_count: 0,
-                message_count: 0,
-                error_count: 0,
-            })),
+            metrics: Arc::new(Mutex::new(PerformanceMetrics::default())),
+            policy: MigrationPolicy::default(),
+            resume: None,
+            outbox: Arc::new(AsyncMutex::new(Outbox::default())),
+            inbound: Arc::new(AsyncMutex::new(inbound)),
+            inbound_sender,
+            probe: Arc::new(Mutex::new(Probe::default())),
+            probe_task: None,
         })
     }
 
+    /// Migrate under `policy` instead of the default one
+    pub fn with_migration_policy(mut self, policy: MigrationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Only use the transports `capabilities` allows instead of the detected ones
+    pub fn with_capabilities(mut self, capabilities: TransportCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Resume the session on every transport migrated to with the messages of `hook`
+    ///
+    /// They are sent ahead of the replayed messages, e.g. to re-establish
+    /// server signals at the versions the client holds.
+    pub fn on_resume<F>(mut self, hook: F) -> Self
+    where
+        F: Fn() -> Vec<Message> + Send + Sync + 'static,
+    {
+        self.resume = Some(Arc::new(hook));
+        self
+    }
+
     pub async fn detect_capabilities() -> TransportCapabilities {
         TransportCapabilities::detect()
     }
 
-    pub fn selected_transport(&self) -> String {
-        self.selected_transport.lock().unwrap().clone()
+    /// Name of the transport in use, updated as the transport migrates
+    pub fn selected_transport(&self) -> ArcReadSignal<String> {
+        self.selected_transport.read_only()
     }
 
+    /// Connect the most preferred transport that answers: WebSocket, then
+    /// WebTransport when the `webtransport` feature is on
+    ///
+    /// Everything sent goes through the transport picked, so SSE, which only
+    /// receives, is never picked here or when migrating.
     pub async fn connect_with_fallback(&mut self, url: &str) -> Result<(), TransportError> {
-        // Try WebSocket first (most reliable)
-        if self.capabilities.websocket_supported {
-            match self.try_websocket_connection(url).await {
-                Ok(()) => {
-                    *self.selected_transport.lock().unwrap() = "WebSocket".to_string();
-                    *self.state.lock().unwrap() = ConnectionState::Connected;
-                    self.metrics.lock().unwrap().connection_count += 1;
-                    return Ok(());
-                }
-                Err(_e) => {
-                    self.metrics.lock().unwrap().error_count += 1;
-                    // Continue to next transport
-                }
-            }
-        }
+        *self.url.lock().unwrap() = Some(url.to_string());
 
-        // Try WebTransport as second choice (modern, efficient)
-        if self.capabilities.webtransport_supported {
-            match self.try_webtransport_connection(url).await {
-                Ok(()) => {
-                    *self.selected_transport.lock().unwrap() = "WebTransport".to_string();
-                    *self.state.lock().unwrap() = ConnectionState::Connected;
+        let migration = self.migration();
+        for name in &migration.candidates {
+            match ActiveTransport::connect(name, &self.config, url).await {
+                Ok(transport) => {
                     self.metrics.lock().unwrap().connection_count += 1;
+                    migration.install(name, transport).await;
+                    self.start_probing();
                     return Ok(());
                 }
                 Err(_e) => {
@@ -142,48 +612,85 @@ impl AdaptiveTransport {
             }
         }
 
-        // Try SSE as final fallback (simple, widely supported)
-        if self.capabilities.sse_supported {
-            match self.try_sse_connection(url).await {
-                Ok(()) => {
-                    *self.selected_transport.lock().unwrap() = "SSE".to_string();
-                    *self.state.lock().unwrap() = ConnectionState::Connected;
-                    self.metrics.lock().unwrap().connection_count += 1;
-                    return Ok(());
-                }
-                Err(_e) => {
-                    self.metrics.lock().unwrap().error_count += 1;
-                    // All transports failed
-                }
-            }
-        }
-
         Err(TransportError::ConnectionFailed(
             "All transport methods failed".to_string(),
         ))
     }
 
     async fn try_websocket_connection(&mut self, url: &str) -> Result<(), TransportError> {
-        let mut ws_conn = WebSocketConnection::new(self.config.clone()).await?;
-        ws_conn.connect(url).await?;
-        self.websocket_connection = Some(ws_conn);
+        let ws_conn = ActiveTransport::connect("WebSocket", &self.config, url).await?;
+        self.migration().install("WebSocket", ws_conn).await;
         Ok(())
     }
 
     async fn try_sse_connection(&mut self, url: &str) -> Result<(), TransportError> {
-        let mut sse_conn = SseConnection::new(self.config.clone()).await?;
-        sse_conn.connect(url).await?;
-        self.sse_connection = Some(sse_conn);
+        let sse_conn = ActiveTransport::connect("SSE", &self.config, url).await?;
+        self.migration().install("SSE", sse_conn).await;
         Ok(())
     }
 
     async fn try_webtransport_connection(&mut self, url: &str) -> Result<(), TransportError> {
-        let mut wt_conn = WebTransportConnection::new(self.config.clone()).await?;
-        wt_conn.connect(url).await?;
-        self.webtransport_connection = Some(wt_conn);
+        let wt_conn = ActiveTransport::connect("WebTransport", &self.config, url).await?;
+        self.migration().install("WebTransport", wt_conn).await;
         Ok(())
     }
 
+    /// Probe the round trip of the active transport every heartbeat interval
+    fn start_probing(&mut self) {
+        let Some(interval) = self.config.heartbeat_interval else {
+            return;
+        };
+        let probing = tokio::spawn(self.migration().probe_every(interval));
+        if let Some(previous) = self.probe_task.replace(probing) {
+            previous.abort();
+        }
+    }
+
+    fn migration(&self) -> Migration {
+        Migration {
+            config: self.config.clone(),
+            candidates: self.fallback_candidates(),
+            policy: self.policy.clone(),
+            url: self.url.clone(),
+            state: self.state.clone(),
+            selected_transport: self.selected_transport.clone(),
+            active: self.active.clone(),
+            generation: self.generation.clone(),
+            metrics: self.metrics.clone(),
+            resume: self.resume.clone(),
+            outbox: self.outbox.clone(),
+            inbound: self.inbound_sender.clone(),
+            probe: self.probe.clone(),
+        }
+    }
+
+    /// Whether the active transport has degraded under the migration policy
+    pub fn needs_migration(&self) -> bool {
+        self.metrics.lock().unwrap().is_degraded(&self.policy)
+    }
+
+    /// Migrate to a freshly connected transport now
+    ///
+    /// Transports are tried in order of preference, the degraded one included.
+    pub async fn migrate(&self) -> Result<(), TransportError> {
+        let url = self.url.lock().unwrap().clone();
+        let url = url.ok_or(TransportError::NotConnected)?;
+        if std::mem::replace(&mut self.outbox.lock().await.migrating, true) {
+            return Err(TransportError::InvalidState(
+                "Migration already running".to_string(),
+            ));
+        }
+        self.migration().run(url).await
+    }
+
+    /// Record a round trip the application measured, such as an RPC call
+    ///
+    /// Only WebSocket transports are probed with pings; this measures the
+    /// others.
+    pub async fn record_latency(&self, latency: Duration) {
+        self.migration().record_latency(latency).await;
+    }
+
     pub fn get_performance_metrics(&self) -> PerformanceMetrics {
         self.metrics.lock().unwrap().clone()
     }
@@ -192,6 +699,18 @@ impl AdaptiveTransport {
         true // Adaptive transport can always switch
     }
 
+    /// The transports falling back and migrating try, most preferred first
+    ///
+    /// Transports that only receive are left out, as is WebTransport when
+    /// this build can only simulate it.
+    fn fallback_candidates(&self) -> Vec<String> {
+        self.get_available_transports()
+            .into_iter()
+            .filter(|name| ActiveTransport::sends(name))
+            .filter(|name| name != "WebTransport" || cfg!(feature = "webtransport"))
+            .collect()
+    }
+
     pub fn get_available_transports(&self) -> Vec<String> {
         let mut transports = Vec::new();
         if self.capabilities.websocket_supported {
@@ -217,42 +736,132 @@ impl Transport for AdaptiveTransport {
     }
 
     async fn disconnect(&mut self) -> Result<(), TransportError> {
-        if let Some(mut ws_conn) = self.websocket_connection.take() {
-            let _ = ws_conn.disconnect().await;
-        }
-        if let Some(mut sse_conn) = self.sse_connection.take() {
-            let _ = sse_conn.disconnect().await;
+        *self.url.lock().unwrap() = None;
+        *self.outbox.lock().await = Outbox::default();
+        if let Some(probing) = self.probe_task.take() {
+            probing.abort();
         }
-        if let Some(mut wt_conn) = self.webtransport_connection.take() {
-            let _ = wt_conn.disconnect().await;
+        if let Some(mut transport) = self.active.write().await.take() {
+            let _ = transport.disconnect().await;
         }
         *self.state.lock().unwrap() = ConnectionState::Disconnected;
-        *self.selected_transport.lock().unwrap() = "None".to_string();
+        self.selected_transport.set("None".to_string());
         Ok(())
     }
 
+    /// Split into a stream and sink that follow the transport as it migrates
+    ///
+    /// The sink sends like [`send_message`](Transport::send_message); the
+    /// stream yields what every transport migrated to receives.
     fn split(self) -> (Self::Stream, Self::Sink) {
-        // Delegate to the active connection
-        if let Some(ws_conn) = self.websocket_connection {
-            ws_conn.split()
-        } else if let Some(sse_conn) = self.sse_connection {
-            sse_conn.split()
-        } else if let Some(wt_conn) = self.webtransport_connection {
-            wt_conn.split()
-        } else {
-            // Return empty stream and sink if not connected
-            let empty_stream = Box::pin(futures::stream::empty());
-            let empty_sink = Box::pin(
-                futures::sink::drain()
-                    .sink_map_err(|_| TransportError::SendFailed("Not connected".to_string())),
-            );
-            (empty_stream, empty_sink)
-        }
+        let stream = futures::stream::unfold(self.inbound.clone(), |inbound| async move {
+            let message = inbound.lock().await.recv().await?;
+            Some((Ok(message), inbound))
+        })
+        .boxed();
+        let sink = futures::sink::unfold(Arc::new(self), |transport, message: Message| {
+            async move {
+                transport.send_message(&message).await?;
+                Ok(transport)
+            }
+            .boxed()
+        });
+        (Box::pin(stream), Box::pin(sink))
     }
 
     fn state(&self) -> ConnectionState {
         *self.state.lock().unwrap()
     }
+
+    /// Send through the active transport, or queue for replay while migrating
+    ///
+    /// A message the active transport fails to send is queued too, so the
+    /// caller only sees an error once the queue is full, or when the active
+    /// transport only receives.
+    async fn send_message(&self, message: &Message) -> Result<(), TransportError> {
+        {
+            let mut outbox = self.outbox.lock().await;
+            if outbox.migrating {
+                return outbox.push(message.clone(), self.policy.max_queued_messages);
+            }
+            // Earlier messages are still waiting; keep them in order and try
+            // another transport
+            if !outbox.queue.is_empty() {
+                outbox.push(message.clone(), self.policy.max_queued_messages)?;
+                drop(outbox);
+                self.migration().start().await;
+                return Ok(());
+            }
+        }
+
+        let result = match self.active.read().await.as_ref() {
+            Some(transport) if !transport.can_send() => {
+                return Err(TransportError::NotSupported(format!(
+                    "{} only receives",
+                    self.selected_transport.get_untracked()
+                )))
+            }
+            Some(transport) => transport.send_message(message).await,
+            None => return Err(TransportError::NotConnected),
+        };
+        let degraded = {
+            let mut metrics = self.metrics.lock().unwrap();
+            match &result {
+                Ok(()) => {
+                    metrics.message_count += 1;
+                    metrics.consecutive_errors = 0;
+                }
+                Err(_) => {
+                    metrics.error_count += 1;
+                    metrics.consecutive_errors += 1;
+                }
+            }
+            metrics.is_degraded(&self.policy)
+        };
+        if result.is_err() {
+            self.outbox
+                .lock()
+                .await
+                .push(message.clone(), self.policy.max_queued_messages)?;
+        }
+        if degraded {
+            self.migration().start().await;
+        }
+        Ok(())
+    }
+
+    /// Receive the next message from whichever transport is active
+    async fn receive_message(&self) -> Result<Message, TransportError> {
+        self.inbound
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or(TransportError::ConnectionClosed)
+    }
+
+    async fn send_datagram(&self, datagram: Datagram) -> Result<(), TransportError> {
+        // Delegate to the active connection
+        match self.active.read().await.as_ref() {
+            Some(ActiveTransport::WebSocket(ws_conn)) => ws_conn.send_datagram(datagram).await,
+            Some(ActiveTransport::WebTransport(wt_conn)) => wt_conn.send_datagram(datagram).await,
+            Some(ActiveTransport::Sse(sse_conn)) => sse_conn.send_datagram(datagram).await,
+            None => Err(TransportError::NotConnected),
+        }
+    }
+
+    fn datagrams(&self) -> Result<DatagramStream, TransportError> {
+        let active = self
+            .active
+            .try_read()
+            .map_err(|_| TransportError::InvalidState("Transport is migrating".to_string()))?;
+        match active.as_ref() {
+            Some(ActiveTransport::WebSocket(ws_conn)) => ws_conn.datagrams(),
+            Some(ActiveTransport::WebTransport(wt_conn)) => wt_conn.datagrams(),
+            Some(ActiveTransport::Sse(sse_conn)) => sse_conn.datagrams(),
+            None => Err(TransportError::NotConnected),
+        }
+    }
 }
 
 impl AdaptiveTransport {
@@ -270,7 +879,7 @@ impl AdaptiveTransport {
                         .await
                         .is_ok()
                     {
-                        *self.selected_transport.lock().unwrap() = "WebSocket".to_string();
+                        self.selected_transport.set("WebSocket".to_string());
                         return Ok(());
                     }
                 }
@@ -280,7 +889,7 @@ impl AdaptiveTransport {
                         .await
                         .is_ok()
                     {
-                        *self.selected_transport.lock().unwrap() = "WebTransport".to_string();
+                        self.selected_transport.set("WebTransport".to_string());
                         return Ok(());
                     }
                 }
@@ -290,7 +899,7 @@ impl AdaptiveTransport {
                         .await
                         .is_ok()
                     {
-                        *self.selected_transport.lock().unwrap() = "SSE".to_string();
+                        self.selected_transport.set("SSE".to_string());
                         return Ok(());
                     }
                 }
@@ -319,7 +928,7 @@ impl AdaptiveTransport {
 
     /// Get the current protocol being used
     pub async fn current_protocol(&self) -> String {
-        self.selected_transport.lock().unwrap().clone()
+        self.selected_transport.get_untracked()
     }
 
     /// Check if the transport is connected
@@ -329,7 +938,7 @@ impl AdaptiveTransport {
 
     /// Simulate a protocol failure for testing
     pub async fn simulate_protocol_failure(&mut self, protocol: String) {
-        if *self.selected_transport.lock().unwrap() == protocol {
+        if self.selected_transport.get_untracked() == protocol {
             *self.state.lock().unwrap() = ConnectionState::Failed;
         }
     }
@@ -349,7 +958,7 @@ impl AdaptiveTransport {
                         .await
                         .is_ok()
                     {
-                        *self.selected_transport.lock().unwrap() = "WebSocket".to_string();
+                        self.selected_transport.set("WebSocket".to_string());
                         *self.state.lock().unwrap() = ConnectionState::Connected;
                         return Ok(());
                     }
@@ -360,7 +969,7 @@ impl AdaptiveTransport {
                         .await
                         .is_ok()
                     {
-                        *self.selected_transport.lock().unwrap() = "WebTransport".to_string();
+                        self.selected_transport.set("WebTransport".to_string());
                         *self.state.lock().unwrap() = ConnectionState::Connected;
                         return Ok(());
                     }
@@ -371,7 +980,7 @@ impl AdaptiveTransport {
                         .await
                         .is_ok()
                     {
-                        *self.selected_transport.lock().unwrap() = "SSE".to_string();
+                        self.selected_transport.set("SSE".to_string());
                         *self.state.lock().unwrap() = ConnectionState::Connected;
                         return Ok(());
                     }
@@ -0,0 +1,196 @@
+//! Unreliable datagrams
+//!
+//! Datagrams carry state that is superseded faster than it is worth
+//! retransmitting, such as cursor positions or game state. They may be lost
+//! or arrive out of order, and never block the messages sent alongside them.
+//!
+//! WebTransport sends them as QUIC datagrams. WebSocket emulates them on its
+//! ordered stream once both ends negotiated the [`WEBSOCKET_EXTENSION`]:
+//! while the socket is busy, only the latest datagram of each channel is kept.
+
+use crate::transport::TransportError;
+use futures::Stream;
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// Size of the channel header in front of every datagram
+const HEADER_LEN: usize = 2;
+
+/// Marks a WebSocket binary frame as an emulated datagram
+const WEBSOCKET_MARKER: &[u8; 4] = b"\0lwd";
+
+/// WebSocket extension negotiating emulated datagrams
+///
+/// Until both ends agreed to it, a binary frame starting with the datagram
+/// marker is application data like any other.
+pub const WEBSOCKET_EXTENSION: &str = "lwp-datagrams";
+
+/// Whether a `Sec-WebSocket-Extensions` value lists [`WEBSOCKET_EXTENSION`]
+pub fn extension_listed(header: &str) -> bool {
+    header
+        .split(',')
+        .filter_map(|extension| extension.split(';').next())
+        .any(|name| name.trim() == WEBSOCKET_EXTENSION)
+}
+
+/// Stream of the datagrams a transport receives
+pub type DatagramStream = Pin<Box<dyn Stream<Item = Datagram> + Send>>;
+
+/// An unreliable, unordered message
+///
+/// The channel tells apart the kinds of state an application sends; a newer
+/// datagram may replace an older one of the same channel before it is sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub channel: u16,
+    pub data: Vec<u8>,
+}
+
+impl Datagram {
+    pub fn new(channel: u16, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            channel,
+            data: data.into(),
+        }
+    }
+
+    /// Encode as `[channel u16 BE][data]`
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.data.len());
+        bytes.extend_from_slice(&self.channel.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, TransportError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(TransportError::ProtocolError(
+                "Datagram shorter than its header".to_string(),
+            ));
+        }
+        Ok(Self {
+            channel: u16::from_be_bytes([bytes[0], bytes[1]]),
+            data: bytes[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// Encode as the payload of a WebSocket binary frame
+    pub fn to_websocket_frame(&self) -> Vec<u8> {
+        let mut frame = WEBSOCKET_MARKER.to_vec();
+        frame.extend_from_slice(&self.encode());
+        frame
+    }
+
+    /// Decode a WebSocket binary frame, if it carries a datagram
+    pub fn from_websocket_frame(frame: &[u8]) -> Option<Self> {
+        frame
+            .strip_prefix(WEBSOCKET_MARKER)
+            .and_then(|bytes| Self::decode(bytes).ok())
+    }
+}
+
+/// Outgoing datagrams waiting for a busy transport
+///
+/// Holds at most one datagram per channel: pushing to a channel that is
+/// still pending replaces its data but keeps its place in line.
+#[derive(Debug, Default)]
+pub struct Coalescer {
+    pending: Mutex<Pending>,
+    notify: Notify,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    order: VecDeque<u16>,
+    data: HashMap<u16, Vec<u8>>,
+}
+
+impl Coalescer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, datagram: Datagram) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending
+            .data
+            .insert(datagram.channel, datagram.data)
+            .is_none()
+        {
+            pending.order.push_back(datagram.channel);
+        }
+        drop(pending);
+        self.notify.notify_one();
+    }
+
+    pub fn pop(&self) -> Option<Datagram> {
+        let mut pending = self.pending.lock().unwrap();
+        let channel = pending.order.pop_front()?;
+        let data = pending.data.remove(&channel)?;
+        Some(Datagram { channel, data })
+    }
+
+    /// Wait for the next pending datagram
+    pub async fn next(&self) -> Datagram {
+        loop {
+            if let Some(datagram) = self.pop() {
+                return datagram;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Number of channels with a pending datagram
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_datagram_roundtrip() {
+        let datagram = Datagram::new(7, b"cursor".to_vec());
+        assert_eq!(Datagram::decode(&datagram.encode()).unwrap(), datagram);
+        assert!(Datagram::decode(&[1]).is_err());
+    }
+
+    #[test]
+    fn test_websocket_frames_are_marked() {
+        let datagram = Datagram::new(1, b"state".to_vec());
+        let frame = datagram.to_websocket_frame();
+        assert_eq!(Datagram::from_websocket_frame(&frame), Some(datagram));
+        assert_eq!(Datagram::from_websocket_frame(b"{\"plain\":true}"), None);
+    }
+
+    #[test]
+    fn test_extension_is_found_among_others() {
+        assert!(extension_listed(
+            "permessage-deflate; client_max_window_bits, lwp-datagrams"
+        ));
+        assert!(extension_listed("lwp-datagrams"));
+        assert!(!extension_listed("permessage-deflate"));
+        assert!(!extension_listed("x-lwp-datagrams"));
+    }
+
+    #[tokio::test]
+    async fn test_latest_value_wins_per_channel() {
+        let coalescer = Coalescer::new();
+        coalescer.push(Datagram::new(1, b"a1".to_vec()));
+        coalescer.push(Datagram::new(2, b"b1".to_vec()));
+        coalescer.push(Datagram::new(1, b"a2".to_vec()));
+        assert_eq!(coalescer.len(), 2);
+
+        assert_eq!(coalescer.next().await, Datagram::new(1, b"a2".to_vec()));
+        assert_eq!(coalescer.next().await, Datagram::new(2, b"b1".to_vec()));
+        assert!(coalescer.is_empty());
+    }
+}
@@ -11,6 +11,8 @@ use futures::{Sink, Stream};
 use std::pin::Pin;
 
 pub mod adaptive;
+pub mod datagram;
+pub mod multiplex;
 pub mod optimized;
 pub mod sse;
 pub mod websocket;
@@ -18,6 +20,8 @@ pub mod webtransport;
 
 // Re-export main types
 // Transport and TransportError are defined below in this module
+pub use datagram::{Datagram, DatagramStream};
+pub use multiplex::{BiStream, StreamOpener};
 
 /// A unified message type that can be sent over any transport
 #[derive(
@@ -30,6 +34,7 @@ pub mod webtransport;
     rkyv::Serialize,
     rkyv::Deserialize,
 )]
+#[archive(check_bytes)]
 pub struct Message {
     pub data: Vec<u8>,
     pub message_type: MessageType,
@@ -45,6 +50,7 @@ pub struct Message {
     rkyv::Serialize,
     rkyv::Deserialize,
 )]
+#[archive(check_bytes)]
 pub enum MessageType {
     Text,
     Binary,
@@ -127,7 +133,7 @@ impl TransportCapabilities {
         {
             Self {
                 websocket: true,
-                webtransport: false, // Not yet available in native
+                webtransport: cfg!(feature = "webtransport"),
                 sse: true,
                 compression: true,
                 binary: true,
@@ -151,8 +157,9 @@ impl TransportCapabilities {
         self.websocket || self.sse
     }
 
+    /// WebTransport multiplexes natively, native WebSocket clients through `websocket::mux`
     pub fn supports_multiplexing(&self) -> bool {
-        self.webtransport
+        self.webtransport || (self.websocket && cfg!(not(target_arch = "wasm32")))
     }
 
     pub fn supports_server_sent_events(&self) -> bool {
@@ -204,6 +211,23 @@ pub trait Transport: Send + Sync + 'static {
             "Bidirectional streams not supported".to_string(),
         ))
     }
+
+    /// Send an unreliable, unordered datagram
+    ///
+    /// Datagrams may be lost, and a later datagram on the same channel may
+    /// replace one that has not been sent yet.
+    async fn send_datagram(&self, _datagram: Datagram) -> Result<(), TransportError> {
+        Err(TransportError::NotSupported(
+            "Datagrams not supported".to_string(),
+        ))
+    }
+
+    /// Stream of the datagrams received from the peer
+    fn datagrams(&self) -> Result<DatagramStream, TransportError> {
+        Err(TransportError::NotSupported(
+            "Datagrams not supported".to_string(),
+        ))
+    }
 }
 
 /// A connection that can be split into separate stream and sink
@@ -0,0 +1,50 @@
+//! Logical streams
+//!
+//! WebTransport sessions and multiplexed WebSockets both carry many
+//! bidirectional streams over one connection. [`StreamOpener`] and
+//! [`BiStream`] open and use them the same way on either transport.
+
+use crate::transport::{Message, MessageType, TransportError};
+use async_trait::async_trait;
+
+/// A bidirectional stream within a connection
+#[async_trait]
+pub trait BiStream: Send {
+    /// Id of the stream within its connection
+    fn id(&self) -> u32;
+
+    /// Send `message` on the stream
+    async fn send_message(&mut self, message: &Message) -> Result<(), TransportError>;
+
+    /// Receive the next message sent by the peer on the stream
+    async fn receive_message(&mut self) -> Result<Message, TransportError>;
+
+    /// Send `data` as a JSON text message
+    async fn send_data<T: serde::Serialize + Sync>(
+        &mut self,
+        data: &T,
+    ) -> Result<(), TransportError> {
+        let message = Message {
+            data: serde_json::to_vec(data)
+                .map_err(|e| TransportError::SendFailed(e.to_string()))?,
+            message_type: MessageType::Text,
+        };
+        self.send_message(&message).await
+    }
+
+    /// Receive the next message and decode it from JSON
+    async fn receive_data<T: serde::de::DeserializeOwned>(&mut self) -> Result<T, TransportError> {
+        let message = self.receive_message().await?;
+        serde_json::from_slice(&message.data)
+            .map_err(|e| TransportError::ReceiveFailed(e.to_string()))
+    }
+}
+
+/// A connection that opens [`BiStream`]s
+#[async_trait]
+pub trait StreamOpener: Send + Sync {
+    type Stream: BiStream;
+
+    /// Open a reliable, ordered stream to the peer
+    async fn open_stream(&self) -> Result<Self::Stream, TransportError>;
+}
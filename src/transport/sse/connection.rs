@@ -73,6 +73,13 @@ impl SseConnection {
         *self.state.lock().unwrap()
     }
 
+    /// Take the events the server sends, as text messages
+    ///
+    /// Only the first call gets the receiver.
+    pub fn take_incoming(&mut self) -> Option<mpsc::UnboundedReceiver<Message>> {
+        self.event_receiver.take()
+    }
+
     /// Subscribe to specific event types
     pub async fn subscribe_to_event_type(&self, event_type: String) -> Result<(), TransportError> {
         let mut subscribed = self.subscribed_event_types.lock().unwrap();
@@ -168,13 +175,14 @@ impl SseConnection {
                     &subscribed_types,
                     &heartbeat_config,
                     &last_heartbeat,
+                    &state,
                 )
                 .await
                 {
                     Ok(_) => {
-                        // Connection successful, reset attempts
+                        // The server ended the stream; connect again
                         reconnect_attempts = 0;
-                        *state.lock().unwrap() = ConnectionState::Connected;
+                        *state.lock().unwrap() = ConnectionState::Reconnecting;
                     }
                     Err(_e) => {
                         reconnect_attempts += 1;
@@ -218,6 +226,7 @@ impl SseConnection {
         subscribed_types: &Arc<Mutex<HashSet<String>>>,
         heartbeat_config: &Arc<Mutex<HeartbeatConfig>>,
         last_heartbeat: &Arc<Mutex<Option<Instant>>>,
+        state: &Arc<Mutex<ConnectionState>>,
     ) -> Result<(), TransportError> {
         let mut headers = header::HeaderMap::new();
         headers.insert(header::ACCEPT, "text/event-stream".parse().unwrap());
@@ -236,6 +245,8 @@ impl SseConnection {
                 response.status()
             )));
         }
+        // Events flow as long as the response does
+        *state.lock().unwrap() = ConnectionState::Connected;
 
         let mut stream = response.bytes_stream();
         let mut buffer = String::new();
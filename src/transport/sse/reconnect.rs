@@ -137,6 +137,7 @@ impl ReconnectionManager {
 }
 
 /// Exponential backoff calculator
+#[derive(Debug, Clone)]
 pub struct ExponentialBackoff {
     base_delay: Duration,
     max_delay: Duration,
@@ -176,7 +177,7 @@ impl ExponentialBackoff {
         let mut final_delay = capped_delay as u64;
 
         // Add jitter to prevent thundering herd
-        if self.jitter {
+        if self.jitter && final_delay >= 4 {
             let jitter_amount = final_delay / 4; // 25% jitter
             let jitter = (rand::random::<u64>() % jitter_amount) as u64;
             final_delay += jitter;
@@ -0,0 +1,961 @@
+//! permessage-deflate (RFC 7692)
+//!
+//! Negotiation of the `permessage-deflate` extension through the
+//! `Sec-WebSocket-Extensions` header, and the per-message compressor both
+//! ends run once it is agreed. Context takeover and window bits are honoured
+//! in both directions.
+//!
+//! Compressed messages are flagged with the RSV1 frame bit, which tungstenite
+//! rejects. [`DeflateStream`] therefore sits between tungstenite and the
+//! socket: it inflates incoming messages and clears RSV1 before tungstenite
+//! reads them, and deflates the data frames tungstenite writes.
+
+use crate::transport::{datagram, TransportError};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Extension token in `Sec-WebSocket-Extensions`
+pub const EXTENSION_NAME: &str = "permessage-deflate";
+
+/// Trailer every sync-flushed deflate block ends with, stripped on the wire
+const DEFLATE_TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Largest LZ77 window, and the one used when none is negotiated
+const MAX_WINDOW_BITS: u8 = 15;
+
+/// Smallest LZ77 window a peer may ask for
+const MIN_WINDOW_BITS: u8 = 8;
+
+/// What this end is willing to agree on
+///
+/// Parameters are named from the client's point of view, as in the RFC:
+/// `client_*` limits what the client compresses with and `server_*` what the
+/// server compresses with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeflateConfig {
+    pub compression_level: u32,
+    pub client_no_context_takeover: bool,
+    pub server_no_context_takeover: bool,
+    pub client_max_window_bits: Option<u8>,
+    pub server_max_window_bits: Option<u8>,
+}
+
+impl Default for DeflateConfig {
+    fn default() -> Self {
+        Self {
+            compression_level: Compression::default().level(),
+            client_no_context_takeover: false,
+            server_no_context_takeover: false,
+            client_max_window_bits: None,
+            server_max_window_bits: None,
+        }
+    }
+}
+
+impl DeflateConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_compression_level(mut self, level: u32) -> Self {
+        self.compression_level = level;
+        self
+    }
+
+    /// Reset the client's compression context after every message
+    pub fn with_client_no_context_takeover(mut self) -> Self {
+        self.client_no_context_takeover = true;
+        self
+    }
+
+    /// Reset the server's compression context after every message
+    pub fn with_server_no_context_takeover(mut self) -> Self {
+        self.server_no_context_takeover = true;
+        self
+    }
+
+    /// Limit the window the client compresses with to `2^bits` bytes
+    pub fn with_client_max_window_bits(mut self, bits: u8) -> Self {
+        self.client_max_window_bits = Some(bits.clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS));
+        self
+    }
+
+    /// Limit the window the server compresses with to `2^bits` bytes
+    pub fn with_server_max_window_bits(mut self, bits: u8) -> Self {
+        self.server_max_window_bits = Some(bits.clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS));
+        self
+    }
+
+    /// The `Sec-WebSocket-Extensions` value a client sends
+    pub fn offer(&self) -> String {
+        let mut offer = String::from(EXTENSION_NAME);
+        if self.client_no_context_takeover {
+            offer.push_str("; client_no_context_takeover");
+        }
+        if self.server_no_context_takeover {
+            offer.push_str("; server_no_context_takeover");
+        }
+        if let Some(bits) = self.server_max_window_bits {
+            offer.push_str(&format!("; server_max_window_bits={}", bits));
+        }
+        // Always let the server pick a smaller window for us
+        match self.client_max_window_bits {
+            Some(bits) => offer.push_str(&format!("; client_max_window_bits={}", bits)),
+            None => offer.push_str("; client_max_window_bits"),
+        }
+        offer
+    }
+
+    /// Pick the parameters for a connection from a client's offers
+    ///
+    /// Returns `None` when no offer is acceptable, in which case the
+    /// extension is left out of the handshake response.
+    pub fn accept_offer(&self, header: &str) -> Option<DeflateParams> {
+        parse_extensions(header)
+            .filter(|(name, _)| name == EXTENSION_NAME)
+            .find_map(|(_, params)| self.accept_params(&params).ok())
+    }
+
+    fn accept_params(&self, params: &[(String, Option<String>)]) -> Result<DeflateParams, String> {
+        let offer = Offer::parse(params)?;
+
+        let client_max_window_bits = match offer.client_max_window_bits {
+            // The client didn't say it can limit its window
+            None => MAX_WINDOW_BITS,
+            Some(offered) => {
+                let offered = offered.unwrap_or(MAX_WINDOW_BITS);
+                offered.min(self.client_max_window_bits.unwrap_or(MAX_WINDOW_BITS))
+            }
+        };
+        let server_max_window_bits = offer
+            .server_max_window_bits
+            .unwrap_or(MAX_WINDOW_BITS)
+            .min(self.server_max_window_bits.unwrap_or(MAX_WINDOW_BITS));
+
+        Ok(DeflateParams {
+            client_no_context_takeover: offer.client_no_context_takeover
+                || self.client_no_context_takeover,
+            server_no_context_takeover: offer.server_no_context_takeover
+                || self.server_no_context_takeover,
+            client_max_window_bits,
+            server_max_window_bits,
+        })
+    }
+
+    /// Check the server's handshake response against what this client offered
+    ///
+    /// Returns `None` when the server declined the extension.
+    pub fn accept_response(
+        &self,
+        header: Option<&str>,
+    ) -> Result<Option<DeflateParams>, TransportError> {
+        let Some(header) = header else {
+            return Ok(None);
+        };
+        // Emulated datagrams are negotiated alongside, by the connection itself
+        let mut extensions =
+            parse_extensions(header).filter(|(name, _)| name != datagram::WEBSOCKET_EXTENSION);
+        let Some((name, params)) = extensions.next() else {
+            return Ok(None);
+        };
+        if name != EXTENSION_NAME || extensions.next().is_some() {
+            return Err(invalid_response(format!(
+                "unexpected extensions '{}'",
+                header
+            )));
+        }
+
+        let response = Offer::parse(&params).map_err(invalid_response)?;
+        if self.server_no_context_takeover && !response.server_no_context_takeover {
+            return Err(invalid_response("server_no_context_takeover was dropped"));
+        }
+
+        let server_max_window_bits =
+            match (self.server_max_window_bits, response.server_max_window_bits) {
+                (Some(requested), Some(bits)) if bits <= requested => bits,
+                (None, Some(bits)) => bits,
+                (None, None) => MAX_WINDOW_BITS,
+                _ => return Err(invalid_response("server_max_window_bits was not honoured")),
+            };
+        let client_max_window_bits = match response.client_max_window_bits {
+            None => self.client_max_window_bits.unwrap_or(MAX_WINDOW_BITS),
+            Some(None) => return Err(invalid_response("client_max_window_bits needs a value")),
+            Some(Some(bits)) => bits.min(self.client_max_window_bits.unwrap_or(MAX_WINDOW_BITS)),
+        };
+
+        Ok(Some(DeflateParams {
+            client_no_context_takeover: response.client_no_context_takeover
+                || self.client_no_context_takeover,
+            server_no_context_takeover: response.server_no_context_takeover,
+            client_max_window_bits,
+            server_max_window_bits,
+        }))
+    }
+}
+
+/// Parameters both ends agreed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeflateParams {
+    pub client_no_context_takeover: bool,
+    pub server_no_context_takeover: bool,
+    pub client_max_window_bits: u8,
+    pub server_max_window_bits: u8,
+}
+
+impl DeflateParams {
+    /// The `Sec-WebSocket-Extensions` value a server answers with
+    pub fn response(&self) -> String {
+        let mut response = String::from(EXTENSION_NAME);
+        if self.client_no_context_takeover {
+            response.push_str("; client_no_context_takeover");
+        }
+        if self.server_no_context_takeover {
+            response.push_str("; server_no_context_takeover");
+        }
+        if self.server_max_window_bits < MAX_WINDOW_BITS {
+            response.push_str(&format!(
+                "; server_max_window_bits={}",
+                self.server_max_window_bits
+            ));
+        }
+        // Only ever below the maximum when the client offered to limit it
+        if self.client_max_window_bits < MAX_WINDOW_BITS {
+            response.push_str(&format!(
+                "; client_max_window_bits={}",
+                self.client_max_window_bits
+            ));
+        }
+        response
+    }
+}
+
+/// Parameters of one `permessage-deflate` offer or response
+///
+/// `client_max_window_bits` may be given without a value, read as `Some(None)`.
+#[derive(Default)]
+struct Offer {
+    client_no_context_takeover: bool,
+    server_no_context_takeover: bool,
+    client_max_window_bits: Option<Option<u8>>,
+    server_max_window_bits: Option<u8>,
+}
+
+impl Offer {
+    fn parse(params: &[(String, Option<String>)]) -> Result<Self, String> {
+        let mut offer = Offer::default();
+        for (name, value) in params {
+            let duplicate = match name.as_str() {
+                "client_no_context_takeover" if value.is_none() => {
+                    std::mem::replace(&mut offer.client_no_context_takeover, true)
+                }
+                "server_no_context_takeover" if value.is_none() => {
+                    std::mem::replace(&mut offer.server_no_context_takeover, true)
+                }
+                "client_max_window_bits" => offer
+                    .client_max_window_bits
+                    .replace(value.as_deref().map(window_bits).transpose()?)
+                    .is_some(),
+                "server_max_window_bits" => offer
+                    .server_max_window_bits
+                    .replace(window_bits(value.as_deref().unwrap_or_default())?)
+                    .is_some(),
+                _ => return Err(format!("unknown parameter '{}'", name)),
+            };
+            if duplicate {
+                return Err(format!("duplicate parameter '{}'", name));
+            }
+        }
+        Ok(offer)
+    }
+}
+
+fn window_bits(value: &str) -> Result<u8, String> {
+    value
+        .parse::<u8>()
+        .ok()
+        .filter(|bits| (MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(bits))
+        .ok_or_else(|| format!("invalid window bits '{}'", value))
+}
+
+fn invalid_response(reason: impl std::fmt::Display) -> TransportError {
+    TransportError::ProtocolError(format!("{} response: {}", EXTENSION_NAME, reason))
+}
+
+/// Split a `Sec-WebSocket-Extensions` value into extensions and their parameters
+fn parse_extensions(
+    header: &str,
+) -> impl Iterator<Item = (String, Vec<(String, Option<String>)>)> + '_ {
+    header.split(',').filter_map(|extension| {
+        let mut parts = extension.split(';').map(str::trim);
+        let name = parts.next().filter(|name| !name.is_empty())?.to_string();
+        let params = parts
+            .filter(|param| !param.is_empty())
+            .map(|param| match param.split_once('=') {
+                Some((key, value)) => (
+                    key.trim().to_string(),
+                    Some(value.trim().trim_matches('"').to_string()),
+                ),
+                None => (param.to_string(), None),
+            })
+            .collect();
+        Some((name, params))
+    })
+}
+
+/// Which end of the connection a [`PerMessageDeflate`] runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeflateRole {
+    Client,
+    Server,
+}
+
+/// Compresses outgoing and decompresses incoming message payloads
+pub struct PerMessageDeflate {
+    compress: Compress,
+    decompress: Decompress,
+    reset_compress: bool,
+    reset_decompress: bool,
+}
+
+impl PerMessageDeflate {
+    pub fn new(params: &DeflateParams, role: DeflateRole, compression_level: u32) -> Self {
+        let (own_bits, peer_bits, reset_compress, reset_decompress) = match role {
+            DeflateRole::Client => (
+                params.client_max_window_bits,
+                params.server_max_window_bits,
+                params.client_no_context_takeover,
+                params.server_no_context_takeover,
+            ),
+            DeflateRole::Server => (
+                params.server_max_window_bits,
+                params.client_max_window_bits,
+                params.server_no_context_takeover,
+                params.client_no_context_takeover,
+            ),
+        };
+
+        Self {
+            // zlib can't produce raw deflate with an 8-bit window; 9 bits fits
+            // every peer that allows 8 (RFC 7692, section 7.1.2.2)
+            compress: Compress::new_with_window_bits(
+                Compression::new(compression_level),
+                false,
+                own_bits.max(MIN_WINDOW_BITS + 1),
+            ),
+            decompress: Decompress::new_with_window_bits(false, peer_bits.max(MIN_WINDOW_BITS + 1)),
+            reset_compress,
+            reset_decompress,
+        }
+    }
+
+    /// Compress the payload of an outgoing message
+    pub fn compress(&mut self, payload: &[u8]) -> Result<Vec<u8>, TransportError> {
+        let mut output = Vec::with_capacity(payload.len() / 2 + 64);
+        let start = self.compress.total_in();
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            self.compress
+                .compress_vec(&payload[consumed..], &mut output, FlushCompress::Sync)
+                .map_err(|e| TransportError::SendFailed(e.to_string()))?;
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == payload.len() && output.len() < output.capacity() {
+                break;
+            }
+            output.reserve(output.capacity().max(64));
+        }
+
+        if output.ends_with(&DEFLATE_TRAILER) {
+            output.truncate(output.len() - DEFLATE_TRAILER.len());
+        }
+        if self.reset_compress {
+            self.compress.reset();
+        }
+        Ok(output)
+    }
+
+    /// Decompress the payload of an incoming message flagged with RSV1
+    ///
+    /// Fails once the message grows past `max_size` bytes.
+    pub fn decompress(
+        &mut self,
+        payload: &[u8],
+        max_size: usize,
+    ) -> Result<Vec<u8>, TransportError> {
+        let mut input = Vec::with_capacity(payload.len() + DEFLATE_TRAILER.len());
+        input.extend_from_slice(payload);
+        input.extend_from_slice(&DEFLATE_TRAILER);
+
+        let mut output = Vec::with_capacity((payload.len() * 4).clamp(64, max_size.max(64)));
+        let start = self.decompress.total_in();
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let status = self
+                .decompress
+                .decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+                .map_err(|e| TransportError::ReceiveFailed(e.to_string()))?;
+            if output.len() > max_size {
+                return Err(TransportError::ReceiveFailed(format!(
+                    "decompressed message exceeds {} bytes",
+                    max_size
+                )));
+            }
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let done = consumed == input.len() && output.len() < output.capacity();
+            if done || status == Status::StreamEnd {
+                break;
+            }
+            output.reserve(output.capacity());
+        }
+
+        if self.reset_decompress {
+            self.decompress.reset(false);
+        }
+        Ok(output)
+    }
+}
+
+/// Largest frame a [`DeflateStream`] reads, tungstenite's default limit
+///
+/// Frames written come from tungstenite, which has already checked them
+/// against its own configuration, so they are not limited here.
+const MAX_FRAME_SIZE: usize = 16 << 20;
+
+// Opcodes of the frames that carry messages
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+
+/// A WebSocket frame, with its payload unmasked
+struct Frame {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Parse the frame at the start of `buf`, with the number of bytes it spans
+    ///
+    /// Fails on frames whose payload is longer than `max_size` bytes.
+    fn parse(buf: &[u8], max_size: usize) -> io::Result<Option<(Self, usize)>> {
+        let [first, second, ..] = *buf else {
+            return Ok(None);
+        };
+        let mut start = 2;
+        let len = match second & 0x7f {
+            126 => {
+                let Some(len) = buf.get(2..4) else {
+                    return Ok(None);
+                };
+                start = 4;
+                u16::from_be_bytes([len[0], len[1]]) as usize
+            }
+            127 => {
+                let Some(len) = buf.get(2..10) else {
+                    return Ok(None);
+                };
+                start = 10;
+                let len = u64::from_be_bytes(len.try_into().expect("eight bytes"));
+                usize::try_from(len).unwrap_or(usize::MAX)
+            }
+            len => len as usize,
+        };
+        if len > max_size {
+            return Err(invalid_data(format!("frame of {} bytes is too large", len)));
+        }
+
+        let mask = if second & 0x80 != 0 {
+            let Some(key) = buf.get(start..start + 4) else {
+                return Ok(None);
+            };
+            start += 4;
+            Some([key[0], key[1], key[2], key[3]])
+        } else {
+            None
+        };
+        let Some(payload) = buf.get(start..start + len) else {
+            return Ok(None);
+        };
+
+        let mut payload = payload.to_vec();
+        if let Some(mask) = mask {
+            apply_mask(&mut payload, mask);
+        }
+        let frame = Frame {
+            fin: first & 0x80 != 0,
+            rsv1: first & 0x40 != 0,
+            opcode: first & 0x0f,
+            mask,
+            payload,
+        };
+        Ok(Some((frame, start + len)))
+    }
+
+    /// Append the frame to `out`, masking the payload with the frame's key
+    fn write(mut self, out: &mut Vec<u8>) {
+        out.push(u8::from(self.fin) << 7 | u8::from(self.rsv1) << 6 | self.opcode);
+        let mask_bit = if self.mask.is_some() { 0x80 } else { 0 };
+        match self.payload.len() {
+            len @ 0..=125 => out.push(mask_bit | len as u8),
+            len @ 126..=0xffff => {
+                out.push(mask_bit | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                out.push(mask_bit | 127);
+                out.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        if let Some(mask) = self.mask {
+            out.extend_from_slice(&mask);
+            apply_mask(&mut self.payload, mask);
+        }
+        out.extend_from_slice(&self.payload);
+    }
+
+    fn is_data(&self) -> bool {
+        matches!(self.opcode, OP_TEXT | OP_BINARY)
+    }
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+fn invalid_data(reason: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+/// Runs permessage-deflate underneath tungstenite
+///
+/// Until an extension is agreed, bytes pass through untouched. Incoming
+/// compressed messages are handed on as a single uncompressed frame, and
+/// every unfragmented text or binary frame written is compressed. Control
+/// frames and fragmented outgoing messages are left as they are.
+pub struct DeflateStream<S> {
+    inner: S,
+    /// What a client offered, until the handshake response has been read
+    offer: Option<DeflateConfig>,
+    params: Option<DeflateParams>,
+    deflate: Option<PerMessageDeflate>,
+    max_message_size: usize,
+    /// Bytes read from the socket and not processed yet
+    read_buf: Vec<u8>,
+    /// Processed bytes waiting to be read, from `read_pos`
+    readable: Vec<u8>,
+    read_pos: usize,
+    /// A compressed message arriving in fragments
+    fragments: Option<Frame>,
+    /// Bytes written that don't make up a whole frame yet
+    write_buf: Vec<u8>,
+    /// Processed bytes waiting to be written to the socket, from `write_pos`
+    writable: Vec<u8>,
+    write_pos: usize,
+}
+
+impl<S> DeflateStream<S> {
+    /// Wrap a server's socket once the handshake agreed on `params`
+    ///
+    /// Without `params`, the stream passes every byte through.
+    pub fn server(
+        inner: S,
+        params: Option<DeflateParams>,
+        compression_level: u32,
+        max_message_size: usize,
+    ) -> Self {
+        let deflate = params
+            .map(|params| PerMessageDeflate::new(&params, DeflateRole::Server, compression_level));
+        Self::new(inner, None, params, deflate, max_message_size)
+    }
+
+    /// Wrap a client's socket before the handshake, which offers `offer`
+    ///
+    /// The handshake passes through; the server's response decides whether
+    /// the frames after it are compressed.
+    pub fn client(inner: S, offer: Option<DeflateConfig>, max_message_size: usize) -> Self {
+        Self::new(inner, offer, None, None, max_message_size)
+    }
+
+    fn new(
+        inner: S,
+        offer: Option<DeflateConfig>,
+        params: Option<DeflateParams>,
+        deflate: Option<PerMessageDeflate>,
+        max_message_size: usize,
+    ) -> Self {
+        Self {
+            inner,
+            offer,
+            params,
+            deflate,
+            max_message_size,
+            read_buf: Vec::new(),
+            readable: Vec::new(),
+            read_pos: 0,
+            fragments: None,
+            write_buf: Vec::new(),
+            writable: Vec::new(),
+            write_pos: 0,
+        }
+    }
+
+    /// The parameters agreed in the handshake, if the extension is in use
+    pub fn params(&self) -> Option<DeflateParams> {
+        self.params
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Process buffered input, returning whether anything became readable
+    fn process_reads(&mut self) -> io::Result<bool> {
+        if let Some(offer) = &self.offer {
+            let Some(end) = self.read_buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+                return Ok(false);
+            };
+            let end = end + 4;
+            let response = String::from_utf8_lossy(&self.read_buf[..end]);
+            let extensions: Vec<&str> = response
+                .lines()
+                .skip(1)
+                .filter_map(|line| line.split_once(':'))
+                .filter(|(name, _)| name.trim().eq_ignore_ascii_case("sec-websocket-extensions"))
+                .map(|(_, value)| value.trim())
+                .collect();
+            let header = (!extensions.is_empty()).then(|| extensions.join(", "));
+            self.params = offer
+                .accept_response(header.as_deref())
+                .map_err(invalid_data)?;
+            self.deflate = self.params.map(|params| {
+                PerMessageDeflate::new(&params, DeflateRole::Client, offer.compression_level)
+            });
+            self.offer = None;
+            self.readable.extend(self.read_buf.drain(..end));
+            return Ok(true);
+        }
+
+        let Some(deflate) = &mut self.deflate else {
+            self.readable.append(&mut self.read_buf);
+            return Ok(!self.readable.is_empty());
+        };
+
+        let mut consumed = 0;
+        while let Some((frame, len)) = Frame::parse(&self.read_buf[consumed..], MAX_FRAME_SIZE)? {
+            let raw = consumed..consumed + len;
+            consumed += len;
+
+            let message = match (frame.opcode, &mut self.fragments) {
+                (OP_CONTINUATION, Some(message)) => {
+                    message.payload.extend_from_slice(&frame.payload);
+                    if message.payload.len() > self.max_message_size {
+                        return Err(invalid_data("compressed message is too large"));
+                    }
+                    if !frame.fin {
+                        continue;
+                    }
+                    self.fragments.take().expect("message in fragments")
+                }
+                _ if frame.is_data() && frame.rsv1 => {
+                    if !frame.fin {
+                        self.fragments = Some(frame);
+                        continue;
+                    }
+                    frame
+                }
+                _ => {
+                    self.readable.extend_from_slice(&self.read_buf[raw]);
+                    continue;
+                }
+            };
+
+            let payload = deflate
+                .decompress(&message.payload, self.max_message_size)
+                .map_err(invalid_data)?;
+            Frame {
+                fin: true,
+                rsv1: false,
+                payload,
+                ..message
+            }
+            .write(&mut self.readable);
+        }
+        self.read_buf.drain(..consumed);
+        Ok(self.read_pos < self.readable.len())
+    }
+
+    /// Compress the whole frames written so far
+    fn process_writes(&mut self) -> io::Result<()> {
+        let Some(deflate) = &mut self.deflate else {
+            self.writable.append(&mut self.write_buf);
+            return Ok(());
+        };
+
+        let mut consumed = 0;
+        while let Some((mut frame, len)) = Frame::parse(&self.write_buf[consumed..], usize::MAX)? {
+            if frame.is_data() && frame.fin && !frame.rsv1 {
+                frame.payload = deflate
+                    .compress(&frame.payload)
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                frame.rsv1 = true;
+                frame.write(&mut self.writable);
+            } else {
+                self.writable
+                    .extend_from_slice(&self.write_buf[consumed..consumed + len]);
+            }
+            consumed += len;
+        }
+        self.write_buf.drain(..consumed);
+        Ok(())
+    }
+}
+
+impl<S: AsyncWrite + Unpin> DeflateStream<S> {
+    /// Write out processed bytes
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.write_pos < self.writable.len() {
+            let written =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.writable[self.write_pos..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_pos += written;
+        }
+        self.writable.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DeflateStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.read_pos < this.readable.len() {
+                let len = buf.remaining().min(this.readable.len() - this.read_pos);
+                buf.put_slice(&this.readable[this.read_pos..this.read_pos + len]);
+                this.read_pos += len;
+                if this.read_pos == this.readable.len() {
+                    this.readable.clear();
+                    this.read_pos = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            let passthrough = this.offer.is_none() && this.deflate.is_none();
+            if passthrough && this.read_buf.is_empty() {
+                return Pin::new(&mut this.inner).poll_read(cx, buf);
+            }
+            if this.process_reads()? {
+                continue;
+            }
+
+            let mut chunk = [0u8; 8192];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                // Hand over a truncated frame for tungstenite to report
+                this.readable.append(&mut this.read_buf);
+                if this.readable.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+            }
+            this.read_buf.extend_from_slice(chunk.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DeflateStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        if this.deflate.is_none() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        this.write_buf.extend_from_slice(buf);
+        this.process_writes()?;
+        // The bytes are accepted; whatever the socket doesn't take now is
+        // written on the next call or flush
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(params: &DeflateParams) -> (PerMessageDeflate, PerMessageDeflate) {
+        (
+            PerMessageDeflate::new(params, DeflateRole::Client, 6),
+            PerMessageDeflate::new(params, DeflateRole::Server, 6),
+        )
+    }
+
+    #[test]
+    fn test_negotiation_honours_offer() {
+        let client = DeflateConfig::new()
+            .with_server_no_context_takeover()
+            .with_server_max_window_bits(10);
+        let server = DeflateConfig::new().with_client_max_window_bits(12);
+
+        let offer = client.offer();
+        assert_eq!(
+            offer,
+            "permessage-deflate; server_no_context_takeover; server_max_window_bits=10; client_max_window_bits"
+        );
+
+        let agreed = server.accept_offer(&offer).unwrap();
+        assert!(agreed.server_no_context_takeover);
+        assert_eq!(agreed.server_max_window_bits, 10);
+        assert_eq!(agreed.client_max_window_bits, 12);
+
+        let response = agreed.response();
+        assert_eq!(
+            client.accept_response(Some(&response)).unwrap(),
+            Some(agreed)
+        );
+        assert_eq!(client.accept_response(None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_offers_and_responses_are_rejected() {
+        let server = DeflateConfig::new();
+        assert!(server
+            .accept_offer("permessage-deflate; server_max_window_bits=7")
+            .is_none());
+        assert!(server
+            .accept_offer("permessage-deflate; unknown_param")
+            .is_none());
+        // A later acceptable offer is picked
+        let agreed = server
+            .accept_offer("permessage-deflate; foo, permessage-deflate; client_no_context_takeover")
+            .unwrap();
+        assert!(agreed.client_no_context_takeover);
+        assert!(server.accept_offer("x-webkit-deflate-frame").is_none());
+
+        let client = DeflateConfig::new().with_server_max_window_bits(10);
+        assert!(client.accept_response(Some("permessage-deflate")).is_err());
+        assert!(client
+            .accept_response(Some("permessage-deflate; server_max_window_bits=12"))
+            .is_err());
+        assert!(client
+            .accept_response(Some("x-webkit-deflate-frame"))
+            .is_err());
+        assert_eq!(client.accept_response(Some("lwp-datagrams")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_messages_round_trip_with_context_takeover() {
+        let params = DeflateConfig::new()
+            .accept_offer(&DeflateConfig::new().offer())
+            .unwrap();
+        let (mut client, mut server) = pair(&params);
+
+        let message = br#"{"type":"update","name":"counter","value":1}"#;
+        let first = client.compress(message).unwrap();
+        let second = client.compress(message).unwrap();
+        assert!(!first.ends_with(&DEFLATE_TRAILER));
+        // The shared window makes a repeated message almost free
+        assert!(second.len() < first.len());
+
+        assert_eq!(server.decompress(&first, 1024).unwrap(), message);
+        assert_eq!(server.decompress(&second, 1024).unwrap(), message);
+        assert!(server
+            .decompress(&client.compress(message).unwrap(), 8)
+            .is_err());
+    }
+
+    #[test]
+    fn test_no_context_takeover_and_small_windows() {
+        let params = DeflateConfig::new()
+            .with_server_no_context_takeover()
+            .with_client_no_context_takeover()
+            .accept_offer(
+                &DeflateConfig::new()
+                    .with_client_max_window_bits(8)
+                    .with_server_max_window_bits(9)
+                    .offer(),
+            )
+            .unwrap();
+        let (mut client, mut server) = pair(&params);
+
+        let message = "row ".repeat(500);
+        let first = server.compress(message.as_bytes()).unwrap();
+        let second = server.compress(message.as_bytes()).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(client.decompress(&first, 4096).unwrap(), message.as_bytes());
+        assert_eq!(
+            client.decompress(&second, 4096).unwrap(),
+            message.as_bytes()
+        );
+
+        let reply = client.compress(message.as_bytes()).unwrap();
+        assert_eq!(server.decompress(&reply, 4096).unwrap(), message.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_frame_limit_applies_to_reads_only() {
+        use tokio::io::AsyncWriteExt;
+
+        let params = DeflateConfig::new()
+            .accept_offer(&DeflateConfig::new().offer())
+            .unwrap();
+        let mut frame = Vec::new();
+        Frame {
+            fin: true,
+            rsv1: false,
+            opcode: OP_BINARY,
+            mask: None,
+            payload: vec![0; MAX_FRAME_SIZE + 1],
+        }
+        .write(&mut frame);
+        assert!(Frame::parse(&frame, MAX_FRAME_SIZE).is_err());
+
+        let mut stream = DeflateStream::server(Vec::new(), Some(params), 6, usize::MAX);
+        stream.write_all(&frame).await.unwrap();
+        stream.flush().await.unwrap();
+
+        let (written, len) = Frame::parse(stream.get_ref(), MAX_FRAME_SIZE)
+            .unwrap()
+            .unwrap();
+        assert_eq!(len, stream.get_ref().len());
+        assert!(written.rsv1);
+        let (mut client, _) = pair(&params);
+        assert_eq!(
+            client
+                .decompress(&written.payload, usize::MAX)
+                .unwrap()
+                .len(),
+            MAX_FRAME_SIZE + 1
+        );
+    }
+}
@@ -4,6 +4,9 @@
 
 pub mod wasm;
 pub mod native;
+#[cfg(feature = "compression")]
+pub mod deflate;
+pub mod mux;
 
 // Re-export the WASM WebSocket implementation
 pub use wasm::WasmWebSocketConnection;
@@ -0,0 +1,889 @@
+//! WebSocket Multiplexing
+//!
+//! An optional framing layer carrying many logical channels over one
+//! WebSocket, so that RPC, signals and bulk transfers share a socket without
+//! blocking each other. Channels mirror WebTransport streams: either side
+//! opens them, each has its own flow-control window, and each direction is
+//! closed on its own.
+//!
+//! Every frame is a binary WebSocket message laid out as
+//! `[marker][kind u8][channel u32 BE][payload]`. Clients open odd channel
+//! ids and servers even ones, so both sides can open channels at once.
+//!
+//! Each message counts its length plus [`MESSAGE_OVERHEAD`] against the
+//! window. A peer sending past the window it was granted has its channel
+//! reset, so a channel never queues more than its window.
+
+use crate::transport::{BiStream, Message, MessageType, StreamOpener, TransportError};
+use async_trait::async_trait;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use leptos::logging::error;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, Notify};
+
+/// Marks a WebSocket binary frame as a multiplexed frame
+const MARKER: &[u8; 4] = b"\0lwm";
+
+/// Size of the kind and channel id following the marker
+const HEADER_LEN: usize = 5;
+
+/// Bytes of window every message costs on top of its data, bounding the
+/// number of messages a window admits
+pub const MESSAGE_OVERHEAD: u32 = 64;
+
+/// Stream of the messages received on a channel
+pub type ChannelStream = Pin<Box<dyn Stream<Item = Result<Message, TransportError>> + Send>>;
+
+/// Sink writing messages to a channel
+pub type ChannelSink = Pin<Box<dyn Sink<Message, Error = TransportError> + Send>>;
+
+/// Which end of the socket a multiplexer runs on, deciding the ids it opens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuxRole {
+    Client,
+    Server,
+}
+
+impl MuxRole {
+    fn first_id(self) -> u32 {
+        match self {
+            MuxRole::Client => 1,
+            MuxRole::Server => 2,
+        }
+    }
+
+    /// Whether the peer of this role may open `id`
+    fn peer_opens(self, id: u32) -> bool {
+        match self {
+            MuxRole::Client => id % 2 == 0,
+            MuxRole::Server => id % 2 == 1,
+        }
+    }
+}
+
+/// Configuration of a [`Multiplexer`]
+#[derive(Debug, Clone)]
+pub struct MuxConfig {
+    /// Bytes a peer may send on a channel before this side has read them
+    pub initial_window: u32,
+    /// Channels the peer may keep open at once
+    pub max_channels: usize,
+}
+
+impl Default for MuxConfig {
+    fn default() -> Self {
+        Self {
+            initial_window: 256 * 1024,
+            max_channels: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+    /// Opens a channel; carries the opener's window and the channel's label
+    Open,
+    /// One message on a channel
+    Data,
+    /// Grants the peer more bytes to send
+    Window,
+    /// The sender will send no more on the channel
+    Close,
+    /// Abandons the channel in both directions
+    Reset,
+}
+
+impl FrameKind {
+    fn code(self) -> u8 {
+        match self {
+            FrameKind::Open => 0,
+            FrameKind::Data => 1,
+            FrameKind::Window => 2,
+            FrameKind::Close => 3,
+            FrameKind::Reset => 4,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(FrameKind::Open),
+            1 => Some(FrameKind::Data),
+            2 => Some(FrameKind::Window),
+            3 => Some(FrameKind::Close),
+            4 => Some(FrameKind::Reset),
+            _ => None,
+        }
+    }
+}
+
+struct Frame {
+    kind: FrameKind,
+    channel: u32,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    fn encode(kind: FrameKind, channel: u32, payload: &[u8]) -> Message {
+        let mut data = Vec::with_capacity(MARKER.len() + HEADER_LEN + payload.len());
+        data.extend_from_slice(MARKER);
+        data.push(kind.code());
+        data.extend_from_slice(&channel.to_be_bytes());
+        data.extend_from_slice(payload);
+        Message {
+            data,
+            message_type: MessageType::Binary,
+        }
+    }
+
+    /// Decode a WebSocket message, if it is a multiplexed frame
+    fn decode(data: &[u8]) -> Option<Self> {
+        let data = data.strip_prefix(MARKER)?;
+        if data.len() < HEADER_LEN {
+            return None;
+        }
+        Some(Self {
+            kind: FrameKind::from_code(data[0])?,
+            channel: u32::from_be_bytes([data[1], data[2], data[3], data[4]]),
+            payload: data[HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+fn encode_data(message: &Message) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(1 + message.data.len());
+    payload.push(match message.message_type {
+        MessageType::Text => 0,
+        _ => 1,
+    });
+    payload.extend_from_slice(&message.data);
+    payload
+}
+
+/// Window a message costs
+fn cost(message: &Message) -> u32 {
+    u32::try_from(message.data.len())
+        .unwrap_or(u32::MAX)
+        .saturating_add(MESSAGE_OVERHEAD)
+}
+
+fn decode_data(payload: &[u8]) -> Option<Message> {
+    let (message_type, data) = payload.split_first()?;
+    Some(Message {
+        data: data.to_vec(),
+        message_type: match message_type {
+            0 => MessageType::Text,
+            _ => MessageType::Binary,
+        },
+    })
+}
+
+/// Bytes a channel may still send before the peer grants more
+#[derive(Default)]
+struct Credit {
+    state: Mutex<CreditState>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct CreditState {
+    available: i64,
+    reset: bool,
+}
+
+impl Credit {
+    fn grant(&self, bytes: u32) {
+        self.state.lock().unwrap().available += i64::from(bytes);
+        self.notify.notify_waiters();
+    }
+
+    fn reset(&self) {
+        self.state.lock().unwrap().reset = true;
+        self.notify.notify_waiters();
+    }
+
+    /// Wait for any credit, then take `bytes`
+    ///
+    /// A message larger than the remaining credit still goes out once some is
+    /// left, so a message larger than the whole window cannot stall the
+    /// channel; the peer buffers at most one message beyond its window.
+    async fn acquire(&self, bytes: u32) -> Result<(), TransportError> {
+        loop {
+            let notified = self.notify.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.reset {
+                    return Err(TransportError::ConnectionClosed);
+                }
+                if state.available > 0 {
+                    state.available -= i64::from(bytes);
+                    return Ok(());
+                }
+            }
+            notified.await;
+        }
+    }
+}
+
+struct Channel {
+    /// Delivers received messages, until the peer closes or the receiver is dropped
+    incoming: Option<mpsc::Sender<Message>>,
+    /// Bytes the peer may still send before it is granted more
+    window: i64,
+    credit: Arc<Credit>,
+    local_closed: bool,
+    remote_closed: bool,
+}
+
+impl Channel {
+    fn is_done(&self) -> bool {
+        self.local_closed && (self.remote_closed || self.incoming.is_none())
+    }
+}
+
+struct Shared {
+    role: MuxRole,
+    config: MuxConfig,
+    outgoing: mpsc::UnboundedSender<Message>,
+    channels: Mutex<HashMap<u32, Channel>>,
+    next_id: AtomicU32,
+}
+
+impl Shared {
+    fn send_frame(
+        &self,
+        kind: FrameKind,
+        channel: u32,
+        payload: &[u8],
+    ) -> Result<(), TransportError> {
+        self.outgoing
+            .send(Frame::encode(kind, channel, payload))
+            .map_err(|_| TransportError::ConnectionClosed)
+    }
+
+    /// Track channel `id`, which may send `credit` bytes before the peer grants more
+    fn register(self: &Arc<Self>, id: u32, label: String, credit: u32) -> MuxStream {
+        // Every queued message took at least its overhead of the window, and
+        // one more may arrive while any window is left
+        let capacity = self.config.initial_window / MESSAGE_OVERHEAD + 1;
+        let (sender, receiver) = mpsc::channel(capacity as usize);
+        let credit_handle = Arc::new(Credit::default());
+        credit_handle.grant(credit);
+        self.channels.lock().unwrap().insert(
+            id,
+            Channel {
+                incoming: Some(sender),
+                window: i64::from(self.config.initial_window),
+                credit: credit_handle.clone(),
+                local_closed: false,
+                remote_closed: false,
+            },
+        );
+
+        MuxStream {
+            label,
+            sender: Arc::new(ChannelSender {
+                id,
+                shared: self.clone(),
+                credit: credit_handle,
+                closed: AtomicBool::new(false),
+            }),
+            receiver: ChannelReceiver {
+                id,
+                shared: self.clone(),
+                incoming: receiver,
+                consumed: 0,
+            },
+        }
+    }
+
+    /// Apply `update` to channel `id`, forgetting the channel once both directions are done
+    fn update(&self, id: u32, update: impl FnOnce(&mut Channel)) {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(channel) = channels.get_mut(&id) {
+            update(channel);
+            if channel.is_done() {
+                channels.remove(&id);
+            }
+        }
+    }
+
+    fn remove(&self, id: u32) {
+        if let Some(channel) = self.channels.lock().unwrap().remove(&id) {
+            channel.credit.reset();
+        }
+    }
+
+    fn handle(self: &Arc<Self>, frame: Frame, accepted: &mpsc::UnboundedSender<MuxStream>) {
+        match frame.kind {
+            FrameKind::Open => self.handle_open(frame, accepted),
+            FrameKind::Data => {
+                let Some(message) = decode_data(&frame.payload) else {
+                    return;
+                };
+                let mut violated = false;
+                self.update(frame.channel, |channel| {
+                    if channel.window <= 0 {
+                        violated = true;
+                        return;
+                    }
+                    channel.window -= i64::from(cost(&message));
+                    if let Some(incoming) = &channel.incoming {
+                        violated = matches!(
+                            incoming.try_send(message),
+                            Err(mpsc::error::TrySendError::Full(_))
+                        );
+                    }
+                });
+                if violated {
+                    error!("Channel {} sent past its window", frame.channel);
+                    let _ = self.send_frame(FrameKind::Reset, frame.channel, &[]);
+                    self.remove(frame.channel);
+                }
+            }
+            FrameKind::Window => {
+                let Ok(bytes) = <[u8; 4]>::try_from(frame.payload.as_slice()) else {
+                    return;
+                };
+                self.update(frame.channel, |channel| {
+                    channel.credit.grant(u32::from_be_bytes(bytes))
+                });
+            }
+            FrameKind::Close => self.update(frame.channel, |channel| {
+                channel.incoming = None;
+                channel.remote_closed = true;
+            }),
+            FrameKind::Reset => self.remove(frame.channel),
+        }
+    }
+
+    fn handle_open(self: &Arc<Self>, frame: Frame, accepted: &mpsc::UnboundedSender<MuxStream>) {
+        let id = frame.channel;
+        let refused = !self.role.peer_opens(id) || frame.payload.len() < 4 || {
+            let channels = self.channels.lock().unwrap();
+            channels.contains_key(&id) || channels.len() >= self.config.max_channels
+        };
+        if refused {
+            let _ = self.send_frame(FrameKind::Reset, id, &[]);
+            return;
+        }
+
+        let window = u32::from_be_bytes([
+            frame.payload[0],
+            frame.payload[1],
+            frame.payload[2],
+            frame.payload[3],
+        ]);
+        let label = String::from_utf8_lossy(&frame.payload[4..]).into_owned();
+        let stream = self.register(id, label, window);
+        let _ = self.send_frame(
+            FrameKind::Window,
+            id,
+            &self.config.initial_window.to_be_bytes(),
+        );
+        if let Err(mpsc::error::SendError(stream)) = accepted.send(stream) {
+            stream.reset();
+        }
+    }
+
+    /// End every channel once the socket is gone
+    fn shutdown(&self) {
+        for (_, channel) in self.channels.lock().unwrap().drain() {
+            channel.credit.reset();
+        }
+    }
+}
+
+/// Reads the socket and dispatches its frames until it closes
+async fn drive<S>(shared: Arc<Shared>, incoming: S, accepted: mpsc::UnboundedSender<MuxStream>)
+where
+    S: Stream<Item = Result<Message, TransportError>> + Send,
+{
+    futures::pin_mut!(incoming);
+    while let Some(Ok(message)) = incoming.next().await {
+        if message.message_type == MessageType::Close {
+            break;
+        }
+        if let Some(frame) = Frame::decode(&message.data) {
+            shared.handle(frame, &accepted);
+        }
+    }
+    shared.shutdown();
+}
+
+struct ChannelSender {
+    id: u32,
+    shared: Arc<Shared>,
+    credit: Arc<Credit>,
+    closed: AtomicBool,
+}
+
+impl ChannelSender {
+    async fn send(&self, message: &Message) -> Result<(), TransportError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(TransportError::InvalidState(
+                "Channel closed for sending".to_string(),
+            ));
+        }
+        self.credit.acquire(cost(message)).await?;
+        self.shared
+            .send_frame(FrameKind::Data, self.id, &encode_data(message))
+    }
+
+    fn close(&self) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let _ = self.shared.send_frame(FrameKind::Close, self.id, &[]);
+        self.shared
+            .update(self.id, |channel| channel.local_closed = true);
+    }
+
+    fn reset(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _ = self.shared.send_frame(FrameKind::Reset, self.id, &[]);
+        self.shared.remove(self.id);
+    }
+}
+
+impl Drop for ChannelSender {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+struct ChannelReceiver {
+    id: u32,
+    shared: Arc<Shared>,
+    incoming: mpsc::Receiver<Message>,
+    /// Bytes read since the peer was last granted more
+    consumed: u32,
+}
+
+impl ChannelReceiver {
+    async fn receive(&mut self) -> Option<Message> {
+        let message = self.incoming.recv().await?;
+        self.consumed = self.consumed.saturating_add(cost(&message));
+        if self.consumed >= self.shared.config.initial_window / 2 {
+            let consumed = self.consumed;
+            self.shared
+                .update(self.id, |channel| channel.window += i64::from(consumed));
+            let _ = self
+                .shared
+                .send_frame(FrameKind::Window, self.id, &consumed.to_be_bytes());
+            self.consumed = 0;
+        }
+        Some(message)
+    }
+}
+
+impl Drop for ChannelReceiver {
+    fn drop(&mut self) {
+        self.shared
+            .update(self.id, |channel| channel.incoming = None);
+    }
+}
+
+/// A logical channel of a [`Multiplexer`], like a bidirectional WebTransport stream
+pub struct MuxStream {
+    label: String,
+    sender: Arc<ChannelSender>,
+    receiver: ChannelReceiver,
+}
+
+impl MuxStream {
+    pub fn id(&self) -> u32 {
+        self.sender.id
+    }
+
+    /// Label the opener gave the channel, empty if none
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Send `message`, waiting while the peer's window is used up
+    pub async fn send(&self, message: &Message) -> Result<(), TransportError> {
+        self.sender.send(message).await
+    }
+
+    /// Receive the next message, or `ConnectionClosed` once the peer closed the channel
+    pub async fn receive(&mut self) -> Result<Message, TransportError> {
+        self.receiver
+            .receive()
+            .await
+            .ok_or(TransportError::ConnectionClosed)
+    }
+
+    pub async fn send_data<T: serde::Serialize>(&self, data: &T) -> Result<(), TransportError> {
+        let message = Message {
+            data: serde_json::to_vec(data)
+                .map_err(|e| TransportError::SendFailed(e.to_string()))?,
+            message_type: MessageType::Text,
+        };
+        self.send(&message).await
+    }
+
+    pub async fn receive_data<T: serde::de::DeserializeOwned>(
+        &mut self,
+    ) -> Result<T, TransportError> {
+        let message = self.receive().await?;
+        serde_json::from_slice(&message.data)
+            .map_err(|e| TransportError::ReceiveFailed(e.to_string()))
+    }
+
+    /// Stop sending; the peer receives what was sent, then the end of the channel
+    ///
+    /// Dropping the stream closes it too.
+    pub fn close(&self) {
+        self.sender.close();
+    }
+
+    /// Abandon the channel in both directions
+    pub fn reset(&self) {
+        self.sender.reset();
+    }
+
+    /// Split into a message stream and sink, the way a whole transport splits
+    pub fn split(self) -> (ChannelStream, ChannelSink) {
+        let MuxStream {
+            sender, receiver, ..
+        } = self;
+        let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+            let message = receiver.receive().await?;
+            Some((Ok(message), receiver))
+        });
+        let sink = futures::sink::unfold(sender, |sender, message: Message| async move {
+            sender.send(&message).await?;
+            Ok::<_, TransportError>(sender)
+        });
+        (Box::pin(stream), Box::pin(sink))
+    }
+}
+
+#[async_trait]
+impl BiStream for MuxStream {
+    fn id(&self) -> u32 {
+        MuxStream::id(self)
+    }
+
+    async fn send_message(&mut self, message: &Message) -> Result<(), TransportError> {
+        self.send(message).await
+    }
+
+    async fn receive_message(&mut self) -> Result<Message, TransportError> {
+        self.receive().await
+    }
+}
+
+/// Logical channels over one WebSocket
+///
+/// Built from the message stream and sink of a socket, like those
+/// `Transport::split` returns. Messages that are not multiplexed frames are
+/// ignored.
+///
+/// # Example
+///
+/// ```ignore
+/// let mux = connection.multiplex(MuxConfig::default())?;
+///
+/// let rpc = mux.open_labeled("rpc").await?;
+/// let mut upload = mux.open_labeled("upload").await?;
+/// // A slow upload only waits on its own window
+/// for chunk in file.chunks(64 * 1024) {
+///     upload.send(&Message { data: chunk.to_vec(), message_type: MessageType::Binary }).await?;
+/// }
+/// upload.close();
+/// ```
+pub struct Multiplexer {
+    shared: Arc<Shared>,
+    accepted: tokio::sync::Mutex<mpsc::UnboundedReceiver<MuxStream>>,
+}
+
+impl Multiplexer {
+    pub fn new<S, K>(role: MuxRole, incoming: S, outgoing: K, config: MuxConfig) -> Self
+    where
+        S: Stream<Item = Result<Message, TransportError>> + Send + 'static,
+        K: Sink<Message, Error = TransportError> + Send + 'static,
+    {
+        let (outgoing_sender, mut outgoing_receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut outgoing = Box::pin(outgoing);
+            while let Some(message) = outgoing_receiver.recv().await {
+                if let Err(e) = outgoing.send(message).await {
+                    error!("Multiplexed socket closed: {}", e);
+                    break;
+                }
+            }
+        });
+
+        let shared = Arc::new(Shared {
+            role,
+            next_id: AtomicU32::new(role.first_id()),
+            config,
+            outgoing: outgoing_sender,
+            channels: Mutex::new(HashMap::new()),
+        });
+        let (accepted_sender, accepted) = mpsc::unbounded_channel();
+        tokio::spawn(drive(shared.clone(), incoming, accepted_sender));
+
+        Self {
+            shared,
+            accepted: tokio::sync::Mutex::new(accepted),
+        }
+    }
+
+    /// Multiplex a socket read and written through channels
+    pub(crate) fn over_channels(
+        role: MuxRole,
+        mut incoming: mpsc::UnboundedReceiver<Message>,
+        outgoing: mpsc::UnboundedSender<Message>,
+        config: MuxConfig,
+    ) -> Self {
+        let incoming =
+            futures::stream::poll_fn(move |cx| incoming.poll_recv(cx).map(|m| m.map(Ok)));
+        let outgoing = futures::sink::unfold(outgoing, |outgoing, message: Message| async move {
+            outgoing
+                .send(message)
+                .map_err(|_| TransportError::ConnectionClosed)?;
+            Ok::<_, TransportError>(outgoing)
+        });
+        Self::new(role, incoming, outgoing, config)
+    }
+
+    pub fn role(&self) -> MuxRole {
+        self.shared.role
+    }
+
+    /// Open a channel without a label
+    pub async fn open_bi(&self) -> Result<MuxStream, TransportError> {
+        self.open_labeled("").await
+    }
+
+    /// Open a channel, telling the peer what it carries
+    pub async fn open_labeled(&self, label: &str) -> Result<MuxStream, TransportError> {
+        let id = self.shared.next_id.fetch_add(2, Ordering::SeqCst);
+        // Nothing may be sent until the peer grants its window
+        let stream = self.shared.register(id, label.to_string(), 0);
+
+        let mut payload = self.shared.config.initial_window.to_be_bytes().to_vec();
+        payload.extend_from_slice(label.as_bytes());
+        self.shared.send_frame(FrameKind::Open, id, &payload)?;
+        Ok(stream)
+    }
+
+    /// Accept the next channel the peer opens
+    pub async fn accept_bi(&self) -> Result<MuxStream, TransportError> {
+        self.accepted
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or(TransportError::ConnectionClosed)
+    }
+
+    /// Number of channels open in at least one direction
+    pub fn channel_count(&self) -> usize {
+        self.shared.channels.lock().unwrap().len()
+    }
+}
+
+#[async_trait]
+impl StreamOpener for Multiplexer {
+    type Stream = MuxStream;
+
+    async fn open_stream(&self) -> Result<MuxStream, TransportError> {
+        self.open_bi().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    /// Two multiplexers connected back to back
+    fn pair(config: MuxConfig) -> (Multiplexer, Multiplexer) {
+        let (client_sender, server_receiver) = mpsc::unbounded_channel();
+        let (server_sender, client_receiver) = mpsc::unbounded_channel();
+        (
+            Multiplexer::over_channels(
+                MuxRole::Client,
+                client_receiver,
+                client_sender,
+                config.clone(),
+            ),
+            Multiplexer::over_channels(MuxRole::Server, server_receiver, server_sender, config),
+        )
+    }
+
+    fn text(data: &str) -> Message {
+        Message {
+            data: data.as_bytes().to_vec(),
+            message_type: MessageType::Text,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_channels_exchange_messages() {
+        let (client, server) = pair(MuxConfig::default());
+
+        let mut rpc = client.open_labeled("rpc").await.unwrap();
+        rpc.send(&text("ping")).await.unwrap();
+
+        let mut accepted = server.accept_bi().await.unwrap();
+        assert_eq!(accepted.label(), "rpc");
+        assert_eq!(accepted.id(), rpc.id());
+        assert_eq!(accepted.receive().await.unwrap(), text("ping"));
+
+        accepted.send_data(&vec![1, 2, 3]).await.unwrap();
+        assert_eq!(rpc.receive_data::<Vec<i32>>().await.unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_channels_are_bi_streams() {
+        let (client, server) = pair(MuxConfig::default());
+        tokio::spawn(async move {
+            let mut accepted = server.accept_bi().await.unwrap();
+            while let Ok(message) = accepted.receive().await {
+                accepted.send(&message).await.unwrap();
+            }
+        });
+
+        let mut stream = StreamOpener::open_stream(&client).await.unwrap();
+        assert_eq!(BiStream::id(&stream), 1);
+        BiStream::send_data(&mut stream, &vec![1, 2, 3])
+            .await
+            .unwrap();
+        let echoed: Vec<i32> = BiStream::receive_data(&mut stream).await.unwrap();
+        assert_eq!(echoed, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_either_side_opens_channels() {
+        let (client, server) = pair(MuxConfig::default());
+
+        let from_client = client.open_bi().await.unwrap();
+        let from_server = server.open_bi().await.unwrap();
+        assert_eq!(from_client.id() % 2, 1);
+        assert_eq!(from_server.id() % 2, 0);
+
+        from_server.send(&text("hello")).await.unwrap();
+        let mut accepted = client.accept_bi().await.unwrap();
+        assert_eq!(accepted.id(), from_server.id());
+        assert_eq!(accepted.receive().await.unwrap(), text("hello"));
+    }
+
+    #[tokio::test]
+    async fn test_window_blocks_only_its_channel() {
+        let config = MuxConfig {
+            initial_window: 8,
+            ..Default::default()
+        };
+        let (client, server) = pair(config);
+
+        let bulk = client.open_labeled("bulk").await.unwrap();
+        let small = client.open_labeled("small").await.unwrap();
+        bulk.send(&text("12345678")).await.unwrap();
+
+        // The server has not read the first chunk, so the window is used up
+        let blocked = timeout(Duration::from_millis(100), bulk.send(&text("more"))).await;
+        assert!(blocked.is_err());
+        small.send(&text("quick")).await.unwrap();
+
+        let mut bulk_end = server.accept_bi().await.unwrap();
+        let mut small_end = server.accept_bi().await.unwrap();
+        assert_eq!(small_end.receive().await.unwrap(), text("quick"));
+
+        // Reading the chunk grants the window back
+        assert_eq!(bulk_end.receive().await.unwrap(), text("12345678"));
+        timeout(Duration::from_secs(1), bulk.send(&text("more")))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bulk_end.receive().await.unwrap(), text("more"));
+    }
+
+    #[tokio::test]
+    async fn test_close_ends_one_direction() {
+        let (client, server) = pair(MuxConfig::default());
+
+        let mut channel = client.open_bi().await.unwrap();
+        channel.send(&text("last")).await.unwrap();
+        channel.close();
+        assert!(channel.send(&text("late")).await.is_err());
+
+        let mut accepted = server.accept_bi().await.unwrap();
+        assert_eq!(accepted.receive().await.unwrap(), text("last"));
+        assert!(matches!(
+            accepted.receive().await,
+            Err(TransportError::ConnectionClosed)
+        ));
+
+        // The other direction stays open until its own close
+        accepted.send(&text("reply")).await.unwrap();
+        assert_eq!(channel.receive().await.unwrap(), text("reply"));
+        accepted.close();
+        assert!(channel.receive().await.is_err());
+        assert_eq!(client.channel_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_channels_beyond_the_limit_are_reset() {
+        let config = MuxConfig {
+            max_channels: 1,
+            ..Default::default()
+        };
+        let (client, server) = pair(config);
+
+        let first = client.open_bi().await.unwrap();
+        let second = client.open_bi().await.unwrap();
+        first.send(&text("kept")).await.unwrap();
+        assert!(matches!(
+            timeout(Duration::from_secs(1), second.send(&text("refused")))
+                .await
+                .unwrap(),
+            Err(TransportError::ConnectionClosed)
+        ));
+        assert_eq!(server.channel_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sending_past_the_window_resets_the_channel() {
+        let config = MuxConfig {
+            initial_window: MESSAGE_OVERHEAD * 2,
+            ..Default::default()
+        };
+        let (peer, incoming) = mpsc::unbounded_channel();
+        let (outgoing, mut from_server) = mpsc::unbounded_channel();
+        let server = Multiplexer::over_channels(MuxRole::Server, incoming, outgoing, config);
+
+        // A peer that ignores the window it is granted
+        let mut open = 1024u32.to_be_bytes().to_vec();
+        open.extend_from_slice(b"flood");
+        peer.send(Frame::encode(FrameKind::Open, 1, &open)).unwrap();
+        for _ in 0..4 {
+            let data = encode_data(&text("x"));
+            peer.send(Frame::encode(FrameKind::Data, 1, &data)).unwrap();
+        }
+
+        let mut accepted = server.accept_bi().await.unwrap();
+        let reset = async {
+            loop {
+                let frame = Frame::decode(&from_server.recv().await.unwrap().data).unwrap();
+                if frame.kind == FrameKind::Reset {
+                    return frame.channel;
+                }
+            }
+        };
+        assert_eq!(timeout(Duration::from_secs(1), reset).await.unwrap(), 1);
+        assert_eq!(server.channel_count(), 0);
+
+        // Only what fit in the window was queued
+        assert_eq!(accepted.receive().await.unwrap(), text("x"));
+        assert_eq!(accepted.receive().await.unwrap(), text("x"));
+        assert!(accepted.receive().await.is_err());
+    }
+}
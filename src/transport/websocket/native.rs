@@ -1,32 +1,75 @@
+use crate::codec::{Codec, CodecProtocol, FrameCodec};
+use crate::transport::datagram::{self, Coalescer};
+#[cfg(feature = "compression")]
+use crate::transport::websocket::deflate::{DeflateConfig, DeflateParams, DeflateStream};
+use crate::transport::websocket::mux::{Multiplexer, MuxConfig, MuxRole};
 use crate::transport::{
-    ConnectionState, Message, MessageType, Transport, TransportCapabilities, TransportConfig,
-    TransportError,
+    ConnectionState, Datagram, DatagramStream, Message, MessageType, Transport,
+    TransportCapabilities, TransportConfig, TransportError,
 };
 use async_trait::async_trait;
 use futures::{Sink, SinkExt, Stream, StreamExt};
+use leptos::logging::error;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tokio::net::TcpStream;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio::sync::{mpsc, Notify};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::error::{
+    Error as WsError, ProtocolError, SubProtocolError, UrlError,
+};
+use tokio_tungstenite::tungstenite::handshake::client::{Request, Response};
+use tokio_tungstenite::tungstenite::http::header::{
+    SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL,
+};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::{client_async, WebSocketStream};
+
+/// The socket under the WebSocket protocol, compressing messages once
+/// permessage-deflate is agreed
+#[cfg(feature = "compression")]
+type Socket = DeflateStream<TcpStream>;
+#[cfg(not(feature = "compression"))]
+type Socket = TcpStream;
 
 /// WebSocket connection implementation
 #[allow(dead_code)]
 pub struct WebSocketConnection {
     config: TransportConfig,
     state: Arc<Mutex<ConnectionState>>,
-    stream: Option<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+    stream: Option<WebSocketStream<Socket>>,
     message_sender: Option<mpsc::UnboundedSender<Message>>,
     message_receiver: Option<mpsc::UnboundedReceiver<Message>>,
     connection_task: Option<tokio::task::JoinHandle<()>>,
     // Send channel for outgoing messages
     send_channel: Option<mpsc::UnboundedSender<Message>>,
+    // Task writing the send channel to the socket; yields what it could not write
+    writer_task: Option<tokio::task::JoinHandle<Vec<Message>>>,
+    writer_stop: Arc<Notify>,
+    // Subprotocol the server selected during the handshake
+    protocol: Option<String>,
+    // permessage-deflate offer, and the parameters the server agreed to
+    #[cfg(feature = "compression")]
+    deflate: Option<DeflateConfig>,
+    #[cfg(feature = "compression")]
+    deflate_params: Option<DeflateParams>,
+    // Whether the server agreed to emulated datagrams during the handshake
+    datagrams_negotiated: bool,
+    // Emulated datagrams waiting to be written, latest per channel
+    outgoing_datagrams: Arc<Coalescer>,
+    datagram_sender: Option<mpsc::UnboundedSender<Datagram>>,
+    datagram_receiver: Mutex<Option<mpsc::UnboundedReceiver<Datagram>>>,
 }
 
 impl WebSocketConnection {
     pub async fn new(config: TransportConfig) -> Result<Self, TransportError> {
         let (message_sender, message_receiver) = mpsc::unbounded_channel();
+        let (datagram_sender, datagram_receiver) = mpsc::unbounded_channel();
+
+        #[cfg(feature = "compression")]
+        let deflate = config.enable_compression.then(DeflateConfig::default);
 
         Ok(Self {
             config,
@@ -36,16 +79,179 @@ impl WebSocketConnection {
             message_receiver: Some(message_receiver),
             connection_task: None,
             send_channel: None,
+            writer_task: None,
+            writer_stop: Arc::new(Notify::new()),
+            protocol: None,
+            #[cfg(feature = "compression")]
+            deflate,
+            #[cfg(feature = "compression")]
+            deflate_params: None,
+            datagrams_negotiated: false,
+            outgoing_datagrams: Arc::new(Coalescer::new()),
+            datagram_sender: Some(datagram_sender),
+            datagram_receiver: Mutex::new(Some(datagram_receiver)),
         })
     }
 
+    /// Subprotocol the server selected during the last handshake
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Codec negotiated with the server
+    ///
+    /// Servers that select no subprotocol speak JSON.
+    pub fn codec(&self) -> Result<Box<dyn Codec<Message> + Send + Sync>, TransportError> {
+        CodecProtocol::selected(self.protocol())
+            .and_then(|protocol| protocol.codec())
+            .map_err(|e| TransportError::ProtocolError(e.to_string()))
+    }
+
+    /// Offer permessage-deflate with `config` on the next connect
+    ///
+    /// Set by default, with default parameters, when
+    /// [`TransportConfig::enable_compression`] is on.
+    #[cfg(feature = "compression")]
+    pub fn with_deflate(mut self, config: DeflateConfig) -> Self {
+        self.deflate = Some(config);
+        self
+    }
+
+    /// permessage-deflate parameters the server agreed to during the last handshake
+    #[cfg(feature = "compression")]
+    pub fn deflate_params(&self) -> Option<DeflateParams> {
+        self.deflate_params
+    }
+
+    /// Build the handshake request, offering `protocols` as subprotocols
+    fn handshake_request(
+        &self,
+        url: &str,
+        protocols: &[String],
+    ) -> Result<Request, TransportError> {
+        let mut request = url
+            .into_client_request()
+            .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?;
+
+        if !protocols.is_empty() {
+            let offered = HeaderValue::from_str(&protocols.join(", "))
+                .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?;
+            request.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, offered);
+        }
+
+        let mut extensions = vec![datagram::WEBSOCKET_EXTENSION.to_string()];
+        #[cfg(feature = "compression")]
+        if let Some(deflate) = &self.deflate {
+            extensions.insert(0, deflate.offer());
+        }
+        let offered = HeaderValue::from_str(&extensions.join(", "))
+            .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?;
+        request.headers_mut().insert(SEC_WEBSOCKET_EXTENSIONS, offered);
+
+        Ok(request)
+    }
+
+    /// Run the opening handshake with `url`, offering the configured subprotocols
+    async fn handshake(
+        &self,
+        url: &str,
+    ) -> Result<(WebSocketStream<Socket>, Response), TransportError> {
+        let request = self.handshake_request(url, &self.config.protocols)?;
+        match self.open(request).await {
+            // Servers that predate codec negotiation ignore the offered
+            // subprotocols, which tungstenite rejects; connect again without them
+            Err(WsError::Protocol(ProtocolError::SecWebSocketSubProtocolError(
+                SubProtocolError::NoSubProtocol,
+            ))) => self.open(self.handshake_request(url, &[])?).await,
+            result => result,
+        }
+        .map_err(|e| TransportError::ConnectionFailed(e.to_string()))
+    }
+
+    /// Open a TCP connection for `request` and run the opening handshake
+    async fn open(&self, request: Request) -> Result<(WebSocketStream<Socket>, Response), WsError> {
+        let uri = request.uri();
+        match uri.scheme_str() {
+            Some("ws") => {}
+            Some("wss") => return Err(WsError::Url(UrlError::TlsFeatureNotEnabled)),
+            _ => return Err(WsError::Url(UrlError::UnsupportedUrlScheme)),
+        }
+        let host = uri
+            .host()
+            .ok_or(WsError::Url(UrlError::NoHostName))?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let stream = TcpStream::connect((host, uri.port_u16().unwrap_or(80))).await?;
+
+        #[cfg(feature = "compression")]
+        let stream =
+            DeflateStream::client(stream, self.deflate.clone(), self.config.max_message_size);
+
+        client_async(request, stream).await
+    }
+
+    /// Carry logical channels over this connection
+    ///
+    /// Consumes the connected socket; every frame then belongs to a channel
+    /// of the returned multiplexer.
+    pub fn multiplex(mut self, config: MuxConfig) -> Result<Multiplexer, TransportError> {
+        let (Some(incoming), Some(outgoing)) =
+            (self.message_receiver.take(), self.send_channel.take())
+        else {
+            return Err(TransportError::NotConnected);
+        };
+        Ok(Multiplexer::over_channels(
+            MuxRole::Client,
+            incoming,
+            outgoing,
+            config,
+        ))
+    }
+
+    /// Take the messages the server sends, leaving the connection to send
+    ///
+    /// Only the first call gets the receiver. It ends once the socket closes.
+    pub fn take_incoming(&mut self) -> Option<mpsc::UnboundedReceiver<Message>> {
+        self.message_receiver.take()
+    }
+
+    /// Close the connection, handing back the messages it did not write
+    ///
+    /// Messages already queued are flushed for up to `grace`. Those the
+    /// socket did not take, in order, come back so they can be sent on
+    /// another connection; one cut off mid-write may arrive twice.
+    pub async fn drain(&mut self, grace: Duration) -> Vec<Message> {
+        // The writer stops once it has flushed the queue
+        self.send_channel = None;
+        let unsent = match self.writer_task.take() {
+            Some(mut writer) => match tokio::time::timeout(grace, &mut writer).await {
+                Ok(unsent) => unsent.unwrap_or_default(),
+                Err(_) => {
+                    self.writer_stop.notify_one();
+                    writer.await.unwrap_or_default()
+                }
+            },
+            None => Vec::new(),
+        };
+        let _ = self.disconnect().await;
+        unsent
+    }
+
+    /// What the connection supports; compression reflects what the last
+    /// handshake negotiated, permessage-deflate or a zstd codec
     pub fn capabilities(&self) -> TransportCapabilities {
+        #[cfg(feature = "compression")]
+        let deflate = self.deflate_params.is_some();
+        #[cfg(not(feature = "compression"))]
+        let deflate = false;
+        let zstd = CodecProtocol::selected(self.protocol()).is_ok_and(|protocol| protocol.zstd);
+
         TransportCapabilities {
             websocket: true,
             webtransport: false,
             sse: false,
             binary: true,
-            compression: false,
+            compression: deflate || zstd,
         }
     }
 
@@ -63,41 +269,92 @@ impl WebSocketConnection {
             TransportError::ConnectionFailed("No message sender available".to_string())
         })?;
 
+        let datagram_sender = self.datagram_sender.take().ok_or_else(|| {
+            TransportError::ConnectionFailed("No datagram sender available".to_string())
+        })?;
+
+        let frame_codec = FrameCodec::selected(self.protocol())
+            .map(Arc::new)
+            .map_err(|e| TransportError::ProtocolError(e.to_string()))?;
+
         let (send_sender, mut send_receiver) = mpsc::unbounded_channel::<Message>();
         self.send_channel = Some(send_sender);
 
         let state = Arc::clone(&self.state);
-
-        let task = tokio::spawn(async move {
-            let (mut write, mut read) = stream.split();
-
-            // Spawn task for handling outgoing messages
-            tokio::spawn(async move {
-                while let Some(message) = send_receiver.recv().await {
-                    let ws_msg = match message.message_type {
-                        MessageType::Text => {
-                            let text = String::from_utf8_lossy(&message.data);
-                            tokio_tungstenite::tungstenite::Message::Text(text.to_string().into())
-                        }
-                        MessageType::Binary => {
-                            tokio_tungstenite::tungstenite::Message::Binary(message.data.into())
-                        }
-                        MessageType::Ping => {
-                            tokio_tungstenite::tungstenite::Message::Ping(message.data.into())
-                        }
-                        MessageType::Pong => {
-                            tokio_tungstenite::tungstenite::Message::Pong(message.data.into())
-                        }
-                        MessageType::Close => tokio_tungstenite::tungstenite::Message::Close(None),
-                    };
-
-                    if let Err(e) = write.send(ws_msg).await {
-                        eprintln!("Failed to send WebSocket message: {}", e);
-                        break;
+        let datagrams_negotiated = self.datagrams_negotiated;
+        let outgoing_datagrams = Arc::clone(&self.outgoing_datagrams);
+        let outgoing_codec = Arc::clone(&frame_codec);
+        let stop = Arc::clone(&self.writer_stop);
+
+        let (mut write, mut read) = stream.split();
+
+        // Write outgoing messages; datagrams wait behind messages, coalescing
+        // while the socket is busy. Once the socket fails or the writer is
+        // stopped, the messages it did not write are handed back.
+        let writer = tokio::spawn(async move {
+            let mut unsent = Vec::new();
+            loop {
+                let (frame, message) = tokio::select! {
+                    biased;
+                    _ = stop.notified() => break,
+                    message = send_receiver.recv() => match message {
+                        Some(message) => match outgoing_codec.encode(message.clone()) {
+                            Ok(frame) => (frame, Some(message)),
+                            Err(e) => {
+                                error!("Failed to encode WebSocket message: {}", e);
+                                continue;
+                            }
+                        },
+                        None => break,
+                    },
+                    datagram = outgoing_datagrams.next() => {
+                        let frame = Message {
+                            data: datagram.to_websocket_frame(),
+                            message_type: MessageType::Binary,
+                        };
+                        (frame, None)
+                    }
+                };
+                let ws_msg = match frame.message_type {
+                    MessageType::Text => {
+                        let text = String::from_utf8_lossy(&frame.data);
+                        tokio_tungstenite::tungstenite::Message::Text(text.to_string().into())
+                    }
+                    MessageType::Binary => {
+                        tokio_tungstenite::tungstenite::Message::Binary(frame.data.into())
+                    }
+                    MessageType::Ping => {
+                        tokio_tungstenite::tungstenite::Message::Ping(frame.data.into())
                     }
+                    MessageType::Pong => {
+                        tokio_tungstenite::tungstenite::Message::Pong(frame.data.into())
+                    }
+                    MessageType::Close => tokio_tungstenite::tungstenite::Message::Close(None),
+                };
+
+                let sent = tokio::select! {
+                    biased;
+                    _ = stop.notified() => Err(None),
+                    sent = write.send(ws_msg) => sent.map_err(Some),
+                };
+                if let Err(e) = sent {
+                    if let Some(e) = e {
+                        error!("Failed to send WebSocket message: {}", e);
+                    }
+                    // Datagrams are not worth sending again
+                    unsent.extend(message);
+                    break;
                 }
-            });
+            }
+            send_receiver.close();
+            while let Ok(message) = send_receiver.try_recv() {
+                unsent.push(message);
+            }
+            unsent
+        });
+        self.writer_task = Some(writer);
 
+        let task = tokio::spawn(async move {
             // Handle incoming messages
             while let Some(msg) = read.next().await {
                 match msg {
@@ -107,10 +364,19 @@ impl WebSocketConnection {
                                 data: text.as_bytes().to_vec(),
                                 message_type: MessageType::Text,
                             },
-                            tokio_tungstenite::tungstenite::Message::Binary(data) => Message {
-                                data: data.to_vec(),
-                                message_type: MessageType::Binary,
-                            },
+                            tokio_tungstenite::tungstenite::Message::Binary(data) => {
+                                if let Some(datagram) = datagrams_negotiated
+                                    .then(|| Datagram::from_websocket_frame(&data))
+                                    .flatten()
+                                {
+                                    let _ = datagram_sender.send(datagram);
+                                    continue;
+                                }
+                                Message {
+                                    data: data.to_vec(),
+                                    message_type: MessageType::Binary,
+                                }
+                            }
                             tokio_tungstenite::tungstenite::Message::Ping(data) => Message {
                                 data: data.to_vec(),
                                 message_type: MessageType::Ping,
@@ -125,13 +391,20 @@ impl WebSocketConnection {
                             }
                             tokio_tungstenite::tungstenite::Message::Frame(_) => continue,
                         };
+                        let message = match frame_codec.decode(message) {
+                            Ok(message) => message,
+                            Err(e) => {
+                                error!("Failed to decode WebSocket message: {}", e);
+                                continue;
+                            }
+                        };
 
                         if message_sender.send(message).is_err() {
                             break;
                         }
                     }
                     Err(e) => {
-                        eprintln!("WebSocket error: {}", e);
+                        error!("WebSocket error: {}", e);
                         *state.lock().unwrap() = ConnectionState::Failed;
                         break;
                     }
@@ -152,12 +425,24 @@ impl Transport for WebSocketConnection {
     async fn connect(&mut self, url: &str) -> Result<(), TransportError> {
         *self.state.lock().unwrap() = ConnectionState::Connecting;
 
-        // Connect using tokio-tungstenite
-        let result = connect_async(url).await;
-
-        match result {
-            Ok((ws_stream, _)) => {
+        match self.handshake(url).await {
+            Ok((ws_stream, response)) => {
+                #[cfg(feature = "compression")]
+                {
+                    self.deflate_params = ws_stream.get_ref().params();
+                }
                 self.stream = Some(ws_stream);
+                self.protocol = response
+                    .headers()
+                    .get(SEC_WEBSOCKET_PROTOCOL)
+                    .and_then(|protocol| protocol.to_str().ok())
+                    .map(str::to_string);
+                self.datagrams_negotiated = response
+                    .headers()
+                    .get_all(SEC_WEBSOCKET_EXTENSIONS)
+                    .iter()
+                    .filter_map(|extensions| extensions.to_str().ok())
+                    .any(datagram::extension_listed);
                 *self.state.lock().unwrap() = ConnectionState::Connected;
 
                 // Start background task for handling messages
@@ -167,7 +452,7 @@ impl Transport for WebSocketConnection {
             }
             Err(e) => {
                 *self.state.lock().unwrap() = ConnectionState::Disconnected;
-                Err(TransportError::ConnectionFailed(e.to_string()))
+                Err(e)
             }
         }
     }
@@ -177,6 +462,11 @@ impl Transport for WebSocketConnection {
         if let Some(task) = self.connection_task.take() {
             task.abort();
         }
+        // The writer holds the other half of the socket
+        self.send_channel = None;
+        if let Some(writer) = self.writer_task.take() {
+            writer.abort();
+        }
 
         if let Some(stream) = &mut self.stream {
             // Close the WebSocket connection
@@ -226,7 +516,7 @@ impl Transport for WebSocketConnection {
             // Create a custom sink that converts our Message type to tungstenite messages
             struct MessageSink {
                 inner: futures::stream::SplitSink<
-                    WebSocketStream<MaybeTlsStream<TcpStream>>,
+                    WebSocketStream<Socket>,
                     tokio_tungstenite::tungstenite::Message,
                 >,
             }
@@ -291,6 +581,21 @@ impl Transport for WebSocketConnection {
 
             let message_sink = MessageSink { inner: write };
 
+            (Box::pin(message_stream), Box::pin(message_sink))
+        } else if let (Some(mut incoming), Some(outgoing)) =
+            (self.message_receiver, self.send_channel)
+        {
+            // The background task owns the socket once connected; hand out
+            // its channels, which carry messages through the negotiated codec
+            let message_stream =
+                futures::stream::poll_fn(move |cx| incoming.poll_recv(cx).map(|m| m.map(Ok)));
+            let message_sink = futures::sink::unfold(outgoing, |outgoing, message: Message| {
+                let sent = outgoing
+                    .send(message)
+                    .map(|()| outgoing)
+                    .map_err(|_| TransportError::SendFailed("Connection closed".to_string()));
+                futures::future::ready(sent)
+            });
             (Box::pin(message_stream), Box::pin(message_sink))
         } else {
             // Return empty stream and sink if not connected
@@ -336,6 +641,32 @@ impl Transport for WebSocketConnection {
         Ok(())
     }
 
+    async fn send_datagram(&self, datagram: Datagram) -> Result<(), TransportError> {
+        if self.state() != ConnectionState::Connected {
+            return Err(TransportError::NotConnected);
+        }
+        if !self.datagrams_negotiated {
+            return Err(TransportError::NotSupported(
+                "Server did not negotiate datagrams".to_string(),
+            ));
+        }
+
+        self.outgoing_datagrams.push(datagram);
+        Ok(())
+    }
+
+    fn datagrams(&self) -> Result<DatagramStream, TransportError> {
+        let mut receiver = self
+            .datagram_receiver
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| TransportError::InvalidState("Datagrams already taken".to_string()))?;
+        Ok(Box::pin(futures::stream::poll_fn(move |cx| {
+            receiver.poll_recv(cx)
+        })))
+    }
+
     fn state(&self) -> ConnectionState {
         *self.state.lock().unwrap()
     }
@@ -369,10 +700,285 @@ mod tests {
             message_receiver: None,
             connection_task: None,
             send_channel: None,
+            writer_task: None,
+            writer_stop: Arc::new(Notify::new()),
+            protocol: None,
+            #[cfg(feature = "compression")]
+            deflate: None,
+            #[cfg(feature = "compression")]
+            deflate_params: None,
+            datagrams_negotiated: false,
+            outgoing_datagrams: Arc::new(Coalescer::new()),
+            datagram_sender: None,
+            datagram_receiver: Mutex::new(None),
         };
 
         let caps = connection.capabilities();
         assert!(caps.websocket);
         assert!(caps.binary);
     }
+
+    /// Accept one connection, answering with `selected` as the subprotocol
+    async fn serve_once(selected: Option<&'static str>) -> String {
+        use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            // A server predating negotiation only sees the retried handshake
+            while let Ok((stream, _)) = listener.accept().await {
+                let callback = |_: &Request, mut response: Response| {
+                    if let Some(selected) = selected {
+                        response
+                            .headers_mut()
+                            .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(selected));
+                    }
+                    Ok(response)
+                };
+                if let Ok(ws) = tokio_tungstenite::accept_hdr_async(stream, callback).await {
+                    tokio::spawn(async move {
+                        let (_write, mut read) = ws.split();
+                        while read.next().await.is_some() {}
+                    });
+                }
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_codec_is_negotiated_during_handshake() {
+        let url = serve_once(Some("lwp.rkyv")).await;
+        let config = TransportConfig {
+            protocols: vec!["lwp.rkyv".to_string(), "lwp.json".to_string()],
+            ..Default::default()
+        };
+        let mut connection = WebSocketConnection::new(config).await.unwrap();
+        connection.connect(&url).await.unwrap();
+
+        assert_eq!(connection.protocol(), Some("lwp.rkyv"));
+        assert_eq!(connection.codec().unwrap().content_type(), "application/rkyv");
+        assert!(!connection.capabilities().compression);
+    }
+
+    #[tokio::test]
+    async fn test_servers_without_negotiation_fall_back_to_json() {
+        let url = serve_once(None).await;
+        let config = TransportConfig {
+            protocols: vec!["lwp.rkyv".to_string()],
+            ..Default::default()
+        };
+        let mut connection = WebSocketConnection::new(config).await.unwrap();
+        connection.connect(&url).await.unwrap();
+
+        assert_eq!(connection.protocol(), None);
+        assert_eq!(connection.codec().unwrap().content_type(), "application/json");
+    }
+
+    /// Accept one connection and echo its frames, agreeing to emulated
+    /// datagrams when `datagrams` is set
+    async fn echo_once(datagrams: bool) -> String {
+        use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let callback = |_: &Request, mut response: Response| {
+                if datagrams {
+                    response.headers_mut().insert(
+                        SEC_WEBSOCKET_EXTENSIONS,
+                        HeaderValue::from_static(datagram::WEBSOCKET_EXTENSION),
+                    );
+                }
+                Ok(response)
+            };
+            let mut ws = tokio_tungstenite::accept_hdr_async(stream, callback)
+                .await
+                .unwrap();
+            while let Some(Ok(message)) = ws.next().await {
+                if ws.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_datagrams_are_emulated_over_the_socket() {
+        let url = echo_once(true).await;
+
+        let mut connection = WebSocketConnection::new(TransportConfig::default())
+            .await
+            .unwrap();
+        let mut datagrams = connection.datagrams().unwrap();
+        assert!(connection.datagrams().is_err());
+        connection.connect(&url).await.unwrap();
+
+        let datagram = Datagram::new(3, b"cursor".to_vec());
+        connection.send_datagram(datagram.clone()).await.unwrap();
+        let echoed = tokio::time::timeout(std::time::Duration::from_secs(5), datagrams.next())
+            .await
+            .unwrap();
+        assert_eq!(echoed, Some(datagram));
+    }
+
+    #[tokio::test]
+    async fn test_datagrams_need_the_extension() {
+        let url = echo_once(false).await;
+
+        let mut connection = WebSocketConnection::new(TransportConfig::default())
+            .await
+            .unwrap();
+        connection.connect(&url).await.unwrap();
+
+        let datagram = Datagram::new(3, b"cursor".to_vec());
+        assert!(matches!(
+            connection.send_datagram(datagram.clone()).await,
+            Err(TransportError::NotSupported(_))
+        ));
+
+        // A message that happens to look like a datagram is still a message
+        let message = Message {
+            data: datagram.to_websocket_frame(),
+            message_type: MessageType::Binary,
+        };
+        let (mut stream, mut sink) = connection.split();
+        sink.send(message.clone()).await.unwrap();
+        let echoed = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next())
+            .await
+            .unwrap();
+        assert_eq!(echoed.unwrap().unwrap(), message);
+    }
+
+    #[tokio::test]
+    async fn test_drain_hands_back_what_the_socket_did_not_take() {
+        // Reports what the first connection receives; closes the second one
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (received, mut server_received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            tokio::spawn(async move {
+                while let Some(Ok(message)) = ws.next().await {
+                    let _ = received.send(message.into_data());
+                }
+            });
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let _ = ws.close(None).await;
+            while ws.next().await.is_some() {}
+        });
+        let timeout = std::time::Duration::from_secs(5);
+        let text = |data: &str| Message {
+            data: data.as_bytes().to_vec(),
+            message_type: MessageType::Text,
+        };
+
+        // A healthy socket flushes its queue before closing
+        let mut connection = WebSocketConnection::new(TransportConfig::default())
+            .await
+            .unwrap();
+        connection.connect(&url).await.unwrap();
+        connection.send_message(&text("flushed")).await.unwrap();
+        assert!(connection.drain(timeout).await.is_empty());
+        let flushed = tokio::time::timeout(timeout, server_received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&flushed[..], b"flushed");
+
+        // One closed by the server hands its queue back
+        let mut connection = WebSocketConnection::new(TransportConfig::default())
+            .await
+            .unwrap();
+        connection.connect(&url).await.unwrap();
+        tokio::time::timeout(timeout, async {
+            while connection.state() == ConnectionState::Connected {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let queued = vec![text("a"), text("b")];
+        for message in &queued {
+            let send_channel = connection.send_channel.as_ref().unwrap();
+            send_channel.send(message.clone()).unwrap();
+        }
+        assert_eq!(connection.drain(timeout).await, queued);
+    }
+
+    #[tokio::test]
+    async fn test_multiplexed_channels_share_the_socket() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (write, read) = tokio_tungstenite::accept_async(stream)
+                .await
+                .unwrap()
+                .split();
+            let incoming = read.filter_map(|message| async move {
+                match message {
+                    Ok(tokio_tungstenite::tungstenite::Message::Binary(data)) => {
+                        Some(Ok(Message {
+                            data: data.to_vec(),
+                            message_type: MessageType::Binary,
+                        }))
+                    }
+                    _ => None,
+                }
+            });
+            let outgoing = write
+                .sink_map_err(|e| TransportError::SendFailed(e.to_string()))
+                .with(|message: Message| async move {
+                    Ok::<_, TransportError>(tokio_tungstenite::tungstenite::Message::Binary(
+                        message.data.into(),
+                    ))
+                });
+            let mux = Multiplexer::new(MuxRole::Server, incoming, outgoing, MuxConfig::default());
+            // Echo every channel back, prefixed with its label
+            while let Ok(mut channel) = mux.accept_bi().await {
+                tokio::spawn(async move {
+                    while let Ok(message) = channel.receive().await {
+                        let mut data = channel.label().as_bytes().to_vec();
+                        data.extend_from_slice(&message.data);
+                        let reply = Message {
+                            data,
+                            message_type: message.message_type,
+                        };
+                        if channel.send(&reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        let mut connection = WebSocketConnection::new(TransportConfig::default())
+            .await
+            .unwrap();
+        connection.connect(&url).await.unwrap();
+        let mux = connection.multiplex(MuxConfig::default()).unwrap();
+
+        let mut rpc = mux.open_labeled("rpc:").await.unwrap();
+        let mut signals = mux.open_labeled("signals:").await.unwrap();
+        let message = |data: &str| Message {
+            data: data.as_bytes().to_vec(),
+            message_type: MessageType::Text,
+        };
+        signals.send(&message("update")).await.unwrap();
+        rpc.send(&message("call")).await.unwrap();
+
+        let timeout = std::time::Duration::from_secs(5);
+        let reply = tokio::time::timeout(timeout, rpc.receive()).await.unwrap();
+        assert_eq!(reply.unwrap().data, b"rpc:call");
+        let reply = tokio::time::timeout(timeout, signals.receive())
+            .await
+            .unwrap();
+        assert_eq!(reply.unwrap().data, b"signals:update");
+    }
 }
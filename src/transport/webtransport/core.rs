@@ -11,6 +11,10 @@ use tokio::sync::mpsc;
 use crate::transport::{ConnectionState, Message, TransportConfig, TransportError};
 use super::config::PerformanceMetrics;
 use super::stream::AdvancedWebTransportStream;
+#[cfg(feature = "webtransport")]
+use super::session::{Sha256Digest, WebTransportSession};
+#[cfg(feature = "webtransport")]
+use super::stream::StreamChannel;
 
 /// WebTransport connection implementation
 pub struct WebTransportConnection {
@@ -24,6 +28,10 @@ pub struct WebTransportConnection {
     pub(super) connection_task: Option<tokio::task::JoinHandle<()>>,
     pub(super) url: Option<String>,
     pub(super) metrics: Arc<Mutex<PerformanceMetrics>>,
+    #[cfg(feature = "webtransport")]
+    pub(super) session: Option<WebTransportSession>,
+    #[cfg(feature = "webtransport")]
+    pub(super) server_certificate_hashes: Vec<Sha256Digest>,
 }
 
 impl WebTransportConnection {
@@ -48,9 +56,40 @@ impl WebTransportConnection {
             connection_task: None,
             url: None,
             metrics: Arc::new(Mutex::new(PerformanceMetrics::default())),
+            #[cfg(feature = "webtransport")]
+            session: None,
+            #[cfg(feature = "webtransport")]
+            server_certificate_hashes: Vec::new(),
         })
     }
 
+    /// Wrap a session accepted by a [`WebTransportServer`](super::WebTransportServer)
+    #[cfg(feature = "webtransport")]
+    pub async fn from_session(
+        config: TransportConfig,
+        session: WebTransportSession,
+    ) -> Result<Self, TransportError> {
+        let mut connection = Self::new(config).await?;
+        connection.session = Some(session);
+        *connection.state.lock().unwrap() = ConnectionState::Connected;
+        Ok(connection)
+    }
+
+    /// Accept only server certificates with one of these SHA-256 digests
+    ///
+    /// Needed for servers with self-signed certificates, which must be valid
+    /// for at most two weeks. Without hashes the native root store is used.
+    #[cfg(feature = "webtransport")]
+    pub fn set_server_certificate_hashes(&mut self, hashes: Vec<Sha256Digest>) {
+        self.server_certificate_hashes = hashes;
+    }
+
+    /// The established HTTP/3 session, if connected
+    #[cfg(feature = "webtransport")]
+    pub fn session(&self) -> Option<&WebTransportSession> {
+        self.session.as_ref()
+    }
+
     /// Get current connection state
     pub fn state(&self) -> ConnectionState {
         *self.state.lock().unwrap()
@@ -65,6 +104,13 @@ impl WebTransportConnection {
             id
         };
 
+        #[cfg(feature = "webtransport")]
+        let stream = {
+            let session = self.session.as_ref().ok_or(TransportError::NotConnected)?;
+            let channel = StreamChannel::open(session, &stream_config).await?;
+            AdvancedWebTransportStream::new(stream_id, stream_config).with_channel(channel)
+        };
+        #[cfg(not(feature = "webtransport"))]
         let stream = AdvancedWebTransportStream::new(stream_id, stream_config);
 
         self.streams.lock().unwrap().insert(stream_id, stream.clone());
@@ -215,6 +261,66 @@ impl WebTransportConnection {
         Ok(())
     }
 
+    /// Open the HTTP/3 session to `url`
+    #[cfg(feature = "webtransport")]
+    pub(super) async fn establish(&mut self, url: &str) -> Result<(), TransportError> {
+        self.metrics.lock().unwrap().connection_attempts += 1;
+        match WebTransportSession::connect(url, &self.config, &self.server_certificate_hashes).await
+        {
+            Ok(session) => {
+                self.session = Some(session);
+                *self.state.lock().unwrap() = ConnectionState::Connected;
+                self.metrics.lock().unwrap().successful_connections += 1;
+                Ok(())
+            }
+            Err(e) => {
+                *self.state.lock().unwrap() = ConnectionState::Disconnected;
+                self.metrics.lock().unwrap().failed_connections += 1;
+                Err(e)
+            }
+        }
+    }
+
+    /// Run the simulated connection task and wait for its outcome
+    #[cfg(not(feature = "webtransport"))]
+    pub(super) async fn establish(&mut self, url: &str) -> Result<(), TransportError> {
+        // Start the connection task
+        self.start_connection_task(url.to_string()).await?;
+
+        // Wait for connection to be established
+        let mut attempts = 0;
+        const MAX_WAIT_ATTEMPTS: u32 = 30; // 30 * 100ms = 3 seconds
+
+        while attempts < MAX_WAIT_ATTEMPTS {
+            match self.state() {
+                ConnectionState::Connected => return Ok(()),
+                ConnectionState::Disconnected => {
+                    return Err(TransportError::ConnectionFailed(
+                        "Failed to establish connection".to_string(),
+                    ))
+                }
+                ConnectionState::Connecting => {
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    attempts += 1;
+                }
+                ConnectionState::Reconnecting => {
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    attempts += 1;
+                }
+                ConnectionState::Failed => {
+                    return Err(TransportError::ConnectionFailed(
+                        "Connection failed".to_string(),
+                    ))
+                }
+            }
+        }
+
+        Err(TransportError::ConnectionFailed(format!(
+            "no response from {} in time",
+            url
+        )))
+    }
+
     /// Attempt a single connection
     async fn attempt_connection(_client: &Client, url: &str) -> Result<(), TransportError> {
         // For testing, simulate WebTransport connection
@@ -239,6 +345,11 @@ impl WebTransportConnection {
             task.abort();
         }
 
+        #[cfg(feature = "webtransport")]
+        if let Some(session) = self.session.take() {
+            session.close();
+        }
+
         // Close all streams
         let stream_ids: Vec<u32> = self.streams.lock().unwrap().keys().copied().collect();
         for stream_id in stream_ids {
@@ -7,6 +7,10 @@ pub mod core;
 pub mod transport_impl;
 pub mod sink;
 pub mod stream;
+#[cfg(feature = "webtransport")]
+pub mod session;
+#[cfg(feature = "webtransport")]
+pub mod server;
 
 // Re-export main types for backward compatibility
 pub use config::{
@@ -16,6 +20,12 @@ pub use core::{WebTransportConnection, ConnectionStats};
 pub use transport_impl::{TransportInfo, TransportCapabilities, ConnectionDiagnostics};
 pub use sink::{WebTransportSink, AdvancedWebTransportSink, CompressedWebTransportSink, SinkFactory};
 pub use stream::AdvancedWebTransportStream;
+#[cfg(feature = "webtransport")]
+pub use server::{AcceptedSession, SessionRequestParts, WebTransportServer};
+#[cfg(feature = "webtransport")]
+pub use session::{Sha256Digest, WebTransportSession};
+#[cfg(feature = "webtransport")]
+pub use stream::StreamChannel;
 
 // Legacy compatibility module
 pub mod connection {
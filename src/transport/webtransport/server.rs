@@ -0,0 +1,432 @@
+//! WebTransport Server
+//!
+//! Accepts HTTP/3 WebTransport sessions and routes them by request path to
+//! server signals, an RPC router or a handler of their own
+
+#[cfg(feature = "ssr")]
+use futures::future;
+use futures::future::BoxFuture;
+use futures::Future;
+use leptos::logging::error;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use wtransport::endpoint::{endpoint_side, SessionRequest};
+use wtransport::{Endpoint, Identity, ServerConfig};
+
+use super::core::WebTransportConnection;
+use super::session::{Sha256Digest, WebTransportSession};
+use crate::rpc::router::RpcRouter;
+use crate::transport::{Transport, TransportConfig, TransportError};
+#[cfg(feature = "ssr")]
+use crate::{server_signal::SignalScope, server_signals::ServerSignals};
+
+/// How long browsers may keep using an advertised HTTP/3 endpoint, in seconds
+const ALT_SVC_MAX_AGE: u32 = 86400;
+
+/// The parts of a session request a [`WebTransportServer`] authenticates
+#[derive(Debug, Clone)]
+pub struct SessionRequestParts {
+    /// Path of the session request, without its query
+    pub path: String,
+    /// Header fields of the session request
+    pub headers: HashMap<String, String>,
+    /// Address the request came from
+    pub remote_address: SocketAddr,
+}
+
+#[cfg(feature = "ssr")]
+impl SessionRequestParts {
+    fn of(request: &SessionRequest) -> Self {
+        Self {
+            path: request_path(request).to_string(),
+            headers: request.headers().clone(),
+            remote_address: request.remote_address(),
+        }
+    }
+}
+
+/// A session accepted by a [`WebTransportServer`]
+pub struct AcceptedSession {
+    /// Path of the session request, without its query
+    pub path: String,
+    /// Header fields of the session request
+    pub headers: HashMap<String, String>,
+    /// The session, already connected
+    pub connection: WebTransportConnection,
+}
+
+type SessionHandler =
+    Arc<dyn Fn(SessionRequest, TransportConfig) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// WebTransport server for the signals and RPC otherwise served over WebSocket
+///
+/// Sessions are routed by the path of their request; requests for other
+/// paths are answered with `404`. Serve it from the same binary as an axum
+/// app and advertise it with [`WebTransportServer::alt_svc`].
+///
+/// # Example
+///
+/// ```ignore
+/// use axum::{http::header::ALT_SVC, routing::get, Router};
+/// use leptos_ws_pro::transport::webtransport::WebTransportServer;
+/// use tower_http::set_header::SetResponseHeaderLayer;
+/// use wtransport::Identity;
+///
+/// let identity = Identity::load_pemfiles("cert.pem", "key.pem").await?;
+/// let server = WebTransportServer::bind("0.0.0.0:4433".parse()?, identity, Default::default())?
+///     .signals("/ws", state.server_signals.clone())
+///     .rpc("/rpc", rpc, move |_| state.clone());
+///
+/// let app = Router::new()
+///     .route("/ws", get(leptos_ws_pro::axum::websocket(state.server_signals.clone())))
+///     .layer(SetResponseHeaderLayer::overriding(ALT_SVC, server.alt_svc()?.parse()?));
+///
+/// tokio::spawn(server.serve());
+/// axum::serve(listener, app).await?;
+/// ```
+pub struct WebTransportServer {
+    endpoint: Endpoint<endpoint_side::Server>,
+    certificate_hashes: Vec<Sha256Digest>,
+    config: TransportConfig,
+    routes: HashMap<String, SessionHandler>,
+}
+
+impl WebTransportServer {
+    /// Listen on `address`, presenting `identity` to clients
+    ///
+    /// `config` sets the idle timeout, keep-alive interval and maximum message
+    /// size of every session.
+    pub fn bind(
+        address: SocketAddr,
+        identity: Identity,
+        config: TransportConfig,
+    ) -> Result<Self, TransportError> {
+        let certificate_hashes = identity
+            .certificate_chain()
+            .as_slice()
+            .iter()
+            .map(|certificate| certificate.hash())
+            .collect();
+
+        let server_config = ServerConfig::builder()
+            .with_bind_address(address)
+            .with_identity(identity)
+            .max_idle_timeout(Some(config.timeout))
+            .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?
+            .keep_alive_interval(config.heartbeat_interval)
+            .build();
+        let endpoint = Endpoint::server(server_config)
+            .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?;
+
+        Ok(Self {
+            endpoint,
+            certificate_hashes,
+            config,
+            routes: HashMap::new(),
+        })
+    }
+
+    /// Address the server listens on
+    pub fn local_addr(&self) -> Result<SocketAddr, TransportError> {
+        self.endpoint
+            .local_addr()
+            .map_err(|e| TransportError::InvalidState(e.to_string()))
+    }
+
+    /// SHA-256 digests of the certificate chain, for clients pinning a self-signed certificate
+    pub fn certificate_hashes(&self) -> &[Sha256Digest] {
+        &self.certificate_hashes
+    }
+
+    /// Value of the `Alt-Svc` header advertising this endpoint over HTTP/3
+    pub fn alt_svc(&self) -> Result<String, TransportError> {
+        Ok(format!(
+            "h3=\":{}\"; ma={}",
+            self.local_addr()?.port(),
+            ALT_SVC_MAX_AGE
+        ))
+    }
+
+    /// Serve sessions requested at `path` with `handler`
+    pub fn route<F, Fut>(self, path: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(AcceptedSession) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.handle(path, move |request, config| {
+            let handler = handler.clone();
+            async move {
+                match accept_session(request, &config).await {
+                    Ok(session) => handler(session).await,
+                    Err(e) => error!("WebTransport session failed: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Serve session requests at `path` with `handler`, which decides whether to accept them
+    fn handle<F, Fut>(mut self, path: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(SessionRequest, TransportConfig) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handler: SessionHandler =
+            Arc::new(move |request, config| Box::pin(handler(request, config)));
+        self.routes.insert(path.into(), handler);
+        self
+    }
+
+    /// Serve RPC requests at `path`, as the axum `rpc` handler does over WebSocket
+    ///
+    /// `context` turns each accepted session into the context its handlers
+    /// receive.
+    pub fn rpc<Ctx, F>(self, path: impl Into<String>, router: RpcRouter<Ctx>, context: F) -> Self
+    where
+        Ctx: Clone + Send + Sync + 'static,
+        F: Fn(&AcceptedSession) -> Ctx + Send + Sync + 'static,
+    {
+        let router = Arc::new(router);
+        let context = Arc::new(context);
+        self.route(path, move |session: AcceptedSession| {
+            let router = router.clone();
+            let context = context(&session);
+            async move {
+                let (incoming, outgoing) = session.connection.split();
+                if let Err(e) = router.serve(context, incoming, outgoing).await {
+                    error!("RPC session closed with error: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Serve `server_signals` at `path`, as the axum `websocket` handler does over WebSocket
+    #[cfg(feature = "ssr")]
+    pub fn signals(self, path: impl Into<String>, server_signals: ServerSignals) -> Self {
+        self.signals_scoped(
+            path,
+            server_signals,
+            |_| future::ready(Some(())),
+            |_, _| SignalScope::Global,
+        )
+    }
+
+    /// Serve `server_signals` at `path`, scoped per session
+    ///
+    /// `authenticate` turns the parts of a session request into `A`, typically
+    /// the user a session token in its headers belongs to; requests it returns
+    /// `None` for are refused with `403` and never accepted. When the client
+    /// establishes a signal, `resolve` maps `A` and the signal name to the
+    /// scope to serve it from, like the axum `websocket_scoped` handler.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let server = server.signals_scoped(
+    ///     "/ws",
+    ///     state.server_signals.clone(),
+    ///     move |request: SessionRequestParts| {
+    ///         let sessions = sessions.clone();
+    ///         async move { sessions.user(request.headers.get("cookie")?).await }
+    ///     },
+    ///     |user: &AuthUser, name: &str| match name {
+    ///         "notifications" => SignalScope::Session(user.id.to_string()),
+    ///         _ => SignalScope::Global,
+    ///     },
+    /// );
+    /// ```
+    #[cfg(feature = "ssr")]
+    pub fn signals_scoped<A, Auth, Fut, F>(
+        self,
+        path: impl Into<String>,
+        server_signals: ServerSignals,
+        authenticate: Auth,
+        resolve: F,
+    ) -> Self
+    where
+        A: Send + Sync + 'static,
+        Auth: Fn(SessionRequestParts) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<A>> + Send + 'static,
+        F: Fn(&A, &str) -> SignalScope + Send + Sync + 'static,
+    {
+        let authenticate = Arc::new(authenticate);
+        let resolve = Arc::new(resolve);
+        self.handle(path, move |request: SessionRequest, config| {
+            let server_signals = server_signals.clone();
+            let authenticate = authenticate.clone();
+            let resolve = resolve.clone();
+            async move {
+                let Some(auth) = authenticate(SessionRequestParts::of(&request)).await else {
+                    request.forbidden().await;
+                    return;
+                };
+                let session = match accept_session(request, &config).await {
+                    Ok(session) => session,
+                    Err(e) => {
+                        error!("WebTransport session failed: {}", e);
+                        return;
+                    }
+                };
+                let (incoming, outgoing) = session.connection.split();
+                server_signals
+                    .serve(incoming, outgoing, move |name: &str| resolve(&auth, name))
+                    .await;
+            }
+        })
+    }
+
+    /// Accept the next session, whatever its path
+    pub async fn accept(&self) -> Result<AcceptedSession, TransportError> {
+        let request = self
+            .endpoint
+            .accept()
+            .await
+            .await
+            .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?;
+        accept_session(request, &self.config).await
+    }
+
+    /// Accept sessions for as long as the server runs, each on its route's handler
+    pub async fn serve(self) {
+        let routes = Arc::new(self.routes);
+        loop {
+            let incoming = self.endpoint.accept().await;
+            let routes = routes.clone();
+            let config = self.config.clone();
+            tokio::spawn(async move {
+                let request = match incoming.await {
+                    Ok(request) => request,
+                    Err(e) => {
+                        error!("WebTransport handshake failed: {}", e);
+                        return;
+                    }
+                };
+                let Some(handler) = routes.get(request_path(&request)).cloned() else {
+                    request.not_found().await;
+                    return;
+                };
+                handler(request, config).await;
+            });
+        }
+    }
+}
+
+fn request_path(request: &SessionRequest) -> &str {
+    let path = request.path();
+    path.split_once('?').map_or(path, |(path, _)| path)
+}
+
+async fn accept_session(
+    request: SessionRequest,
+    config: &TransportConfig,
+) -> Result<AcceptedSession, TransportError> {
+    let path = request_path(&request).to_string();
+    let headers = request.headers().clone();
+    let connection = request
+        .accept()
+        .await
+        .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?;
+
+    let session = WebTransportSession::accepted(connection, config.max_message_size);
+    let connection = WebTransportConnection::from_session(config.clone(), session).await?;
+    Ok(AcceptedSession {
+        path,
+        headers,
+        connection,
+    })
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{Messages, ServerSignalMessage},
+        server_signal::{ServerSignal, SignalKey},
+        transport::{Message, MessageType},
+    };
+    use leptos::prelude::{provide_context, Owner};
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    async fn client(
+        address: SocketAddr,
+        hashes: &[Sha256Digest],
+        token: &str,
+    ) -> Result<WebTransportConnection, TransportError> {
+        let mut config = TransportConfig::default();
+        config
+            .headers
+            .insert("authorization".to_string(), format!("Bearer {}", token));
+        let mut client = WebTransportConnection::new(config).await?;
+        client.set_server_certificate_hashes(hashes.to_vec());
+        client.connect(&format!("https://{}/ws", address)).await?;
+        Ok(client)
+    }
+
+    async fn establish(client: &WebTransportConnection, name: &str) -> Messages {
+        let message = Messages::ServerSignal(ServerSignalMessage::Establish(name.to_string()));
+        client
+            .send_message(&Message {
+                data: serde_json::to_vec(&message).unwrap(),
+                message_type: MessageType::Text,
+            })
+            .await
+            .unwrap();
+        let response = timeout(Duration::from_secs(5), client.receive_message())
+            .await
+            .unwrap()
+            .unwrap();
+        serde_json::from_slice(&response.data).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_signals_are_scoped_by_authenticated_user() {
+        let owner = Owner::new();
+        owner.set();
+        let signals = ServerSignals::new();
+        provide_context(signals.clone());
+        let _alice = ServerSignal::new(SignalKey::session("alice", "notifications"), 3u32).unwrap();
+        let _bob = ServerSignal::new(SignalKey::session("bob", "notifications"), 5u32).unwrap();
+
+        let identity = Identity::self_signed(["localhost", "127.0.0.1"]).unwrap();
+        let server = WebTransportServer::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            identity,
+            TransportConfig::default(),
+        )
+        .unwrap()
+        .signals_scoped(
+            "/ws",
+            signals,
+            |request: SessionRequestParts| async move {
+                match request.headers.get("authorization")?.as_str() {
+                    "Bearer alice-token" => Some("alice".to_string()),
+                    "Bearer bob-token" => Some("bob".to_string()),
+                    _ => None,
+                }
+            },
+            |user: &String, _: &str| SignalScope::Session(user.clone()),
+        );
+
+        let address = server.local_addr().unwrap();
+        let hashes = server.certificate_hashes().to_vec();
+        tokio::spawn(server.serve());
+
+        // Each user sees the signal of their own session
+        for (token, value) in [("alice-token", 3), ("bob-token", 5)] {
+            let client = client(address, &hashes, token).await.unwrap();
+            let response = establish(&client, "notifications").await;
+            assert!(matches!(
+                response,
+                Messages::ServerSignal(ServerSignalMessage::EstablishResponse((name, v)))
+                    if name == "notifications" && v == json!(value)
+            ));
+        }
+
+        // Naming a user is not enough to be served their signals
+        let intruder = client(address, &hashes, "alice").await;
+        assert!(matches!(intruder, Err(TransportError::ConnectionFailed(_))));
+    }
+}
@@ -0,0 +1,407 @@
+//! WebTransport Session
+//!
+//! HTTP/3 WebTransport session backed by `wtransport`. Messages travel as
+//! length-prefixed frames over QUIC streams; datagrams carry raw payloads.
+
+use futures::Stream;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Mutex, OnceCell};
+use wtransport::endpoint::{endpoint_side, ConnectOptions};
+use wtransport::{ClientConfig, Connection, Endpoint, RecvStream, SendStream, VarInt};
+
+use super::sink::WebTransportSink;
+use crate::transport::{Message, MessageType, TransportConfig, TransportError};
+
+pub use wtransport::tls::Sha256Digest;
+
+/// Messages received on a split session
+pub type MessageStream =
+    Pin<Box<dyn Stream<Item = Result<Message, TransportError>> + Send + Unpin>>;
+
+/// Bytes before the payload of a frame: the message type and the payload length
+const FRAME_HEADER_LEN: usize = 5;
+
+/// The two halves of the stream carrying a session's messages
+struct MessageStreams {
+    send: Mutex<SendStream>,
+    recv: Mutex<RecvStream>,
+}
+
+/// An established WebTransport session
+///
+/// Messages sent with [`WebTransportSession::send`] share one bidirectional
+/// stream, so they arrive in order. The client opens that stream as the
+/// session's first bidirectional stream; the server accepts it when the first
+/// message arrives. Cloning yields another handle to the same session.
+#[derive(Clone)]
+pub struct WebTransportSession {
+    connection: Connection,
+    streams: Arc<OnceCell<MessageStreams>>,
+    max_message_size: usize,
+    // Kept so a client endpoint outlives every handle to the session
+    _endpoint: Option<Arc<Endpoint<endpoint_side::Client>>>,
+}
+
+impl fmt::Debug for WebTransportSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebTransportSession")
+            .field("connection", &self.connection)
+            .field("max_message_size", &self.max_message_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WebTransportSession {
+    /// Open a session to `url` and its message stream
+    ///
+    /// With `certificate_hashes` empty the server certificate is checked
+    /// against the native root store; otherwise only certificates with one of
+    /// the given SHA-256 digests are accepted, as with self-signed servers.
+    pub async fn connect(
+        url: &str,
+        config: &TransportConfig,
+        certificate_hashes: &[Sha256Digest],
+    ) -> Result<Self, TransportError> {
+        let builder = ClientConfig::builder().with_bind_default();
+        let builder = if certificate_hashes.is_empty() {
+            builder.with_native_certs()
+        } else {
+            builder.with_server_certificate_hashes(certificate_hashes.iter().cloned())
+        };
+        let client_config = builder
+            .max_idle_timeout(Some(config.timeout))
+            .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?
+            .keep_alive_interval(config.heartbeat_interval)
+            .build();
+
+        let endpoint = Endpoint::client(client_config)
+            .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?;
+
+        let mut options = ConnectOptions::builder(url);
+        for (name, value) in &config.headers {
+            options = options.add_header(name, value);
+        }
+
+        let connection = tokio::time::timeout(config.connection_timeout, endpoint.connect(options))
+            .await
+            .map_err(|_| {
+                TransportError::ConnectionFailed(format!(
+                    "no response from {} within {:?}",
+                    url, config.connection_timeout
+                ))
+            })?
+            .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?;
+
+        let (send, recv) = open_bi(&connection).await?;
+        let streams = MessageStreams {
+            send: Mutex::new(send),
+            recv: Mutex::new(recv),
+        };
+
+        Ok(Self {
+            connection,
+            streams: Arc::new(OnceCell::new_with(Some(streams))),
+            max_message_size: config.max_message_size,
+            _endpoint: Some(Arc::new(endpoint)),
+        })
+    }
+
+    /// Wrap a session a server accepted
+    pub fn accepted(connection: Connection, max_message_size: usize) -> Self {
+        Self {
+            connection,
+            streams: Arc::new(OnceCell::new()),
+            max_message_size,
+            _endpoint: None,
+        }
+    }
+
+    /// The message stream, accepting it from the client on first use
+    async fn streams(&self) -> Result<&MessageStreams, TransportError> {
+        self.streams
+            .get_or_try_init(|| async {
+                let (send, recv) = self
+                    .connection
+                    .accept_bi()
+                    .await
+                    .map_err(|e| TransportError::ReceiveFailed(e.to_string()))?;
+                Ok(MessageStreams {
+                    send: Mutex::new(send),
+                    recv: Mutex::new(recv),
+                })
+            })
+            .await
+    }
+
+    /// The underlying `wtransport` connection
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Largest message payload accepted from the peer
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Send a message on the session's message stream
+    pub async fn send(&self, message: &Message) -> Result<(), TransportError> {
+        write_frame(&mut *self.streams().await?.send.lock().await, message).await
+    }
+
+    /// Receive the next message from the session's message stream
+    pub async fn receive(&self) -> Result<Message, TransportError> {
+        read_frame(
+            &mut *self.streams().await?.recv.lock().await,
+            self.max_message_size,
+        )
+            .await?
+            .ok_or(TransportError::ConnectionClosed)
+    }
+
+    /// Open a new bidirectional stream
+    pub async fn open_bi(&self) -> Result<(SendStream, RecvStream), TransportError> {
+        open_bi(&self.connection).await
+    }
+
+    /// Send a message on a unidirectional stream of its own
+    ///
+    /// Messages sent this way do not wait on each other, so a lost packet
+    /// only delays the message it belongs to.
+    pub async fn send_uni(&self, message: &Message) -> Result<(), TransportError> {
+        let mut stream = self
+            .connection
+            .open_uni()
+            .await
+            .map_err(|e| TransportError::SendFailed(e.to_string()))?
+            .await
+            .map_err(|e| TransportError::SendFailed(e.to_string()))?;
+        write_frame(&mut stream, message).await?;
+        stream
+            .finish()
+            .await
+            .map_err(|e| TransportError::SendFailed(e.to_string()))
+    }
+
+    /// Receive the message carried by the next unidirectional stream the peer opens
+    pub async fn receive_uni(&self) -> Result<Message, TransportError> {
+        let mut stream = self
+            .connection
+            .accept_uni()
+            .await
+            .map_err(|e| TransportError::ReceiveFailed(e.to_string()))?;
+        read_frame(&mut stream, self.max_message_size)
+            .await?
+            .ok_or(TransportError::ConnectionClosed)
+    }
+
+    /// Send an unreliable, unordered datagram
+    pub fn send_datagram(&self, payload: &[u8]) -> Result<(), TransportError> {
+        self.connection
+            .send_datagram(payload)
+            .map_err(|e| TransportError::SendFailed(e.to_string()))
+    }
+
+    /// Receive the next datagram
+    pub async fn receive_datagram(&self) -> Result<Vec<u8>, TransportError> {
+        self.connection
+            .receive_datagram()
+            .await
+            .map(|datagram| datagram.payload().to_vec())
+            .map_err(|e| TransportError::ReceiveFailed(e.to_string()))
+    }
+
+    /// Largest datagram payload the peer accepts, if it accepts datagrams at all
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        self.connection.max_datagram_size()
+    }
+
+    /// Close the session
+    pub fn close(&self) {
+        self.connection.close(VarInt::from_u32(0), b"");
+    }
+
+    /// Split the session into a message stream and sink
+    ///
+    /// The stream yields messages from the message stream and from every
+    /// unidirectional stream the peer opens; the sink writes to the message
+    /// stream.
+    pub fn split(self) -> (MessageStream, WebTransportSink) {
+        let (incoming_sender, mut incoming_receiver) = mpsc::unbounded_channel();
+        let (outgoing_sender, mut outgoing_receiver) = mpsc::unbounded_channel::<Message>();
+
+        let writer = self.clone();
+        tokio::spawn(async move {
+            while let Some(message) = outgoing_receiver.recv().await {
+                if writer.send(&message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let reader = self.clone();
+        let sender = incoming_sender.clone();
+        tokio::spawn(async move {
+            loop {
+                match reader.receive().await {
+                    Ok(message) => {
+                        if sender.send(Ok(message)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(error) => {
+                        let _ = sender.send(Err(error));
+                        break;
+                    }
+                }
+            }
+        });
+
+        let uni_reader = self;
+        tokio::spawn(async move {
+            while let Ok(message) = uni_reader.receive_uni().await {
+                if incoming_sender.send(Ok(message)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stream = futures::stream::poll_fn(move |cx| incoming_receiver.poll_recv(cx));
+        (
+            Box::pin(stream),
+            WebTransportSink::new(Some(outgoing_sender)),
+        )
+    }
+}
+
+async fn open_bi(connection: &Connection) -> Result<(SendStream, RecvStream), TransportError> {
+    connection
+        .open_bi()
+        .await
+        .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?
+        .await
+        .map_err(|e| TransportError::ConnectionFailed(e.to_string()))
+}
+
+fn message_type_code(message_type: &MessageType) -> u8 {
+    match message_type {
+        MessageType::Text => 0,
+        MessageType::Binary => 1,
+        MessageType::Ping => 2,
+        MessageType::Pong => 3,
+        MessageType::Close => 4,
+    }
+}
+
+fn message_type_from_code(code: u8) -> Result<MessageType, TransportError> {
+    match code {
+        0 => Ok(MessageType::Text),
+        1 => Ok(MessageType::Binary),
+        2 => Ok(MessageType::Ping),
+        3 => Ok(MessageType::Pong),
+        4 => Ok(MessageType::Close),
+        other => Err(TransportError::ProtocolError(format!(
+            "Unknown message type {}",
+            other
+        ))),
+    }
+}
+
+/// Write `message` as one frame: its type, its big-endian `u32` length and its data
+pub async fn write_frame<W>(writer: &mut W, message: &Message) -> Result<(), TransportError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let len = u32::try_from(message.data.len())
+        .map_err(|_| TransportError::SendFailed("Message too large".to_string()))?;
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + message.data.len());
+    frame.push(message_type_code(&message.message_type));
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&message.data);
+    writer
+        .write_all(&frame)
+        .await
+        .map_err(|e| TransportError::SendFailed(e.to_string()))
+}
+
+/// Read the next frame written by [`write_frame`], or `None` at the end of the stream
+pub async fn read_frame<R>(
+    reader: &mut R,
+    max_message_size: usize,
+) -> Result<Option<Message>, TransportError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(TransportError::ReceiveFailed(e.to_string())),
+    }
+
+    let message_type = message_type_from_code(header[0])?;
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > max_message_size {
+        return Err(TransportError::ProtocolError(format!(
+            "Message of {} bytes exceeds the limit of {}",
+            len, max_message_size
+        )));
+    }
+
+    let mut data = vec![0u8; len];
+    reader
+        .read_exact(&mut data)
+        .await
+        .map_err(|e| TransportError::ReceiveFailed(e.to_string()))?;
+    Ok(Some(Message { data, message_type }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frames_round_trip() {
+        let messages = [
+            Message {
+                data: b"hello".to_vec(),
+                message_type: MessageType::Text,
+            },
+            Message {
+                data: vec![],
+                message_type: MessageType::Ping,
+            },
+            Message {
+                data: vec![0, 1, 2, 255],
+                message_type: MessageType::Binary,
+            },
+        ];
+
+        let mut buffer = Vec::new();
+        for message in &messages {
+            write_frame(&mut buffer, message).await.unwrap();
+        }
+
+        let mut reader = buffer.as_slice();
+        for message in &messages {
+            let read = read_frame(&mut reader, 1024).await.unwrap().unwrap();
+            assert_eq!(&read, message);
+        }
+        assert!(read_frame(&mut reader, 1024).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frames_are_rejected() {
+        let mut buffer = Vec::new();
+        let message = Message {
+            data: vec![0; 64],
+            message_type: MessageType::Binary,
+        };
+        write_frame(&mut buffer, &message).await.unwrap();
+
+        let result = read_frame(&mut buffer.as_slice(), 32).await;
+        assert!(matches!(result, Err(TransportError::ProtocolError(_))));
+    }
+}
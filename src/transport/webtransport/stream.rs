@@ -2,10 +2,61 @@
 //!
 //! Advanced stream implementation for WebTransport
 
-use crate::transport::TransportError;
+use crate::transport::{BiStream, Message, MessageType, TransportError};
+use async_trait::async_trait;
 use std::time::{Duration, Instant};
 
 use super::config::{CongestionControl, OrderingMode, ReliabilityMode, StreamConfig};
+#[cfg(feature = "webtransport")]
+use super::session::{read_frame, write_frame, WebTransportSession};
+#[cfg(feature = "webtransport")]
+use std::sync::Arc;
+#[cfg(feature = "webtransport")]
+use tokio::sync::Mutex;
+#[cfg(feature = "webtransport")]
+use wtransport::{RecvStream, SendStream};
+
+/// How the data of a stream reaches the peer, following its reliability and ordering modes
+#[cfg(feature = "webtransport")]
+#[derive(Debug, Clone)]
+pub enum StreamChannel {
+    /// A bidirectional QUIC stream delivering every message in order
+    Ordered {
+        send: Arc<Mutex<SendStream>>,
+        recv: Arc<Mutex<RecvStream>>,
+        max_message_size: usize,
+    },
+    /// A fresh unidirectional stream per message, delivered reliably but in any order
+    Unordered(WebTransportSession),
+    /// Datagrams, which may be lost or reordered
+    Datagram(WebTransportSession),
+}
+
+#[cfg(feature = "webtransport")]
+impl StreamChannel {
+    /// Open the channel matching `config` on `session`
+    ///
+    /// Best-effort streams use datagrams and unordered streams use one
+    /// unidirectional stream per message. Partial reliability is delivered
+    /// reliably, as QUIC streams do not expose a retransmission limit.
+    pub async fn open(
+        session: &WebTransportSession,
+        config: &StreamConfig,
+    ) -> Result<Self, TransportError> {
+        match (config.reliability, config.ordering) {
+            (ReliabilityMode::BestEffort, _) => Ok(Self::Datagram(session.clone())),
+            (_, OrderingMode::Unordered) => Ok(Self::Unordered(session.clone())),
+            _ => {
+                let (send, recv) = session.open_bi().await?;
+                Ok(Self::Ordered {
+                    send: Arc::new(Mutex::new(send)),
+                    recv: Arc::new(Mutex::new(recv)),
+                    max_message_size: session.max_message_size(),
+                })
+            }
+        }
+    }
+}
 
 /// WebTransport stream with advanced features
 #[derive(Debug, Clone)]
@@ -23,6 +74,8 @@ pub struct AdvancedWebTransportStream {
     retransmission_count: u32,
     average_send_rate: f64,
     last_used: Instant,
+    #[cfg(feature = "webtransport")]
+    channel: Option<StreamChannel>,
 }
 
 impl AdvancedWebTransportStream {
@@ -46,9 +99,23 @@ impl AdvancedWebTransportStream {
             retransmission_count: 0,
             average_send_rate: 0.0,
             last_used: Instant::now(),
+            #[cfg(feature = "webtransport")]
+            channel: None,
         }
     }
 
+    /// Attach the channel carrying the stream's data
+    #[cfg(feature = "webtransport")]
+    pub fn with_channel(mut self, channel: StreamChannel) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    #[cfg(feature = "webtransport")]
+    pub fn channel(&self) -> Option<&StreamChannel> {
+        self.channel.as_ref()
+    }
+
     pub fn stream_id(&self) -> u32 {
         self.stream_id
     }
@@ -73,15 +140,66 @@ impl AdvancedWebTransportStream {
         self.can_receive
     }
 
-    pub async fn send_data<T: serde::Serialize>(
-        &mut self,
-        _data: &T,
-    ) -> Result<(), TransportError> {
-        // Simulate sending data
+    pub async fn send_data<T: serde::Serialize>(&mut self, data: &T) -> Result<(), TransportError> {
+        let message = Message {
+            data: serde_json::to_vec(data)
+                .map_err(|e| TransportError::SendFailed(e.to_string()))?,
+            message_type: MessageType::Text,
+        };
+        self.send_message(&message).await
+    }
+
+    /// Send `message` over the stream's channel
+    pub async fn send_message(&mut self, message: &Message) -> Result<(), TransportError> {
         self.last_used = Instant::now();
+
+        #[cfg(feature = "webtransport")]
+        match self.channel.as_ref().ok_or(TransportError::NotConnected)? {
+            StreamChannel::Ordered { send, .. } => {
+                write_frame(&mut *send.lock().await, message).await?
+            }
+            StreamChannel::Unordered(session) => session.send_uni(message).await?,
+            StreamChannel::Datagram(session) => session.send_datagram(&message.data)?,
+        }
+        #[cfg(not(feature = "webtransport"))]
+        let _ = message;
+
         Ok(())
     }
 
+    /// Receive the next value sent by the peer on this stream
+    ///
+    /// Only ordered streams have data of their own; unordered streams and
+    /// datagrams arrive on the session.
+    #[cfg(feature = "webtransport")]
+    pub async fn receive_data<T: serde::de::DeserializeOwned>(
+        &mut self,
+    ) -> Result<T, TransportError> {
+        let message = self.receive_message().await?;
+        serde_json::from_slice(&message.data)
+            .map_err(|e| TransportError::ReceiveFailed(e.to_string()))
+    }
+
+    /// Receive the next message sent by the peer on this stream
+    #[cfg(feature = "webtransport")]
+    pub async fn receive_message(&mut self) -> Result<Message, TransportError> {
+        let Some(StreamChannel::Ordered {
+            recv,
+            max_message_size,
+            ..
+        }) = &self.channel
+        else {
+            return Err(TransportError::NotSupported(
+                "Only ordered streams can be read directly".to_string(),
+            ));
+        };
+        let message = read_frame(&mut *recv.lock().await, *max_message_size)
+            .await?
+            .ok_or(TransportError::ConnectionClosed)?;
+        self.last_used = Instant::now();
+        Ok(message)
+    }
+
     pub async fn send_latency(&self) -> Duration {
         self.send_latency
     }
@@ -107,3 +225,26 @@ impl AdvancedWebTransportStream {
         self.average_send_rate
     }
 }
+
+#[async_trait]
+impl BiStream for AdvancedWebTransportStream {
+    fn id(&self) -> u32 {
+        self.stream_id
+    }
+
+    async fn send_message(&mut self, message: &Message) -> Result<(), TransportError> {
+        AdvancedWebTransportStream::send_message(self, message).await
+    }
+
+    #[cfg(feature = "webtransport")]
+    async fn receive_message(&mut self) -> Result<Message, TransportError> {
+        AdvancedWebTransportStream::receive_message(self).await
+    }
+
+    #[cfg(not(feature = "webtransport"))]
+    async fn receive_message(&mut self) -> Result<Message, TransportError> {
+        Err(TransportError::NotSupported(
+            "Receiving on a stream needs the webtransport feature".to_string(),
+        ))
+    }
+}
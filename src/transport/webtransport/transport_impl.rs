@@ -6,8 +6,12 @@ use async_trait::async_trait;
 use futures::{Sink, Stream};
 use std::pin::Pin;
 
-use crate::transport::{ConnectionState, Message, Transport, TransportError};
+use crate::transport::{ConnectionState, Message, StreamOpener, Transport, TransportError};
+#[cfg(feature = "webtransport")]
+use crate::transport::{Datagram, DatagramStream};
+use super::config::StreamConfig;
 use super::core::WebTransportConnection;
+use super::stream::AdvancedWebTransportStream;
 use super::sink::WebTransportSink;
 
 #[async_trait]
@@ -18,39 +22,7 @@ impl Transport for WebTransportConnection {
     async fn connect(&mut self, url: &str) -> Result<(), TransportError> {
         *self.state.lock().unwrap() = ConnectionState::Connecting;
         self.set_url(url.to_string());
-
-        // Start the connection task
-        self.start_connection_task(url.to_string()).await?;
-
-        // Wait for connection to be established
-        let mut attempts = 0;
-        const MAX_WAIT_ATTEMPTS: u32 = 30; // 30 * 100ms = 3 seconds
-
-        while attempts < MAX_WAIT_ATTEMPTS {
-            match self.state() {
-                ConnectionState::Connected => return Ok(()),
-                ConnectionState::Disconnected => {
-                    return Err(TransportError::ConnectionFailed(
-                        "Failed to establish connection".to_string(),
-                    ))
-                }
-                ConnectionState::Connecting => {
-                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                    attempts += 1;
-                }
-                ConnectionState::Reconnecting => {
-                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                    attempts += 1;
-                }
-                ConnectionState::Failed => {
-                    return Err(TransportError::ConnectionFailed(
-                        "Connection failed".to_string(),
-                    ))
-                }
-            }
-        }
-
-        Err(TransportError::Timeout)
+        self.establish(url).await
     }
 
     async fn disconnect(&mut self) -> Result<(), TransportError> {
@@ -62,19 +34,37 @@ impl Transport for WebTransportConnection {
             return Err(TransportError::NotConnected);
         }
 
-        // For testing, simulate successful message sending
-        // In a real implementation, this would send through WebTransport streams
+        #[cfg(feature = "webtransport")]
+        if let Some(session) = &self.session {
+            session.send(message).await?;
+        }
 
         // Update metrics to track sent messages
         let mut metrics = self.metrics.lock().unwrap();
         metrics.messages_sent += 1;
         metrics.bytes_sent += message.data.len() as u64;
 
-        // Simulate successful sending
         Ok(())
     }
 
+    #[cfg(feature = "webtransport")]
+    async fn receive_message(&self) -> Result<Message, TransportError> {
+        let session = self.session.as_ref().ok_or(TransportError::NotConnected)?;
+        let message = session.receive().await?;
+
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.message_count += 1;
+        metrics.bytes_received += message.data.len() as u64;
+        Ok(message)
+    }
+
     fn split(self) -> (Self::Stream, Self::Sink) {
+        #[cfg(feature = "webtransport")]
+        if let Some(session) = self.session.clone() {
+            let (stream, sink) = session.split();
+            return (stream, Box::pin(sink) as Self::Sink);
+        }
+
         // Create a new channel for the split connection
         let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
 
@@ -103,6 +93,36 @@ impl Transport for WebTransportConnection {
         self.create_stream(stream_config).await?;
         Ok(())
     }
+
+    #[cfg(feature = "webtransport")]
+    async fn send_datagram(&self, datagram: Datagram) -> Result<(), TransportError> {
+        let session = self.session.as_ref().ok_or(TransportError::NotConnected)?;
+        session.send_datagram(&datagram.encode())
+    }
+
+    #[cfg(feature = "webtransport")]
+    fn datagrams(&self) -> Result<DatagramStream, TransportError> {
+        let session = self.session.clone().ok_or(TransportError::NotConnected)?;
+        let datagrams = futures::stream::unfold(session, |session| async move {
+            loop {
+                let payload = session.receive_datagram().await.ok()?;
+                // Skip datagrams sent by a raw stream rather than as a `Datagram`
+                if let Ok(datagram) = Datagram::decode(&payload) {
+                    return Some((datagram, session));
+                }
+            }
+        });
+        Ok(Box::pin(datagrams))
+    }
+}
+
+#[async_trait]
+impl StreamOpener for WebTransportConnection {
+    type Stream = AdvancedWebTransportStream;
+
+    async fn open_stream(&self) -> Result<AdvancedWebTransportStream, TransportError> {
+        self.create_stream(StreamConfig::default()).await
+    }
 }
 
 impl WebTransportConnection {
@@ -2,13 +2,23 @@
 //!
 //! Memory-efficient buffer for zero-copy message handling
 
+use crate::codec::RKYV_ALIGNMENT;
 use crate::zero_copy::codec::ZeroCopyMessage;
+use rkyv::AlignedVec;
+
+#[cfg(feature = "zero-copy")]
+use crate::codec::CodecError;
+#[cfg(feature = "zero-copy")]
+use crate::zero_copy::codec::ZeroCopyCodec;
+#[cfg(feature = "zero-copy")]
+use rkyv::{validation::validators::DefaultValidator, Archive, CheckBytes};
 
 /// Batch message container for efficient bulk operations
 #[derive(Clone, Debug)]
 #[cfg_attr(
     feature = "zero-copy",
-    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    archive(check_bytes)
 )]
 #[cfg_attr(
     not(feature = "zero-copy"),
@@ -56,8 +66,11 @@ impl<T> Default for MessageBatch<T> {
 }
 
 /// Zero-copy buffer for memory-efficient message handling
+///
+/// Every message starts on a 16-byte boundary, so rkyv archives appended
+/// here can be read in place for as long as the buffer lives.
 pub struct ZeroCopyBuffer {
-    data: Vec<u8>,
+    data: AlignedVec,
     positions: Vec<MessagePosition>,
 }
 
@@ -71,12 +84,16 @@ struct MessagePosition {
 impl ZeroCopyBuffer {
     pub fn new() -> Self {
         Self {
-            data: Vec::new(),
+            data: AlignedVec::new(),
             positions: Vec::new(),
         }
     }
 
     pub fn append(&mut self, data: &[u8], message_type: &str) {
+        // Pad so the message starts aligned
+        let padding = (RKYV_ALIGNMENT - self.data.len() % RKYV_ALIGNMENT) % RKYV_ALIGNMENT;
+        self.data.resize(self.data.len() + padding, 0);
+
         let start = self.data.len();
         self.data.extend_from_slice(data);
         let end = self.data.len();
@@ -88,6 +105,7 @@ impl ZeroCopyBuffer {
         });
     }
 
+    /// Number of bytes held, including alignment padding between messages
     pub fn len(&self) -> usize {
         self.data.len()
     }
@@ -113,6 +131,19 @@ impl ZeroCopyBuffer {
     pub fn read_optional(&self, index: usize) -> Option<&[u8]> {
         self.read(index)
     }
+
+    /// Validate the archive stored at `index` and borrow it in place
+    ///
+    /// Returns `None` when there is no message at `index`.
+    #[cfg(feature = "zero-copy")]
+    pub fn access<T>(&self, index: usize) -> Option<Result<&T::Archived, CodecError>>
+    where
+        T: Archive,
+        T::Archived: for<'a> CheckBytes<DefaultValidator<'a>>,
+    {
+        self.read(index)
+            .map(|data| ZeroCopyCodec::<T>::new().access(data))
+    }
 }
 
 impl Default for ZeroCopyBuffer {
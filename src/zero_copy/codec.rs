@@ -1,14 +1,22 @@
 //! Zero-Copy Codec
 //!
-//! High-performance serialization using rkyv
+//! High-performance serialization using rkyv. Besides decoding into owned
+//! values, archives can be validated once and read in place through
+//! [`ZeroCopyCodec::access`].
 
 use crate::codec::{Codec, CodecError};
 use serde::{Deserialize, Serialize};
 use std::marker::PhantomData;
 
+#[cfg(feature = "zero-copy")]
+use crate::codec::{RkyvCodec, RKYV_ALIGNMENT, RKYV_SCRATCH_SPACE};
+#[cfg(feature = "zero-copy")]
+use crate::transport::Message;
 #[cfg(feature = "zero-copy")]
 use rkyv::{
-    from_bytes, to_bytes, Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize,
+    de::deserializers::SharedDeserializeMap, ser::serializers::AllocSerializer,
+    validation::validators::DefaultValidator, AlignedVec, Archive, CheckBytes,
+    Deserialize as RkyvDeserialize, Serialize as RkyvSerialize,
 };
 
 /// Zero-copy codec using rkyv serialization
@@ -30,31 +38,71 @@ impl<T> Default for ZeroCopyCodec<T> {
     }
 }
 
+#[cfg(feature = "zero-copy")]
+impl<T> ZeroCopyCodec<T>
+where
+    T: Archive,
+    T::Archived: for<'a> CheckBytes<DefaultValidator<'a>>,
+{
+    /// Validate an archive once and borrow it in place, without deserializing
+    ///
+    /// `data` must be aligned to 16 bytes. Buffers kept in a
+    /// [`crate::zero_copy::ZeroCopyBuffer`] always are; anything else is
+    /// rejected rather than silently copied.
+    pub fn access<'a>(&self, data: &'a [u8]) -> Result<&'a T::Archived, CodecError> {
+        if data.as_ptr() as usize % RKYV_ALIGNMENT != 0 {
+            return Err(CodecError::DeserializationFailed(
+                "rkyv: archive is not aligned".to_string(),
+            ));
+        }
+
+        rkyv::check_archived_root::<T>(data)
+            .map_err(|e| CodecError::DeserializationFailed(format!("rkyv: {}", e)))
+    }
+
+    /// Validate an archive that may not be aligned and borrow it
+    ///
+    /// Aligned archives are read in place; anything else is copied into
+    /// `scratch` first and read from there.
+    pub fn access_realigned<'a>(
+        &self,
+        data: &'a [u8],
+        scratch: &'a mut AlignedVec,
+    ) -> Result<&'a T::Archived, CodecError> {
+        if data.as_ptr() as usize % RKYV_ALIGNMENT == 0 {
+            return self.access(data);
+        }
+        scratch.clear();
+        scratch.extend_from_slice(data);
+        self.access(scratch)
+    }
+
+    /// Validate the archive carried by a received message and borrow it
+    ///
+    /// Message data comes from the socket with no alignment guarantee, so
+    /// it may be realigned into `scratch`; see [`ZeroCopyCodec::access_realigned`].
+    pub fn access_message<'a>(
+        &self,
+        message: &'a Message,
+        scratch: &'a mut AlignedVec,
+    ) -> Result<&'a T::Archived, CodecError> {
+        self.access_realigned(&message.data, scratch)
+    }
+}
+
 #[cfg(feature = "zero-copy")]
 impl<T> Codec<T> for ZeroCopyCodec<T>
 where
-    T: Archive
-        + RkyvSerialize<rkyv::rancor::Strategy<rkyv::rancor::Panic, rkyv::rancor::Panic>>
-        + for<'a> RkyvDeserialize<T, rkyv::rancor::Strategy<rkyv::rancor::Panic, rkyv::rancor::Panic>>
-        + Clone
-        + Send
-        + Sync
-        + 'static,
+    T: Archive + RkyvSerialize<AllocSerializer<RKYV_SCRATCH_SPACE>> + Send + Sync,
     T::Archived:
-        rkyv::Deserialize<T, rkyv::rancor::Strategy<rkyv::rancor::Panic, rkyv::rancor::Panic>>,
+        for<'a> CheckBytes<DefaultValidator<'a>> + RkyvDeserialize<T, SharedDeserializeMap>,
 {
     fn encode(&self, message: &T) -> Result<Vec<u8>, CodecError> {
-        to_bytes(message)
-            .map_err(|e| {
-                CodecError::SerializationFailed(format!("rkyv serialization failed: {}", e))
-            })
-            .map(|bytes| bytes.to_vec())
+        RkyvCodec::new().encode(message)
     }
 
     fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
-        from_bytes(data).map_err(|e| {
-            CodecError::DeserializationFailed(format!("rkyv deserialization failed: {}", e))
-        })
+        RkyvCodec::new().decode(data)
     }
 
     fn content_type(&self) -> &'static str {
@@ -90,6 +138,7 @@ where
 /// High-performance message with zero-copy deserialization support
 #[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "zero-copy", derive(Archive, RkyvSerialize, RkyvDeserialize))]
+#[cfg_attr(feature = "zero-copy", archive(check_bytes))]
 #[cfg_attr(not(feature = "zero-copy"), derive(Serialize, Deserialize))]
 pub struct ZeroCopyMessage<T> {
     pub id: String,
@@ -100,6 +149,7 @@ pub struct ZeroCopyMessage<T> {
 
 #[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "zero-copy", derive(Archive, RkyvSerialize, RkyvDeserialize))]
+#[cfg_attr(feature = "zero-copy", archive(check_bytes))]
 #[cfg_attr(not(feature = "zero-copy"), derive(Serialize, Deserialize))]
 pub struct MessageMetadata {
     pub content_type: String,
@@ -149,3 +199,93 @@ impl<T> ZeroCopyMessage<T> {
         }
     }
 }
+
+#[cfg(all(test, feature = "zero-copy"))]
+mod tests {
+    use super::*;
+    use crate::zero_copy::ZeroCopyBuffer;
+
+    #[derive(Debug, Clone, PartialEq, Archive, RkyvSerialize, RkyvDeserialize)]
+    #[archive(check_bytes)]
+    struct MarketSnapshot {
+        symbol: String,
+        bids: Vec<(u64, u64)>,
+    }
+
+    fn snapshot() -> MarketSnapshot {
+        MarketSnapshot {
+            symbol: "BTC-USD".to_string(),
+            bids: (0..100).map(|i| (50_000 - i, i + 1)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_access_reads_archive_in_place() {
+        let codec = ZeroCopyCodec::<MarketSnapshot>::new();
+        let encoded = codec.encode(&snapshot()).unwrap();
+
+        // Push the archive off its natural alignment; the buffer realigns it
+        let mut buffer = ZeroCopyBuffer::new();
+        buffer.append(b"abc", "text");
+        buffer.append(&encoded, "application/rkyv");
+
+        let archived = buffer.access::<MarketSnapshot>(1).unwrap().unwrap();
+        assert_eq!(archived.symbol, "BTC-USD");
+        assert_eq!(archived.bids.len(), 100);
+        assert_eq!(archived.bids[0].0, 50_000);
+        assert!(buffer.access::<MarketSnapshot>(2).is_none());
+
+        let message = Message {
+            data: buffer.read(1).unwrap().to_vec(),
+            message_type: crate::transport::MessageType::Binary,
+        };
+        let mut scratch = AlignedVec::new();
+        let archived = codec.access_message(&message, &mut scratch).unwrap();
+        let owned: MarketSnapshot = archived
+            .deserialize(&mut SharedDeserializeMap::default())
+            .unwrap();
+        assert_eq!(owned, snapshot());
+    }
+
+    #[test]
+    fn test_access_rejects_invalid_archives() {
+        let codec = ZeroCopyCodec::<MarketSnapshot>::new();
+        let mut buffer = ZeroCopyBuffer::new();
+        buffer.append(&[0xff; 64], "application/rkyv");
+        assert!(buffer.access::<MarketSnapshot>(0).unwrap().is_err());
+
+        buffer.append(&codec.encode(&snapshot()).unwrap(), "application/rkyv");
+        let aligned = buffer.read(1).unwrap();
+        assert!(codec.access(aligned).is_ok());
+
+        let mut shifted = rkyv::AlignedVec::new();
+        shifted.push(0);
+        shifted.extend_from_slice(aligned);
+        assert!(matches!(
+            codec.access(&shifted[1..]),
+            Err(CodecError::DeserializationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_access_realigns_offset_data() {
+        let codec = ZeroCopyCodec::<MarketSnapshot>::new();
+        let encoded = codec.encode(&snapshot()).unwrap();
+
+        // Every offset but one is misaligned, as socket data may be
+        let mut padded = AlignedVec::new();
+        for offset in 0..RKYV_ALIGNMENT {
+            padded.clear();
+            padded.extend_from_slice(&vec![0; offset]);
+            padded.extend_from_slice(&encoded);
+
+            let mut scratch = AlignedVec::new();
+            let archived = codec
+                .access_realigned(&padded[offset..], &mut scratch)
+                .unwrap();
+            assert_eq!(archived.symbol, "BTC-USD");
+            assert_eq!(archived.bids.len(), 100);
+            assert_eq!(scratch.is_empty(), offset == 0);
+        }
+    }
+}
@@ -3,6 +3,8 @@
 //! These tests ensure that API changes maintain backward compatibility
 //! and that deprecated features are properly handled.
 
+use leptos_ws_pro::codec::{Codec, MigrationRegistry, Versioned, VersionedCodec};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::collections::HashMap;
 
@@ -370,3 +372,86 @@ fn test_breaking_change_detection() {
     // New client should work
     assert!(server.version.is_compatible_with(&new_client));
 }
+
+/// Chat message as shipped by clients still on the previous release
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ChatMessageV1 {
+    text: String,
+}
+
+impl Versioned for ChatMessageV1 {
+    const TYPE_NAME: &'static str = "chat_message";
+    const VERSION: u32 = 1;
+}
+
+/// Chat message after the server added a room
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ChatMessageV2 {
+    text: String,
+    room: String,
+}
+
+impl Versioned for ChatMessageV2 {
+    const TYPE_NAME: &'static str = "chat_message";
+    const VERSION: u32 = 2;
+}
+
+fn chat_registry() -> MigrationRegistry {
+    MigrationRegistry::new()
+        .with_upgrade::<ChatMessageV2, _>(1, |mut data| {
+            data["room"] = json!("general");
+            Ok(data)
+        })
+        .with_downgrade::<ChatMessageV2, _>(2, |mut data| {
+            data.as_object_mut().map(|data| data.remove("room"));
+            Ok(data)
+        })
+        .with_schema::<ChatMessageV2>(json!({
+            "type": "object",
+            "required": ["text"],
+            "properties": {
+                "text": { "type": "string" },
+                "room": { "type": "string" }
+            }
+        }))
+}
+
+fn envelope_schema() -> Value {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/api/schemas/envelope-schema.json");
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn test_envelope_schema_matches_published_schema() {
+    assert_eq!(MigrationRegistry::new().json_schema(), envelope_schema());
+}
+
+#[test]
+fn test_mixed_versions_during_rolling_deploy() {
+    let published = jsonschema::JSONSchema::compile(&envelope_schema()).unwrap();
+    let generated = chat_registry().json_schema();
+    let generated = jsonschema::JSONSchema::compile(&generated).unwrap();
+
+    let old_client = VersionedCodec::new(MigrationRegistry::new().register::<ChatMessageV1>());
+    // The server writes v1 until every client understands v2
+    let server = VersionedCodec::new(chat_registry()).with_pinned_version::<ChatMessageV2>(1);
+
+    let from_client = old_client
+        .encode(&ChatMessageV1 {
+            text: "hello".to_string(),
+        })
+        .unwrap();
+    let received: ChatMessageV2 = server.decode(&from_client).unwrap();
+    assert_eq!(received.room, "general");
+
+    let from_server = server.encode(&received).unwrap();
+    let received: ChatMessageV1 = old_client.decode(&from_server).unwrap();
+    assert_eq!(received.text, "hello");
+
+    for frame in [&from_client, &from_server] {
+        let frame: Value = serde_json::from_slice(frame).unwrap();
+        assert!(published.is_valid(&frame));
+        assert!(generated.is_valid(&frame));
+    }
+    assert!(!generated.is_valid(&json!({"type": "chat_message", "version": 3, "data": {}})));
+}
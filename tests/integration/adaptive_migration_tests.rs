@@ -0,0 +1,272 @@
+//! Adaptive transport migration tests
+//!
+//! Local servers drop the first connection; the adaptive transport migrates
+//! to a new one, resumes the session on it, replays what it could not send
+//! and keeps receiving through it.
+
+use futures::{SinkExt, StreamExt};
+use leptos::prelude::GetUntracked;
+use leptos_ws_pro::transport::{
+    adaptive::{AdaptiveTransport, MigrationPolicy, TransportCapabilities},
+    ConnectionState, Message, MessageType, Transport, TransportConfig, TransportError,
+};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tokio_tungstenite::{accept_async, tungstenite, WebSocketStream};
+
+fn text(data: &str) -> Message {
+    Message {
+        data: data.as_bytes().to_vec(),
+        message_type: MessageType::Text,
+    }
+}
+
+async fn next_message(transport: &AdaptiveTransport) -> Message {
+    timeout(Duration::from_secs(5), transport.receive_message())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+/// Echo the text messages of `socket`, answering its pings on the way
+async fn echo(mut socket: WebSocketStream<TcpStream>) {
+    while let Some(Ok(frame)) = socket.next().await {
+        if let tungstenite::Message::Text(_) = frame {
+            if socket.send(frame).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Echoes every connection, closing the first one after one message when
+/// `flaky` is set
+async fn start_echo_server(flaky: bool) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        let mut first = flaky;
+        while let Ok((stream, _)) = listener.accept().await {
+            let Ok(mut socket) = accept_async(stream).await else {
+                continue;
+            };
+            if std::mem::take(&mut first) {
+                let _ = socket.next().await;
+                let _ = socket.close(None).await;
+                continue;
+            }
+            tokio::spawn(echo(socket));
+        }
+    });
+
+    port
+}
+
+/// Upgrades the first connection to a WebSocket and closes it after one
+/// message, then refuses upgrades and answers event stream requests with
+/// `event`
+async fn start_websocket_then_sse_server(event: &'static str) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        let mut first = true;
+        while let Ok((mut stream, _)) = listener.accept().await {
+            // Look at the request head without taking it off the socket
+            let mut request = vec![0; 4096];
+            let (length, upgrade) = loop {
+                let read = stream.peek(&mut request).await.unwrap();
+                let head = String::from_utf8_lossy(&request[..read]).to_lowercase();
+                if let Some(end) = head.find("\r\n\r\n") {
+                    break (end + 4, head.contains("upgrade: websocket"));
+                }
+            };
+            if upgrade && std::mem::take(&mut first) {
+                let mut socket = accept_async(stream).await.unwrap();
+                let _ = socket.next().await;
+                let _ = socket.close(None).await;
+            } else if upgrade {
+                let refused = "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n";
+                let _ = stream.write_all(refused.as_bytes()).await;
+            } else {
+                tokio::spawn(async move {
+                    stream.read_exact(&mut request[..length]).await.unwrap();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\ndata: {}\n\n",
+                        event
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                    // Hold the stream open until the client leaves
+                    let _ = stream.read(&mut request).await;
+                });
+            }
+        }
+    });
+
+    port
+}
+
+#[tokio::test]
+async fn test_migrates_and_replays_after_the_socket_drops() {
+    let port = start_echo_server(true).await;
+
+    let mut transport = AdaptiveTransport::new(TransportConfig::default())
+        .await
+        .unwrap()
+        .with_migration_policy(MigrationPolicy {
+            max_consecutive_errors: 1,
+            ..MigrationPolicy::default()
+        })
+        .on_resume(|| vec![text("resume")]);
+    transport
+        .connect_with_fallback(&format!("ws://127.0.0.1:{}", port))
+        .await
+        .unwrap();
+    let selected = transport.selected_transport();
+    assert_eq!(selected.get_untracked(), "WebSocket");
+
+    transport.send_message(&text("a")).await.unwrap();
+    // Let the server drop the first connection
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Neither send fails: they are queued behind the migration the dropped
+    // socket started, or go through the new socket once it is in place
+    transport.send_message(&text("b")).await.unwrap();
+    transport.send_message(&text("c")).await.unwrap();
+
+    // The new socket echoes what reached it, in order
+    for expected in ["resume", "b", "c"] {
+        assert_eq!(next_message(&transport).await, text(expected));
+    }
+
+    assert_eq!(selected.get_untracked(), "WebSocket");
+    assert_eq!(transport.state(), ConnectionState::Connected);
+    let metrics = transport.get_performance_metrics();
+    assert_eq!(metrics.migration_count, 1);
+    assert_eq!(metrics.consecutive_errors, 0);
+
+    // Sends go straight through the new transport again
+    transport.send_message(&text("d")).await.unwrap();
+    assert_eq!(next_message(&transport).await, text("d"));
+}
+
+#[tokio::test]
+async fn test_split_transport_follows_the_migration() {
+    let port = start_echo_server(true).await;
+
+    let mut transport = AdaptiveTransport::new(TransportConfig::default())
+        .await
+        .unwrap();
+    transport
+        .connect_with_fallback(&format!("ws://127.0.0.1:{}", port))
+        .await
+        .unwrap();
+    let selected = transport.selected_transport();
+
+    let (mut incoming, mut outgoing) = transport.split();
+    // The server drops the socket after this one
+    outgoing.send(text("a")).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    outgoing.send(text("b")).await.unwrap();
+    let message = timeout(Duration::from_secs(5), incoming.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(message, text("b"));
+    assert_eq!(selected.get_untracked(), "WebSocket");
+}
+
+#[tokio::test]
+async fn test_transports_that_only_receive_are_not_picked() {
+    let port = start_websocket_then_sse_server("pushed over SSE").await;
+    let url = format!("ws://127.0.0.1:{}", port);
+
+    let mut transport = AdaptiveTransport::new(TransportConfig::default())
+        .await
+        .unwrap()
+        .with_capabilities(TransportCapabilities {
+            webtransport_supported: false,
+            ..TransportCapabilities::detect()
+        });
+    transport.connect_with_fallback(&url).await.unwrap();
+    let selected = transport.selected_transport();
+    assert_eq!(selected.get_untracked(), "WebSocket");
+
+    // The server drops the socket after this; WebSocket is refused from
+    // then on, and SSE can't carry what is sent
+    transport.send_message(&text("a")).await.unwrap();
+    timeout(Duration::from_secs(5), async {
+        while transport.state() != ConnectionState::Failed {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(selected.get_untracked(), "WebSocket");
+
+    assert!(matches!(
+        transport.connect_with_fallback(&url).await,
+        Err(TransportError::ConnectionFailed(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_latency_is_measured_from_pings() {
+    let port = start_echo_server(false).await;
+    let url = format!("ws://127.0.0.1:{}", port);
+
+    // Handing messages to the transport says nothing about the round trip
+    let config = TransportConfig {
+        heartbeat_interval: None,
+        ..TransportConfig::default()
+    };
+    let mut transport = AdaptiveTransport::new(config).await.unwrap();
+    transport.connect_with_fallback(&url).await.unwrap();
+    transport.send_message(&text("a")).await.unwrap();
+    assert_eq!(next_message(&transport).await, text("a"));
+    assert_eq!(transport.get_performance_metrics().average_latency, None);
+
+    let config = TransportConfig {
+        heartbeat_interval: Some(Duration::from_millis(20)),
+        ..TransportConfig::default()
+    };
+    let mut transport = AdaptiveTransport::new(config).await.unwrap();
+    transport.connect_with_fallback(&url).await.unwrap();
+    timeout(Duration::from_secs(5), async {
+        while transport
+            .get_performance_metrics()
+            .average_latency
+            .is_none()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // The pongs answering the probes are not passed on
+    transport.send_message(&text("b")).await.unwrap();
+    assert_eq!(next_message(&transport).await, text("b"));
+}
+
+#[tokio::test]
+async fn test_slow_transport_needs_migration() {
+    let transport = AdaptiveTransport::new(TransportConfig::default())
+        .await
+        .unwrap()
+        .with_migration_policy(MigrationPolicy {
+            max_latency: Duration::from_millis(50),
+            ..MigrationPolicy::default()
+        });
+    assert!(!transport.needs_migration());
+
+    // Not connected, so there is nothing to migrate yet
+    transport.record_latency(Duration::from_millis(200)).await;
+    assert!(transport.needs_migration());
+    assert!(transport.migrate().await.is_err());
+}
@@ -96,17 +96,17 @@ mod integration_core_tests {
             room_id: Some("test-room".to_string()),
         };
 
-        // Test query method
+        // The context has no live transport attached, so calls surface an error
+        // instead of waiting for a response that can never arrive
         let query_result = rpc_client
             .query::<SendMessageParams>("get_message", params.clone())
             .await;
-        assert!(query_result.is_ok()); // Should succeed with mock implementation
+        assert!(query_result.is_err());
 
-        // Test mutation method
         let mutation_result = rpc_client
             .mutation::<SendMessageParams>("send_message", params.clone())
             .await;
-        assert!(mutation_result.is_ok()); // Should succeed with mock implementation
+        assert!(mutation_result.is_err());
 
         // Verify ID generation worked
         let id1 = rpc_client.generate_id();
@@ -439,18 +439,19 @@ mod integration_core_tests {
         let acks = context.get_acknowledged_messages();
         assert_eq!(acks, vec![1, 2]);
 
-        // Test with RPC client
+        // Test with RPC client; without a live transport the subscription
+        // request cannot be acknowledged
         let rpc_client = RpcClient::<IntegrationTestData>::from_context(&context, JsonCodec);
         let subscription = rpc_client
             .subscribe(SubscribeMessagesParams {
                 channel: Some("ack_test".to_string()),
                 room_id: None,
             })
-            .await
-            .unwrap();
+            .await;
 
-        // Verify subscription creation doesn't interfere with acknowledgments
-        assert_eq!(subscription.id, "rpc_1");
+        // Verify subscription attempts don't interfere with acknowledgments
+        assert!(subscription.is_err());
+        assert_eq!(rpc_client.active_subscriptions(), 0);
 
         let updated_acks = context.get_acknowledged_messages();
         assert_eq!(updated_acks, vec![1, 2]); // Should be unchanged
@@ -0,0 +1,142 @@
+//! WebTransport server tests
+//!
+//! Clients connect over HTTP/3 to a local `WebTransportServer` with a
+//! self-signed certificate and reach its routes by path.
+
+use futures::{SinkExt, StreamExt};
+use leptos_ws_pro::rpc::{RpcError, RpcRouter};
+use leptos_ws_pro::transport::{
+    webtransport::{AcceptedSession, Sha256Digest, WebTransportConnection, WebTransportServer},
+    Message, MessageType, Transport, TransportConfig, TransportError,
+};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::timeout;
+use wtransport::Identity;
+
+#[derive(Deserialize)]
+struct AddParams {
+    a: i64,
+    b: i64,
+}
+
+fn bind() -> WebTransportServer {
+    let identity = Identity::self_signed(["localhost", "127.0.0.1"]).unwrap();
+    WebTransportServer::bind(
+        "127.0.0.1:0".parse().unwrap(),
+        identity,
+        TransportConfig::default(),
+    )
+    .unwrap()
+}
+
+/// Start `server`, returning its port and certificate hashes
+fn start(server: WebTransportServer) -> (u16, Vec<Sha256Digest>) {
+    let port = server.local_addr().unwrap().port();
+    let hashes = server.certificate_hashes().to_vec();
+    tokio::spawn(server.serve());
+    (port, hashes)
+}
+
+async fn client(config: TransportConfig, hashes: &[Sha256Digest]) -> WebTransportConnection {
+    let mut client = WebTransportConnection::new(config).await.unwrap();
+    client.set_server_certificate_hashes(hashes.to_vec());
+    client
+}
+
+#[tokio::test]
+async fn test_rpc_route() {
+    let mut router = RpcRouter::new();
+    router.register("add", |_: (), params: AddParams| async move {
+        Ok::<_, RpcError>(params.a + params.b)
+    });
+    let (port, hashes) = start(bind().rpc("/rpc", router, |_| ()));
+
+    let mut client = client(TransportConfig::default(), &hashes).await;
+    client
+        .connect(&format!("https://127.0.0.1:{}/rpc", port))
+        .await
+        .unwrap();
+
+    let request = serde_json::json!({"id": "add-1", "method": "add", "params": {"a": 2, "b": 3}});
+    client
+        .send_message(&Message {
+            data: serde_json::to_vec(&request).unwrap(),
+            message_type: MessageType::Text,
+        })
+        .await
+        .unwrap();
+
+    let response = timeout(Duration::from_secs(5), client.receive_message())
+        .await
+        .unwrap()
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&response.data).unwrap();
+    assert_eq!(response["id"], "add-1");
+    assert_eq!(response["result"], 5);
+}
+
+#[tokio::test]
+async fn test_custom_route_sees_request() {
+    let server = bind().route("/greet", |session: AcceptedSession| async move {
+        let user = session.headers.get("x-user").cloned().unwrap_or_default();
+        let (mut incoming, mut outgoing) = session.connection.split();
+        while let Some(Ok(message)) = incoming.next().await {
+            let greeting = format!("{}, {}", String::from_utf8_lossy(&message.data), user);
+            let reply = Message {
+                data: greeting.into_bytes(),
+                message_type: MessageType::Text,
+            };
+            if outgoing.send(reply).await.is_err() {
+                break;
+            }
+        }
+    });
+    let (port, hashes) = start(server);
+
+    let mut config = TransportConfig::default();
+    config
+        .headers
+        .insert("x-user".to_string(), "alice".to_string());
+    let mut client = client(config, &hashes).await;
+    client
+        .connect(&format!("https://127.0.0.1:{}/greet?lang=en", port))
+        .await
+        .unwrap();
+
+    let (mut stream, mut sink) = client.split();
+    sink.send(Message {
+        data: b"hello".to_vec(),
+        message_type: MessageType::Text,
+    })
+    .await
+    .unwrap();
+    let reply = timeout(Duration::from_secs(5), stream.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply.data, b"hello, alice");
+}
+
+#[tokio::test]
+async fn test_unknown_path_is_refused() {
+    let (port, hashes) = start(bind().route("/ws", |_| async {}));
+
+    let mut client = client(TransportConfig::default(), &hashes).await;
+    let result = client
+        .connect(&format!("https://127.0.0.1:{}/missing", port))
+        .await;
+    assert!(matches!(result, Err(TransportError::ConnectionFailed(_))));
+}
+
+#[tokio::test]
+async fn test_alt_svc_advertises_port() {
+    let server = bind();
+    let port = server.local_addr().unwrap().port();
+    assert_eq!(
+        server.alt_svc().unwrap(),
+        format!("h3=\":{}\"; ma=86400", port)
+    );
+    assert_eq!(server.certificate_hashes().len(), 1);
+}
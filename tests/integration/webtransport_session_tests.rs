@@ -0,0 +1,236 @@
+//! WebTransport session tests against a local wtransport server
+//!
+//! The server uses a self-signed certificate, which the client pins by its
+//! SHA-256 digest.
+
+use futures::{SinkExt, StreamExt};
+use leptos_ws_pro::transport::{
+    webtransport::{
+        AdvancedWebTransportStream, OrderingMode, ReliabilityMode, Sha256Digest, StreamConfig,
+        WebTransportConnection,
+    },
+    BiStream, ConnectionState, Datagram, Message, MessageType, StreamOpener, Transport,
+    TransportConfig, TransportError,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::timeout;
+use wtransport::{Endpoint, Identity, ServerConfig};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Position {
+    x: i32,
+    y: i32,
+}
+
+/// Start a server echoing every stream and datagram, returning its URL and certificate hash
+async fn start_echo_server() -> (String, Sha256Digest) {
+    let identity = Identity::self_signed(["localhost", "127.0.0.1"]).unwrap();
+    let hash = identity.certificate_chain().as_slice()[0].hash();
+    let config = ServerConfig::builder()
+        .with_bind_address("127.0.0.1:0".parse().unwrap())
+        .with_identity(identity)
+        .build();
+    let server = Endpoint::server(config).unwrap();
+    let port = server.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        loop {
+            let incoming = server.accept().await;
+            tokio::spawn(async move {
+                let Ok(request) = incoming.await else { return };
+                let Ok(connection) = request.accept().await else {
+                    return;
+                };
+                loop {
+                    tokio::select! {
+                        stream = connection.accept_bi() => {
+                            let Ok((mut send, mut recv)) = stream else { break };
+                            tokio::spawn(async move {
+                                let _ = tokio::io::copy(&mut recv, &mut send).await;
+                            });
+                        }
+                        stream = connection.accept_uni() => {
+                            let Ok(mut recv) = stream else { break };
+                            let connection = connection.clone();
+                            tokio::spawn(async move {
+                                let mut data = Vec::new();
+                                tokio::io::AsyncReadExt::read_to_end(&mut recv, &mut data)
+                                    .await
+                                    .unwrap();
+                                let mut send = connection.open_uni().await.unwrap().await.unwrap();
+                                send.write_all(&data).await.unwrap();
+                                send.finish().await.unwrap();
+                            });
+                        }
+                        datagram = connection.receive_datagram() => {
+                            let Ok(datagram) = datagram else { break };
+                            let _ = connection.send_datagram(datagram.payload());
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    (format!("https://127.0.0.1:{}/", port), hash)
+}
+
+async fn connect(url: &str, hash: Sha256Digest) -> WebTransportConnection {
+    let config = TransportConfig {
+        url: url.to_string(),
+        ..Default::default()
+    };
+    let mut client = WebTransportConnection::new(config).await.unwrap();
+    client.set_server_certificate_hashes(vec![hash]);
+    client.connect(url).await.unwrap();
+    client
+}
+
+#[tokio::test]
+async fn test_session_sends_and_receives_messages() {
+    let (url, hash) = start_echo_server().await;
+    let client = connect(&url, hash).await;
+    assert_eq!(client.state(), ConnectionState::Connected);
+
+    let text = Message {
+        data: b"Hello, WebTransport!".to_vec(),
+        message_type: MessageType::Text,
+    };
+    let binary = Message {
+        data: vec![0x01, 0x02, 0x03],
+        message_type: MessageType::Binary,
+    };
+    client.send_message(&text).await.unwrap();
+    client.send_message(&binary).await.unwrap();
+
+    let received = timeout(Duration::from_secs(5), client.receive_message()).await;
+    assert_eq!(received.unwrap().unwrap(), text);
+    let received = timeout(Duration::from_secs(5), client.receive_message()).await;
+    assert_eq!(received.unwrap().unwrap(), binary);
+    assert_eq!(client.get_metrics().messages_sent, 2);
+}
+
+#[tokio::test]
+async fn test_split_session() {
+    let (url, hash) = start_echo_server().await;
+    let client = connect(&url, hash).await;
+    let (mut stream, mut sink) = client.split();
+
+    for i in 0..3 {
+        let message = Message {
+            data: format!("message {}", i).into_bytes(),
+            message_type: MessageType::Text,
+        };
+        sink.send(message.clone()).await.unwrap();
+        let received = timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap();
+        assert_eq!(received.unwrap().unwrap(), message);
+    }
+}
+
+#[tokio::test]
+async fn test_streams_follow_their_modes() {
+    let (url, hash) = start_echo_server().await;
+    let mut client = connect(&url, hash).await;
+
+    client.create_bidirectional_stream().await.unwrap();
+    assert_eq!(client.stream_count(), 1);
+
+    let mut ordered: AdvancedWebTransportStream =
+        client.create_stream(StreamConfig::default()).await.unwrap();
+    let position = Position { x: 3, y: 4 };
+    ordered.send_data(&position).await.unwrap();
+    let echoed: Position = timeout(Duration::from_secs(5), ordered.receive_data())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(echoed, position);
+
+    let session = client.session().unwrap().clone();
+    let mut unordered = client
+        .create_stream(StreamConfig {
+            ordering: OrderingMode::Unordered,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    unordered.send_data(&position).await.unwrap();
+    let echoed = timeout(Duration::from_secs(5), session.receive_uni())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        serde_json::from_slice::<Position>(&echoed.data).unwrap(),
+        position
+    );
+
+    let mut best_effort = client
+        .create_stream(StreamConfig {
+            reliability: ReliabilityMode::BestEffort,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert!(session.max_datagram_size().is_some());
+    best_effort.send_data(&position).await.unwrap();
+    let echoed = timeout(Duration::from_secs(5), session.receive_datagram())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        serde_json::from_slice::<Position>(&echoed).unwrap(),
+        position
+    );
+}
+
+/// Echo a value through a stream of `opener`, whichever transport it is
+async fn echo_through<O: StreamOpener>(opener: &O, position: &Position) -> Position {
+    let mut stream = opener.open_stream().await.unwrap();
+    stream.send_data(position).await.unwrap();
+    timeout(Duration::from_secs(5), stream.receive_data())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_session_opens_bi_streams() {
+    let (url, hash) = start_echo_server().await;
+    let client = connect(&url, hash).await;
+
+    let position = Position { x: 5, y: 6 };
+    assert_eq!(echo_through(&client, &position).await, position);
+    assert_eq!(client.stream_count(), 1);
+}
+
+#[tokio::test]
+async fn test_datagrams_through_transport() {
+    let (url, hash) = start_echo_server().await;
+    let client = connect(&url, hash).await;
+    let mut datagrams = client.datagrams().unwrap();
+
+    let datagram = Datagram::new(1, b"cursor 3,4".to_vec());
+    client.send_datagram(datagram.clone()).await.unwrap();
+    let echoed = timeout(Duration::from_secs(5), datagrams.next())
+        .await
+        .unwrap();
+    assert_eq!(echoed, Some(datagram));
+}
+
+#[tokio::test]
+async fn test_unknown_certificate_is_rejected() {
+    let (url, _) = start_echo_server().await;
+    let config = TransportConfig {
+        url: url.clone(),
+        ..Default::default()
+    };
+    let mut client = WebTransportConnection::new(config).await.unwrap();
+    client.set_server_certificate_hashes(vec![Sha256Digest::new([0; 32])]);
+
+    let result = client.connect(&url).await;
+    assert!(matches!(result, Err(TransportError::ConnectionFailed(_))));
+    assert_eq!(client.state(), ConnectionState::Disconnected);
+    assert!(client.session().is_none());
+}
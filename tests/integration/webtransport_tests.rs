@@ -56,7 +56,7 @@ async fn test_webtransport_capabilities() {
     let capabilities = TransportCapabilities::detect();
 
     // WebTransport availability depends on platform
-    // On native platforms, it's not yet available
+    // On native platforms, it needs the webtransport feature
     // On WASM platforms, it should be available
     #[cfg(target_arch = "wasm32")]
     {
@@ -67,9 +67,10 @@ async fn test_webtransport_capabilities() {
     }
     #[cfg(not(target_arch = "wasm32"))]
     {
-        assert!(
-            !capabilities.webtransport,
-            "WebTransport should not be available on native platforms yet"
+        assert_eq!(
+            capabilities.webtransport,
+            cfg!(feature = "webtransport"),
+            "WebTransport should be available on native platforms with the webtransport feature"
         );
     }
 
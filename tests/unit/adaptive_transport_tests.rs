@@ -8,6 +8,7 @@ use leptos_ws_pro::transport::{
     adaptive::AdaptiveTransport, ConnectionState, Message, MessageType, Transport, TransportConfig,
     TransportError,
 };
+use leptos::prelude::GetUntracked;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tokio::net::TcpListener;
@@ -61,7 +62,7 @@ async fn test_adaptive_transport_websocket_selection() {
     // Then: Should select WebSocket and connect successfully
     assert!(result.is_ok());
     assert_eq!(transport.state(), ConnectionState::Connected);
-    assert_eq!(transport.selected_transport(), "WebSocket");
+    assert_eq!(transport.selected_transport().get_untracked(), "WebSocket");
 }
 
 #[tokio::test]
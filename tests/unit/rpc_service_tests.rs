@@ -0,0 +1,135 @@
+//! Tests for typed RPC services generated with `#[rpc_service]`
+
+use leptos_ws_pro::rpc::{rpc_service, RpcClient, RpcError, RpcRouter};
+use leptos_ws_pro::transport::{Message, TransportError};
+use leptos_ws_pro::JsonCodec;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct User {
+    id: u64,
+    name: String,
+}
+
+#[rpc_service]
+trait Users {
+    async fn get_user(&self, id: u64) -> Result<User, RpcError>;
+    async fn rename(&self, id: u64, name: String) -> Result<(), RpcError>;
+    async fn count(&self) -> Result<usize, RpcError>;
+
+    async fn exists(&self, id: u64) -> Result<bool, RpcError> {
+        Ok(self.get_user(id).await.is_ok())
+    }
+}
+
+#[derive(Clone, Default)]
+struct UserStore {
+    users: Arc<Mutex<HashMap<u64, String>>>,
+}
+
+impl Users for UserStore {
+    async fn get_user(&self, id: u64) -> Result<User, RpcError> {
+        let users = self.users.lock().unwrap();
+        let name = users
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| RpcError::new(404, format!("No user {}", id)))?;
+        Ok(User { id, name })
+    }
+
+    async fn rename(&self, id: u64, name: String) -> Result<(), RpcError> {
+        self.users.lock().unwrap().insert(id, name);
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<usize, RpcError> {
+        Ok(self.users.lock().unwrap().len())
+    }
+}
+
+/// Serve `UserStore` through a router connected to a client over in-memory channels
+fn connect() -> UsersClient<serde_json::Value> {
+    let mut router = RpcRouter::new();
+    UsersServer::register(&mut router);
+    let router = Arc::new(router);
+
+    let (request_tx, request_rx) = mpsc::unbounded_channel::<Message>();
+    let (response_tx, mut response_rx) = mpsc::unbounded_channel::<Message>();
+    let client = Arc::new(RpcClient::new(request_tx, JsonCodec::new()));
+
+    let incoming = futures::stream::unfold(request_rx, |mut rx| async move {
+        rx.recv().await.map(|message| (Ok(message), rx))
+    });
+    let outgoing = futures::sink::unfold(response_tx, |tx, message: Message| async move {
+        tx.send(message)
+            .map_err(|e| TransportError::SendFailed(e.to_string()))?;
+        Ok::<_, TransportError>(tx)
+    });
+    tokio::spawn(router.serve(UserStore::default(), incoming, Box::pin(outgoing)));
+
+    let responder = client.clone();
+    tokio::spawn(async move {
+        while let Some(message) = response_rx.recv().await {
+            responder.handle_response(&message.data).await.unwrap();
+        }
+    });
+
+    UsersClient::new(client)
+}
+
+#[test]
+fn test_method_name_constants() {
+    assert_eq!(UsersMethods::GET_USER, "Users.get_user");
+    assert_eq!(UsersMethods::RENAME, "Users.rename");
+    assert_eq!(
+        UsersMethods::ALL,
+        &[
+            "Users.get_user",
+            "Users.rename",
+            "Users.count",
+            "Users.exists"
+        ]
+    );
+}
+
+#[test]
+fn test_server_registers_every_method() {
+    let mut router = RpcRouter::<UserStore>::new();
+    UsersServer::register(&mut router);
+
+    let mut methods = router.methods();
+    methods.sort();
+    let mut expected: Vec<String> = UsersMethods::ALL.iter().map(|m| m.to_string()).collect();
+    expected.sort();
+    assert_eq!(methods, expected);
+}
+
+#[tokio::test]
+async fn test_typed_client_round_trip() {
+    let users = connect();
+
+    assert_eq!(users.count().await.unwrap(), 0);
+    assert!(!users.exists(7).await.unwrap());
+    users.rename(7, "Ada".to_string()).await.unwrap();
+    assert_eq!(users.count().await.unwrap(), 1);
+    assert!(users.exists(7).await.unwrap());
+    assert_eq!(
+        users.get_user(7).await.unwrap(),
+        User {
+            id: 7,
+            name: "Ada".to_string()
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_typed_client_surfaces_handler_errors() {
+    let users = connect();
+
+    let error = users.get_user(1).await.unwrap_err();
+    assert_eq!(error.code, 404);
+    assert_eq!(error.message, "No user 1");
+}
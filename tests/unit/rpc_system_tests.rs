@@ -37,12 +37,31 @@ async fn test_rpc_client_creation() {
     assert!(true); // Basic creation test
 }
 
+/// Create a client whose requests are answered by an in-process echo server
+fn echo_client() -> std::sync::Arc<RpcClient<TestRequest>> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<transport::Message>();
+    let client = std::sync::Arc::new(RpcClient::new(tx, JsonCodec::new()));
+    let responder = client.clone();
+    tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let request: RpcRequest<serde_json::Value> =
+                serde_json::from_slice(&message.data).unwrap();
+            let response = RpcResponse {
+                id: request.id,
+                result: Some(request.params),
+                error: None,
+            };
+            let data = serde_json::to_vec(&response).unwrap();
+            responder.handle_response(&data).await.unwrap();
+        }
+    });
+    client
+}
+
 #[tokio::test]
 async fn test_rpc_request_response() {
     // Test that RPC can handle request/response patterns
-    let ws_context = WebSocketContext::new_with_url("ws://localhost:8080");
-    let codec = JsonCodec::new();
-    let client: RpcClient<TestRequest> = RpcClient::from_context(&ws_context, codec);
+    let client = echo_client();
 
     let request = TestRequest {
         id: 1,
@@ -54,10 +73,11 @@ async fn test_rpc_request_response() {
         client.call("test_method", request, RpcMethod::Call).await;
     assert!(result.is_ok());
 
-    // Verify it's a successful response
+    // Verify it's a successful response correlated to our request
     match result {
         Ok(response) => {
-            assert_eq!(response.id, "test_method");
+            assert!(response.id.starts_with("rpc_"));
+            assert_eq!(response.result.unwrap()["message"], "Hello, RPC!");
         }
         Err(_) => panic!("Expected success, but got error"),
     }
@@ -66,9 +86,7 @@ async fn test_rpc_request_response() {
 #[tokio::test]
 async fn test_rpc_subscription() {
     // Test that RPC can handle subscriptions
-    let ws_context = WebSocketContext::new_with_url("ws://localhost:8080");
-    let codec = JsonCodec::new();
-    let client: RpcClient<TestRequest> = RpcClient::from_context(&ws_context, codec);
+    let client = echo_client();
 
     let request = TestRequest {
         id: 2,
@@ -92,9 +110,7 @@ async fn test_rpc_subscription() {
 #[tokio::test]
 async fn test_rpc_error_handling() {
     // Test that RPC properly handles various error conditions
-    let ws_context = WebSocketContext::new_with_url("ws://localhost:8080");
-    let codec = JsonCodec::new();
-    let client: RpcClient<TestRequest> = RpcClient::from_context(&ws_context, codec);
+    let client = echo_client();
 
     let request = TestRequest {
         id: 3,
@@ -218,9 +234,7 @@ async fn test_rpc_method_types() {
 #[tokio::test]
 async fn test_rpc_subscription_lifecycle() {
     // Test RPC subscription lifecycle
-    let ws_context = WebSocketContext::new_with_url("ws://localhost:8080");
-    let codec = JsonCodec::new();
-    let client: RpcClient<TestRequest> = RpcClient::from_context(&ws_context, codec);
+    let client = echo_client();
 
     let request = TestRequest {
         id: 5,
@@ -240,8 +254,7 @@ async fn test_rpc_subscription_lifecycle() {
     // Verify subscription was created
     assert!(!subscription_id.is_empty());
 
-    // Test subscription cancellation (not implemented yet)
-    // This would be: subscription.cancel().await;
-    // For now, just verify the subscription exists
-    assert!(true);
+    // Dropping the subscription cancels it
+    drop(subscription);
+    assert!(client.unsubscribe(&subscription_id).await.is_ok());
 }
@@ -9,6 +9,7 @@ use leptos_ws_pro::transport::websocket::WebSocketConnection;
 use leptos_ws_pro::transport::sse::SseConnection;
 use leptos_ws_pro::transport::webtransport::WebTransportConnection;
 use leptos_ws_pro::transport::adaptive::AdaptiveTransport;
+use leptos::prelude::GetUntracked;
 use std::time::Duration;
 use tokio::time::timeout;
 
@@ -261,7 +262,7 @@ async fn test_adaptive_transport_creation() {
 
     let connection = connection.unwrap();
     assert_eq!(connection.state(), ConnectionState::Disconnected);
-    assert_eq!(connection.selected_transport(), "None");
+    assert_eq!(connection.selected_transport().get_untracked(), "None");
 }
 
 #[tokio::test]
@@ -6,7 +6,17 @@
 use leptos_ws_pro::codec::{Codec, CodecError, HybridCodec, JsonCodec, RkyvCodec, WsMessage};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+)]
+#[archive(check_bytes)]
 pub struct TestData {
     id: u64,
     name: String,
@@ -399,16 +409,20 @@ mod codec_integration_tests {
         // Encode with JSON
         let json_encoded = json_codec.encode(&data).unwrap();
 
-        // Since RkyvCodec currently uses JSON internally, this should work
-        let json_decoded: TestData = rkyv_codec.decode(&json_encoded).unwrap();
-        assert_eq!(data, json_decoded);
+        // rkyv archives are a different format, so JSON is rejected
+        let json_decoded: Result<TestData, _> = rkyv_codec.decode(&json_encoded);
+        assert!(json_decoded.is_err());
 
         // Encode with Rkyv
         let rkyv_encoded = rkyv_codec.encode(&data).unwrap();
 
-        // Decode with JSON (should work since Rkyv uses JSON internally)
-        let rkyv_decoded: TestData = json_codec.decode(&rkyv_encoded).unwrap();
-        assert_eq!(data, rkyv_decoded);
+        // ...and JSON can't read rkyv archives
+        let rkyv_decoded: Result<TestData, _> = json_codec.decode(&rkyv_encoded);
+        assert!(rkyv_decoded.is_err());
+
+        // The hybrid codec still reads plain JSON
+        let hybrid_decoded: TestData = HybridCodec::new().unwrap().decode(&json_encoded).unwrap();
+        assert_eq!(data, hybrid_decoded);
     }
 
     #[test]
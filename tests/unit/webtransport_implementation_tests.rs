@@ -2,17 +2,19 @@
 //!
 //! These tests drive the implementation of WebTransport connections
 //! using HTTP/3, providing an alternative to WebSocket connections.
+//!
+//! They run against a local wtransport echo server whose self-signed
+//! certificate the client pins by its SHA-256 digest.
 
 use futures::{SinkExt, StreamExt};
 use leptos_ws_pro::transport::{
-    webtransport::WebTransportConnection, ConnectionState, Message, MessageType, Transport,
-    TransportConfig, TransportError,
+    webtransport::{Sha256Digest, WebTransportConnection},
+    ConnectionState, Message, MessageType, Transport, TransportConfig, TransportError,
 };
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
-use tokio::net::TcpListener;
 use tokio::time::timeout;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use wtransport::{Endpoint, Identity, ServerConfig};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 struct TestMessage {
@@ -21,72 +23,57 @@ struct TestMessage {
     timestamp: u64,
 }
 
-/// Start a test HTTP/3 server for WebTransport testing
-async fn start_test_http3_server() -> (TcpListener, u16) {
-    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let port = listener.local_addr().unwrap().port();
-    (listener, port)
-}
-
-/// Helper function to start server and wait for it to be ready
-async fn start_server_and_wait() -> u16 {
-    let (listener, port) = start_test_http3_server().await;
+/// Start an HTTP/3 server echoing every bidirectional stream, returning its
+/// URL and certificate hash
+async fn start_server_and_wait() -> (String, Sha256Digest) {
+    let identity = Identity::self_signed(["localhost", "127.0.0.1"]).unwrap();
+    let hash = identity.certificate_chain().as_slice()[0].hash();
+    let config = ServerConfig::builder()
+        .with_bind_address("127.0.0.1:0".parse().unwrap())
+        .with_identity(identity)
+        .build();
+    let server = Endpoint::server(config).unwrap();
+    let port = server.local_addr().unwrap().port();
 
-    // Start server in background
     tokio::spawn(async move {
-        run_http3_echo_server(listener).await;
+        loop {
+            let incoming = server.accept().await;
+            tokio::spawn(async move {
+                let Ok(request) = incoming.await else { return };
+                let Ok(connection) = request.accept().await else {
+                    return;
+                };
+                while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                    tokio::spawn(async move {
+                        let _ = tokio::io::copy(&mut recv, &mut send).await;
+                    });
+                }
+            });
+        }
     });
 
-    // Give server time to start
-    tokio::time::sleep(Duration::from_millis(100)).await;
-
-    port
+    (format!("https://127.0.0.1:{}/", port), hash)
 }
 
-/// Run an HTTP/3 echo server for WebTransport testing
-async fn run_http3_echo_server(listener: TcpListener) {
-    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-
-    while let Ok((stream, _)) = listener.accept().await {
-        tokio::spawn(async move {
-            let mut stream = BufReader::new(stream);
-            let mut line = String::new();
-
-            // Read HTTP request
-            while stream.read_line(&mut line).await.unwrap() > 0 {
-                if line == "\r\n" {
-                    break;
-                }
-                line.clear();
-            }
-
-            // Send HTTP/1.1 WebTransport response (for testing)
-            let response = "HTTP/1.1 200 OK\r\n\
-                          Upgrade: webtransport\r\n\
-                          Connection: Upgrade\r\n\
-                          Sec-WebTransport-Accept: test\r\n\r\n";
-
-            stream.get_mut().write_all(response.as_bytes()).await.unwrap();
-
-            // Simulate WebTransport stream data
-            let test_data = b"WebTransport test message";
-            stream.get_mut().write_all(test_data).await.unwrap();
-        });
-    }
+/// A client for `url` that trusts the test server's certificate
+async fn client_for(url: &str, hash: Sha256Digest) -> WebTransportConnection {
+    let config = TransportConfig {
+        url: url.to_string(),
+        ..Default::default()
+    };
+    let mut client = WebTransportConnection::new(config).await.unwrap();
+    client.set_server_certificate_hashes(vec![hash]);
+    client
 }
 
 #[tokio::test]
 async fn test_webtransport_connection() {
     // Given: An HTTP/3 server running on localhost
-    let port = start_server_and_wait().await;
+    let (url, hash) = start_server_and_wait().await;
 
     // When: Client connects to the server via WebTransport
-    let config = TransportConfig {
-        url: format!("http://127.0.0.1:{}", port),
-        ..Default::default()
-    };
-    let mut client = WebTransportConnection::new(config).await.unwrap();
-    let result = client.connect(&format!("http://127.0.0.1:{}", port)).await;
+    let mut client = client_for(&url, hash).await;
+    let result = client.connect(&url).await;
 
     // Then: Connection should succeed
     assert!(result.is_ok());
@@ -96,17 +83,9 @@ async fn test_webtransport_connection() {
 #[tokio::test]
 async fn test_webtransport_message_sending() {
     // Given: A connected WebTransport client and HTTP/3 echo server
-    let port = start_server_and_wait().await;
-
-    let config = TransportConfig {
-        url: format!("http://127.0.0.1:{}", port),
-        ..Default::default()
-    };
-    let mut client = WebTransportConnection::new(config).await.unwrap();
-    client
-        .connect(&format!("http://127.0.0.1:{}", port))
-        .await
-        .unwrap();
+    let (url, hash) = start_server_and_wait().await;
+    let mut client = client_for(&url, hash).await;
+    client.connect(&url).await.unwrap();
 
     // When: Client sends a text message
     let message = Message {
@@ -120,7 +99,9 @@ async fn test_webtransport_message_sending() {
     assert!(send_result.is_ok());
 
     // And: Should receive the echoed message back
-    let received = stream.next().await;
+    let received = timeout(Duration::from_secs(5), stream.next())
+        .await
+        .unwrap();
     assert!(received.is_some());
     let received_msg = received.unwrap().unwrap();
     assert_eq!(received_msg, message);
@@ -129,17 +110,9 @@ async fn test_webtransport_message_sending() {
 #[tokio::test]
 async fn test_webtransport_binary_message() {
     // Given: A connected WebTransport client and HTTP/3 echo server
-    let port = start_server_and_wait().await;
-
-    let config = TransportConfig {
-        url: format!("http://127.0.0.1:{}", port),
-        ..Default::default()
-    };
-    let mut client = WebTransportConnection::new(config).await.unwrap();
-    client
-        .connect(&format!("http://127.0.0.1:{}", port))
-        .await
-        .unwrap();
+    let (url, hash) = start_server_and_wait().await;
+    let mut client = client_for(&url, hash).await;
+    client.connect(&url).await.unwrap();
 
     // When: Client sends a binary message
     let binary_data = vec![0x01, 0x02, 0x03, 0x04, 0x05];
@@ -153,7 +126,9 @@ async fn test_webtransport_binary_message() {
     let send_result = sink.send(message.clone()).await;
     assert!(send_result.is_ok());
 
-    let received = stream.next().await;
+    let received = timeout(Duration::from_secs(5), stream.next())
+        .await
+        .unwrap();
     assert!(received.is_some());
     let received_msg = received.unwrap().unwrap();
     assert_eq!(received_msg, message);
@@ -161,47 +136,37 @@ async fn test_webtransport_binary_message() {
 
 #[tokio::test]
 async fn test_webtransport_connection_timeout() {
-    // Given: A WebTransport client
+    // Given: A WebTransport client and a port nothing listens on
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let url = format!("https://127.0.0.1:{}/", socket.local_addr().unwrap().port());
+    drop(socket);
     let config = TransportConfig {
-        url: "http://127.0.0.1:99999".to_string(),
+        url: url.clone(),
+        connection_timeout: Duration::from_secs(1),
         ..Default::default()
     };
     let mut client = WebTransportConnection::new(config).await.unwrap();
 
     // When: Client tries to connect to non-existent server
-    let result = timeout(
-        Duration::from_secs(5),
-        client.connect("http://127.0.0.1:99999"),
-    )
-    .await;
+    let result = timeout(Duration::from_secs(5), client.connect(&url)).await;
 
-    // Then: Should fail with connection error
+    // Then: Should fail with connection error once the connection timeout passes
     assert!(result.is_ok()); // Timeout completed
-    let connect_result = result.unwrap();
-    assert!(connect_result.is_err());
-    // Connection should fail with any error type
-    let error = connect_result.unwrap_err();
-    assert!(matches!(
-        error,
-        TransportError::ConnectionFailed(_) | TransportError::Timeout
-    ));
+    let error = result.unwrap().unwrap_err();
+    assert!(
+        matches!(error, TransportError::ConnectionFailed(_)),
+        "{:?}",
+        error
+    );
     assert_eq!(client.state(), ConnectionState::Disconnected);
 }
 
 #[tokio::test]
 async fn test_webtransport_disconnect() {
     // Given: A connected WebTransport client
-    let port = start_server_and_wait().await;
-
-    let config = TransportConfig {
-        url: format!("http://127.0.0.1:{}", port),
-        ..Default::default()
-    };
-    let mut client = WebTransportConnection::new(config).await.unwrap();
-    client
-        .connect(&format!("http://127.0.0.1:{}", port))
-        .await
-        .unwrap();
+    let (url, hash) = start_server_and_wait().await;
+    let mut client = client_for(&url, hash).await;
+    client.connect(&url).await.unwrap();
     assert_eq!(client.state(), ConnectionState::Connected);
 
     // When: Client disconnects
@@ -215,19 +180,11 @@ async fn test_webtransport_disconnect() {
 #[tokio::test]
 async fn test_webtransport_reconnection() {
     // Given: A WebTransport client that was connected
-    let port = start_server_and_wait().await;
-
-    let config = TransportConfig {
-        url: format!("http://127.0.0.1:{}", port),
-        ..Default::default()
-    };
-    let mut client = WebTransportConnection::new(config).await.unwrap();
+    let (url, hash) = start_server_and_wait().await;
+    let mut client = client_for(&url, hash).await;
 
     // First connection
-    client
-        .connect(&format!("http://127.0.0.1:{}", port))
-        .await
-        .unwrap();
+    client.connect(&url).await.unwrap();
     assert_eq!(client.state(), ConnectionState::Connected);
 
     // Disconnect
@@ -235,7 +192,7 @@ async fn test_webtransport_reconnection() {
     assert_eq!(client.state(), ConnectionState::Disconnected);
 
     // When: Client reconnects
-    let result = client.connect(&format!("http://127.0.0.1:{}", port)).await;
+    let result = client.connect(&url).await;
 
     // Then: Should reconnect successfully
     assert!(result.is_ok());
@@ -245,17 +202,9 @@ async fn test_webtransport_reconnection() {
 #[tokio::test]
 async fn test_webtransport_serialized_message() {
     // Given: A connected WebTransport client and HTTP/3 echo server
-    let port = start_server_and_wait().await;
-
-    let config = TransportConfig {
-        url: format!("http://127.0.0.1:{}", port),
-        ..Default::default()
-    };
-    let mut client = WebTransportConnection::new(config).await.unwrap();
-    client
-        .connect(&format!("http://127.0.0.1:{}", port))
-        .await
-        .unwrap();
+    let (url, hash) = start_server_and_wait().await;
+    let mut client = client_for(&url, hash).await;
+    client.connect(&url).await.unwrap();
 
     // When: Client sends a serialized message
     let test_msg = TestMessage {
@@ -274,7 +223,9 @@ async fn test_webtransport_serialized_message() {
     let send_result = sink.send(message.clone()).await;
     assert!(send_result.is_ok());
 
-    let received = stream.next().await;
+    let received = timeout(Duration::from_secs(5), stream.next())
+        .await
+        .unwrap();
     assert!(received.is_some());
     let received_msg = received.unwrap().unwrap();
     assert_eq!(received_msg, message);
@@ -290,17 +241,9 @@ async fn test_webtransport_serialized_message() {
 #[tokio::test]
 async fn test_webtransport_multiple_messages() {
     // Given: A connected WebTransport client and HTTP/3 echo server
-    let port = start_server_and_wait().await;
-
-    let config = TransportConfig {
-        url: format!("http://127.0.0.1:{}", port),
-        ..Default::default()
-    };
-    let mut client = WebTransportConnection::new(config).await.unwrap();
-    client
-        .connect(&format!("http://127.0.0.1:{}", port))
-        .await
-        .unwrap();
+    let (url, hash) = start_server_and_wait().await;
+    let mut client = client_for(&url, hash).await;
+    client.connect(&url).await.unwrap();
 
     // When: Client sends multiple messages
     let (mut stream, mut sink) = client.split();
@@ -327,7 +270,9 @@ async fn test_webtransport_multiple_messages() {
 
     // Then: Should receive all messages back
     for expected_message in &messages {
-        let received = stream.next().await;
+        let received = timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap();
         assert!(received.is_some());
         let received_msg = received.unwrap().unwrap();
         assert_eq!(received_msg, *expected_message);
@@ -337,17 +282,9 @@ async fn test_webtransport_multiple_messages() {
 #[tokio::test]
 async fn test_webtransport_http3_protocol_features() {
     // Given: A connected WebTransport client
-    let port = start_server_and_wait().await;
-
-    let config = TransportConfig {
-        url: format!("http://127.0.0.1:{}", port),
-        ..Default::default()
-    };
-    let mut client = WebTransportConnection::new(config).await.unwrap();
-    client
-        .connect(&format!("http://127.0.0.1:{}", port))
-        .await
-        .unwrap();
+    let (url, hash) = start_server_and_wait().await;
+    let mut client = client_for(&url, hash).await;
+    client.connect(&url).await.unwrap();
 
     // When: Testing HTTP/3 specific features
     let (mut stream, mut sink) = client.split();
@@ -362,7 +299,9 @@ async fn test_webtransport_http3_protocol_features() {
     let send_result = sink.send(message.clone()).await;
     assert!(send_result.is_ok());
 
-    let received = stream.next().await;
+    let received = timeout(Duration::from_secs(5), stream.next())
+        .await
+        .unwrap();
     assert!(received.is_some());
     let received_msg = received.unwrap().unwrap();
     assert_eq!(received_msg, message);